@@ -1,9 +1,11 @@
 #[cfg(not(target_arch = "wasm32"))]
 use crate::p2p;
 use crate::{
-    AesStream, ClientMode, Config, HostShell, RelayStream, ServerStream, ShellKey, TunnelStream,
+    AesStream, ClientMode, Config, HostShell, ReconnectPolicy, Redial, RelayStream, ServerStream,
+    ShellKey, TunnelStream,
 };
 use anyhow::{Error, Result};
+use async_trait::async_trait;
 use futures::{future, stream::StreamExt, FutureExt};
 use log::*;
 use std::sync::{Arc, Mutex};
@@ -15,6 +17,11 @@ pub type ClientMessageStream = MessageStream<ClientMessage, ServerMessage, Compa
 pub struct Client {
     config: Config,
     host_shell: Option<HostShell>,
+    /// The connection to the relay server, kept open until the session ends
+    relay: Option<Arc<Mutex<ClientMessageStream>>>,
+    /// Runs the shell server in place of one configured from the environment
+    #[cfg(not(target_arch = "wasm32"))]
+    shell_server: Option<crate::ShellServer>,
 }
 
 impl Client {
@@ -22,14 +29,44 @@ impl Client {
         Self {
             config,
             host_shell: Some(host_shell),
+            relay: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            shell_server: None,
         }
     }
 
+    /// Serves the peer's shells with the supplied server, which is built using
+    /// `ShellServer::builder()`, rather than one configured from the environment
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_shell_server(mut self, server: crate::ShellServer) -> Self {
+        self.shell_server = Some(server);
+        self
+    }
+
+    /// Prints the line to the host shell, lines are discarded while
+    /// the host shell is in use by the shell client
     pub async fn println(&mut self, line: &str) {
-        self.host_shell.as_mut().unwrap().println(line).await;
+        if let Some(host_shell) = self.host_shell.as_mut() {
+            host_shell.println(line).await;
+        }
     }
 
     pub async fn start_session(&mut self) -> Result<u8> {
+        let (peer_socket, peer_info) = self.connect_to_peer().await?;
+
+        let exit_code = match self.config.mode() {
+            ClientMode::Target => self.start_shell_server(peer_socket, peer_info).await?,
+            ClientMode::Local => self.start_shell_client(peer_socket).await?,
+        };
+
+        self.close_relay().await?;
+
+        Ok(exit_code)
+    }
+
+    /// Connects to the peer through the relay server, returning the connection to the
+    /// peer and its details
+    async fn connect_to_peer(&mut self) -> Result<(Box<dyn TunnelStream>, PeerJoinedPayload)> {
         self.println("Connecting to relay server...").await;
         let relay_socket = ServerStream::connect(&self.config).await?;
 
@@ -46,16 +83,18 @@ impl Client {
         let (peer_socket, message_stream) = self
             .negotiate_peer_connection(message_stream, &mut peer_info, self.config.is_target())
             .await?;
+        self.relay = Some(message_stream);
 
-        let exit_code = match self.config.mode() {
-            ClientMode::Target => self.start_shell_server(peer_socket).await?,
-            ClientMode::Local => self.start_shell_client(peer_socket).await?,
-        };
+        Ok((peer_socket, peer_info))
+    }
 
-        let message_stream = if let Ok(message_stream) = Arc::try_unwrap(message_stream) {
-            message_stream.into_inner().ok()
-        } else {
-            None
+    /// Sends the close message to the relay server, the connection to the peer
+    /// must have been dropped beforehand
+    async fn close_relay(&mut self) -> Result<()> {
+        let message_stream = match self.relay.take().map(Arc::try_unwrap) {
+            Some(Ok(message_stream)) => message_stream.into_inner().ok(),
+            Some(Err(_)) => None,
+            None => return Ok(()),
         };
 
         if let Some(mut message_stream) = message_stream {
@@ -65,7 +104,7 @@ impl Client {
             warn!("failed to take ownership of message stream");
         }
 
-        Ok(exit_code)
+        Ok(())
     }
 
     async fn send_key(&self, message_stream: &mut ClientMessageStream) -> Result<()> {
@@ -80,7 +119,10 @@ impl Client {
             Some(Ok(ServerMessage::KeyRejected)) => {
                 Err(Error::msg("The session key has expired or is invalid"))
             }
-            result @ _ => Err(self.handle_unexpected_message(result)),
+            Some(Ok(ServerMessage::PeerRejected)) => Err(Error::msg(
+                "The session cannot be joined from this network address",
+            )),
+            result => Err(self.handle_unexpected_message(result)),
         }
     }
 
@@ -89,13 +131,11 @@ impl Client {
         message_stream: &mut ClientMessageStream,
     ) -> Result<PeerJoinedPayload> {
         match message_stream.next().await {
-            Some(Ok(ServerMessage::AlreadyJoined)) => {
-                return Err(Error::msg(
-                    "Connection has already been joined by another host",
-                ))
-            }
+            Some(Ok(ServerMessage::AlreadyJoined)) => Err(Error::msg(
+                "Connection has already been joined by another host",
+            )),
             Some(Ok(ServerMessage::PeerJoined(payload))) => Ok(payload),
-            result @ _ => Err(self.handle_unexpected_message(result)),
+            result => Err(self.handle_unexpected_message(result)),
         }
     }
 
@@ -133,7 +173,7 @@ impl Client {
                         ));
                     }
 
-                    let bound = std::mem::replace(&mut bound, None).unwrap();
+                    let bound = bound.take().unwrap();
                     match self
                         .attempt_direct_connection(bound.0, bound.1, ports, master_side)
                         .await?
@@ -159,7 +199,7 @@ impl Client {
                     let relay_stream = Box::new(RelayStream::new(Arc::clone(&message_stream)));
                     break (relay_stream, message_stream);
                 }
-                result @ _ => return Err(self.handle_unexpected_message(result)),
+                result => return Err(self.handle_unexpected_message(result)),
             }
         };
 
@@ -229,6 +269,7 @@ impl Client {
         master_side: bool,
     ) -> Result<Option<Box<dyn TunnelStream>>> {
         use crate::p2p::P2PConnection;
+        use crate::probe_transport;
 
         let tcp_connection = match (&mut tcp, &peer_ports) {
             (
@@ -260,6 +301,24 @@ impl Client {
         );
 
         let stream: Option<Box<dyn TunnelStream>> = match attempt_connection.await {
+            (Ok(Ok(_)), Ok(Ok(_))) => {
+                info!("direct TCP and UDP connections established with peer, probing");
+                let candidates: Vec<Box<dyn TunnelStream>> =
+                    vec![Box::new(tcp.unwrap()), Box::new(udp.unwrap())];
+
+                match tokio::time::timeout(timeout, probe_transport(candidates, master_side)).await
+                {
+                    Ok(Ok(stream)) => Some(stream),
+                    Ok(Err(err)) => {
+                        warn!("failed to probe direct connections: {}", err);
+                        None
+                    }
+                    Err(_) => {
+                        warn!("timed out while probing direct connections");
+                        None
+                    }
+                }
+            }
             (Ok(Ok(_)), _) => {
                 info!("direct TCP connection established with peer");
                 Some(Box::new(tcp.unwrap()))
@@ -310,40 +369,125 @@ impl Client {
         Ok((Box::new(stream), message_stream))
     }
 
+    /// Runs the shell server for the peer. While shells are detached the server waits
+    /// for the peer to reconnect through the relay, until the detached shells expire.
     #[cfg(not(target_arch = "wasm32"))]
-    async fn start_shell_server(&self, peer_socket: Box<dyn TunnelStream>) -> Result<u8> {
-        crate::ShellServer::new()?
-            .run(peer_socket, ShellKey::new(self.config.encryption_key()))
-            .await
-            .and_then(|_| Ok(0))
+    async fn start_shell_server(
+        &mut self,
+        mut peer_socket: Box<dyn TunnelStream>,
+        mut peer_info: PeerJoinedPayload,
+    ) -> Result<u8> {
+        let server = match self.shell_server.clone() {
+            Some(server) => server,
+            None => crate::ShellServer::builder()
+                .config(self.config.shell_server_config().clone())
+                .build()?,
+        };
+
+        loop {
+            let result = server
+                .clone()
+                .with_peer_addr(&peer_info.peer_ip_address)
+                .with_reconnect_token(&peer_info.reconnect_token)
+                .run(peer_socket, ShellKey::new(self.config.encryption_key()))
+                .await;
+
+            if let (Ok(report), Some(path)) = (&result, self.config.session_report_path()) {
+                report
+                    .write_to(path)
+                    .unwrap_or_else(|err| warn!("failed to write session report: {:?}", err));
+            }
+
+            if server.detached_shell_count() == 0 {
+                return result.map(|_| 0);
+            }
+
+            if let Err(err) = result {
+                warn!("shell detached after error: {:#}", err);
+            }
+
+            self.println("Waiting for peer to reconnect...").await;
+            self.close_relay().await?;
+
+            let ttl = server.detached_shell_ttl();
+            let (socket, info) = tokio::time::timeout(ttl, self.connect_to_peer())
+                .await
+                .map_err(|_| {
+                    Error::msg("peer did not reconnect before the detached shell expired")
+                })??;
+            peer_socket = socket;
+            peer_info = info;
+        }
     }
 
     #[cfg(target_arch = "wasm32")]
-    async fn start_shell_server(&self, _peer_socket: Box<dyn TunnelStream>) -> Result<u8> {
+    async fn start_shell_server(
+        &mut self,
+        _peer_socket: Box<dyn TunnelStream>,
+        _peer_info: PeerJoinedPayload,
+    ) -> Result<u8> {
         unreachable!()
     }
 
     async fn start_shell_client(&mut self, peer_socket: Box<dyn TunnelStream>) -> Result<u8> {
-        let mut client = crate::ShellClient::new(self.host_shell.take().unwrap())?;
+        let mut client = crate::ShellClient::new(self.host_shell.take().unwrap())?
+            .with_message_format(self.config.message_format())
+            .with_sequence_numbers(self.config.sequence_numbers())
+            .with_line_mode(self.config.line_mode())
+            .with_reconnect_token(self.config.reconnect_token());
+        let policy = ReconnectPolicy {
+            max_attempts: self.config.reconnect_attempts(),
+            backoff: self.config.reconnect_backoff(),
+        };
+
+        // The relay connection is handed to the redial so it can be replaced
+        let mut redial = PeerRedial(Client {
+            config: self.config.clone(),
+            host_shell: None,
+            relay: self.relay.take(),
+            #[cfg(not(target_arch = "wasm32"))]
+            shell_server: None,
+        });
         let result = client
-            .connect(peer_socket, ShellKey::new(self.config.encryption_key()))
+            .connect_with_reconnect(
+                peer_socket,
+                ShellKey::new(self.config.encryption_key()),
+                policy,
+                &mut redial,
+            )
             .await;
 
+        self.relay = redial.0.relay.take();
         self.host_shell.replace(client.host_shell);
         result
     }
 
     fn handle_unexpected_message(&self, message: Option<Result<ServerMessage>>) -> Error {
         match message {
-            Some(Ok(message)) => {
-                return Error::msg(format!(
-                    "Unexpected response returned by server: {:?}",
-                    message
-                ))
-            }
-            Some(Err(err)) => return err,
-            None => return Error::msg("Connection closed unexpectedly"),
+            Some(Ok(message)) => Error::msg(format!(
+                "Unexpected response returned by server: {:?}",
+                message
+            )),
+            Some(Err(err)) => err,
+            None => Error::msg("Connection closed unexpectedly"),
+        }
+    }
+}
+
+/// Reconnects to the peer through the relay server after the connection to it is lost
+struct PeerRedial(Client);
+
+#[async_trait(?Send)]
+impl Redial for PeerRedial {
+    async fn redial(&mut self) -> Result<Box<dyn TunnelStream>> {
+        // The previous relay connection is closed so the session can be joined again
+        if let Err(err) = self.0.close_relay().await {
+            debug!("failed to close previous relay connection: {}", err);
         }
+
+        let (peer_socket, _) = self.0.connect_to_peer().await?;
+
+        Ok(peer_socket)
     }
 }
 
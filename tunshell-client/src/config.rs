@@ -1,8 +1,14 @@
+#[cfg(not(target_arch = "wasm32"))]
+use crate::ShellServerConfig;
 use anyhow::Error;
 use std::{convert::TryFrom, env, time::Duration};
+use tunshell_shared::MessageFormat;
 
 const DEFAULT_DIRECT_CONNECT_TIMEOUT: u64 = 3000; // ms
+const DEFAULT_RECONNECT_ATTEMPTS: u32 = 5;
+const DEFAULT_RECONNECT_BACKOFF: u64 = 1000; // ms
 
+#[derive(Clone)]
 pub struct Config {
     mode: ClientMode,
     session_key: String,
@@ -12,6 +18,28 @@ pub struct Config {
     direct_connection_timeout: Duration,
     enable_direct_connection: bool,
     dangerous_disable_relay_server_verification: bool,
+    /// When set a JSON summary of each shell session is appended to this file
+    session_report_path: Option<String>,
+    /// The format of shell messages, JSON can be selected for debugging
+    message_format: MessageFormat,
+    /// Numbers each shell message to diagnose transports which lose or reorder them
+    sequence_numbers: bool,
+    /// Sends input a line at a time with local echo, for high latency links
+    line_mode: bool,
+    /// When set the relay is connected to through this HTTP proxy using CONNECT,
+    /// in the form `http://[user:password@]host[:port]`
+    http_proxy: Option<String>,
+    /// The session's reconnect token, when set the local client reattaches to the
+    /// shell after losing the connection to the target. The target must enable
+    /// `TUNSHELL_SHELL_DETACH_ON_DISCONNECT` for its shell to outlive the connection.
+    reconnect_token: Option<String>,
+    /// The number of consecutive failed reconnection attempts before giving up
+    reconnect_attempts: u32,
+    /// The delay before the first reconnection attempt, doubled after each failure
+    reconnect_backoff: Duration,
+    /// Configures the shell server run in target mode, see `ShellServerConfig::from_env`
+    #[cfg(not(target_arch = "wasm32"))]
+    shell_server_config: ShellServerConfig,
 }
 
 #[derive(PartialEq, Copy, Clone, Debug)]
@@ -22,7 +50,7 @@ pub enum ClientMode {
 
 impl Config {
     pub fn new_from_env() -> Self {
-        let mut args = env::args().into_iter();
+        let mut args = env::args();
 
         args.next().expect("first argument must be set");
 
@@ -50,6 +78,35 @@ impl Config {
             direct_connection_timeout: Duration::from_millis(DEFAULT_DIRECT_CONNECT_TIMEOUT),
             enable_direct_connection: true,
             dangerous_disable_relay_server_verification: false,
+            session_report_path: env::var("TUNSHELL_SESSION_REPORT").ok(),
+            message_format: match env::var("TUNSHELL_MESSAGE_FORMAT") {
+                Ok(format) if format == "json" => MessageFormat::Json,
+                _ => MessageFormat::Binary,
+            },
+            sequence_numbers: env::var("TUNSHELL_SEQUENCE_NUMBERS").is_ok_and(|i| i == "1"),
+            line_mode: env::var("TUNSHELL_LINE_MODE").is_ok_and(|i| i == "1"),
+            http_proxy: env::var("TUNSHELL_HTTP_PROXY")
+                .ok()
+                .filter(|i| !i.is_empty()),
+            reconnect_token: env::var("TUNSHELL_RECONNECT_TOKEN")
+                .ok()
+                .filter(|i| !i.is_empty()),
+            reconnect_attempts: env::var("TUNSHELL_RECONNECT_ATTEMPTS").map_or(
+                DEFAULT_RECONNECT_ATTEMPTS,
+                |i| {
+                    i.parse()
+                        .expect("could not parse TUNSHELL_RECONNECT_ATTEMPTS as a number")
+                },
+            ),
+            reconnect_backoff: Duration::from_millis(
+                env::var("TUNSHELL_RECONNECT_BACKOFF_MS").map_or(DEFAULT_RECONNECT_BACKOFF, |i| {
+                    i.parse()
+                        .expect("could not parse TUNSHELL_RECONNECT_BACKOFF_MS as milliseconds")
+                }),
+            ),
+            #[cfg(not(target_arch = "wasm32"))]
+            shell_server_config: ShellServerConfig::from_env()
+                .unwrap_or_else(|err| panic!("invalid shell server config: {:#}", err)),
         }
     }
 
@@ -70,6 +127,16 @@ impl Config {
             direct_connection_timeout: Duration::from_millis(DEFAULT_DIRECT_CONNECT_TIMEOUT),
             enable_direct_connection,
             dangerous_disable_relay_server_verification: false,
+            session_report_path: None,
+            message_format: MessageFormat::Binary,
+            sequence_numbers: false,
+            line_mode: false,
+            http_proxy: None,
+            reconnect_token: None,
+            reconnect_attempts: DEFAULT_RECONNECT_ATTEMPTS,
+            reconnect_backoff: Duration::from_millis(DEFAULT_RECONNECT_BACKOFF),
+            #[cfg(not(target_arch = "wasm32"))]
+            shell_server_config: ShellServerConfig::default(),
         }
     }
 
@@ -109,6 +176,75 @@ impl Config {
         self.dangerous_disable_relay_server_verification
     }
 
+    pub fn session_report_path(&self) -> Option<&str> {
+        self.session_report_path.as_deref()
+    }
+
+    pub fn set_session_report_path(&mut self, path: Option<&str>) {
+        self.session_report_path = path.map(|i| i.to_owned());
+    }
+
+    pub fn message_format(&self) -> MessageFormat {
+        self.message_format
+    }
+
+    pub fn set_message_format(&mut self, format: MessageFormat) {
+        self.message_format = format;
+    }
+
+    pub fn sequence_numbers(&self) -> bool {
+        self.sequence_numbers
+    }
+
+    pub fn set_sequence_numbers(&mut self, enabled: bool) {
+        self.sequence_numbers = enabled;
+    }
+
+    pub fn line_mode(&self) -> bool {
+        self.line_mode
+    }
+
+    pub fn set_line_mode(&mut self, enabled: bool) {
+        self.line_mode = enabled;
+    }
+
+    pub fn http_proxy(&self) -> Option<&str> {
+        self.http_proxy.as_deref()
+    }
+
+    pub fn set_http_proxy(&mut self, proxy: Option<&str>) {
+        self.http_proxy = proxy.map(|i| i.to_owned());
+    }
+
+    pub fn reconnect_token(&self) -> Option<&str> {
+        self.reconnect_token.as_deref()
+    }
+
+    pub fn set_reconnect_token(&mut self, token: Option<&str>) {
+        self.reconnect_token = token.map(|i| i.to_owned());
+    }
+
+    pub fn reconnect_attempts(&self) -> u32 {
+        self.reconnect_attempts
+    }
+
+    pub fn set_reconnect_attempts(&mut self, attempts: u32) {
+        self.reconnect_attempts = attempts;
+    }
+
+    pub fn reconnect_backoff(&self) -> Duration {
+        self.reconnect_backoff
+    }
+
+    pub fn set_reconnect_backoff(&mut self, backoff: Duration) {
+        self.reconnect_backoff = backoff;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn shell_server_config(&self) -> &ShellServerConfig {
+        &self.shell_server_config
+    }
+
     pub fn set_dangerous_disable_relay_server_verification(&mut self, flag: bool) {
         log::warn!("disabling TLS cert verification for relay server");
         self.dangerous_disable_relay_server_verification = flag;
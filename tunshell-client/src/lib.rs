@@ -18,6 +18,9 @@ pub use stream::*;
 
 pub mod util;
 
+#[cfg(any(test, feature = "test-support"))]
+pub mod testing;
+
 cfg_if::cfg_if! {
     if #[cfg(target_arch = "wasm32")] {
         mod wasm;
@@ -52,7 +52,14 @@ impl AsyncWrite for TcpConnection {
     }
 }
 
-impl TunnelStream for TcpConnection {}
+impl TunnelStream for TcpConnection {
+    fn set_nodelay(&mut self, enabled: bool) -> std::io::Result<()> {
+        match self.socket.as_ref() {
+            Some(socket) => socket.set_nodelay(enabled),
+            None => Ok(()),
+        }
+    }
+}
 
 #[async_trait]
 impl P2PConnection for TcpConnection {
@@ -136,6 +143,7 @@ mod tests {
                 peer_ip_address: "127.0.0.1".to_owned(),
                 peer_key: "test".to_owned(),
                 session_nonce: "nonce".to_owned(),
+                reconnect_token: String::new(),
             });
 
             connection1.bind().await.expect("failed to bind");
@@ -150,7 +158,7 @@ mod tests {
                 .expect("failed to accept connection");
 
             connection1
-                .write("hello".as_bytes())
+                .write_all("hello".as_bytes())
                 .await
                 .expect("failed to write to socket");
 
@@ -163,7 +171,7 @@ mod tests {
             assert_eq!(String::from_utf8(buff[..read].to_vec()).unwrap(), "hello");
 
             socket
-                .write("hi".as_bytes())
+                .write_all("hi".as_bytes())
                 .await
                 .expect("failed to write to socket");
 
@@ -183,6 +191,7 @@ mod tests {
                 peer_ip_address: "127.0.0.1".to_owned(),
                 peer_key: "test".to_owned(),
                 session_nonce: "nonce".to_owned(),
+                reconnect_token: String::new(),
             };
             let mut connection1 = TcpConnection::new(peer_info.clone());
 
@@ -195,14 +204,14 @@ mod tests {
             let (_, mut socket) = futures::try_join!(connection1.connect(22444, false), socket)
                 .expect("failed to connect");
 
-            socket.write("hello".as_bytes()).await.unwrap();
+            socket.write_all("hello".as_bytes()).await.unwrap();
 
             let mut buff = [0; 1024];
             let read = connection1.read(&mut buff).await.unwrap();
 
             assert_eq!(String::from_utf8(buff[..read].to_vec()).unwrap(), "hello");
 
-            connection1.write("hi".as_bytes()).await.unwrap();
+            connection1.write_all("hi".as_bytes()).await.unwrap();
 
             let read = socket.read(&mut buff).await.unwrap();
 
@@ -110,9 +110,9 @@ mod tests {
         con.peer_ack_number = SequenceNumber(100);
         con.peer_window = 10;
 
-        assert_eq!(con.can_send_sequence_number(SequenceNumber(111)), false);
-        assert_eq!(con.can_send_sequence_number(SequenceNumber(109)), true);
-        assert_eq!(con.can_send_sequence_number(SequenceNumber(100)), true);
+        assert!(!con.can_send_sequence_number(SequenceNumber(111)));
+        assert!(con.can_send_sequence_number(SequenceNumber(109)));
+        assert!(con.can_send_sequence_number(SequenceNumber(100)));
     }
 
     #[test]
@@ -123,10 +123,10 @@ mod tests {
         con.transit_window = 5;
         con.peer_window = 10;
 
-        assert_eq!(con.can_send_sequence_number(SequenceNumber(111)), false);
-        assert_eq!(con.can_send_sequence_number(SequenceNumber(109)), false);
-        assert_eq!(con.can_send_sequence_number(SequenceNumber(104)), true);
-        assert_eq!(con.can_send_sequence_number(SequenceNumber(100)), true);
+        assert!(!con.can_send_sequence_number(SequenceNumber(111)));
+        assert!(!con.can_send_sequence_number(SequenceNumber(109)));
+        assert!(con.can_send_sequence_number(SequenceNumber(104)));
+        assert!(con.can_send_sequence_number(SequenceNumber(100)));
     }
 
     #[test]
@@ -137,11 +137,11 @@ mod tests {
         con.peer_ack_number = SequenceNumber(100);
         con.peer_window = 10;
 
-        assert_eq!(con.is_congested(), false);
+        assert!(!con.is_congested());
 
         con.sequence_number = SequenceNumber(110);
 
-        assert_eq!(con.is_congested(), true);
+        assert!(con.is_congested());
     }
 
     #[test]
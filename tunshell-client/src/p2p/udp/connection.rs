@@ -54,18 +54,12 @@ impl UdpConnection {
 
     /// Whether the connection has been activated
     pub fn is_new(&self) -> bool {
-        match &self.state {
-            State::New(_) => true,
-            _ => false,
-        }
+        matches!(&self.state, State::New(_))
     }
 
     /// Whether the connection is bound
     pub fn is_bound(&self) -> bool {
-        match &self.state {
-            State::Bound(_, _) => true,
-            _ => false,
-        }
+        matches!(&self.state, State::Bound(_, _))
     }
 
     /// Whether the connection is in a valid connected state.
@@ -89,7 +83,7 @@ impl UdpConnection {
         };
 
         let con = running.con.lock().unwrap();
-        return !con.is_connected();
+        !con.is_connected()
     }
 
     /// Bind the connection to the UDP socket
@@ -202,7 +196,7 @@ impl UdpConnection {
             return Err(Error::msg("Connection must be in CONNECTED state"));
         }
 
-        self.shutdown().await.map_err(|err| Error::from(err))
+        self.shutdown().await.map_err(Error::from)
     }
 }
 
@@ -226,11 +220,11 @@ impl AsyncRead for UdpConnection {
 
         if con.recv_available_bytes() == 0 {
             con.recv_wakers.push(cx.waker().clone());
-            return Poll::Pending;
+            Poll::Pending
         } else {
             let data = con.recv_drain_bytes(buff.len());
-            &buff[..data.len()].copy_from_slice(&data[..]);
-            return Poll::Ready(Ok(data.len()));
+            buff[..data.len()].copy_from_slice(&data[..]);
+            Poll::Ready(Ok(data.len()))
         }
     }
 }
@@ -241,7 +235,7 @@ impl AsyncWrite for UdpConnection {
         cx: &mut Context<'_>,
         buff: &[u8],
     ) -> Poll<io::Result<usize>> {
-        assert!(buff.len() > 0);
+        assert!(!buff.is_empty());
 
         if !self.is_connected() {
             warn!("attempted to poll connection which is not connected");
@@ -320,7 +314,7 @@ impl AsyncWrite for UdpConnection {
         }
 
         self.state = State::Disconnecting(running);
-        return Poll::Pending;
+        Poll::Pending
     }
 }
 
@@ -383,10 +377,10 @@ mod tests {
     fn test_new_connection() {
         let connection = UdpConnection::new(UdpConnectionConfig::default());
 
-        assert_eq!(connection.is_new(), true);
-        assert_eq!(connection.is_bound(), false);
-        assert_eq!(connection.is_connected(), false);
-        assert_eq!(connection.is_disconnected(), false);
+        assert!(connection.is_new());
+        assert!(!connection.is_bound());
+        assert!(!connection.is_connected());
+        assert!(!connection.is_disconnected());
     }
 
     #[test]
@@ -398,10 +392,10 @@ mod tests {
             let result = connection.bind().await;
 
             result.unwrap();
-            assert_eq!(connection.is_new(), false);
-            assert_eq!(connection.is_bound(), true);
-            assert_eq!(connection.is_connected(), false);
-            assert_eq!(connection.is_disconnected(), false);
+            assert!(!connection.is_new());
+            assert!(connection.is_bound());
+            assert!(!connection.is_connected());
+            assert!(!connection.is_disconnected());
         });
     }
 
@@ -420,11 +414,11 @@ mod tests {
                 })
                 .await;
 
-            assert_eq!(result.is_err(), true);
-            assert_eq!(connection.is_new(), true);
-            assert_eq!(connection.is_bound(), false);
-            assert_eq!(connection.is_connected(), false);
-            assert_eq!(connection.is_disconnected(), false);
+            assert!(result.is_err());
+            assert!(connection.is_new());
+            assert!(!connection.is_bound());
+            assert!(!connection.is_connected());
+            assert!(!connection.is_disconnected());
         });
     }
 
@@ -440,13 +434,13 @@ mod tests {
         Runtime::new().unwrap().block_on(async {
             let (mut con1, mut con2) = init_connection_pair().await;
 
-            con1.write(&[1u8, 2, 3, 4, 5]).await.unwrap();
+            con1.write_all(&[1u8, 2, 3, 4, 5]).await.unwrap();
 
             let mut buff = [0u8; 1024];
             let read = con2.read(&mut buff).await.unwrap();
             assert_eq!(&buff[..read], [1u8, 2, 3, 4, 5]);
 
-            con2.write(&[6u8, 7, 8, 9, 10]).await.unwrap();
+            con2.write_all(&[6u8, 7, 8, 9, 10]).await.unwrap();
 
             let mut buff = [0u8; 1024];
             let read = con1.read(&mut buff).await.unwrap();
@@ -464,28 +458,28 @@ mod tests {
         Runtime::new().unwrap().block_on(async {
             let (mut con1, mut con2) = init_connection_pair().await;
 
-            con1.write(&[1u8, 2, 3, 4, 5]).await.unwrap();
+            con1.write_all(&[1u8, 2, 3, 4, 5]).await.unwrap();
 
             con2.close().await.unwrap();
 
-            assert_eq!(con2.is_new(), false);
-            assert_eq!(con2.is_connected(), false);
-            assert_eq!(con2.is_disconnected(), true);
+            assert!(!con2.is_new());
+            assert!(!con2.is_connected());
+            assert!(con2.is_disconnected());
 
             // Wait for close packet to be sent and process
             delay_for(Duration::from_millis(500)).await;
 
-            assert_eq!(con1.is_new(), false);
-            assert_eq!(con1.is_connected(), false);
-            assert_eq!(con1.is_disconnected(), true);
+            assert!(!con1.is_new());
+            assert!(!con1.is_connected());
+            assert!(con1.is_disconnected());
 
             // Write / recv should fail
             let result = con1.write(&[1u8, 2, 3, 4, 5]).await;
-            assert_eq!(result.is_err(), true);
+            assert!(result.is_err());
 
-            let mut buff = [0u8; 1024];
-            let result = con1.write(&mut buff).await;
-            assert_eq!(result.is_err(), true);
+            let buff = [0u8; 1024];
+            let result = con1.write(&buff).await;
+            assert!(result.is_err());
         });
     }
 }
@@ -34,10 +34,7 @@ pub(super) async fn negotiate_connection(
     // If one of the peers is not behind a NAT, this received the hello packet.
     // We then connect on the outbound port received from the hello packet.
     let peer_addr = wait_for_magic_hello(socket, peer_ip, timeout).await?;
-    socket
-        .connect(peer_addr)
-        .await
-        .map_err(|err| Error::from(err))?;
+    socket.connect(peer_addr).await.map_err(Error::from)?;
 
     // We send a second hello packet to the same port as received from the hello packet.
     // In the case where neither peers are behind NAT's the packet can be ignored.
@@ -212,7 +209,7 @@ mod tests {
             .await
             .unwrap();
 
-        return (socket1, socket2);
+        (socket1, socket2)
     }
 
     #[test]
@@ -285,8 +282,8 @@ mod tests {
                 negotiate_connection(&mut con2, &mut socket2, addr1.ip(), 2, false)
             );
 
-            assert_eq!(result1.is_err(), true);
-            assert_eq!(result2.is_err(), true);
+            assert!(result1.is_err());
+            assert!(result2.is_err());
         });
     }
 
@@ -74,12 +74,12 @@ impl RecvLoop {
             }
 
             // Prevent the loop from blocking the executor
-            tokio::task::yield_now().await;
+            let _ = tokio::task::yield_now().await;
         }
 
         try_disconnect(&self.con);
         debug!("recv loop ended");
-        send_terminator.send(()).unwrap_or_else(|_| ());
+        send_terminator.send(()).unwrap_or(());
         self
     }
 }
@@ -129,12 +129,12 @@ impl SendLoop {
             }
 
             // Prevent the loop from blocking the executor
-            tokio::task::yield_now().await;
+            let _ = tokio::task::yield_now().await;
         }
 
         try_disconnect(&self.con);
         debug!("send loop ended");
-        recv_terminator.send(()).unwrap_or_else(|_| ());
+        recv_terminator.send(()).unwrap_or(());
         self
     }
 }
@@ -318,16 +318,16 @@ async fn handle_send_packet(
                 con.increase_transit_window_after_send();
             }
 
-            if packet.payload.len() > 0 {
+            if !packet.payload.is_empty() {
                 schedule_resend_if_dropped(con, packet);
             }
 
-            return Ok(());
+            Ok(())
         }
         UdpPacketType::Close => {
             info!("close packet sent");
             try_disconnect(&con);
-            return Ok(());
+            Ok(())
         }
         _ => panic!("unexpected send packet type"),
     }
@@ -386,7 +386,7 @@ mod tests {
             .await
             .unwrap();
 
-        return (socket1, socket2);
+        (socket1, socket2)
     }
 
     async fn init_udp_orchestrator_and_raw_socket(
@@ -667,7 +667,7 @@ mod tests {
                 if std::env::var("CI").is_err() {
                     // RTT estimate should be roughly the initial delay time (50ms)
                     // as the ack will be sent almost instantly
-                    assert_eq!((con.rtt_estimate.as_millis() as i32 - 50) < 10, true);
+                    assert!((con.rtt_estimate.as_millis() as i32 - 50) < 10);
                 }
             }
         });
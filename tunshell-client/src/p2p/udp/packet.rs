@@ -1,7 +1,6 @@
 use super::SequenceNumber;
 use anyhow::Result;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use log::*;
 use std::hash::Hasher;
 use std::io::Cursor;
 use thiserror::Error;
@@ -65,7 +64,7 @@ impl UdpPacketType {
         }
     }
 
-    pub(super) fn to_byte(&self) -> u8 {
+    pub(super) fn to_byte(self) -> u8 {
         match self {
             Self::Open => 0,
             Self::Data => 1,
@@ -138,7 +137,7 @@ impl UdpPacket {
 
         packet.checksum = packet.calculate_checksum();
 
-        return packet;
+        packet
     }
 
     pub(super) fn open(
@@ -233,7 +232,7 @@ mod tests {
         assert_eq!(UdpPacketType::parse(0).unwrap(), UdpPacketType::Open);
         assert_eq!(UdpPacketType::parse(1).unwrap(), UdpPacketType::Data);
         assert_eq!(UdpPacketType::parse(2).unwrap(), UdpPacketType::Close);
-        assert_eq!(UdpPacketType::parse(3).is_err(), true);
+        assert!(UdpPacketType::parse(3).is_err());
     }
 
     #[test]
@@ -448,12 +447,12 @@ mod tests {
             resend_count: 0,
         };
 
-        assert_eq!(packet1.overlaps(&packet1), true);
-        assert_eq!(packet1.overlaps(&packet2), false);
-        assert_eq!(packet2.overlaps(&packet1), false);
-        assert_eq!(packet1.overlaps(&packet3), true);
-        assert_eq!(packet3.overlaps(&packet1), true);
-        assert_eq!(packet2.overlaps(&packet3), true);
-        assert_eq!(packet3.overlaps(&packet2), true);
+        assert!(packet1.overlaps(&packet1));
+        assert!(!packet1.overlaps(&packet2));
+        assert!(!packet2.overlaps(&packet1));
+        assert!(packet1.overlaps(&packet3));
+        assert!(packet3.overlaps(&packet1));
+        assert!(packet2.overlaps(&packet3));
+        assert!(packet3.overlaps(&packet2));
     }
 }
@@ -26,7 +26,7 @@ pub(super) enum UdpRecvError {
 impl UdpConnectionVars {
     pub(super) fn recv_process_packet(&mut self, packet: UdpPacket) -> Result<u32, UdpRecvError> {
         // If the packet is a pure ACK/window update we can ignore it here
-        if packet.payload.len() == 0 {
+        if packet.payload.is_empty() {
             return Ok(0);
         }
 
@@ -164,11 +164,7 @@ impl UdpConnectionVars {
             + self.reassembled_buffer.len() as u32;
         let recv_window = self.config().recv_window();
 
-        if buffered_packet_bytes > recv_window {
-            0
-        } else {
-            recv_window - buffered_packet_bytes
-        }
+        recv_window.saturating_sub(buffered_packet_bytes)
     }
 }
 
@@ -297,7 +293,7 @@ mod tests {
                 &[1],
             ));
 
-            assert_eq!(result.is_err(), true);
+            assert!(result.is_err());
         });
     }
 
@@ -319,7 +315,7 @@ mod tests {
                 &[1],
             ));
 
-            assert_eq!(result.is_err(), true);
+            assert!(result.is_err());
         });
     }
 
@@ -347,7 +343,7 @@ mod tests {
                 &[1],
             ));
 
-            assert_eq!(result.is_err(), true);
+            assert!(result.is_err());
         });
     }
 
@@ -413,12 +409,12 @@ mod tests {
                 tokio::spawn(async move {
                     futures::future::poll_fn(move |cx| {
                         let mut con = con.lock().unwrap();
-                        if con.reassembled_buffer.len() == 0 {
+                        if con.reassembled_buffer.is_empty() {
                             con.recv_wakers.push(cx.waker().clone());
                             return Poll::Pending;
                         }
 
-                        return Poll::Ready(con.reassembled_buffer.clone());
+                        Poll::Ready(con.reassembled_buffer.clone())
                     })
                     .await
                 })
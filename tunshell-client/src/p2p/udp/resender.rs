@@ -63,9 +63,8 @@ pub(super) fn schedule_resend_if_dropped(
             }
 
             let mut con = loop {
-                match con.try_lock() {
-                    Ok(con) => break con,
-                    Err(_) => {}
+                if let Ok(con) = con.try_lock() {
+                    break con;
                 };
 
                 tokio::time::delay_for(position_delay).await;
@@ -187,8 +186,8 @@ mod tests {
         con.update_peer_ack_number(SequenceNumber(20));
 
         assert_eq!(con.peer_ack_number, SequenceNumber(20));
-        assert_eq!(con.sent_packets.contains_key(&SequenceNumber(10)), false);
-        assert_eq!(con.sent_packets.contains_key(&SequenceNumber(30)), true);
+        assert!(!con.sent_packets.contains_key(&SequenceNumber(10)));
+        assert!(con.sent_packets.contains_key(&SequenceNumber(30)));
     }
 
     #[test]
@@ -357,7 +356,7 @@ mod tests {
 
             // Should not send resend event
             let result = rx.try_recv();
-            assert_eq!(result.is_err(), true);
+            assert!(result.is_err());
         });
     }
 
@@ -386,7 +385,7 @@ mod tests {
             let result = rx.try_recv();
             match result {
                 Ok(SendEvent::Close) => {}
-                result @ _ => panic!("resend should have triggered close event, got {:?}", result),
+                result => panic!("resend should have triggered close event, got {:?}", result),
             }
         });
     }
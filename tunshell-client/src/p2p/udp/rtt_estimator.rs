@@ -48,7 +48,7 @@ mod tests {
             &[1],
         ));
 
-        assert_eq!(con.send_times.get(&SequenceNumber(51)).is_some(), true);
+        assert!(con.send_times.contains_key(&SequenceNumber(51)));
     }
 
     #[test]
@@ -85,7 +85,7 @@ mod tests {
             &[],
         ));
 
-        assert_eq!((con.rtt_estimate.as_millis() as i32 - 100).abs() < 5, true);
+        assert!((con.rtt_estimate.as_millis() as i32 - 100).abs() < 5);
     }
 
     #[test]
@@ -106,7 +106,7 @@ mod tests {
             &[],
         ));
 
-        assert_eq!((con.rtt_estimate.as_millis() as i32 - 125).abs() < 5, true);
+        assert!((con.rtt_estimate.as_millis() as i32 - 125).abs() < 5);
     }
 
     #[test]
@@ -91,6 +91,7 @@ mod tests {
                 peer_ip_address: "127.0.0.1".to_owned(),
                 peer_key: "test".to_owned(),
                 session_nonce: "nonce".to_owned(),
+                reconnect_token: String::new(),
             };
 
             let mut connection1 = UdpConnectionAdaptor::new(peer_info.clone());
@@ -106,7 +107,10 @@ mod tests {
             )
             .expect("failed to connect");
 
-            connection1.write("hello from 1".as_bytes()).await.unwrap();
+            connection1
+                .write_all("hello from 1".as_bytes())
+                .await
+                .unwrap();
             connection1.flush().await.unwrap();
 
             delay_for(Duration::from_millis(50)).await;
@@ -119,7 +123,10 @@ mod tests {
                 "hello from 1"
             );
 
-            connection2.write("hello from 2".as_bytes()).await.unwrap();
+            connection2
+                .write_all("hello from 2".as_bytes())
+                .await
+                .unwrap();
             connection2.flush().await.unwrap();
 
             delay_for(Duration::from_millis(50)).await;
@@ -1,6 +1,8 @@
 cfg_if::cfg_if! {
     if #[cfg(not(target_arch = "wasm32"))] {
+        mod http_proxy;
         mod tls_server_stream;
+        pub use http_proxy::*;
         pub use tls_server_stream::*;
     } else {
         mod websocket_stream;
@@ -1,3 +1,4 @@
+use super::HttpProxy;
 use crate::Config;
 use anyhow::Result;
 use std::{
@@ -53,13 +54,31 @@ impl ServerStream {
 
         let relay_dns_name = DNSNameRef::try_from_ascii_str(config.relay_host())?;
 
-        let relay_addr = (config.relay_host(), config.relay_port())
-            .to_socket_addrs()?
-            .next()
-            .unwrap();
+        let network_stream = match config.http_proxy() {
+            Some(proxy) => {
+                let proxy = HttpProxy::parse(proxy)?;
+                let proxy_addr = (proxy.host.as_str(), proxy.port)
+                    .to_socket_addrs()?
+                    .next()
+                    .unwrap();
 
-        let network_stream = TcpStream::connect(relay_addr).await?;
-        network_stream.set_keepalive(Some(Duration::from_secs(30)))?;
+                let network_stream = TcpStream::connect(proxy_addr).await?;
+                network_stream.set_keepalive(Some(Duration::from_secs(30)))?;
+                proxy
+                    .connect(network_stream, config.relay_host(), config.relay_port())
+                    .await?
+            }
+            None => {
+                let relay_addr = (config.relay_host(), config.relay_port())
+                    .to_socket_addrs()?
+                    .next()
+                    .unwrap();
+
+                let network_stream = TcpStream::connect(relay_addr).await?;
+                network_stream.set_keepalive(Some(Duration::from_secs(30)))?;
+                network_stream
+            }
+        };
         let transport_stream = connector.connect(relay_dns_name, network_stream).await?;
 
         Ok(Self {
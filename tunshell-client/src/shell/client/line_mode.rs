@@ -0,0 +1,60 @@
+/// Buffers the input read from the local terminal in line mode so that each
+/// complete line, including its newline, is sent to the server as a single message
+pub(super) struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    pub(super) fn new() -> Self {
+        Self { pending: vec![] }
+    }
+
+    /// Adds the input to the buffer, returning the lines which it completed
+    pub(super) fn push(&mut self, input: &[u8]) -> Vec<Vec<u8>> {
+        self.pending.extend_from_slice(input);
+
+        let mut lines = vec![];
+
+        while let Some(pos) = self.pending.iter().position(|i| *i == b'\n') {
+            lines.push(self.pending.drain(..=pos).collect());
+        }
+
+        lines
+    }
+
+    /// Returns the incomplete line, if any, once the input has ended
+    pub(super) fn take_remaining(&mut self) -> Option<Vec<u8>> {
+        if self.pending.is_empty() {
+            None
+        } else {
+            Some(std::mem::take(&mut self.pending))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_full_line_returned_once() {
+        let mut buffer = LineBuffer::new();
+
+        assert_eq!(buffer.push(b"echo "), Vec::<Vec<u8>>::new());
+        assert_eq!(buffer.push(b"hel"), Vec::<Vec<u8>>::new());
+        assert_eq!(buffer.push(b"lo\n"), vec![b"echo hello\n".to_vec()]);
+        assert_eq!(buffer.take_remaining(), None);
+    }
+
+    #[test]
+    fn test_multiple_lines_in_one_read() {
+        let mut buffer = LineBuffer::new();
+
+        assert_eq!(
+            buffer.push(b"one\ntwo\nthr"),
+            vec![b"one\n".to_vec(), b"two\n".to_vec()]
+        );
+        assert_eq!(buffer.take_remaining(), Some(b"thr".to_vec()));
+        assert_eq!(buffer.take_remaining(), None);
+    }
+}
@@ -1,12 +1,18 @@
 use super::{
-    ShellClientMessage, ShellClientStream, ShellServerMessage, StartShellPayload, WindowSize,
+    negotiate_protocol_version, ErrorSeverity, HelloPayload, ReattachPayload, ShellClientMessage,
+    ShellClientStream, ShellServerMessage, StartShellPayload, WindowSize, PROTOCOL_VERSION,
 };
 use crate::{util::delay::delay_for, ShellKey, TunnelStream};
 use anyhow::{Context, Error, Result};
+use async_trait::async_trait;
 use futures::stream::StreamExt;
 use log::*;
-use std::time::Duration;
+use std::{cmp, env, time::Duration};
 use tokio_util::compat::*;
+use tunshell_shared::{Capabilities, FrameVersion, MessageFormat};
+
+mod line_mode;
+use line_mode::LineBuffer;
 
 cfg_if::cfg_if! {
     if #[cfg(target_arch = "wasm32")] {
@@ -16,6 +22,7 @@ cfg_if::cfg_if! {
         mod test;
         pub use test::*;
     } else {
+        mod resize;
         mod shell;
         pub use shell::*;
     }
@@ -23,13 +30,165 @@ cfg_if::cfg_if! {
 
 pub struct ShellClient {
     pub(crate) host_shell: HostShell,
+    /// The format requested for messages after the handshake
+    message_format: MessageFormat,
+    /// Whether messages after the handshake should be numbered
+    sequence_numbers: bool,
+    /// Whether input is echoed and edited locally and sent a line at a time
+    line_mode: bool,
+    /// The session's reconnect token, the shell is reattached using the token
+    /// when the connection to the shell server is lost
+    reconnect_token: Option<String>,
+    /// Whether a shell has been started, later connections reattach to it
+    shell_started: bool,
+    /// Whether the current connection has started streaming the shell's io
+    streaming: bool,
+}
+
+/// Opens a new connection to the shell server after the previous connection was lost
+#[async_trait(?Send)]
+pub(crate) trait Redial {
+    async fn redial(&mut self) -> Result<Box<dyn TunnelStream>>;
+}
+
+/// How the client reconnects to the shell server after losing the connection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ReconnectPolicy {
+    /// The number of consecutive attempts made before giving up, 0 disables reconnecting
+    pub(crate) max_attempts: u32,
+    /// The delay before the first attempt, doubled after each failed attempt
+    pub(crate) backoff: Duration,
+}
+
+/// The longest delay between reconnection attempts
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+impl ReconnectPolicy {
+    /// The delay before the attempt, attempts are numbered from 1
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+
+        self.backoff
+            .checked_mul(factor)
+            .map_or(MAX_RECONNECT_BACKOFF, |i| {
+                cmp::min(i, MAX_RECONNECT_BACKOFF)
+            })
+    }
+}
+
+/// Returned when the connection to the shell server is lost while the shell is running
+#[derive(thiserror::Error, Debug)]
+#[error("lost connection to shell server")]
+pub(crate) struct ShellDisconnected;
+
+/// Whether the error was caused by losing the connection to the shell server
+fn is_disconnect(err: &Error) -> bool {
+    err.downcast_ref::<ShellDisconnected>().is_some()
 }
 
 type ShellStream = ShellClientStream<Compat<Box<dyn TunnelStream>>>;
 
+/// Stdin is read and sent in chunks of up to this size. Each chunk is written to the server
+/// before the next is read, so large inputs such as redirected files are streamed
+/// at the pace the server accepts them rather than read into memory.
+const STDIN_CHUNK_SIZE: usize = 1024;
+
+/// Whether the client can prove its key rather than sending it, the HMAC
+/// implementation is not available in wasm builds which send the key instead
+const SUPPORTS_KEY_PROOF: bool = cfg!(not(target_arch = "wasm32"));
+
 impl ShellClient {
     pub(crate) fn new(host_shell: HostShell) -> Result<ShellClient> {
-        Ok(ShellClient { host_shell })
+        Ok(ShellClient {
+            host_shell,
+            message_format: MessageFormat::Binary,
+            sequence_numbers: false,
+            line_mode: false,
+            reconnect_token: None,
+            shell_started: false,
+            streaming: false,
+        })
+    }
+
+    /// Requests the messages after the handshake are sent as JSON, which can be
+    /// read in a packet capture. The binary format is used if the server declines.
+    pub(crate) fn with_message_format(mut self, format: MessageFormat) -> Self {
+        self.message_format = format;
+        self
+    }
+
+    /// Requests the messages after the handshake are numbered, gaps or reordering
+    /// are logged as warnings. Ignored if the server does not support it.
+    pub(crate) fn with_sequence_numbers(mut self, enabled: bool) -> Self {
+        self.sequence_numbers = enabled;
+        self
+    }
+
+    /// Leaves the local terminal in cooked mode, which echoes and edits the input,
+    /// and sends each complete line to the server rather than each key press.
+    /// Suited to links where the round trip of remote echo is too slow to type over.
+    pub(crate) fn with_line_mode(mut self, enabled: bool) -> Self {
+        self.line_mode = enabled;
+        self
+    }
+
+    /// Sets the session's reconnect token, which allows the shell to be reattached over
+    /// a new connection by `connect_with_reconnect`. An empty token is ignored.
+    pub(crate) fn with_reconnect_token(mut self, token: Option<&str>) -> Self {
+        self.reconnect_token = token.filter(|i| !i.is_empty()).map(|i| i.to_owned());
+        self
+    }
+
+    /// Runs the session, reattaching to the shell over a connection opened by `redial`
+    /// when the connection is lost. Reconnecting is given up after the policy's
+    /// maximum attempts fail in a row, or if the server refuses to reattach the shell.
+    pub(crate) async fn connect_with_reconnect(
+        &mut self,
+        stream: Box<dyn TunnelStream>,
+        key: ShellKey,
+        policy: ReconnectPolicy,
+        redial: &mut dyn Redial,
+    ) -> Result<u8> {
+        let mut result = self.connect(stream, key.clone()).await;
+        let mut attempt = 0;
+
+        loop {
+            let err = match result {
+                Err(err) if self.reconnect_token.is_some() && is_disconnect(&err) => err,
+                result => return result,
+            };
+
+            // Attempts are counted from the last connection which resumed the shell
+            if self.streaming {
+                attempt = 0;
+                self.streaming = false;
+            }
+
+            attempt += 1;
+            if attempt > policy.max_attempts {
+                return Err(err.context(format!(
+                    "gave up reconnecting after {} attempts",
+                    policy.max_attempts
+                )));
+            }
+
+            let delay = policy.delay(attempt);
+            warn!("{:#}, reconnecting in {:?}", err, delay);
+            self.host_shell
+                .println(&format!(
+                    "Connection lost, reconnecting (attempt {} of {})...",
+                    attempt, policy.max_attempts
+                ))
+                .await;
+            delay_for(delay).await;
+
+            result = match redial.redial().await {
+                Ok(stream) => self.connect(stream, key.clone()).await,
+                Err(err) => Err(err.context(ShellDisconnected)),
+            };
+        }
     }
 
     pub(crate) async fn connect(
@@ -37,82 +196,259 @@ impl ShellClient {
         stream: Box<dyn TunnelStream>,
         key: ShellKey,
     ) -> Result<u8> {
+        self.streaming = false;
         info!("connecting to shell server");
         let mut stream = ShellStream::new(stream.compat());
 
+        info!("shell client negotiating protocol version");
+        let (protocol_version, capabilities) = self
+            .negotiate_protocol(&mut stream)
+            .await
+            .with_context(|| "Error while negotiating protocol with shell server")?;
+
+        info!("negotiated protocol version {}", protocol_version);
+
         info!("shell client attempting to authenticate");
-        self.authenticate(&mut stream, key)
+        self.authenticate(&mut stream, key, capabilities)
             .await
             .with_context(|| "Error while authenticating with shell server")?;
 
         info!("shell client authenticated");
 
-        debug!("requesting shell from server");
-        stream
-            .write(&ShellClientMessage::StartShell(StartShellPayload {
-                term: self.host_shell.term().unwrap_or("".to_owned()),
-                size: WindowSize::from(self.host_shell.size().await?),
-            }))
-            .await?;
+        match self.reconnect_token.as_ref() {
+            Some(token) if self.shell_started => {
+                debug!("requesting server reattaches the shell");
+                stream
+                    .write(&ShellClientMessage::Reattach(ReattachPayload {
+                        token: token.to_owned(),
+                        size: Some(WindowSize::from(self.host_shell.size().await?)),
+                    }))
+                    .await?;
+            }
+            _ => {
+                debug!("requesting shell from server");
+                stream
+                    .write(&ShellClientMessage::StartShell(StartShellPayload {
+                        term: self.host_shell.term().unwrap_or("".to_owned()),
+                        size: Some(WindowSize::from(self.host_shell.size().await?)),
+                        pty: true,
+                        login: true,
+                        interactive: true,
+                        raw: false,
+                        shell_path: None,
+                        colors: None,
+                        truecolor: host_supports_truecolor(),
+                        line_mode: self.line_mode,
+                        locale: host_locale(),
+                        timezone: env::var("TZ").ok(),
+                        titles: false,
+                    }))
+                    .await?;
+                self.shell_started = true;
+            }
+        }
 
         info!("shell requested");
         info!("starting shell stream");
 
-        self.host_shell.enable_raw_mode()?;
+        if !self.line_mode {
+            self.host_shell.enable_raw_mode()?;
+        }
 
+        self.streaming = true;
         let exit_code = self.stream_shell_io(&mut stream).await;
 
-        self.host_shell.disable_raw_mode()?;
+        if !self.line_mode {
+            self.host_shell.disable_raw_mode()?;
+        }
 
         info!("session finished");
 
-        Ok(exit_code?)
+        exit_code
     }
 
-    async fn authenticate(&self, stream: &mut ShellStream, key: ShellKey) -> Result<()> {
-        stream.write(&ShellClientMessage::Key(key.key)).await?;
-        debug!("sent shell key to peer");
+    /// Returns the negotiated protocol version and the capabilities of the server
+    async fn negotiate_protocol(&self, stream: &mut ShellStream) -> Result<(u16, Capabilities)> {
+        stream
+            .write(&ShellClientMessage::Hello(HelloPayload {
+                protocol_version: PROTOCOL_VERSION,
+                format: self.message_format,
+                key_proof: SUPPORTS_KEY_PROOF,
+                sequence_numbers: self.sequence_numbers,
+                frame_version: FrameVersion::V2,
+            }))
+            .await?;
+        debug!("sent hello to peer");
 
         let response = tokio::select! {
             message = stream.next() => match message {
                 Some(Ok(message)) => message,
-                Some(Err(err)) => return Err(Error::from(err).context("shell server returned invalid response")),
+                Some(Err(err)) => return Err(err.context("shell server returned invalid response")),
+                None => return Err(Error::msg("did not receive hello response"))
+            },
+            _ = delay_for(Duration::from_millis(3000)) =>  return Err(Error::msg("timed out while waiting for hello response"))
+        };
+
+        let ack = match response {
+            ShellServerMessage::HelloAck(ack) => ack,
+            ShellServerMessage::Error { message, .. } => {
+                return Err(Error::msg(format!(
+                    "shell server returned error: {}",
+                    message
+                )))
+            }
+            message => {
+                return Err(Error::msg(format!(
+                    "unexpected message returned from server: {:?}",
+                    message
+                )))
+            }
+        };
+
+        debug!("shell server capabilities: {:?}", ack.capabilities.names());
+
+        if !ack.accepted {
+            return Err(Error::msg(format!(
+                "protocol version {} was rejected by the server (server version {})",
+                PROTOCOL_VERSION, ack.protocol_version
+            )));
+        }
+
+        let version = match negotiate_protocol_version(ack.protocol_version) {
+            Some(version) if version == ack.protocol_version => version,
+            _ => {
+                return Err(Error::msg(format!(
+                    "server protocol version {} is not supported by this client",
+                    ack.protocol_version
+                )))
+            }
+        };
+
+        debug!("using {:?} message format", ack.format);
+        stream.set_format(ack.format);
+
+        if self.sequence_numbers && ack.capabilities.contains(Capabilities::SEQUENCE_NUMBERS) {
+            debug!("using message sequence numbers");
+            stream.set_sequence_numbers(true);
+        }
+
+        // Servers which do not advertise version 2 frames continue with the legacy framing
+        if ack.capabilities.contains(Capabilities::FRAME_V2) {
+            debug!("using version 2 frames");
+            stream.set_frame_version(FrameVersion::V2);
+        }
+
+        Ok((version, ack.capabilities))
+    }
+
+    async fn authenticate(
+        &self,
+        stream: &mut ShellStream,
+        key: ShellKey,
+        capabilities: Capabilities,
+    ) -> Result<()> {
+        // Servers which support key proofs send a challenge in response to our hello
+        if SUPPORTS_KEY_PROOF && capabilities.contains(Capabilities::KEY_PROOF) {
+            self.prove_key(stream, &key).await?;
+            debug!("sent shell key proof to peer");
+        } else {
+            stream.write(&ShellClientMessage::Key(key.key)).await?;
+            debug!("sent shell key to peer");
+        }
+
+        let response = tokio::select! {
+            message = stream.next() => match message {
+                Some(Ok(message)) => message,
+                Some(Err(err)) => return Err(err.context("shell server returned invalid response")),
                 None => return Err(Error::msg("did not receive authentication response"))
             },
             _ = delay_for(Duration::from_millis(3000)) =>  return Err(Error::msg("timed out while waiting for authentication"))
         };
 
         match response {
-            ShellServerMessage::KeyAccepted => return Ok(()),
-            ShellServerMessage::KeyRejected => {
-                return Err(Error::msg("shell key rejected by server"))
-            }
-            message @ _ => {
+            ShellServerMessage::KeyAccepted => Ok(()),
+            ShellServerMessage::KeyRejected => Err(Error::msg("shell key rejected by server")),
+            message => Err(Error::msg(format!(
+                "unexpected message returned from server: {:?}",
+                message
+            ))),
+        }
+    }
+
+    /// Waits for the server's challenge and responds with the proof of the key
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn prove_key(&self, stream: &mut ShellStream, key: &ShellKey) -> Result<()> {
+        let response = tokio::select! {
+            message = stream.next() => match message {
+                Some(Ok(message)) => message,
+                Some(Err(err)) => return Err(err.context("shell server returned invalid response")),
+                None => return Err(Error::msg("did not receive key challenge"))
+            },
+            _ = delay_for(Duration::from_millis(3000)) =>  return Err(Error::msg("timed out while waiting for key challenge"))
+        };
+
+        let nonce = match response {
+            ShellServerMessage::Challenge(nonce) => nonce,
+            message => {
                 return Err(Error::msg(format!(
                     "unexpected message returned from server: {:?}",
                     message
                 )))
             }
-        }
+        };
+
+        stream
+            .write(&ShellClientMessage::KeyProof(key.proof(&nonce)))
+            .await
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    async fn prove_key(&self, _stream: &mut ShellStream, _key: &ShellKey) -> Result<()> {
+        unreachable!("key proofs are not supported in wasm")
     }
 
     async fn stream_shell_io(&mut self, stream: &mut ShellStream) -> Result<u8> {
-        let mut buff = [0u8; 1024];
+        let mut buff = [0u8; STDIN_CHUNK_SIZE];
         let mut stdin = self.host_shell.stdin()?;
         let mut stdout = self.host_shell.stdout()?;
         let mut resize_watcher = self.host_shell.resize_watcher()?;
+        let mut stdin_open = true;
+        let mut line_buffer = if self.line_mode {
+            Some(LineBuffer::new())
+        } else {
+            None
+        };
 
         loop {
             info!("waiting for shell message");
             tokio::select! {
-                result = stdin.read(&mut buff) => match result {
+                result = stdin.read(&mut buff), if stdin_open => match result {
                     Ok(read) => {
                         info!("read {} bytes from stdin", read);
                         if read == 0 {
-                            return Err(Error::msg("stdin closed"));
+                            if let Some(line) = line_buffer.as_mut().and_then(|i| i.take_remaining()) {
+                                send(stream, &ShellClientMessage::Stdin(line)).await?;
+                            }
+
+                            // Continue to receive output until the remote shell exits
+                            info!("stdin closed, closing remote stdin");
+                            send(stream, &ShellClientMessage::StdinClose).await?;
+                            stdin_open = false;
+                            continue;
+                        }
+
+                        match line_buffer.as_mut() {
+                            Some(line_buffer) => {
+                                for line in line_buffer.push(&buff[..read]) {
+                                    info!("sending {} byte line to remote shell", line.len());
+                                    send(stream, &ShellClientMessage::Stdin(line)).await?;
+                                }
+                            }
+                            None => {
+                                send(stream, &ShellClientMessage::Stdin(buff[..read].to_vec())).await?;
+                                info!("sent {} bytes to remote shell", read);
+                            }
                         }
-                        stream.write(&ShellClientMessage::Stdin(buff[..read].to_vec())).await?;
-                        info!("sent {} bytes to remote shell", read);
                     },
                     Err(err) => {
                         error!("error while reading from stdin: {}", err);
@@ -122,25 +458,54 @@ impl ShellClient {
                 message = stream.next() => match message {
                     Some(Ok(ShellServerMessage::Stdout(payload))) => {
                         info!("received {} bytes from remote shell", payload.len());
-                        stdout.write(payload.as_slice()).await?;
+                        stdout.write(&payload).await?;
+                    }
+                    Some(Ok(ShellServerMessage::Exited(status))) => {
+                        match status.signal {
+                            Some(signal) => info!("remote shell was terminated by signal {}", signal),
+                            None => info!("remote shell exited with code {:?}", status.code),
+                        }
+                        return Ok(status.process_exit_code());
+                    }
+                    Some(Ok(ShellServerMessage::Ping)) => {
+                        debug!("received keepalive from shell server");
+                    }
+                    Some(Ok(ShellServerMessage::SizeApplied(size))) => {
+                        info!("remote shell size applied: {:?}", size);
+                    }
+                    Some(Ok(ShellServerMessage::Title(title))) => {
+                        debug!("remote shell set title: {}", title);
                     }
-                    Some(Ok(ShellServerMessage::Exited(code))) => {
-                        info!("remote shell exited with code {}", code);
-                        return Ok(code);
+                    Some(Ok(ShellServerMessage::Detached(token))) => {
+                        info!("remote shell detached with token {}", token);
+                        return Ok(0);
+                    }
+                    Some(Ok(ShellServerMessage::Error { severity: ErrorSeverity::Warning, message })) => {
+                        warn!("shell server returned warning: {}", message);
+                    }
+                    Some(Ok(ShellServerMessage::Error { severity: ErrorSeverity::Fatal, message })) => {
+                        return Err(Error::msg(format!("shell server returned error: {}", message)));
+                    }
+                    Some(Ok(ShellServerMessage::Close { reason })) => {
+                        info!("shell server closed the session: {}", reason);
+                        return Err(Error::msg(format!("session closed by shell server: {}", reason)));
                     }
                     Some(Ok(message)) => {
                         return Err(Error::msg(format!("received unexpected message from shell server {:?}", message)));
                     }
+                    Some(Err(err)) if err.downcast_ref::<std::io::Error>().is_some() => {
+                        return Err(err.context(ShellDisconnected));
+                    }
                     Some(Err(err)) => {
-                        return Err(Error::from(err).context("received invalid message from shell server"));
+                        return Err(err.context("received invalid message from shell server"));
                     }
                     None => {
                         warn!("remote shell stream ended");
-                        return Err(Error::msg("shell server stream closed unexpectedly"));
+                        return Err(Error::new(ShellDisconnected).context("shell server stream closed unexpectedly"));
                     }
                 },
                 size = resize_watcher.next() => match size {
-                    Ok(size) => stream.write(&ShellClientMessage::Resize(WindowSize::from(size))).await?,
+                    Ok(size) => send(stream, &ShellClientMessage::Resize(WindowSize::from(size))).await?,
                     Err(err) => error!("Error received from terminal resize event: {}", err)
                 }
             }
@@ -148,10 +513,39 @@ impl ShellClient {
     }
 }
 
+/// Writes the message to the shell server, marking a failure with `ShellDisconnected`
+async fn send(stream: &mut ShellStream, message: &ShellClientMessage) -> Result<()> {
+    stream
+        .write(message)
+        .await
+        .map_err(|err| err.context(ShellDisconnected))
+}
+
+/// Whether the local terminal advertises 24-bit colour support through `COLORTERM`
+fn host_supports_truecolor() -> bool {
+    match std::env::var("COLORTERM") {
+        Ok(colorterm) => colorterm == "truecolor" || colorterm == "24bit",
+        Err(_) => false,
+    }
+}
+
+/// The locale of the local terminal, from the variables which take precedence in setting it
+fn host_locale() -> Option<String> {
+    ["LC_ALL", "LANG"]
+        .iter()
+        .filter_map(|i| env::var(i).ok())
+        .find(|i| !i.is_empty())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::shell::proto::{
+        ExitStatus, HelloAckPayload, ShellServerStream, MIN_PROTOCOL_VERSION,
+    };
+    use bytes::Bytes;
     use futures::io::Cursor;
+    use tokio::io::AsyncWriteExt;
     use tokio::runtime::Runtime;
     use tokio::time::timeout;
     use tunshell_shared::Message;
@@ -161,10 +555,22 @@ mod tests {
         ShellClient::new(HostShell::new().unwrap()).unwrap();
     }
 
+    fn hello_ack(protocol_version: u16, accepted: bool) -> Vec<u8> {
+        ShellServerMessage::HelloAck(HelloAckPayload {
+            protocol_version,
+            accepted,
+            capabilities: Default::default(),
+            format: MessageFormat::Binary,
+        })
+        .serialise()
+        .unwrap()
+        .to_vec()
+    }
+
     #[test]
     fn test_rejected_key() {
         Runtime::new().unwrap().block_on(async {
-            let mut mock_data = Vec::<u8>::new();
+            let mut mock_data = hello_ack(PROTOCOL_VERSION, true);
 
             mock_data.extend_from_slice(
                 ShellServerMessage::KeyRejected
@@ -184,6 +590,43 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_json_format_negotiated() {
+        Runtime::new().unwrap().block_on(async {
+            let mut mock_data = ShellServerMessage::HelloAck(HelloAckPayload {
+                protocol_version: PROTOCOL_VERSION,
+                accepted: true,
+                capabilities: Default::default(),
+                format: MessageFormat::Json,
+            })
+            .serialise()
+            .unwrap()
+            .to_vec();
+
+            mock_data.extend_from_slice(
+                ShellServerMessage::KeyRejected
+                    .serialise_json()
+                    .unwrap()
+                    .to_vec()
+                    .as_slice(),
+            );
+
+            // The client's messages are larger in JSON so a shared cursor would overwrite the
+            // responses before they are read, the server's end is kept separate instead
+            let (client_end, mut server_end) = crate::testing::tunnel_pair();
+            server_end.write_all(&mock_data).await.unwrap();
+
+            let err = ShellClient::new(HostShell::new().unwrap())
+                .unwrap()
+                .with_message_format(MessageFormat::Json)
+                .connect(client_end, ShellKey::new("MyKey"))
+                .await
+                .expect_err("client key should be rejected");
+
+            assert_eq!(err.root_cause().to_string(), "shell key rejected by server");
+        });
+    }
+
     #[test]
     fn test_key_timeout() {
         Runtime::new().unwrap().block_on(async {
@@ -206,7 +649,7 @@ mod tests {
     #[test]
     fn test_start_shell_timeout() {
         Runtime::new().unwrap().block_on(async {
-            let mut mock_data = Vec::<u8>::new();
+            let mut mock_data = hello_ack(PROTOCOL_VERSION, true);
 
             mock_data.extend_from_slice(
                 ShellServerMessage::KeyAccepted
@@ -229,4 +672,291 @@ mod tests {
             .expect_err("should timeout");
         });
     }
+
+    #[test]
+    fn test_protocol_version_rejected() {
+        Runtime::new().unwrap().block_on(async {
+            let mock_stream = Cursor::new(hello_ack(PROTOCOL_VERSION, false)).compat();
+
+            let err = ShellClient::new(HostShell::new().unwrap())
+                .unwrap()
+                .connect(Box::new(mock_stream), ShellKey::new("CorrectKey"))
+                .await
+                .expect_err("protocol version should be rejected");
+
+            assert_eq!(
+                err.to_string(),
+                "Error while negotiating protocol with shell server"
+            );
+        });
+    }
+
+    #[test]
+    fn test_server_protocol_version_too_old() {
+        Runtime::new().unwrap().block_on(async {
+            let mock_stream = Cursor::new(hello_ack(MIN_PROTOCOL_VERSION - 1, true)).compat();
+
+            ShellClient::new(HostShell::new().unwrap())
+                .unwrap()
+                .connect(Box::new(mock_stream), ShellKey::new("CorrectKey"))
+                .await
+                .expect_err("server protocol version should not be supported");
+        });
+    }
+
+    type MockServerStream = ShellServerStream<Compat<Box<dyn TunnelStream>>>;
+
+    /// Accepts the client's hello and key, returning the message sent to start the shell
+    async fn accept_client(stream: &mut MockServerStream) -> ShellClientMessage {
+        match stream.next().await {
+            Some(Ok(ShellClientMessage::Hello(_))) => {}
+            message => panic!("expected hello, received {:?}", message),
+        }
+        stream
+            .write(&ShellServerMessage::HelloAck(HelloAckPayload {
+                protocol_version: PROTOCOL_VERSION,
+                accepted: true,
+                capabilities: Default::default(),
+                format: MessageFormat::Binary,
+            }))
+            .await
+            .unwrap();
+
+        match stream.next().await {
+            Some(Ok(ShellClientMessage::Key(key))) => assert_eq!(key, "CorrectKey"),
+            message => panic!("expected key, received {:?}", message),
+        }
+        stream
+            .write(&ShellServerMessage::KeyAccepted)
+            .await
+            .unwrap();
+
+        stream.next().await.unwrap().unwrap()
+    }
+
+    struct MockRedial {
+        streams: Vec<Box<dyn TunnelStream>>,
+        redials: usize,
+    }
+
+    #[async_trait(?Send)]
+    impl Redial for MockRedial {
+        async fn redial(&mut self) -> Result<Box<dyn TunnelStream>> {
+            self.redials += 1;
+
+            match self.streams.pop() {
+                Some(stream) => Ok(stream),
+                None => Err(Error::msg("no more connections")),
+            }
+        }
+    }
+
+    const TEST_RECONNECT_POLICY: ReconnectPolicy = ReconnectPolicy {
+        max_attempts: 2,
+        backoff: Duration::from_millis(10),
+    };
+
+    /// Runs a session to completion, stdin is read on a blocking thread which
+    /// may never return so the runtime is not waited on to shut down
+    fn run_session<F: std::future::Future>(session: F) -> F::Output {
+        let mut runtime = Runtime::new().unwrap();
+        let output = runtime.block_on(session);
+        runtime.shutdown_timeout(Duration::from_millis(100));
+        output
+    }
+
+    #[test]
+    fn test_reconnect_policy_backoff() {
+        let policy = ReconnectPolicy {
+            max_attempts: 100,
+            backoff: Duration::from_millis(500),
+        };
+
+        assert_eq!(policy.delay(1), Duration::from_millis(500));
+        assert_eq!(policy.delay(2), Duration::from_millis(1000));
+        assert_eq!(policy.delay(3), Duration::from_millis(2000));
+        assert_eq!(policy.delay(10), MAX_RECONNECT_BACKOFF);
+        assert_eq!(policy.delay(100), MAX_RECONNECT_BACKOFF);
+    }
+
+    #[test]
+    fn test_reconnect_after_connection_dropped() {
+        run_session(async {
+            let (client_end, server_end) = crate::testing::tunnel_pair();
+            let (redial_client_end, redial_server_end) = crate::testing::tunnel_pair();
+
+            let server = tokio::spawn(async move {
+                let mut stream = MockServerStream::new(server_end.compat());
+                match accept_client(&mut stream).await {
+                    ShellClientMessage::StartShell(_) => {}
+                    message => panic!("expected start shell, received {:?}", message),
+                }
+                stream
+                    .write(&ShellServerMessage::Stdout(Bytes::from_static(b"before")))
+                    .await
+                    .unwrap();
+                // The connection is lost mid-session
+                drop(stream);
+
+                let mut stream = MockServerStream::new(redial_server_end.compat());
+                match accept_client(&mut stream).await {
+                    ShellClientMessage::Reattach(payload) => assert_eq!(payload.token, "token"),
+                    message => panic!("expected reattach, received {:?}", message),
+                }
+                stream
+                    .write(&ShellServerMessage::Stdout(Bytes::from_static(b"after")))
+                    .await
+                    .unwrap();
+                stream
+                    .write(&ShellServerMessage::Exited(ExitStatus {
+                        code: Some(7),
+                        signal: None,
+                    }))
+                    .await
+                    .unwrap();
+                // Remains connected until the client has read the exit status
+                while stream.next().await.is_some() {}
+            });
+
+            let mut redial = MockRedial {
+                streams: vec![redial_client_end],
+                redials: 0,
+            };
+            let exit_code = timeout(
+                Duration::from_millis(5000),
+                ShellClient::new(HostShell::new().unwrap())
+                    .unwrap()
+                    .with_line_mode(true)
+                    .with_reconnect_token(Some("token"))
+                    .connect_with_reconnect(
+                        client_end,
+                        ShellKey::new("CorrectKey"),
+                        TEST_RECONNECT_POLICY,
+                        &mut redial,
+                    ),
+            )
+            .await
+            .expect("client should reconnect")
+            .unwrap();
+
+            server.await.unwrap();
+            assert_eq!(exit_code, 7);
+            assert_eq!(redial.redials, 1);
+        });
+    }
+
+    #[test]
+    fn test_reconnect_gives_up_after_max_attempts() {
+        run_session(async {
+            let (client_end, server_end) = crate::testing::tunnel_pair();
+
+            let server = tokio::spawn(async move {
+                let mut stream = MockServerStream::new(server_end.compat());
+                accept_client(&mut stream).await;
+            });
+
+            let mut redial = MockRedial {
+                streams: vec![],
+                redials: 0,
+            };
+            let err = timeout(
+                Duration::from_millis(5000),
+                ShellClient::new(HostShell::new().unwrap())
+                    .unwrap()
+                    .with_line_mode(true)
+                    .with_reconnect_token(Some("token"))
+                    .connect_with_reconnect(
+                        client_end,
+                        ShellKey::new("CorrectKey"),
+                        TEST_RECONNECT_POLICY,
+                        &mut redial,
+                    ),
+            )
+            .await
+            .expect("client should give up")
+            .expect_err("reconnecting should fail");
+
+            server.await.unwrap();
+            assert_eq!(err.to_string(), "gave up reconnecting after 2 attempts");
+            assert_eq!(redial.redials, 2);
+        });
+    }
+
+    #[test]
+    fn test_no_reconnect_without_reconnect_token() {
+        run_session(async {
+            let (client_end, server_end) = crate::testing::tunnel_pair();
+
+            let server = tokio::spawn(async move {
+                let mut stream = MockServerStream::new(server_end.compat());
+                accept_client(&mut stream).await;
+            });
+
+            let mut redial = MockRedial {
+                streams: vec![],
+                redials: 0,
+            };
+            let err = timeout(
+                Duration::from_millis(5000),
+                ShellClient::new(HostShell::new().unwrap())
+                    .unwrap()
+                    .with_line_mode(true)
+                    .connect_with_reconnect(
+                        client_end,
+                        ShellKey::new("CorrectKey"),
+                        TEST_RECONNECT_POLICY,
+                        &mut redial,
+                    ),
+            )
+            .await
+            .unwrap()
+            .expect_err("session should fail");
+
+            server.await.unwrap();
+            assert!(is_disconnect(&err));
+            assert_eq!(redial.redials, 0);
+        });
+    }
+
+    #[test]
+    fn test_no_reconnect_after_session_closed() {
+        run_session(async {
+            let (client_end, server_end) = crate::testing::tunnel_pair();
+
+            let server = tokio::spawn(async move {
+                let mut stream = MockServerStream::new(server_end.compat());
+                accept_client(&mut stream).await;
+                stream
+                    .write(&ShellServerMessage::Close {
+                        reason: "shell exited".to_owned(),
+                    })
+                    .await
+                    .unwrap();
+            });
+
+            let mut redial = MockRedial {
+                streams: vec![],
+                redials: 0,
+            };
+            timeout(
+                Duration::from_millis(5000),
+                ShellClient::new(HostShell::new().unwrap())
+                    .unwrap()
+                    .with_line_mode(true)
+                    .with_reconnect_token(Some("token"))
+                    .connect_with_reconnect(
+                        client_end,
+                        ShellKey::new("CorrectKey"),
+                        TEST_RECONNECT_POLICY,
+                        &mut redial,
+                    ),
+            )
+            .await
+            .unwrap()
+            .expect_err("session should be closed");
+
+            server.await.unwrap();
+            assert_eq!(redial.redials, 0);
+        });
+    }
 }
@@ -0,0 +1,137 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Coalesces terminal size changes so a single resize is sent once the size has
+/// been stable for the debounce window, rather than one for each step of a drag-resize
+pub(super) struct ResizeDebouncer {
+    window: Duration,
+    last_sent: Option<(u16, u16)>,
+    pending: Option<((u16, u16), Instant)>,
+}
+
+impl ResizeDebouncer {
+    /// The initial size is the size already known by the server
+    pub(super) fn new(window: Duration, initial_size: Option<(u16, u16)>) -> Self {
+        Self {
+            window,
+            last_sent: initial_size,
+            pending: None,
+        }
+    }
+
+    /// Records the current size of the terminal, restarting the debounce window if it changed
+    pub(super) fn sample(&mut self, size: (u16, u16), now: Instant) {
+        if self.last_sent == Some(size) {
+            // The terminal has been resized back to the size the server already has
+            self.pending = None;
+            return;
+        }
+
+        match self.pending {
+            Some((pending, _)) if pending == size => {}
+            _ => self.pending = Some((size, now + self.window)),
+        }
+    }
+
+    /// The time at which the pending size should be sent, if any
+    pub(super) fn deadline(&self) -> Option<Instant> {
+        self.pending.map(|(_, deadline)| deadline)
+    }
+
+    /// Returns the size to send once the debounce window has elapsed
+    pub(super) fn poll(&mut self, now: Instant) -> Option<(u16, u16)> {
+        match self.pending {
+            Some((size, deadline)) if deadline <= now => {
+                self.pending = None;
+                self.last_sent = Some(size);
+                Some(size)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_millis(100);
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_unchanged_size_is_not_sent() {
+        let start = Instant::now();
+        let mut debouncer = ResizeDebouncer::new(WINDOW, Some((80, 24)));
+
+        debouncer.sample((80, 24), start);
+
+        assert_eq!(debouncer.deadline(), None);
+        assert_eq!(debouncer.poll(ms(start, 1000)), None);
+    }
+
+    #[test]
+    fn test_changed_size_is_sent_after_window() {
+        let start = Instant::now();
+        let mut debouncer = ResizeDebouncer::new(WINDOW, Some((80, 24)));
+
+        debouncer.sample((100, 30), start);
+
+        assert_eq!(debouncer.deadline(), Some(ms(start, 100)));
+        assert_eq!(debouncer.poll(ms(start, 50)), None);
+        assert_eq!(debouncer.poll(ms(start, 100)), Some((100, 30)));
+        assert_eq!(debouncer.poll(ms(start, 200)), None);
+
+        debouncer.sample((100, 30), ms(start, 300));
+
+        assert_eq!(debouncer.deadline(), None);
+    }
+
+    #[test]
+    fn test_drag_resize_is_coalesced() {
+        let start = Instant::now();
+        let mut debouncer = ResizeDebouncer::new(WINDOW, Some((80, 24)));
+        let mut sent = vec![];
+
+        let samples = [
+            (0, (81, 24)),
+            (20, (82, 25)),
+            (40, (84, 26)),
+            (60, (84, 26)),
+            (80, (90, 30)),
+        ];
+
+        for (time, size) in samples.iter() {
+            debouncer.sample(*size, ms(start, *time));
+            sent.extend(debouncer.poll(ms(start, *time)));
+        }
+
+        assert_eq!(sent, vec![]);
+        assert_eq!(debouncer.deadline(), Some(ms(start, 180)));
+        assert_eq!(debouncer.poll(ms(start, 180)), Some((90, 30)));
+    }
+
+    #[test]
+    fn test_resize_back_to_sent_size_is_dropped() {
+        let start = Instant::now();
+        let mut debouncer = ResizeDebouncer::new(WINDOW, Some((80, 24)));
+
+        debouncer.sample((100, 30), start);
+        debouncer.sample((80, 24), ms(start, 50));
+
+        assert_eq!(debouncer.deadline(), None);
+        assert_eq!(debouncer.poll(ms(start, 200)), None);
+    }
+
+    #[test]
+    fn test_unknown_initial_size() {
+        let start = Instant::now();
+        let mut debouncer = ResizeDebouncer::new(WINDOW, None);
+
+        debouncer.sample((80, 24), start);
+
+        assert_eq!(debouncer.poll(ms(start, 100)), Some((80, 24)));
+    }
+}
@@ -1,12 +1,18 @@
+use super::resize::ResizeDebouncer;
 use anyhow::{Error, Result};
-use crossterm;
-use futures::channel::mpsc;
-use futures::channel::mpsc::{UnboundedReceiver};
-use futures::stream::StreamExt;
 use io::{AsyncWriteExt, AsyncReadExt};
 use log::*;
+use std::time::Duration;
 use tokio::io;
-use std::thread::{self, JoinHandle};
+#[cfg(unix)]
+use tokio::signal;
+use tokio::time::{self, Instant};
+
+/// The time the terminal size must be stable for before it is sent to the server
+const RESIZE_DEBOUNCE_WINDOW: Duration = Duration::from_millis(100);
+
+#[cfg(not(unix))]
+const RESIZE_POLL_INTERVAL: Duration = Duration::from_millis(250);
 
 pub struct HostShellStdin {
     stdin: io::Stdin,
@@ -17,7 +23,8 @@ pub struct HostShellStdout {
 }
 
 pub struct HostShellResizeWatcher {
-    receiver: UnboundedReceiver<(u16, u16)>,
+    changes: ResizeEvents,
+    debouncer: ResizeDebouncer,
 }
 
 pub struct HostShell {}
@@ -46,52 +53,78 @@ impl HostShellStdout {
 
 impl HostShellResizeWatcher {
     pub fn new() -> Result<Self> {
-        let (receiver, _thread) = Self::create_thread();
+        let initial_size = crossterm::terminal::size().ok();
 
-        Ok(Self { receiver })
+        Ok(Self {
+            changes: ResizeEvents::new()?,
+            debouncer: ResizeDebouncer::new(RESIZE_DEBOUNCE_WINDOW, initial_size),
+        })
     }
 
-    fn create_thread() -> (UnboundedReceiver<(u16, u16)>, JoinHandle<()>) {
-        let (tx, rx) = mpsc::unbounded::<(u16, u16)>();
-        let mut prev_size = None;
-
-        let thread = thread::spawn(move || loop {
-            let size = match crossterm::terminal::size() {
-                Ok(size) => size,
-                Err(err) => {
-                    error!("Error while receiving terminal size: {:?}", err);
-                    break;
+    /// Waits for the terminal size to change, returning the new size
+    /// once it has been stable for the debounce window
+    pub async fn next(&mut self) -> Result<(u16, u16)> {
+        loop {
+            let deadline = self.debouncer.deadline();
+
+            tokio::select! {
+                _ = self.changes.next() => {
+                    let size = crossterm::terminal::size().map_err(Error::new)?;
+                    debug!("terminal size sampled as {:?}", size);
+                    self.debouncer.sample(size, Instant::now());
                 }
-            };
-
-            if prev_size.is_some() && prev_size.unwrap() == size {
-                std::thread::sleep(std::time::Duration::from_millis(500));
-                continue;
+                _ = wait_until(deadline) => {}
             }
 
-            prev_size = Some(size);
-            info!("terminal size changed to {:?}", size);
-
-            match tx.unbounded_send(size) {
-                Ok(_) => info!("sent terminal size to channel"),
-                Err(err) => {
-                    error!("Failed to send resize event to channel: {:?}", err);
-                    break;
-                }
+            if let Some(size) = self.debouncer.poll(Instant::now()) {
+                info!("terminal size changed to {:?}", size);
+                return Ok(size);
             }
-        });
+        }
+    }
+}
+
+/// Notifies of potential changes to the terminal size, using SIGWINCH
+/// where available and otherwise polling
+#[cfg(unix)]
+struct ResizeEvents(signal::unix::Signal);
 
-        (rx, thread)
+#[cfg(unix)]
+impl ResizeEvents {
+    fn new() -> Result<Self> {
+        Ok(Self(signal::unix::signal(
+            signal::unix::SignalKind::window_change(),
+        )?))
     }
 
-    pub async fn next(&mut self) -> Result<(u16, u16)> {
-        match self.receiver.next().await {
-            Some(size) => Ok(size),
-            None => Err(Error::msg("Resize watcher has been disposed")),
+    async fn next(&mut self) {
+        if self.0.recv().await.is_none() {
+            futures::future::pending::<()>().await;
         }
     }
 }
 
+#[cfg(not(unix))]
+struct ResizeEvents(time::Interval);
+
+#[cfg(not(unix))]
+impl ResizeEvents {
+    fn new() -> Result<Self> {
+        Ok(Self(time::interval(RESIZE_POLL_INTERVAL)))
+    }
+
+    async fn next(&mut self) {
+        self.0.tick().await;
+    }
+}
+
+async fn wait_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => time::delay_until(deadline).await,
+        None => futures::future::pending().await,
+    }
+}
+
 impl HostShell {
     pub fn new() -> Result<Self> {
         Ok(Self {})
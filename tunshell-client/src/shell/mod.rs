@@ -5,24 +5,325 @@ cfg_if::cfg_if! {
     if #[cfg(not(target_arch = "wasm32"))] {
         mod server;
         pub(crate) use server::*;
+        pub use server::{
+            AuthObserver, AuthPeer, NoDelay, NoShellAction, ResourceLimits, RunAs, SandboxConfig,
+            ShellServer, ShellServerBuilder,
+        };
     }
 }
 
 mod proto;
 use proto::*;
 
+use tunshell_shared::KeyGenConfig;
+
+#[derive(Clone)]
 pub struct ShellKey {
     key: String,
+    forced_command: Option<Vec<String>>,
 }
 
 impl ShellKey {
     pub fn new(key: &str) -> Self {
         Self {
             key: key.to_owned(),
+            forced_command: None,
+        }
+    }
+
+    /// Generates a random key using a CSPRNG
+    pub fn generate(config: &KeyGenConfig) -> Self {
+        Self {
+            key: config.generate(),
+            forced_command: None,
         }
     }
 
+    /// Restricts the key to running the supplied command and arguments,
+    /// which is run in place of the shell requested by the client
+    pub fn with_forced_command(mut self, command: Vec<String>) -> Self {
+        self.forced_command = Some(command);
+        self
+    }
+
     pub fn key(&self) -> &str {
         &self.key
     }
+
+    pub fn forced_command(&self) -> Option<&[String]> {
+        self.forced_command.as_deref()
+    }
+
+    /// Proves the key is held without revealing it, returning the HMAC-SHA256
+    /// of the server's challenge nonce keyed with the key
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn proof(&self, nonce: &[u8]) -> Vec<u8> {
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, self.key.as_bytes());
+
+        ring::hmac::sign(&key, nonce).as_ref().to_vec()
+    }
+
+    /// Returns whether the proof was created from this key and the nonce,
+    /// the comparison is made in constant time
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn verify_proof(&self, nonce: &[u8], proof: &[u8]) -> bool {
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, self.key.as_bytes());
+
+        ring::hmac::verify(&key, nonce, proof).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll, Waker},
+    };
+    use tokio::io::{AsyncRead, AsyncWrite};
+
+    /// Bytes written to one end of a memory stream pair which are waiting to be read from the other
+    #[derive(Default)]
+    struct MemoryPipe {
+        data: Vec<u8>,
+        closed: bool,
+        waker: Option<Waker>,
+    }
+
+    /// One end of a connected pair of in-memory streams, used to run
+    /// a shell client and server against each other
+    struct MemoryStream {
+        read: Arc<Mutex<MemoryPipe>>,
+        write: Arc<Mutex<MemoryPipe>>,
+    }
+
+    fn memory_stream_pair() -> (MemoryStream, MemoryStream) {
+        let a_to_b = Arc::new(Mutex::new(MemoryPipe::default()));
+        let b_to_a = Arc::new(Mutex::new(MemoryPipe::default()));
+
+        (
+            MemoryStream {
+                read: Arc::clone(&b_to_a),
+                write: Arc::clone(&a_to_b),
+            },
+            MemoryStream {
+                read: a_to_b,
+                write: b_to_a,
+            },
+        )
+    }
+
+    impl MemoryPipe {
+        fn close(&mut self) {
+            self.closed = true;
+
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    impl AsyncRead for MemoryStream {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buff: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let mut pipe = self.read.lock().unwrap();
+
+            if pipe.data.is_empty() {
+                if pipe.closed {
+                    return Poll::Ready(Ok(0));
+                }
+
+                pipe.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            let len = std::cmp::min(buff.len(), pipe.data.len());
+            buff[..len].copy_from_slice(&pipe.data[..len]);
+            pipe.data.drain(..len);
+
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    impl AsyncWrite for MemoryStream {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buff: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let mut pipe = self.write.lock().unwrap();
+
+            if pipe.closed {
+                return Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()));
+            }
+
+            pipe.data.extend_from_slice(buff);
+
+            if let Some(waker) = pipe.waker.take() {
+                waker.wake();
+            }
+
+            Poll::Ready(Ok(buff.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            self.write.lock().unwrap().close();
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl Drop for MemoryStream {
+        fn drop(&mut self) {
+            self.write.lock().unwrap().close();
+            self.read.lock().unwrap().close();
+        }
+    }
+
+    impl crate::TunnelStream for MemoryStream {}
+
+    #[test]
+    fn test_generate_shell_key() {
+        let config = KeyGenConfig::new(40, "abcdefghijklmnopqrstuvwxyz").unwrap();
+
+        let key1 = ShellKey::generate(&config);
+        let key2 = ShellKey::generate(&config);
+
+        assert_eq!(key1.key().len(), 40);
+        assert!(key1.key().chars().all(|i| i.is_ascii_lowercase()));
+        assert_ne!(key1.key(), key2.key());
+    }
+
+    #[test]
+    fn test_memory_stream_pair() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::runtime::Runtime;
+
+        Runtime::new().unwrap().block_on(async {
+            let (mut a, mut b) = memory_stream_pair();
+
+            a.write_all("hello".as_bytes()).await.unwrap();
+            b.write_all("world".as_bytes()).await.unwrap();
+            a.shutdown().await.unwrap();
+
+            let mut received = vec![];
+            b.read_to_end(&mut received).await.unwrap();
+            assert_eq!(received, "hello".as_bytes());
+
+            let mut buff = [0u8; 5];
+            a.read_exact(&mut buff).await.unwrap();
+            assert_eq!(&buff, "world".as_bytes());
+
+            drop(b);
+            assert_eq!(a.read(&mut buff).await.unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn test_client_proves_key_when_challenged() {
+        use futures::StreamExt;
+        use tokio::runtime::Runtime;
+        use tokio_util::compat::*;
+        use tunshell_shared::{Capabilities, MessageFormat};
+
+        Runtime::new().unwrap().block_on(async {
+            let (client_stream, server_stream) = memory_stream_pair();
+            let mut client = ShellClient::new(HostShell::new().unwrap()).unwrap();
+            let nonce = vec![1u8; 32];
+
+            let server = async {
+                let mut stream = ShellServerStream::new(server_stream.compat());
+
+                match stream.next().await.unwrap().unwrap() {
+                    ShellClientMessage::Hello(hello) => assert!(hello.key_proof),
+                    message => panic!("expected hello, got {:?}", message),
+                }
+
+                stream
+                    .write(&ShellServerMessage::HelloAck(HelloAckPayload {
+                        protocol_version: PROTOCOL_VERSION,
+                        accepted: true,
+                        capabilities: Capabilities::KEY_PROOF,
+                        format: MessageFormat::Binary,
+                    }))
+                    .await
+                    .unwrap();
+                stream
+                    .write(&ShellServerMessage::Challenge(nonce.clone()))
+                    .await
+                    .unwrap();
+
+                // The raw key is never sent
+                assert_eq!(
+                    stream.next().await.unwrap().unwrap(),
+                    ShellClientMessage::KeyProof(ShellKey::new("key").proof(&nonce))
+                );
+
+                stream
+                    .write(&ShellServerMessage::KeyRejected)
+                    .await
+                    .unwrap();
+            };
+
+            let (result, _) = futures::future::join(
+                client.connect(Box::new(client_stream), ShellKey::new("key")),
+                server,
+            )
+            .await;
+
+            result.expect_err("client key should be rejected");
+        });
+    }
+
+    /// Runs a real shell client against a shell server over an in-memory stream,
+    /// the client's mock host shell is only available when built with `--cfg integration_test`
+    #[test]
+    #[cfg(all(unix, integration_test))]
+    fn test_client_server_round_trip() {
+        use std::time::Duration;
+        use tokio::runtime::Runtime;
+        use tokio::time::{delay_for, timeout};
+
+        Runtime::new().unwrap().block_on(async {
+            let (client_stream, server_stream) = memory_stream_pair();
+            let host_shell = HostShell::new().unwrap();
+            let mut client = ShellClient::new(host_shell.clone()).unwrap();
+
+            let interact = async {
+                host_shell.write_to_stdin("echo $((6000 + 42))\n".as_bytes());
+
+                // The command is only sent once the output of the previous one has been received
+                let mut stdout = vec![];
+                while !String::from_utf8_lossy(&stdout).contains("6042") {
+                    delay_for(Duration::from_millis(10)).await;
+                    stdout.extend_from_slice(host_shell.drain_stdout().as_slice());
+                }
+
+                host_shell.write_to_stdin("exit\n".as_bytes());
+            };
+
+            let (server_result, client_result, _) = timeout(
+                Duration::from_millis(10000),
+                futures::future::join3(
+                    ShellServer::new()
+                        .unwrap()
+                        .run(Box::new(server_stream), ShellKey::new("key")),
+                    client.connect(Box::new(client_stream), ShellKey::new("key")),
+                    interact,
+                ),
+            )
+            .await
+            .expect("session should finish");
+
+            server_result.unwrap();
+            assert_eq!(client_result.unwrap(), 0);
+        });
+    }
 }
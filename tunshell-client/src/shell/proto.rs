@@ -1,35 +1,270 @@
 use anyhow::{Error, Result};
+use bytes::Bytes;
 use serde::{Deserialize, Serialize};
-use std::convert::From;
-use tunshell_shared::{Message, MessageStream, RawMessage};
+use std::{cmp, convert::From};
+use tunshell_shared::{
+    Capabilities, FrameVersion, Message, MessageFormat, MessageStream, RawMessage,
+    SHELL_PROTOCOL_VERSION,
+};
 
-#[derive(Debug, PartialEq, Clone)]
+/// The version of the shell protocol implemented by this build
+pub(super) const PROTOCOL_VERSION: u16 = SHELL_PROTOCOL_VERSION;
+/// The oldest version of the shell protocol this build can communicate with
+pub(super) const MIN_PROTOCOL_VERSION: u16 = 2;
+/// The largest number of columns or rows the shell server will apply to a shell
+pub(super) const MAX_WINDOW_DIMENSION: u16 = 1000;
+/// The size applied to a shell when the client does not know its window size
+pub(super) const DEFAULT_WINDOW_SIZE: WindowSize = WindowSize(80, 24);
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub(super) enum ShellClientMessage {
+    Hello(HelloPayload),
     Key(String),
     StartShell(StartShellPayload),
     Stdin(Vec<u8>),
     Resize(WindowSize),
+    Signal(u8),
+    /// The client has finished writing to stdin, output continues until the shell exits
+    StdinClose,
+    /// Leaves the shell running on the server so it can be reattached later
+    Detach,
+    /// Requests the current working directory of the shell
+    GetCwd,
+    /// Changes the working directory of the shell by writing a `cd` command to it
+    SetCwd(String),
+    /// Requests the shells which can be selected in `StartShellPayload::shell_path`
+    ListShells,
+    /// Pasted input, written to pty shells within bracketed paste markers so that
+    /// paste-aware shells treat it as literal text rather than executing each line
+    Paste(Vec<u8>),
+    /// The HMAC-SHA256 of the server's challenge nonce using the key, sent in place of the key
+    KeyProof(Vec<u8>),
+    /// Requests information about the host the shell server is running on
+    SystemInfo,
+    /// Sent in place of `StartShell` to resume a detached shell, using the session's
+    /// reconnect token
+    Reattach(ReattachPayload),
     Error(String),
+    /// A message with an unrecognised type id, sent by a newer client
+    #[serde(skip)]
+    Unknown(u8),
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub(super) enum ShellServerMessage {
+    HelloAck(HelloAckPayload),
     KeyAccepted,
     KeyRejected,
-    Stdout(Vec<u8>),
-    Exited(u8),
-    Error(String),
+    Stdout(#[serde(with = "bytes_as_vec")] Bytes),
+    Exited(ExitStatus),
+    SizeApplied(WindowSize),
+    /// The shell has been detached and can be reattached using the token
+    Detached(String),
+    Error {
+        severity: ErrorSeverity,
+        message: String,
+    },
+    /// Sent immediately before the server intentionally closes the connection,
+    /// so the client can tell the end of a session apart from a dropped connection
+    Close {
+        reason: String,
+    },
+    /// The current working directory of the shell, in response to `GetCwd`
+    Cwd(String),
+    /// The paths of the shells available on the server, in response to `ListShells`
+    Shells(Vec<String>),
+    /// Sent when the session has been silent for the keepalive interval so that
+    /// idle connections are not dropped by NATs or proxies, clients ignore it
+    Ping,
+    /// A random nonce which the client must prove its key against with `KeyProof`,
+    /// only sent to clients which requested it in their hello
+    Challenge(Vec<u8>),
+    /// Information about the host, in response to `SystemInfo`
+    SystemInfo(SystemInfoPayload),
+    /// The title set by an OSC 0 or OSC 2 sequence in the shell's output, which is still
+    /// included in the output. Only sent to clients which requested titles.
+    Title(String),
+}
+
+/// Whether the session continues after an error is reported by the server.
+/// Fatal errors are sent with the original error type id so they are understood
+/// by older clients, warnings use their own type id.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub(super) enum ErrorSeverity {
+    /// The session continues, for example when a signal could not be delivered
+    Warning,
+    /// The session is ending
+    Fatal,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub(super) struct HelloPayload {
+    pub(super) protocol_version: u16,
+    /// The format the client would like to use for the messages after the handshake
+    #[serde(default, skip_serializing_if = "MessageFormat::is_binary")]
+    pub(super) format: MessageFormat,
+    /// Whether the client would like to prove its key in response to a `Challenge`
+    /// rather than sending it, servers without the key proof capability ignore this
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub(super) key_proof: bool,
+    /// Whether the client would like the messages after the handshake to be numbered,
+    /// used only if the server has the sequence numbers capability
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub(super) sequence_numbers: bool,
+    /// The frame version the client would like to use for the messages after the handshake,
+    /// used only if the server has the capability for that version
+    #[serde(default, skip_serializing_if = "FrameVersion::is_v1")]
+    pub(super) frame_version: FrameVersion,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub(super) struct HelloAckPayload {
+    pub(super) protocol_version: u16,
+    pub(super) accepted: bool,
+    /// The optional features supported by the shell server
+    #[serde(default)]
+    pub(super) capabilities: Capabilities,
+    /// The format used for the messages after the handshake in both directions.
+    /// Older servers omit this and continue with the binary format.
+    #[serde(default, skip_serializing_if = "MessageFormat::is_binary")]
+    pub(super) format: MessageFormat,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub(super) struct StartShellPayload {
     pub(super) term: String,
-    pub(super) size: WindowSize,
+    /// The client's window size, clients which do not know their size when
+    /// connecting omit this or send 0x0 and the default size is used instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) size: Option<WindowSize>,
+    /// Whether the shell should be run in a pty, when false the shell
+    /// is run with plain pipes which is better suited to scripting
+    #[serde(default = "default_true")]
+    pub(super) pty: bool,
+    /// Whether the pty shell is started as a login shell (-l).
+    /// Ignored in no-pty mode and by the fallback shell, which never sources a profile.
+    #[serde(default = "default_true")]
+    pub(super) login: bool,
+    /// Whether the pty shell is started as an interactive shell (-i).
+    /// Ignored in no-pty mode, the fallback shell is always interactive.
+    #[serde(default = "default_true")]
+    pub(super) interactive: bool,
+    /// Runs the shell with plain pipes and passes stdin and stdout through
+    /// byte-for-byte, stderr is not forwarded so it cannot corrupt the output.
+    /// Takes precedence over the pty flag.
+    #[serde(default)]
+    pub(super) raw: bool,
+    /// The shell to run in place of the server's default shell, which must
+    /// be one of the shells returned in response to `ListShells`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) shell_path: Option<String>,
+    /// The number of colours supported by the client's terminal, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) colors: Option<u16>,
+    /// Whether the client's terminal supports 24-bit colour, sets `COLORTERM` for the shell
+    #[serde(default)]
+    pub(super) truecolor: bool,
+    /// The client echoes and edits input locally, sending each complete line as a single
+    /// stdin message, for links where the round trip of remote echo is too slow.
+    /// The shell is run without a pty so it does not echo the input a second time.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub(super) line_mode: bool,
+    /// The client's locale, such as "en_GB.UTF-8", set as `LANG` and `LC_ALL` for the shell
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) locale: Option<String>,
+    /// The client's timezone, such as "Europe/London", set as `TZ` for the shell
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) timezone: Option<String>,
+    /// Whether the client would like a `Title` message each time the shell sets the
+    /// terminal's title, older clients cannot parse them so they are only sent on request
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub(super) titles: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub(super) struct ReattachPayload {
+    /// The session's reconnect token, returned by the relay when the session was created
+    pub(super) token: String,
+    /// The client's window size, applied to the shell once it has been reattached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) size: Option<WindowSize>,
+}
+
+/// Information about the host the shell server is running on, gathered when requested.
+/// Fields which cannot be determined on the host's platform are `None`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub(super) struct SystemInfoPayload {
+    pub(super) hostname: Option<String>,
+    /// The operating system, as in `std::env::consts::OS`
+    pub(super) os: String,
+    /// The CPU architecture, as in `std::env::consts::ARCH`
+    pub(super) arch: String,
+    pub(super) kernel_version: Option<String>,
+    /// The 1, 5 and 15 minute load averages
+    pub(super) load_average: Option<(f64, f64, f64)>,
+    pub(super) uptime_secs: Option<u64>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// Serialises `Bytes` as a sequence of bytes, as the serde feature of bytes is not enabled
+mod bytes_as_vec {
+    use bytes::Bytes;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(
+        bytes: &Bytes,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(bytes.iter())
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Bytes, D::Error> {
+        Ok(Bytes::from(Vec::<u8>::deserialize(deserializer)?))
+    }
+}
+
+/// Serialises the message as JSON, keeping the binary type id in the frame header
+fn serialise_json<M: Message + Serialize>(message: &M) -> Result<RawMessage> {
+    RawMessage::new(message.type_id(), serde_json::to_vec(message)?)
+}
+
+/// Parses a JSON message, the type id of the frame must match the parsed message
+fn deserialise_json<M: Message + for<'de> Deserialize<'de>>(raw_message: &RawMessage) -> Result<M> {
+    let message: M = serde_json::from_slice(raw_message.data().as_slice())?;
+
+    if message.type_id() != raw_message.type_id() {
+        return Err(Error::msg(format!(
+            "message type id {} does not match frame type id {}",
+            message.type_id(),
+            raw_message.type_id()
+        )));
+    }
+
+    Ok(message)
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub(super) struct WindowSize(pub(super) u16, pub(super) u16);
 
+/// How the shell process ended, either exiting with a code or being terminated by a signal.
+/// Both are `None` if it could not be determined.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub(super) struct ExitStatus {
+    #[serde(default)]
+    pub(super) code: Option<i32>,
+    #[serde(default)]
+    pub(super) signal: Option<i32>,
+}
+
 pub(super) type ShellClientStream<S> = MessageStream<ShellClientMessage, ShellServerMessage, S>;
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -42,17 +277,41 @@ impl Message for ShellClientMessage {
             Self::StartShell(_) => 2,
             Self::Stdin(_) => 3,
             Self::Resize(_) => 4,
+            Self::Hello(_) => 5,
+            Self::Signal(_) => 6,
+            Self::StdinClose => 7,
+            Self::Detach => 8,
+            Self::GetCwd => 9,
+            Self::SetCwd(_) => 10,
+            Self::ListShells => 11,
+            Self::Paste(_) => 12,
+            Self::KeyProof(_) => 13,
+            Self::SystemInfo => 14,
+            Self::Reattach(_) => 15,
             Self::Error(_) => 255,
+            Self::Unknown(type_id) => *type_id,
         }
     }
 
     fn serialise(&self) -> Result<RawMessage> {
         let buff = match self {
+            Self::Hello(payload) => serde_json::to_vec(&payload)?,
             Self::Key(key) => key.as_bytes().to_vec(),
             Self::StartShell(payload) => serde_json::to_vec(&payload)?,
             Self::Stdin(payload) => payload.clone(),
             Self::Resize(payload) => serde_json::to_vec(&payload)?,
+            Self::Signal(signal) => vec![*signal],
+            Self::StdinClose => vec![],
+            Self::Detach => vec![],
+            Self::GetCwd => vec![],
+            Self::SetCwd(path) => path.as_bytes().to_vec(),
+            Self::ListShells => vec![],
+            Self::Paste(payload) => payload.clone(),
+            Self::KeyProof(proof) => proof.clone(),
+            Self::SystemInfo => vec![],
+            Self::Reattach(payload) => serde_json::to_vec(&payload)?,
             Self::Error(payload) => payload.as_bytes().to_vec(),
+            Self::Unknown(_) => vec![],
         };
 
         RawMessage::new(self.type_id(), buff)
@@ -64,17 +323,41 @@ impl Message for ShellClientMessage {
             2 => Self::StartShell(serde_json::from_slice(raw_message.data().as_slice())?),
             3 => Self::Stdin(raw_message.data().clone()),
             4 => Self::Resize(serde_json::from_slice(raw_message.data().as_slice())?),
+            5 => Self::Hello(serde_json::from_slice(raw_message.data().as_slice())?),
+            6 => Self::Signal(raw_message.data().first().map_or_else(
+                || Err(Error::msg("encountered signal message without signal")),
+                |v| Ok(*v),
+            )?),
+            7 => Self::StdinClose,
+            8 => Self::Detach,
+            9 => Self::GetCwd,
+            10 => Self::SetCwd(String::from_utf8(raw_message.data().clone())?),
+            11 => Self::ListShells,
+            12 => Self::Paste(raw_message.data().clone()),
+            13 => Self::KeyProof(raw_message.data().clone()),
+            14 => Self::SystemInfo,
+            15 => Self::Reattach(serde_json::from_slice(raw_message.data().as_slice())?),
             255 => Self::Error(String::from_utf8(raw_message.data().clone())?),
-            id @ _ => {
-                return Err(Error::msg(format!(
-                    "Unknown type id for ShellClientMessage: {}",
-                    id
-                )))
-            }
+            id => Self::Unknown(id),
         };
 
         Ok(parsed)
     }
+
+    fn serialise_json(&self) -> Result<RawMessage> {
+        match self {
+            Self::Unknown(_) => RawMessage::new(self.type_id(), vec![]),
+            _ => serialise_json(self),
+        }
+    }
+
+    fn deserialise_json(raw_message: &RawMessage) -> Result<Self> {
+        // Messages from newer clients are passed through as unknown in either format
+        match raw_message.type_id() {
+            1..=15 | 255 => deserialise_json(raw_message),
+            id => Ok(Self::Unknown(id)),
+        }
+    }
 }
 
 impl Message for ShellServerMessage {
@@ -84,33 +367,90 @@ impl Message for ShellServerMessage {
             Self::KeyRejected => 2,
             Self::Stdout(_) => 3,
             Self::Exited(_) => 4,
-            Self::Error(_) => 255,
+            Self::HelloAck(_) => 5,
+            Self::SizeApplied(_) => 6,
+            Self::Detached(_) => 7,
+            Self::Error {
+                severity: ErrorSeverity::Warning,
+                ..
+            } => 8,
+            Self::Close { .. } => 9,
+            Self::Cwd(_) => 10,
+            Self::Shells(_) => 11,
+            Self::Ping => 12,
+            Self::Challenge(_) => 13,
+            Self::SystemInfo(_) => 14,
+            Self::Title(_) => 15,
+            Self::Error {
+                severity: ErrorSeverity::Fatal,
+                ..
+            } => 255,
         }
     }
 
     fn serialise(&self) -> Result<RawMessage> {
         let buff = match self {
+            Self::HelloAck(payload) => serde_json::to_vec(&payload)?,
             Self::KeyAccepted => Vec::<u8>::new(),
             Self::KeyRejected => Vec::<u8>::new(),
-            Self::Stdout(payload) => payload.clone(),
-            Self::Exited(payload) => vec![*payload],
-            Self::Error(payload) => payload.as_bytes().to_vec(),
+            Self::Stdout(payload) => payload.to_vec(),
+            // The first byte is the process exit code understood by older clients
+            Self::Exited(status) => {
+                let mut buff = vec![status.process_exit_code()];
+                buff.extend(serde_json::to_vec(&status)?);
+                buff
+            }
+            Self::SizeApplied(payload) => serde_json::to_vec(&payload)?,
+            Self::Detached(token) => token.as_bytes().to_vec(),
+            Self::Error { message, .. } => message.as_bytes().to_vec(),
+            Self::Close { reason } => reason.as_bytes().to_vec(),
+            Self::Cwd(path) => path.as_bytes().to_vec(),
+            Self::Shells(shells) => serde_json::to_vec(&shells)?,
+            Self::Ping => vec![],
+            Self::Challenge(nonce) => nonce.clone(),
+            Self::SystemInfo(payload) => serde_json::to_vec(&payload)?,
+            Self::Title(title) => title.as_bytes().to_vec(),
         };
 
         RawMessage::new(self.type_id(), buff)
     }
 
+    fn serialise_into(&self, buff: &mut Vec<u8>) -> Result<()> {
+        match self {
+            // Stdout is written directly from the payload to avoid copying it per chunk
+            Self::Stdout(payload) => RawMessage::serialise_into(self.type_id(), payload, buff),
+            _ => {
+                let raw_message = self.serialise()?;
+                RawMessage::serialise_into(raw_message.type_id(), raw_message.data(), buff)
+            }
+        }
+    }
+
     fn deserialise(raw_message: &RawMessage) -> Result<Self> {
         let message = match raw_message.type_id() {
             1 => Self::KeyAccepted,
             2 => Self::KeyRejected,
-            3 => Self::Stdout(raw_message.data().clone()),
-            4 => Self::Exited(raw_message.data().get(0).map_or_else(
-                || Err(Error::msg("encountered exit message without exit code")),
-                |v| Ok(*v),
-            )?),
-            255 => Self::Error(String::from_utf8(raw_message.data().clone())?),
-            id @ _ => {
+            3 => Self::Stdout(Bytes::from(raw_message.data().clone())),
+            4 => Self::Exited(match raw_message.data().split_first() {
+                Some((_, status)) if !status.is_empty() => serde_json::from_slice(status)?,
+                Some((code, _)) => ExitStatus::exited(*code as i32),
+                None => return Err(Error::msg("encountered exit message without exit code")),
+            }),
+            5 => Self::HelloAck(serde_json::from_slice(raw_message.data().as_slice())?),
+            6 => Self::SizeApplied(serde_json::from_slice(raw_message.data().as_slice())?),
+            7 => Self::Detached(String::from_utf8(raw_message.data().clone())?),
+            8 => Self::warning(String::from_utf8(raw_message.data().clone())?),
+            9 => Self::Close {
+                reason: String::from_utf8(raw_message.data().clone())?,
+            },
+            10 => Self::Cwd(String::from_utf8(raw_message.data().clone())?),
+            11 => Self::Shells(serde_json::from_slice(raw_message.data().as_slice())?),
+            12 => Self::Ping,
+            13 => Self::Challenge(raw_message.data().clone()),
+            14 => Self::SystemInfo(serde_json::from_slice(raw_message.data().as_slice())?),
+            15 => Self::Title(String::from_utf8(raw_message.data().clone())?),
+            255 => Self::fatal(String::from_utf8(raw_message.data().clone())?),
+            id => {
                 return Err(Error::msg(format!(
                     "Unknown type id for ShellServerMessage: {}",
                     id
@@ -120,6 +460,83 @@ impl Message for ShellServerMessage {
 
         Ok(message)
     }
+
+    fn serialise_json(&self) -> Result<RawMessage> {
+        serialise_json(self)
+    }
+
+    fn deserialise_json(raw_message: &RawMessage) -> Result<Self> {
+        deserialise_json(raw_message)
+    }
+}
+
+impl ShellServerMessage {
+    pub(super) fn warning(message: impl Into<String>) -> Self {
+        Self::Error {
+            severity: ErrorSeverity::Warning,
+            message: message.into(),
+        }
+    }
+
+    pub(super) fn fatal(message: impl Into<String>) -> Self {
+        Self::Error {
+            severity: ErrorSeverity::Fatal,
+            message: message.into(),
+        }
+    }
+}
+
+/// Returns the highest protocol version supported by both this build and the peer,
+/// or `None` if the peer's version is too old to be compatible
+pub(super) fn negotiate_protocol_version(peer_version: u16) -> Option<u16> {
+    if peer_version < MIN_PROTOCOL_VERSION {
+        return None;
+    }
+
+    Some(cmp::min(peer_version, PROTOCOL_VERSION))
+}
+
+impl WindowSize {
+    /// Returns the size with each dimension limited to between 1 and `MAX_WINDOW_DIMENSION`
+    pub(super) fn clamped(&self) -> Self {
+        Self(
+            self.0.clamp(1, MAX_WINDOW_DIMENSION),
+            self.1.clamp(1, MAX_WINDOW_DIMENSION),
+        )
+    }
+}
+
+impl ExitStatus {
+    pub(super) fn exited(code: i32) -> Self {
+        Self {
+            code: Some(code),
+            signal: None,
+        }
+    }
+
+    pub(super) fn signalled(signal: i32) -> Self {
+        Self {
+            code: None,
+            signal: Some(signal),
+        }
+    }
+
+    pub(super) fn unknown() -> Self {
+        Self {
+            code: None,
+            signal: None,
+        }
+    }
+
+    /// The exit code a process should exit with to report this status,
+    /// following the shell convention of 128 + signo for a signal and 255 if unknown
+    pub(super) fn process_exit_code(&self) -> u8 {
+        match (self.code, self.signal) {
+            (Some(code), _) => code as u8,
+            (None, Some(signal)) => (128 + signal) as u8,
+            (None, None) => 255,
+        }
+    }
 }
 
 impl From<(u16, u16)> for WindowSize {
@@ -131,6 +548,112 @@ impl From<(u16, u16)> for WindowSize {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use futures::io::{AsyncRead, AsyncWrite, Cursor};
+    use futures::stream::StreamExt;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::runtime::Runtime;
+
+    /// Mock stream which returns a single byte per read, interleaved with pending
+    /// polls, to simulate a message being fragmented over many tcp reads
+    struct FragmentedStream {
+        data: Vec<u8>,
+        pos: usize,
+        pending: bool,
+    }
+
+    impl FragmentedStream {
+        fn new(data: Vec<u8>) -> Self {
+            Self {
+                data,
+                pos: 0,
+                pending: true,
+            }
+        }
+    }
+
+    impl AsyncRead for FragmentedStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buff: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            if self.pending {
+                self.pending = false;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            self.pending = true;
+
+            if self.pos == self.data.len() {
+                return Poll::Ready(Ok(0));
+            }
+
+            buff[0] = self.data[self.pos];
+            self.pos += 1;
+
+            Poll::Ready(Ok(1))
+        }
+    }
+
+    impl AsyncWrite for FragmentedStream {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buff: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            Poll::Ready(Ok(buff.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn decode_client_messages<S>(stream: S) -> Vec<ShellClientMessage>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        Runtime::new()
+            .unwrap()
+            .block_on(ShellServerStream::new(stream).map(|i| i.unwrap()).collect())
+    }
+
+    #[test]
+    fn test_decode_message_fragmented_byte_by_byte() {
+        let messages = vec![
+            ShellClientMessage::Key("SomeKey".to_owned()),
+            ShellClientMessage::Stdin(vec![1u8; 2000]),
+        ];
+        let data = messages
+            .iter()
+            .flat_map(|i| i.serialise().unwrap().to_vec())
+            .collect::<Vec<u8>>();
+
+        assert_eq!(
+            decode_client_messages(FragmentedStream::new(data)),
+            messages
+        );
+    }
+
+    #[test]
+    fn test_decode_two_messages_in_one_read() {
+        let messages = vec![
+            ShellClientMessage::Stdin("ls\n".as_bytes().to_vec()),
+            ShellClientMessage::Resize(WindowSize(80, 24)),
+        ];
+        let data = messages
+            .iter()
+            .flat_map(|i| i.serialise().unwrap().to_vec())
+            .collect::<Vec<u8>>();
+
+        assert_eq!(decode_client_messages(Cursor::new(data)), messages);
+    }
 
     #[test]
     fn test_client_serialise_key() {
@@ -151,7 +674,18 @@ mod tests {
     fn test_client_serialise_start_shell() {
         let message = ShellClientMessage::StartShell(StartShellPayload {
             term: "test".to_owned(),
-            size: WindowSize(100, 50),
+            size: Some(WindowSize(100, 50)),
+            pty: false,
+            login: false,
+            interactive: true,
+            raw: false,
+            shell_path: None,
+            colors: None,
+            truecolor: false,
+            line_mode: false,
+            locale: None,
+            timezone: None,
+            titles: false,
         });
         let serialised = message.serialise().unwrap();
 
@@ -159,7 +693,9 @@ mod tests {
             serialised,
             RawMessage::new(
                 2,
-                "{\"term\":\"test\",\"size\":[100,50]}".as_bytes().to_vec()
+                "{\"term\":\"test\",\"size\":[100,50],\"pty\":false,\"login\":false,\"interactive\":true,\"raw\":false,\"truecolor\":false}"
+                    .as_bytes()
+                    .to_vec()
             )
             .unwrap()
         );
@@ -169,6 +705,44 @@ mod tests {
         assert_eq!(message, deserialised);
     }
 
+    #[test]
+    fn test_client_deserialise_start_shell_defaults() {
+        let serialised = RawMessage::new(
+            2,
+            "{\"term\":\"test\",\"size\":[100,50]}".as_bytes().to_vec(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            ShellClientMessage::deserialise(&serialised).unwrap(),
+            ShellClientMessage::StartShell(StartShellPayload {
+                term: "test".to_owned(),
+                size: Some(WindowSize(100, 50)),
+                pty: true,
+                login: true,
+                interactive: true,
+                raw: false,
+                shell_path: None,
+                colors: None,
+                truecolor: false,
+                line_mode: false,
+                locale: None,
+                timezone: None,
+                titles: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_client_deserialise_start_shell_without_size() {
+        let serialised = RawMessage::new(2, "{\"term\":\"test\"}".as_bytes().to_vec()).unwrap();
+
+        match ShellClientMessage::deserialise(&serialised).unwrap() {
+            ShellClientMessage::StartShell(payload) => assert_eq!(payload.size, None),
+            message => panic!("unexpected message: {:?}", message),
+        }
+    }
+
     #[test]
     fn test_client_serialise_stdin() {
         let message = ShellClientMessage::Stdin(vec![1, 2, 3, 4, 5]);
@@ -181,6 +755,121 @@ mod tests {
         assert_eq!(message, deserialised);
     }
 
+    #[test]
+    fn test_client_serialise_signal() {
+        let message = ShellClientMessage::Signal(15);
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(serialised, RawMessage::new(6, vec![15]).unwrap());
+
+        let deserialised = ShellClientMessage::deserialise(&serialised).unwrap();
+
+        assert_eq!(message, deserialised);
+    }
+
+    #[test]
+    fn test_client_serialise_stdin_close() {
+        let message = ShellClientMessage::StdinClose;
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(serialised, RawMessage::new(7, vec![]).unwrap());
+
+        let deserialised = ShellClientMessage::deserialise(&serialised).unwrap();
+
+        assert_eq!(message, deserialised);
+    }
+
+    #[test]
+    fn test_client_serialise_detach() {
+        let message = ShellClientMessage::Detach;
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(serialised, RawMessage::new(8, vec![]).unwrap());
+
+        let deserialised = ShellClientMessage::deserialise(&serialised).unwrap();
+
+        assert_eq!(message, deserialised);
+    }
+
+    #[test]
+    fn test_client_serialise_reattach() {
+        let message = ShellClientMessage::Reattach(ReattachPayload {
+            token: "token".to_owned(),
+            size: None,
+        });
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(
+            serialised,
+            RawMessage::new(15, "{\"token\":\"token\"}".as_bytes().to_vec()).unwrap()
+        );
+        assert_eq!(
+            ShellClientMessage::deserialise(&serialised).unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn test_client_serialise_cwd() {
+        let message = ShellClientMessage::GetCwd;
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(serialised, RawMessage::new(9, vec![]).unwrap());
+        assert_eq!(
+            ShellClientMessage::deserialise(&serialised).unwrap(),
+            message
+        );
+
+        let message = ShellClientMessage::SetCwd("/tmp".to_owned());
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(
+            serialised,
+            RawMessage::new(10, "/tmp".as_bytes().to_vec()).unwrap()
+        );
+        assert_eq!(
+            ShellClientMessage::deserialise(&serialised).unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn test_client_serialise_list_shells() {
+        let message = ShellClientMessage::ListShells;
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(serialised, RawMessage::new(11, vec![]).unwrap());
+
+        let deserialised = ShellClientMessage::deserialise(&serialised).unwrap();
+
+        assert_eq!(message, deserialised);
+    }
+
+    #[test]
+    fn test_client_serialise_paste() {
+        let message = ShellClientMessage::Paste(b"echo one\necho two\n".to_vec());
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(
+            serialised,
+            RawMessage::new(12, b"echo one\necho two\n".to_vec()).unwrap()
+        );
+
+        let deserialised = ShellClientMessage::deserialise(&serialised).unwrap();
+
+        assert_eq!(message, deserialised);
+    }
+
+    #[test]
+    fn test_client_deserialise_unknown() {
+        let raw_message = RawMessage::new(100, vec![1, 2, 3]).unwrap();
+
+        assert_eq!(
+            ShellClientMessage::deserialise(&raw_message).unwrap(),
+            ShellClientMessage::Unknown(100)
+        );
+    }
+
     #[test]
     fn test_client_serialise_resize() {
         let message = ShellClientMessage::Resize(WindowSize(50, 100));
@@ -222,7 +911,7 @@ mod tests {
 
     #[test]
     fn test_server_serialise_stdout() {
-        let message = ShellServerMessage::Stdout(vec![1, 2, 3, 4, 5]);
+        let message = ShellServerMessage::Stdout(Bytes::from(vec![1, 2, 3, 4, 5]));
         let serialised = message.serialise().unwrap();
 
         assert_eq!(serialised, RawMessage::new(3, vec![1, 2, 3, 4, 5]).unwrap());
@@ -232,21 +921,183 @@ mod tests {
         assert_eq!(message, deserialised);
     }
 
+    #[test]
+    fn test_server_serialise_into_stdout() {
+        let message = ShellServerMessage::Stdout(Bytes::from(vec![1, 2, 3, 4, 5]));
+        let mut buff = vec![];
+
+        message.serialise_into(&mut buff).unwrap();
+
+        assert_eq!(buff, message.serialise().unwrap().to_vec());
+    }
+
+    #[test]
+    fn test_server_serialise_size_applied() {
+        let message = ShellServerMessage::SizeApplied(WindowSize(50, 100));
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(
+            serialised,
+            RawMessage::new(6, "[50,100]".as_bytes().to_vec()).unwrap()
+        );
+
+        let deserialised = ShellServerMessage::deserialise(&serialised).unwrap();
+
+        assert_eq!(message, deserialised);
+    }
+
+    #[test]
+    fn test_server_serialise_cwd() {
+        let message = ShellServerMessage::Cwd("/tmp".to_owned());
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(
+            serialised,
+            RawMessage::new(10, "/tmp".as_bytes().to_vec()).unwrap()
+        );
+
+        let deserialised = ShellServerMessage::deserialise(&serialised).unwrap();
+
+        assert_eq!(message, deserialised);
+    }
+
+    #[test]
+    fn test_server_serialise_shells() {
+        let message =
+            ShellServerMessage::Shells(vec!["/bin/sh".to_owned(), "/bin/bash".to_owned()]);
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(
+            serialised,
+            RawMessage::new(11, "[\"/bin/sh\",\"/bin/bash\"]".as_bytes().to_vec()).unwrap()
+        );
+
+        let deserialised = ShellServerMessage::deserialise(&serialised).unwrap();
+
+        assert_eq!(message, deserialised);
+    }
+
+    #[test]
+    fn test_server_serialise_ping() {
+        let message = ShellServerMessage::Ping;
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(serialised, RawMessage::new(12, vec![]).unwrap());
+
+        let deserialised = ShellServerMessage::deserialise(&serialised).unwrap();
+
+        assert_eq!(message, deserialised);
+    }
+
+    #[test]
+    fn test_server_serialise_title() {
+        let message = ShellServerMessage::Title("user@host: ~".to_owned());
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(
+            serialised,
+            RawMessage::new(15, "user@host: ~".as_bytes().to_vec()).unwrap()
+        );
+
+        let deserialised = ShellServerMessage::deserialise(&serialised).unwrap();
+
+        assert_eq!(message, deserialised);
+    }
+
+    #[test]
+    fn test_server_serialise_detached() {
+        let message = ShellServerMessage::Detached("token".to_owned());
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(
+            serialised,
+            RawMessage::new(7, "token".as_bytes().to_vec()).unwrap()
+        );
+
+        let deserialised = ShellServerMessage::deserialise(&serialised).unwrap();
+
+        assert_eq!(message, deserialised);
+    }
+
+    #[test]
+    fn test_server_serialise_close() {
+        let message = ShellServerMessage::Close {
+            reason: "shell exited".to_owned(),
+        };
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(
+            serialised,
+            RawMessage::new(9, "shell exited".as_bytes().to_vec()).unwrap()
+        );
+
+        let deserialised = ShellServerMessage::deserialise(&serialised).unwrap();
+
+        assert_eq!(message, deserialised);
+    }
+
+    #[test]
+    fn test_window_size_clamped() {
+        assert_eq!(WindowSize(100, 50).clamped(), WindowSize(100, 50));
+        assert_eq!(WindowSize(0, 0).clamped(), WindowSize(1, 1));
+        assert_eq!(
+            WindowSize(u16::MAX, MAX_WINDOW_DIMENSION + 1).clamped(),
+            WindowSize(MAX_WINDOW_DIMENSION, MAX_WINDOW_DIMENSION)
+        );
+    }
+
     #[test]
     fn test_server_serialise_exited() {
-        let message = ShellServerMessage::Exited(5);
+        let message = ShellServerMessage::Exited(ExitStatus::exited(5));
         let serialised = message.serialise().unwrap();
 
-        assert_eq!(serialised, RawMessage::new(4, vec![5]).unwrap());
+        let mut expected = vec![5];
+        expected.extend_from_slice("{\"code\":5,\"signal\":null}".as_bytes());
+        assert_eq!(serialised, RawMessage::new(4, expected).unwrap());
 
         let deserialised = ShellServerMessage::deserialise(&serialised).unwrap();
 
         assert_eq!(message, deserialised);
     }
 
+    #[test]
+    fn test_server_serialise_exited_by_signal() {
+        let message = ShellServerMessage::Exited(ExitStatus::signalled(11));
+        let serialised = message.serialise().unwrap();
+
+        let mut expected = vec![139];
+        expected.extend_from_slice("{\"code\":null,\"signal\":11}".as_bytes());
+        assert_eq!(serialised, RawMessage::new(4, expected).unwrap());
+
+        let deserialised = ShellServerMessage::deserialise(&serialised).unwrap();
+
+        assert_eq!(message, deserialised);
+    }
+
+    #[test]
+    fn test_server_deserialise_exited_code_only() {
+        // Sent by older servers
+        let serialised = RawMessage::new(4, vec![2]).unwrap();
+
+        assert_eq!(
+            ShellServerMessage::deserialise(&serialised).unwrap(),
+            ShellServerMessage::Exited(ExitStatus::exited(2))
+        );
+
+        ShellServerMessage::deserialise(&RawMessage::new(4, vec![]).unwrap()).unwrap_err();
+    }
+
+    #[test]
+    fn test_exit_status_process_exit_code() {
+        assert_eq!(ExitStatus::exited(0).process_exit_code(), 0);
+        assert_eq!(ExitStatus::exited(2).process_exit_code(), 2);
+        assert_eq!(ExitStatus::signalled(9).process_exit_code(), 137);
+        assert_eq!(ExitStatus::unknown().process_exit_code(), 255);
+    }
+
     #[test]
     fn test_server_serialise_error() {
-        let message = ShellServerMessage::Error("test".to_owned());
+        let message = ShellServerMessage::fatal("test");
         let serialised = message.serialise().unwrap();
 
         assert_eq!(
@@ -258,4 +1109,297 @@ mod tests {
 
         assert_eq!(message, deserialised);
     }
+
+    #[test]
+    fn test_server_serialise_warning() {
+        let message = ShellServerMessage::warning("test");
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(
+            serialised,
+            RawMessage::new(8, "test".as_bytes().to_vec()).unwrap()
+        );
+
+        let deserialised = ShellServerMessage::deserialise(&serialised).unwrap();
+
+        assert_eq!(message, deserialised);
+    }
+
+    #[test]
+    fn test_client_serialise_hello() {
+        let message = ShellClientMessage::Hello(HelloPayload {
+            protocol_version: 2,
+            format: MessageFormat::Binary,
+            key_proof: false,
+            sequence_numbers: false,
+            frame_version: FrameVersion::V1,
+        });
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(
+            serialised,
+            RawMessage::new(5, "{\"protocol_version\":2}".as_bytes().to_vec()).unwrap()
+        );
+
+        let deserialised = ShellClientMessage::deserialise(&serialised).unwrap();
+
+        assert_eq!(message, deserialised);
+    }
+
+    #[test]
+    fn test_client_serialise_hello_with_key_proof() {
+        let message = ShellClientMessage::Hello(HelloPayload {
+            protocol_version: 2,
+            format: MessageFormat::Binary,
+            key_proof: true,
+            sequence_numbers: false,
+            frame_version: FrameVersion::V1,
+        });
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(
+            serialised,
+            RawMessage::new(
+                5,
+                "{\"protocol_version\":2,\"key_proof\":true}"
+                    .as_bytes()
+                    .to_vec()
+            )
+            .unwrap()
+        );
+
+        let deserialised = ShellClientMessage::deserialise(&serialised).unwrap();
+
+        assert_eq!(message, deserialised);
+    }
+
+    #[test]
+    fn test_server_serialise_hello_ack() {
+        let message = ShellServerMessage::HelloAck(HelloAckPayload {
+            protocol_version: 2,
+            accepted: true,
+            capabilities: Capabilities::PTY | Capabilities::PIPE,
+            format: MessageFormat::Binary,
+        });
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(
+            serialised,
+            RawMessage::new(
+                5,
+                "{\"protocol_version\":2,\"accepted\":true,\"capabilities\":3}"
+                    .as_bytes()
+                    .to_vec()
+            )
+            .unwrap()
+        );
+
+        let deserialised = ShellServerMessage::deserialise(&serialised).unwrap();
+
+        assert_eq!(message, deserialised);
+    }
+
+    fn all_client_messages() -> Vec<ShellClientMessage> {
+        vec![
+            ShellClientMessage::Hello(HelloPayload {
+                protocol_version: 2,
+                format: MessageFormat::Json,
+                key_proof: true,
+                sequence_numbers: true,
+                frame_version: FrameVersion::V2,
+            }),
+            ShellClientMessage::Key("key".to_owned()),
+            ShellClientMessage::StartShell(StartShellPayload {
+                term: "xterm".to_owned(),
+                size: Some(WindowSize(80, 24)),
+                pty: true,
+                login: false,
+                interactive: true,
+                raw: false,
+                shell_path: Some("/bin/sh".to_owned()),
+                colors: Some(256),
+                truecolor: true,
+                line_mode: false,
+                locale: None,
+                timezone: None,
+                titles: false,
+            }),
+            ShellClientMessage::Stdin(vec![0, 1, 2, 255]),
+            ShellClientMessage::Resize(WindowSize(100, 50)),
+            ShellClientMessage::Signal(15),
+            ShellClientMessage::StdinClose,
+            ShellClientMessage::Detach,
+            ShellClientMessage::GetCwd,
+            ShellClientMessage::SetCwd("/tmp".to_owned()),
+            ShellClientMessage::ListShells,
+            ShellClientMessage::Paste(b"echo one\n".to_vec()),
+            ShellClientMessage::KeyProof(vec![0, 1, 2, 255]),
+            ShellClientMessage::SystemInfo,
+            ShellClientMessage::Reattach(ReattachPayload {
+                token: "token".to_owned(),
+                size: Some(WindowSize(80, 24)),
+            }),
+            ShellClientMessage::Error("error".to_owned()),
+            ShellClientMessage::Unknown(100),
+        ]
+    }
+
+    fn all_server_messages() -> Vec<ShellServerMessage> {
+        vec![
+            ShellServerMessage::HelloAck(HelloAckPayload {
+                protocol_version: 2,
+                accepted: true,
+                capabilities: Capabilities::PTY,
+                format: MessageFormat::Json,
+            }),
+            ShellServerMessage::KeyAccepted,
+            ShellServerMessage::KeyRejected,
+            ShellServerMessage::Stdout(Bytes::from(vec![0, 1, 2, 255])),
+            ShellServerMessage::Exited(ExitStatus::exited(1)),
+            ShellServerMessage::Exited(ExitStatus::signalled(11)),
+            ShellServerMessage::SizeApplied(WindowSize(80, 24)),
+            ShellServerMessage::Detached("token".to_owned()),
+            ShellServerMessage::warning("warning"),
+            ShellServerMessage::Close {
+                reason: "shell exited".to_owned(),
+            },
+            ShellServerMessage::Cwd("/tmp".to_owned()),
+            ShellServerMessage::Shells(vec!["/bin/sh".to_owned()]),
+            ShellServerMessage::Ping,
+            ShellServerMessage::Challenge(vec![0, 1, 2, 255]),
+            ShellServerMessage::SystemInfo(SystemInfoPayload {
+                hostname: Some("host".to_owned()),
+                os: "linux".to_owned(),
+                arch: "x86_64".to_owned(),
+                kernel_version: None,
+                load_average: Some((0.5, 0.25, 0.125)),
+                uptime_secs: Some(3600),
+            }),
+            ShellServerMessage::Title("user@host: ~".to_owned()),
+            ShellServerMessage::fatal("error"),
+        ]
+    }
+
+    #[test]
+    fn test_client_messages_round_trip_in_each_format() {
+        for format in &[MessageFormat::Binary, MessageFormat::Json] {
+            for message in all_client_messages() {
+                let serialised = message.serialise_as(*format).unwrap();
+
+                assert_eq!(serialised.type_id(), message.type_id());
+                assert_eq!(
+                    ShellClientMessage::deserialise_as(&serialised, *format).unwrap(),
+                    message,
+                    "{:?} round trip",
+                    format
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_server_messages_round_trip_in_each_format() {
+        for format in &[MessageFormat::Binary, MessageFormat::Json] {
+            for message in all_server_messages() {
+                let serialised = message.serialise_as(*format).unwrap();
+
+                assert_eq!(serialised.type_id(), message.type_id());
+                assert_eq!(
+                    ShellServerMessage::deserialise_as(&serialised, *format).unwrap(),
+                    message,
+                    "{:?} round trip",
+                    format
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_messages_round_trip_in_each_frame_version() {
+        Runtime::new().unwrap().block_on(async {
+            for version in &[FrameVersion::V1, FrameVersion::V2] {
+                let mut client = ShellClientStream::new(Cursor::new(vec![]));
+                let mut server = ShellServerStream::new(Cursor::new(vec![]));
+                client.set_frame_version(*version);
+                server.set_frame_version(*version);
+
+                for message in all_client_messages() {
+                    client.write(&message).await.unwrap();
+                }
+                for message in all_server_messages() {
+                    server.write(&message).await.unwrap();
+                }
+
+                let mut from_client =
+                    ShellServerStream::new(FragmentedStream::new(client.into_inner().into_inner()));
+                let mut from_server =
+                    ShellClientStream::new(FragmentedStream::new(server.into_inner().into_inner()));
+                from_client.set_frame_version(*version);
+                from_server.set_frame_version(*version);
+
+                assert_eq!(
+                    from_client.map(|i| i.unwrap()).collect::<Vec<_>>().await,
+                    all_client_messages(),
+                    "{:?} round trip",
+                    version
+                );
+                assert_eq!(
+                    from_server.map(|i| i.unwrap()).collect::<Vec<_>>().await,
+                    all_server_messages(),
+                    "{:?} round trip",
+                    version
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn test_serialise_json() {
+        let serialised = ShellServerMessage::Stdout(Bytes::from(vec![1, 2]))
+            .serialise_json()
+            .unwrap();
+
+        assert_eq!(
+            serialised,
+            RawMessage::new(3, "{\"Stdout\":[1,2]}".as_bytes().to_vec()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_deserialise_json_mismatched_type_id() {
+        let raw_message = RawMessage::new(1, "{\"Cwd\":\"/tmp\"}".as_bytes().to_vec()).unwrap();
+
+        ShellServerMessage::deserialise_json(&raw_message).unwrap_err();
+    }
+
+    #[test]
+    fn test_decode_json_messages() {
+        let messages = all_client_messages();
+        let data = messages
+            .iter()
+            .flat_map(|i| i.serialise_json().unwrap().to_vec())
+            .collect::<Vec<u8>>();
+
+        let mut stream = ShellServerStream::new(FragmentedStream::new(data));
+        stream.set_format(MessageFormat::Json);
+
+        let decoded: Vec<ShellClientMessage> = Runtime::new()
+            .unwrap()
+            .block_on(stream.map(|i| i.unwrap()).collect());
+
+        assert_eq!(decoded, messages);
+    }
+
+    #[test]
+    fn test_negotiate_protocol_version() {
+        assert_eq!(
+            negotiate_protocol_version(PROTOCOL_VERSION),
+            Some(PROTOCOL_VERSION)
+        );
+        assert_eq!(
+            negotiate_protocol_version(PROTOCOL_VERSION + 1),
+            Some(PROTOCOL_VERSION)
+        );
+        assert_eq!(negotiate_protocol_version(MIN_PROTOCOL_VERSION - 1), None);
+    }
 }
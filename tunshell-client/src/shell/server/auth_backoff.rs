@@ -0,0 +1,138 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// Failures from a peer are forgotten once none have been seen for this long
+const FAILURE_MEMORY: Duration = Duration::from_secs(10 * 60);
+
+/// The maximum number of peers whose failures are tracked, the least recent
+/// is forgotten when a new peer fails once the table is full
+const MAX_TRACKED_PEERS: usize = 1024;
+
+struct PeerFailures {
+    count: u32,
+    last_failure: Instant,
+}
+
+/// Counts the recent authentication failures of each peer address so that the delay
+/// before rejecting a peer can grow with its failures.
+/// Clones share the same table.
+#[derive(Clone, Default)]
+pub(super) struct AuthFailures {
+    peers: Arc<Mutex<HashMap<String, PeerFailures>>>,
+}
+
+impl AuthFailures {
+    /// Records a failure from the peer, returning its number of recent failures
+    pub(super) fn record(&self, peer: &str) -> u32 {
+        self.record_at(peer, Instant::now())
+    }
+
+    /// Forgets the peer's failures, such as after it has authenticated successfully
+    pub(super) fn clear(&self, peer: &str) {
+        self.peers.lock().unwrap().remove(peer);
+    }
+
+    fn record_at(&self, peer: &str, now: Instant) -> u32 {
+        let mut peers = self.peers.lock().unwrap();
+
+        if !peers.contains_key(peer) && peers.len() >= MAX_TRACKED_PEERS {
+            peers.retain(|_, i| now.saturating_duration_since(i.last_failure) < FAILURE_MEMORY);
+
+            if peers.len() >= MAX_TRACKED_PEERS {
+                let oldest = peers
+                    .iter()
+                    .min_by_key(|(_, i)| i.last_failure)
+                    .map(|(peer, _)| peer.clone());
+
+                if let Some(oldest) = oldest {
+                    peers.remove(&oldest);
+                }
+            }
+        }
+
+        let failures = peers.entry(peer.to_owned()).or_insert(PeerFailures {
+            count: 0,
+            last_failure: now,
+        });
+
+        if now.saturating_duration_since(failures.last_failure) >= FAILURE_MEMORY {
+            failures.count = 0;
+        }
+
+        failures.count = failures.count.saturating_add(1);
+        failures.last_failure = now;
+
+        failures.count
+    }
+}
+
+/// The delay before rejecting a peer, which doubles with each of its recent failures
+/// up to the maximum. Without a maximum every failure is delayed by the base delay.
+pub(super) fn auth_failure_delay(base: Duration, max: Option<Duration>, failures: u32) -> Duration {
+    let max = match max {
+        Some(max) => max,
+        None => return base,
+    };
+
+    let exponent = failures.saturating_sub(1).min(31);
+
+    base.checked_mul(1u32 << exponent).unwrap_or(max).min(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_failure_delay() {
+        let base = Duration::from_secs(1);
+        let max = Some(Duration::from_secs(30));
+
+        assert_eq!(auth_failure_delay(base, max, 1), Duration::from_secs(1));
+        assert_eq!(auth_failure_delay(base, max, 2), Duration::from_secs(2));
+        assert_eq!(auth_failure_delay(base, max, 5), Duration::from_secs(16));
+        assert_eq!(auth_failure_delay(base, max, 6), Duration::from_secs(30));
+        assert_eq!(
+            auth_failure_delay(base, max, u32::MAX),
+            Duration::from_secs(30)
+        );
+        assert_eq!(auth_failure_delay(base, None, 10), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_record_failures() {
+        let failures = AuthFailures::default();
+        let now = Instant::now();
+
+        assert_eq!(failures.record_at("1.2.3.4", now), 1);
+        assert_eq!(failures.record_at("1.2.3.4", now), 2);
+        assert_eq!(failures.record_at("5.6.7.8", now), 1);
+
+        // Failures are forgotten after a period without any
+        assert_eq!(failures.record_at("1.2.3.4", now + FAILURE_MEMORY), 1);
+
+        failures.clear("5.6.7.8");
+        assert_eq!(failures.record_at("5.6.7.8", now), 1);
+    }
+
+    #[test]
+    fn test_tracked_peers_are_bounded() {
+        let failures = AuthFailures::default();
+        let now = Instant::now();
+
+        for i in 0..MAX_TRACKED_PEERS {
+            failures.record_at(&format!("peer{}", i), now + Duration::from_millis(i as u64));
+        }
+
+        failures.record_at("new_peer", now + Duration::from_secs(1));
+
+        let peers = failures.peers.lock().unwrap();
+        assert_eq!(peers.len(), MAX_TRACKED_PEERS);
+        assert!(!peers.contains_key("peer0"));
+        assert!(peers.contains_key("new_peer"));
+    }
+}
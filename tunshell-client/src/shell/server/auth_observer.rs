@@ -0,0 +1,26 @@
+/// Details of a client which attempted to authenticate with the shell server
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthPeer {
+    /// The index of the key in the server's key set which the client
+    /// authenticated with, `None` if the key was rejected
+    pub key_index: Option<usize>,
+    /// The address of the client as seen by the relay server, if known
+    pub peer_addr: Option<String>,
+}
+
+/// Receives notifications when a client's key is accepted or rejected,
+/// allowing embedders to run custom logic such as auditing
+pub trait AuthObserver {
+    fn on_accepted(&self, peer: &AuthPeer);
+
+    fn on_rejected(&self, peer: &AuthPeer);
+}
+
+/// The default observer which ignores all events
+pub(crate) struct NoopAuthObserver;
+
+impl AuthObserver for NoopAuthObserver {
+    fn on_accepted(&self, _peer: &AuthPeer) {}
+
+    fn on_rejected(&self, _peer: &AuthPeer) {}
+}
@@ -0,0 +1,54 @@
+/// The values which can be substituted into the banner template
+pub(super) struct BannerContext<'a> {
+    pub(super) session_id: &'a str,
+    pub(super) peer_addr: Option<&'a str>,
+}
+
+/// Renders the banner template, replacing `{session_id}` and `{peer_addr}` with their values.
+/// Line endings are converted to CRLF so the banner displays correctly in a raw mode terminal.
+pub(super) fn render_banner(template: &str, context: &BannerContext) -> String {
+    let banner = template
+        .replace("{session_id}", context.session_id)
+        .replace("{peer_addr}", context.peer_addr.unwrap_or("unknown"))
+        .replace("\r\n", "\n");
+
+    let mut banner = banner.replace('\n', "\r\n");
+
+    if !banner.ends_with("\r\n") {
+        banner.push_str("\r\n");
+    }
+
+    banner
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_banner() {
+        let context = BannerContext {
+            session_id: "abc123",
+            peer_addr: Some("1.2.3.4"),
+        };
+
+        assert_eq!(
+            render_banner(
+                "Authorized use only\nsession {session_id} from {peer_addr}",
+                &context
+            ),
+            "Authorized use only\r\nsession abc123 from 1.2.3.4\r\n"
+        );
+        assert_eq!(render_banner("line\r\n", &context), "line\r\n");
+    }
+
+    #[test]
+    fn test_render_banner_without_peer_addr() {
+        let context = BannerContext {
+            session_id: "abc123",
+            peer_addr: None,
+        };
+
+        assert_eq!(render_banner("{peer_addr}", &context), "unknown\r\n");
+    }
+}
@@ -0,0 +1,478 @@
+use super::{
+    AuthObserver, NoDelay, NoShellAction, ResourceLimits, RunAs, SandboxConfig, ShellServer,
+    ShellServerConfig,
+};
+use anyhow::Result;
+use std::{sync::Arc, time::Duration};
+
+/// Configures and constructs a `ShellServer`, the settings are validated when built
+pub struct ShellServerBuilder {
+    config: ShellServerConfig,
+    auth_observer: Option<Arc<dyn AuthObserver + Send + Sync>>,
+}
+
+impl ShellServerBuilder {
+    pub fn new() -> Self {
+        Self {
+            config: ShellServerConfig::default(),
+            auth_observer: None,
+        }
+    }
+
+    /// Replaces all of the settings, such as with those parsed from the environment
+    pub(crate) fn config(mut self, config: ShellServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn max_session_duration(mut self, duration: Duration) -> Self {
+        self.config.max_session_duration = Some(duration);
+        self
+    }
+
+    pub fn max_concurrent_sessions(mut self, max: usize) -> Self {
+        self.config.max_concurrent_sessions = Some(max);
+        self
+    }
+
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.config.handshake_timeout = timeout;
+        self
+    }
+
+    /// `None` rejects incorrect keys immediately
+    pub fn auth_failure_delay(mut self, delay: Option<Duration>) -> Self {
+        self.config.auth_failure_delay = delay;
+        self
+    }
+
+    /// `None` disables the per-peer backoff
+    pub fn max_auth_failure_delay(mut self, delay: Option<Duration>) -> Self {
+        self.config.max_auth_failure_delay = delay;
+        self
+    }
+
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.config.idle_timeout = Some(timeout);
+        self
+    }
+
+    pub fn utf8_safe_output(mut self, enabled: bool) -> Self {
+        self.config.utf8_safe_output = enabled;
+        self
+    }
+
+    pub fn osc_safe_output(mut self, enabled: bool) -> Self {
+        self.config.osc_safe_output = enabled;
+        self
+    }
+
+    /// `None` writes each stdin message to the shell as it is received
+    pub fn stdin_coalesce_window(mut self, window: Option<Duration>) -> Self {
+        self.config.stdin_coalesce_window = window;
+        self
+    }
+
+    pub fn output_flush_interval(mut self, interval: Duration) -> Self {
+        self.config.output_flush_interval = interval;
+        self
+    }
+
+    pub fn log_payloads(mut self, enabled: bool) -> Self {
+        self.config.log_payloads = enabled;
+        self
+    }
+
+    pub fn banner(mut self, banner: &str) -> Self {
+        self.config.banner = Some(banner.to_owned());
+        self
+    }
+
+    pub fn resource_limits(mut self, limits: ResourceLimits) -> Self {
+        self.config.resource_limits = limits;
+        self
+    }
+
+    pub fn run_as(mut self, run_as: RunAs) -> Self {
+        self.config.run_as = Some(run_as);
+        self
+    }
+
+    pub fn umask(mut self, umask: u32) -> Self {
+        self.config.umask = Some(umask);
+        self
+    }
+
+    pub fn clean_env(mut self, enabled: bool) -> Self {
+        self.config.clean_env = enabled;
+        self
+    }
+
+    pub fn pty_pool_size(mut self, size: usize) -> Self {
+        self.config.pty_pool_size = Some(size);
+        self
+    }
+
+    pub fn pty_spawn_retries(mut self, retries: u32) -> Self {
+        self.config.pty_spawn_retries = retries;
+        self
+    }
+
+    pub fn max_output_bytes(mut self, max: u64) -> Self {
+        self.config.max_output_bytes = Some(max);
+        self
+    }
+
+    /// Zero is unlimited
+    pub fn max_bytes_per_sec(mut self, rate: u64) -> Self {
+        self.config.max_bytes_per_sec = Some(rate);
+        self
+    }
+
+    pub fn sandbox(mut self, sandbox: SandboxConfig) -> Self {
+        self.config.sandbox = Some(sandbox);
+        self
+    }
+
+    pub fn allowed_shells(mut self, shells: Vec<String>) -> Self {
+        self.config.allowed_shells = Some(shells);
+        self
+    }
+
+    pub fn shell_path(mut self, path: &str) -> Self {
+        self.config.shell_path = Some(path.to_owned());
+        self
+    }
+
+    pub fn fallback_on_missing_shell(mut self, enabled: bool) -> Self {
+        self.config.fallback_on_missing_shell = enabled;
+        self
+    }
+
+    pub fn detach_on_disconnect(mut self, enabled: bool) -> Self {
+        self.config.detach_on_disconnect = enabled;
+        self
+    }
+
+    pub fn detached_shell_ttl(mut self, ttl: Duration) -> Self {
+        self.config.detached_shell_ttl = ttl;
+        self
+    }
+
+    pub fn max_detached_shells(mut self, max: usize) -> Self {
+        self.config.max_detached_shells = max;
+        self
+    }
+
+    pub fn keepalive_interval(mut self, interval: Duration) -> Self {
+        self.config.keepalive_interval = Some(interval);
+        self
+    }
+
+    pub fn nodelay(mut self, nodelay: NoDelay) -> Self {
+        self.config.nodelay = nodelay;
+        self
+    }
+
+    /// `None` waits indefinitely for writes to the client
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.write_timeout = timeout;
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.config.read_timeout = Some(timeout);
+        self
+    }
+
+    pub fn pre_shell_hook(mut self, command: Vec<String>) -> Self {
+        self.config.pre_shell_hook = Some(command);
+        self
+    }
+
+    pub fn stream_pre_shell_hook_output(mut self, enabled: bool) -> Self {
+        self.config.stream_pre_shell_hook_output = enabled;
+        self
+    }
+
+    pub fn no_shell_action(mut self, action: NoShellAction) -> Self {
+        self.config.no_shell_action = Some(action);
+        self
+    }
+
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.config.max_message_size = size;
+        self
+    }
+
+    pub fn auth_observer(mut self, observer: Arc<dyn AuthObserver + Send + Sync>) -> Self {
+        self.auth_observer = Some(observer);
+        self
+    }
+
+    /// Returns an error if any of the settings are invalid or conflict with each other
+    pub fn build(self) -> Result<ShellServer> {
+        let server = ShellServer::with_config(self.config)?;
+
+        Ok(match self.auth_observer {
+            Some(observer) => server.with_auth_observer(observer),
+            None => server,
+        })
+    }
+}
+
+impl Default for ShellServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_default() {
+        let server = ShellServerBuilder::new().build().unwrap();
+
+        assert_eq!(server.config, ShellServerConfig::default());
+    }
+
+    #[test]
+    fn test_build_with_settings() {
+        let server = ShellServer::builder()
+            .max_session_duration(Duration::from_secs(3600))
+            .max_concurrent_sessions(5)
+            .handshake_timeout(Duration::from_secs(10))
+            .auth_failure_delay(Some(Duration::from_secs(2)))
+            .max_auth_failure_delay(None)
+            .idle_timeout(Duration::from_secs(600))
+            .utf8_safe_output(false)
+            .osc_safe_output(false)
+            .stdin_coalesce_window(None)
+            .output_flush_interval(Duration::from_millis(10))
+            .log_payloads(true)
+            .banner("Authorized use only")
+            .resource_limits(ResourceLimits {
+                cpu_seconds: Some(60),
+                address_space: None,
+                nofile: Some(256),
+            })
+            .run_as(RunAs {
+                uid: 1000,
+                gid: 1000,
+            })
+            .umask(0o027)
+            .clean_env(false)
+            .pty_pool_size(2)
+            .pty_spawn_retries(5)
+            .max_output_bytes(1024 * 1024 * 1024)
+            .max_bytes_per_sec(1024 * 1024)
+            .allowed_shells(vec!["/bin/sh".to_owned()])
+            .shell_path("/bin/bash")
+            .fallback_on_missing_shell(false)
+            .detach_on_disconnect(true)
+            .detached_shell_ttl(Duration::from_secs(60))
+            .max_detached_shells(2)
+            .keepalive_interval(Duration::from_secs(30))
+            .nodelay(NoDelay::Never)
+            .write_timeout(Some(Duration::from_secs(10)))
+            .read_timeout(Duration::from_secs(300))
+            .pre_shell_hook(vec!["/usr/local/bin/setup".to_owned()])
+            .stream_pre_shell_hook_output(true)
+            .no_shell_action(NoShellAction::MessageOnly)
+            .max_message_size(4096)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            server.config,
+            ShellServerConfig {
+                max_session_duration: Some(Duration::from_secs(3600)),
+                max_concurrent_sessions: Some(5),
+                handshake_timeout: Duration::from_secs(10),
+                auth_failure_delay: Some(Duration::from_secs(2)),
+                max_auth_failure_delay: None,
+                idle_timeout: Some(Duration::from_secs(600)),
+                utf8_safe_output: false,
+                osc_safe_output: false,
+                stdin_coalesce_window: None,
+                output_flush_interval: Duration::from_millis(10),
+                log_payloads: true,
+                banner: Some("Authorized use only".to_owned()),
+                resource_limits: ResourceLimits {
+                    cpu_seconds: Some(60),
+                    address_space: None,
+                    nofile: Some(256),
+                },
+                run_as: Some(RunAs {
+                    uid: 1000,
+                    gid: 1000,
+                }),
+                umask: Some(0o027),
+                clean_env: false,
+                pty_pool_size: Some(2),
+                pty_spawn_retries: 5,
+                max_output_bytes: Some(1024 * 1024 * 1024),
+                max_bytes_per_sec: Some(1024 * 1024),
+                sandbox: None,
+                allowed_shells: Some(vec!["/bin/sh".to_owned()]),
+                shell_path: Some("/bin/bash".to_owned()),
+                fallback_on_missing_shell: false,
+                detach_on_disconnect: true,
+                detached_shell_ttl: Duration::from_secs(60),
+                max_detached_shells: 2,
+                keepalive_interval: Some(Duration::from_secs(30)),
+                nodelay: NoDelay::Never,
+                write_timeout: Some(Duration::from_secs(10)),
+                read_timeout: Some(Duration::from_secs(300)),
+                pre_shell_hook: Some(vec!["/usr/local/bin/setup".to_owned()]),
+                stream_pre_shell_hook_output: true,
+                no_shell_action: Some(NoShellAction::MessageOnly),
+                max_message_size: 4096,
+            }
+        );
+    }
+
+    #[test]
+    fn test_build_with_conflicting_timeouts() {
+        ShellServer::builder()
+            .max_session_duration(Duration::from_secs(60))
+            .idle_timeout(Duration::from_secs(60))
+            .build()
+            .err()
+            .expect("idle timeout should be less than max session duration");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_build_with_sandbox() {
+        let server = ShellServer::builder()
+            .sandbox(SandboxConfig::default())
+            .build()
+            .unwrap();
+
+        assert_eq!(server.config.sandbox, Some(SandboxConfig::default()));
+    }
+
+    #[test]
+    fn test_build_with_auth_observer() {
+        let observer: Arc<dyn AuthObserver + Send + Sync> =
+            Arc::new(super::super::NoopAuthObserver);
+        let server = ShellServer::builder()
+            .auth_observer(Arc::clone(&observer))
+            .build()
+            .unwrap();
+
+        assert!(Arc::ptr_eq(&server.auth_observer, &observer));
+    }
+
+    #[test]
+    fn test_build_with_invalid_settings() {
+        ShellServer::builder()
+            .max_concurrent_sessions(0)
+            .build()
+            .err()
+            .expect("max concurrent sessions should be greater than zero");
+
+        ShellServer::builder()
+            .handshake_timeout(Duration::from_secs(0))
+            .build()
+            .err()
+            .expect("handshake timeout should be greater than zero");
+
+        ShellServer::builder()
+            .auth_failure_delay(Some(Duration::from_secs(0)))
+            .build()
+            .err()
+            .expect("auth failure delay should be greater than zero");
+
+        ShellServer::builder()
+            .auth_failure_delay(Some(Duration::from_secs(10)))
+            .max_auth_failure_delay(Some(Duration::from_secs(5)))
+            .build()
+            .err()
+            .expect("max auth failure delay should not be less than the auth failure delay");
+
+        ShellServer::builder()
+            .output_flush_interval(Duration::from_secs(0))
+            .build()
+            .err()
+            .expect("output flush interval should be greater than zero");
+
+        ShellServer::builder()
+            .resource_limits(ResourceLimits {
+                nofile: Some(0),
+                ..ResourceLimits::default()
+            })
+            .build()
+            .err()
+            .expect("resource limits should be greater than zero");
+
+        ShellServer::builder()
+            .umask(0o1000)
+            .build()
+            .err()
+            .expect("umask should be at most 777");
+
+        ShellServer::builder()
+            .pty_pool_size(0)
+            .build()
+            .err()
+            .expect("pty pool size should be greater than zero");
+
+        ShellServer::builder()
+            .max_output_bytes(0)
+            .build()
+            .err()
+            .expect("max output bytes should be greater than zero");
+
+        ShellServer::builder()
+            .detached_shell_ttl(Duration::from_secs(0))
+            .build()
+            .err()
+            .expect("detached shell ttl should be greater than zero");
+
+        ShellServer::builder()
+            .max_detached_shells(0)
+            .build()
+            .err()
+            .expect("max detached shells should be greater than zero");
+
+        ShellServer::builder()
+            .max_message_size(0)
+            .build()
+            .err()
+            .expect("max message size should be greater than zero");
+
+        ShellServer::builder()
+            .keepalive_interval(Duration::from_secs(0))
+            .build()
+            .err()
+            .expect("keepalive interval should be greater than zero");
+
+        ShellServer::builder()
+            .write_timeout(Some(Duration::from_secs(0)))
+            .build()
+            .err()
+            .expect("write timeout should be greater than zero");
+
+        ShellServer::builder()
+            .read_timeout(Duration::from_secs(0))
+            .build()
+            .err()
+            .expect("read timeout should be greater than zero");
+
+        ShellServer::builder()
+            .shell_path("")
+            .build()
+            .err()
+            .expect("shell path should not be empty");
+
+        ShellServer::builder()
+            .pre_shell_hook(vec![])
+            .build()
+            .err()
+            .expect("pre-shell hook should not be empty");
+    }
+}
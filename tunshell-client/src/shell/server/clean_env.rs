@@ -0,0 +1,115 @@
+use super::DefaultShell;
+#[cfg(not(unix))]
+use log::*;
+
+/// The variables passed through from the environment of this process when
+/// the shell's environment is cleared, TERM is set for each shell
+const CURATED_VARS: &[&str] = &["PATH", "HOME", "USER", "SHELL"];
+
+/// The PATH given to the shell if this process does not have one
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// Returns the curated variables from the environment of this process
+pub(super) fn curated_env() -> Vec<(String, String)> {
+    let mut env: Vec<(String, String)> = CURATED_VARS
+        .iter()
+        .filter_map(|name| {
+            std::env::var(name)
+                .ok()
+                .map(|value| ((*name).to_owned(), value))
+        })
+        .collect();
+
+    if !env.iter().any(|(name, _)| name == "PATH") {
+        env.push(("PATH".to_owned(), DEFAULT_PATH.to_owned()));
+    }
+
+    env
+}
+
+/// Wraps the program so that it is exec'd (via `env -i`) with only the curated variables,
+/// TERM and the program's own variables, rather than inheriting the whole environment of
+/// this process which may include secrets. TERM is passed through from the child as it is
+/// set when the shell is spawned. portable-pty has no way to clear the environment.
+#[cfg(unix)]
+pub(super) fn apply_clean_env(enabled: bool, program: DefaultShell) -> DefaultShell {
+    if !enabled {
+        return program;
+    }
+
+    let mut args = vec![
+        "-c".to_owned(),
+        "exec /usr/bin/env -i TERM=\"$TERM\" \"$@\"".to_owned(),
+        "tunshell".to_owned(),
+    ];
+    args.extend(
+        curated_env()
+            .into_iter()
+            .chain(program.env)
+            .map(|(name, value)| format!("{}={}", name, value)),
+    );
+    args.push(program.path);
+    args.extend(program.args);
+
+    DefaultShell {
+        path: "/bin/sh".to_owned(),
+        args,
+        env: vec![],
+    }
+}
+
+#[cfg(not(unix))]
+pub(super) fn apply_clean_env(enabled: bool, program: DefaultShell) -> DefaultShell {
+    if enabled {
+        warn!("clearing the shell's environment is not supported on this platform, ignoring");
+    }
+
+    program
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::super::{PipeShell, Shell};
+    use super::*;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn test_apply_clean_env_disabled() {
+        let program = DefaultShell::new("/bin/bash".to_owned());
+
+        assert_eq!(apply_clean_env(false, program.clone()), program);
+    }
+
+    #[test]
+    fn test_clean_env_hides_inherited_variables() {
+        Runtime::new().unwrap().block_on(async {
+            std::env::set_var("TUNSHELL_TEST_SECRET", "hunter2");
+
+            let program = DefaultShell {
+                path: "/usr/bin/env".to_owned(),
+                args: vec![],
+                env: vec![("COLORTERM".to_owned(), "truecolor".to_owned())],
+            };
+            let mut shell = PipeShell::with_command(apply_clean_env(true, program), false).unwrap();
+
+            let mut output = vec![];
+            let mut buff = [0u8; 1024];
+            loop {
+                match shell.read(&mut buff).await.unwrap() {
+                    0 => break,
+                    read => output.extend_from_slice(&buff[..read]),
+                }
+            }
+
+            let output = String::from_utf8(output).unwrap();
+            let names: Vec<&str> = output.lines().filter_map(|i| i.split('=').next()).collect();
+
+            assert!(!output.contains("TUNSHELL_TEST_SECRET"));
+            assert!(output.contains("TERM=dumb\n"));
+            assert!(output.contains("COLORTERM=truecolor\n"));
+            assert!(names
+                .iter()
+                .all(|i| ["COLORTERM", "HOME", "PATH", "SHELL", "TERM", "USER"].contains(i)));
+        });
+    }
+}
@@ -0,0 +1,61 @@
+use crate::shell::proto::StartShellPayload;
+
+/// Below this many colours programs cannot use the standard ANSI colours
+const MIN_ANSI_COLORS: u16 = 8;
+
+/// Returns the environment variables describing the colour support of the client's
+/// terminal, which programs check alongside `TERM` as it does not convey 24-bit colour
+pub(super) fn color_env(request: &StartShellPayload) -> Vec<(String, String)> {
+    let mut env = vec![];
+
+    if request.truecolor {
+        env.push(("COLORTERM".to_owned(), "truecolor".to_owned()));
+    }
+
+    if let Some(colors) = request.colors {
+        if colors < MIN_ANSI_COLORS {
+            // See https://no-color.org
+            env.push(("NO_COLOR".to_owned(), "1".to_owned()));
+        }
+    }
+
+    env
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::proto::WindowSize;
+
+    fn request(colors: Option<u16>, truecolor: bool) -> StartShellPayload {
+        StartShellPayload {
+            term: "xterm-256color".to_owned(),
+            size: Some(WindowSize(80, 24)),
+            pty: true,
+            login: true,
+            interactive: true,
+            raw: false,
+            shell_path: None,
+            colors,
+            truecolor,
+            line_mode: false,
+            locale: None,
+            timezone: None,
+            titles: false,
+        }
+    }
+
+    #[test]
+    fn test_color_env() {
+        assert_eq!(color_env(&request(None, false)), vec![]);
+        assert_eq!(color_env(&request(Some(256), false)), vec![]);
+        assert_eq!(
+            color_env(&request(Some(256), true)),
+            vec![("COLORTERM".to_owned(), "truecolor".to_owned())]
+        );
+        assert_eq!(
+            color_env(&request(Some(0), false)),
+            vec![("NO_COLOR".to_owned(), "1".to_owned())]
+        );
+    }
+}
@@ -0,0 +1,565 @@
+use super::{NoShellAction, ResourceLimits, RunAs, SandboxConfig};
+use anyhow::{Error, Result};
+use std::{env, fmt::Display, str::FromStr, time::Duration};
+use tunshell_shared::MAX_MESSAGE_SIZE;
+
+/// The prefix of the environment variables the shell server is configured with
+const VAR_PREFIX: &str = "TUNSHELL_SHELL_";
+
+/// When small writes to the client are sent immediately rather than being coalesced
+/// by the transport, such as by Nagle's algorithm on TCP
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoDelay {
+    /// Sent immediately unless the session is raw, raw sessions usually carry bulk data
+    /// which is sent more efficiently in fewer, larger packets
+    Interactive,
+    Always,
+    Never,
+}
+
+impl FromStr for NoDelay {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "interactive" => Ok(Self::Interactive),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(Error::msg(format!(
+                "unknown nodelay mode: {:?}, expected interactive, always or never",
+                value
+            ))),
+        }
+    }
+}
+
+impl NoDelay {
+    pub(super) fn enabled(self, raw: bool) -> bool {
+        match self {
+            NoDelay::Interactive => !raw,
+            NoDelay::Always => true,
+            NoDelay::Never => false,
+        }
+    }
+}
+
+/// Configuration for the shell server, use `ShellServerConfig::default()`
+/// for the standard behaviour
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ShellServerConfig {
+    /// The maximum duration of a shell session once the shell has started,
+    /// regardless of activity. `None` allows sessions to run indefinitely.
+    pub(crate) max_session_duration: Option<Duration>,
+    /// The maximum number of sessions which may run concurrently across clones
+    /// of the same server. `None` imposes no limit.
+    pub(crate) max_concurrent_sessions: Option<usize>,
+    /// The time to wait for each of the client's hello, key and shell request
+    /// messages before the connection is closed.
+    pub(crate) handshake_timeout: Duration,
+    /// Delays telling a client its key was rejected, slowing brute force attempts
+    /// against the key. Only the rejected connection is held up. `None` rejects immediately.
+    pub(crate) auth_failure_delay: Option<Duration>,
+    /// When the peer's address is known the failure delay doubles with each of its recent
+    /// failures, up to this maximum. `None` delays every failure by the same amount.
+    pub(crate) max_auth_failure_delay: Option<Duration>,
+    /// Terminates the shell when no input or output has been seen for this duration.
+    /// `None` allows sessions to remain idle indefinitely.
+    pub(crate) idle_timeout: Option<Duration>,
+    /// Holds back a trailing partial UTF-8 sequence from each chunk of output
+    /// so that chunks end on a character boundary. Disable for binary output.
+    pub(crate) utf8_safe_output: bool,
+    /// Holds back a trailing unterminated OSC escape sequence, such as an OSC 52
+    /// clipboard sequence, so that it is sent to the client in a single chunk.
+    pub(crate) osc_safe_output: bool,
+    /// Stdin received within this window is coalesced into a single write to the shell.
+    /// `None` writes each stdin message to the shell as it is received.
+    pub(crate) stdin_coalesce_window: Option<Duration>,
+    /// Output held back by the UTF-8 or OSC safe output is sent as is once it has been
+    /// pending for this long, so a prompt is not left waiting for output which may not come.
+    /// The timer only runs while output is held back.
+    pub(crate) output_flush_interval: Duration,
+    /// Logs message contents and byte counts of shell input and output at info level.
+    /// When disabled only message types and byte counts are logged, at debug level,
+    /// so that input such as passwords does not end up in the logs.
+    pub(crate) log_payloads: bool,
+    /// Sent to the client once the shell has started, before any of its output.
+    /// `{session_id}` and `{peer_addr}` are replaced with their values for the session.
+    pub(crate) banner: Option<String>,
+    /// Limits on the resources used by the shell. When any limit is set the
+    /// server will not fall back to the in-built shell as the limits cannot be applied to it.
+    pub(crate) resource_limits: ResourceLimits,
+    /// The user the shell is run as, if the privileges cannot be dropped
+    /// the shell will not be started
+    pub(crate) run_as: Option<RunAs>,
+    /// The umask the shell is started with, which sets the default permissions of the
+    /// files created during the session. `None` inherits the umask of this process.
+    /// Only supported on unix, the server will not fall back to the in-built shell when set.
+    pub(crate) umask: Option<u32>,
+    /// Starts the shell with only PATH, HOME, USER, SHELL and TERM from the environment of
+    /// this process, along with the variables set for the session, so that secrets in this
+    /// process's environment are not exposed to the shell. Only supported on unix.
+    pub(crate) clean_env: bool,
+    /// The number of ptys opened ahead of time so that pty shells start without
+    /// waiting for a pty to be allocated. `None` opens a pty for each shell.
+    pub(crate) pty_pool_size: Option<usize>,
+    /// The number of times to retry allocating a pty after a transient failure,
+    /// such as the system running out of ptys, before falling back to another shell
+    pub(crate) pty_spawn_retries: u32,
+    /// Terminates the shell once the total output sent to the client exceeds this many bytes,
+    /// stopping a runaway command from streaming forever. `None` is unlimited.
+    pub(crate) max_output_bytes: Option<u64>,
+    /// Limits the rate at which shell output is sent to the client, smoothing bursts.
+    /// The shell's output is not read while throttled. `None` or zero is unlimited.
+    pub(crate) max_bytes_per_sec: Option<u64>,
+    /// Runs the shell in new namespaces so it is isolated from the host's processes,
+    /// only supported on linux
+    pub(crate) sandbox: Option<SandboxConfig>,
+    /// The shells the client may select, only those which exist and are executable
+    /// are offered. `None` offers the shells listed in /etc/shells.
+    pub(crate) allowed_shells: Option<Vec<String>>,
+    /// The shell started when the client does not select one.
+    /// `None` uses the user's login shell.
+    pub(crate) shell_path: Option<String>,
+    /// Falls back to the in-built shell when the shell's program does not exist, otherwise
+    /// the session ends with an error naming the missing path so a bad path is noticed
+    pub(crate) fallback_on_missing_shell: bool,
+    /// Detaches the shell when the client can no longer be written to, rather than
+    /// terminating it, so that it can be reattached using the session's reconnect token
+    pub(crate) detach_on_disconnect: bool,
+    /// Detached shells which have not been reattached within this time are terminated
+    pub(crate) detached_shell_ttl: Duration,
+    /// The most shells which may be detached at once across clones of the same server,
+    /// further shells are terminated rather than detached
+    pub(crate) max_detached_shells: usize,
+    /// Sends a ping to the client when no input or output has been seen for this duration,
+    /// keeping the connection from being dropped by idle timeouts in NATs and proxies.
+    /// `None` disables keepalives.
+    pub(crate) keepalive_interval: Option<Duration>,
+    /// Whether writes to the client are sent immediately, so that keystrokes are echoed
+    /// without waiting for the transport to coalesce them. Only applies to TCP connections.
+    pub(crate) nodelay: NoDelay,
+    /// Ends the session when a write to the client makes no progress for this long, such as
+    /// over a wedged connection, rather than holding the shell open forever.
+    /// `None` waits indefinitely.
+    pub(crate) write_timeout: Option<Duration>,
+    /// Ends the session when no message is received from the client for this long.
+    /// Clients send nothing while the user is inactive so this is disabled by default.
+    pub(crate) read_timeout: Option<Duration>,
+    /// A command run to completion before each shell is started, such as a setup script.
+    /// The session is aborted if it exits with a non-zero status.
+    pub(crate) pre_shell_hook: Option<Vec<String>>,
+    /// Sends the output of the pre-shell hook to the client, otherwise it is only logged
+    pub(crate) stream_pre_shell_hook_output: bool,
+    /// What to do when neither a pty nor a pipe shell can be started, for hosts where the
+    /// in-built shell cannot run a useful session. `None` falls back to the in-built shell.
+    pub(crate) no_shell_action: Option<NoShellAction>,
+    /// Messages from the client with a longer payload are rejected and the session ended
+    pub(crate) max_message_size: usize,
+}
+
+impl Default for ShellServerConfig {
+    fn default() -> Self {
+        Self {
+            max_session_duration: None,
+            max_concurrent_sessions: None,
+            handshake_timeout: Duration::from_millis(3000),
+            auth_failure_delay: Some(Duration::from_secs(1)),
+            max_auth_failure_delay: Some(Duration::from_secs(30)),
+            idle_timeout: None,
+            utf8_safe_output: true,
+            osc_safe_output: true,
+            stdin_coalesce_window: Some(Duration::from_millis(2)),
+            output_flush_interval: Duration::from_millis(20),
+            log_payloads: false,
+            banner: None,
+            resource_limits: ResourceLimits::default(),
+            run_as: None,
+            umask: None,
+            clean_env: true,
+            pty_pool_size: None,
+            pty_spawn_retries: 2,
+            max_output_bytes: None,
+            max_bytes_per_sec: None,
+            sandbox: None,
+            allowed_shells: None,
+            shell_path: None,
+            fallback_on_missing_shell: true,
+            detach_on_disconnect: false,
+            detached_shell_ttl: Duration::from_secs(10 * 60),
+            max_detached_shells: 4,
+            keepalive_interval: None,
+            nodelay: NoDelay::Interactive,
+            write_timeout: Some(Duration::from_secs(60)),
+            read_timeout: None,
+            pre_shell_hook: None,
+            stream_pre_shell_hook_output: false,
+            no_shell_action: None,
+            max_message_size: MAX_MESSAGE_SIZE,
+        }
+    }
+}
+
+impl ShellServerConfig {
+    /// Parses the config from the `TUNSHELL_SHELL_*` environment variables,
+    /// the default is used for each variable which is not set
+    pub(crate) fn from_env() -> Result<Self> {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    /// Parses the config using `var` to look up each variable by name.
+    /// Durations are in milliseconds and settings which are enabled by default
+    /// are disabled with "none".
+    pub(crate) fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let vars = Vars(var);
+        let default = Self::default();
+
+        let config = Self {
+            max_session_duration: vars
+                .optional_millis("MAX_SESSION_DURATION_MS", default.max_session_duration)?,
+            max_concurrent_sessions: vars
+                .optional("MAX_CONCURRENT_SESSIONS", default.max_concurrent_sessions)?,
+            handshake_timeout: vars.millis("HANDSHAKE_TIMEOUT_MS", default.handshake_timeout)?,
+            auth_failure_delay: vars
+                .optional_millis("AUTH_FAILURE_DELAY_MS", default.auth_failure_delay)?,
+            max_auth_failure_delay: vars
+                .optional_millis("MAX_AUTH_FAILURE_DELAY_MS", default.max_auth_failure_delay)?,
+            idle_timeout: vars.optional_millis("IDLE_TIMEOUT_MS", default.idle_timeout)?,
+            utf8_safe_output: vars.flag("UTF8_SAFE_OUTPUT", default.utf8_safe_output)?,
+            osc_safe_output: vars.flag("OSC_SAFE_OUTPUT", default.osc_safe_output)?,
+            stdin_coalesce_window: vars
+                .optional_millis("STDIN_COALESCE_WINDOW_MS", default.stdin_coalesce_window)?,
+            output_flush_interval: vars
+                .millis("OUTPUT_FLUSH_INTERVAL_MS", default.output_flush_interval)?,
+            log_payloads: vars.flag("LOG_PAYLOADS", default.log_payloads)?,
+            banner: vars.get("BANNER"),
+            resource_limits: ResourceLimits {
+                cpu_seconds: vars.optional("CPU_SECONDS", None)?,
+                address_space: vars.optional("ADDRESS_SPACE", None)?,
+                nofile: vars.optional("NOFILE", None)?,
+            },
+            run_as: vars.get("RUN_AS").map(|name| run_as(&name)).transpose()?,
+            umask: vars
+                .get("UMASK")
+                .map(|umask| {
+                    u32::from_str_radix(&umask, 8).map_err(|err| vars.invalid("UMASK", err))
+                })
+                .transpose()?,
+            clean_env: vars.flag("CLEAN_ENV", default.clean_env)?,
+            pty_pool_size: vars.optional("PTY_POOL_SIZE", default.pty_pool_size)?,
+            pty_spawn_retries: vars.parse("PTY_SPAWN_RETRIES", default.pty_spawn_retries)?,
+            max_output_bytes: vars.optional("MAX_OUTPUT_BYTES", default.max_output_bytes)?,
+            max_bytes_per_sec: vars.optional("MAX_BYTES_PER_SEC", default.max_bytes_per_sec)?,
+            sandbox: vars.optional("SANDBOX", default.sandbox)?,
+            allowed_shells: vars.list("ALLOWED_SHELLS"),
+            shell_path: vars.get("PATH"),
+            fallback_on_missing_shell: vars.flag(
+                "FALLBACK_ON_MISSING_SHELL",
+                default.fallback_on_missing_shell,
+            )?,
+            detach_on_disconnect: vars
+                .flag("DETACH_ON_DISCONNECT", default.detach_on_disconnect)?,
+            detached_shell_ttl: vars.millis("DETACHED_SHELL_TTL_MS", default.detached_shell_ttl)?,
+            max_detached_shells: vars.parse("MAX_DETACHED_SHELLS", default.max_detached_shells)?,
+            keepalive_interval: vars
+                .optional_millis("KEEPALIVE_INTERVAL_MS", default.keepalive_interval)?,
+            nodelay: vars.parse("NODELAY", default.nodelay)?,
+            write_timeout: vars.optional_millis("WRITE_TIMEOUT_MS", default.write_timeout)?,
+            read_timeout: vars.optional_millis("READ_TIMEOUT_MS", default.read_timeout)?,
+            pre_shell_hook: vars
+                .get("PRE_SHELL_HOOK")
+                .map(|i| i.split_whitespace().map(|i| i.to_owned()).collect()),
+            stream_pre_shell_hook_output: vars.flag(
+                "STREAM_PRE_SHELL_HOOK_OUTPUT",
+                default.stream_pre_shell_hook_output,
+            )?,
+            no_shell_action: vars.optional("NO_SHELL_ACTION", default.no_shell_action)?,
+            max_message_size: vars.parse("MAX_MESSAGE_SIZE", default.max_message_size)?,
+        };
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Returns an error if any of the settings are invalid or conflict with each other
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.handshake_timeout == Duration::from_secs(0) {
+            return Err(Error::msg("handshake timeout must be greater than zero"));
+        }
+
+        if self.auth_failure_delay == Some(Duration::from_secs(0)) {
+            return Err(Error::msg("auth failure delay must be greater than zero"));
+        }
+
+        if let (Some(delay), Some(max_delay)) =
+            (self.auth_failure_delay, self.max_auth_failure_delay)
+        {
+            if max_delay < delay {
+                return Err(Error::msg(format!(
+                    "max auth failure delay ({:?}) must not be less than the auth failure delay ({:?})",
+                    max_delay, delay
+                )));
+            }
+        }
+
+        if self.output_flush_interval == Duration::from_secs(0) {
+            return Err(Error::msg(
+                "output flush interval must be greater than zero",
+            ));
+        }
+
+        if self.max_concurrent_sessions == Some(0) {
+            return Err(Error::msg(
+                "max concurrent sessions must be greater than zero",
+            ));
+        }
+
+        if self.max_session_duration == Some(Duration::from_secs(0)) {
+            return Err(Error::msg("max session duration must be greater than zero"));
+        }
+
+        if self.idle_timeout == Some(Duration::from_secs(0)) {
+            return Err(Error::msg("idle timeout must be greater than zero"));
+        }
+
+        if self.detached_shell_ttl == Duration::from_secs(0) {
+            return Err(Error::msg("detached shell ttl must be greater than zero"));
+        }
+
+        if self.max_detached_shells == 0 {
+            return Err(Error::msg("max detached shells must be greater than zero"));
+        }
+
+        if self.keepalive_interval == Some(Duration::from_secs(0)) {
+            return Err(Error::msg("keepalive interval must be greater than zero"));
+        }
+
+        if self.shell_path.as_ref().is_some_and(|i| i.is_empty()) {
+            return Err(Error::msg("shell path cannot be empty"));
+        }
+
+        if self.write_timeout == Some(Duration::from_secs(0)) {
+            return Err(Error::msg("write timeout must be greater than zero"));
+        }
+
+        if self.read_timeout == Some(Duration::from_secs(0)) {
+            return Err(Error::msg("read timeout must be greater than zero"));
+        }
+
+        if self.pre_shell_hook.as_ref().is_some_and(|i| i.is_empty()) {
+            return Err(Error::msg("pre-shell hook command cannot be empty"));
+        }
+
+        if self.umask.is_some_and(|i| i > 0o777) {
+            return Err(Error::msg("umask must be between 000 and 777"));
+        }
+
+        if self.max_output_bytes == Some(0) {
+            return Err(Error::msg("max output bytes must be greater than zero"));
+        }
+
+        if self.max_message_size == 0 || self.max_message_size > MAX_MESSAGE_SIZE {
+            return Err(Error::msg(format!(
+                "max message size must be between 1 and {}",
+                MAX_MESSAGE_SIZE
+            )));
+        }
+
+        if self.pty_pool_size == Some(0) {
+            return Err(Error::msg("pty pool size must be greater than zero"));
+        }
+
+        let limits = &self.resource_limits;
+        if [limits.cpu_seconds, limits.address_space, limits.nofile].contains(&Some(0)) {
+            return Err(Error::msg("resource limits must be greater than zero"));
+        }
+
+        if let Some(sandbox) = self.sandbox.as_ref() {
+            sandbox.validate()?;
+        }
+
+        if let (Some(idle_timeout), Some(max_duration)) =
+            (self.idle_timeout, self.max_session_duration)
+        {
+            if idle_timeout >= max_duration {
+                return Err(Error::msg(format!(
+                    "idle timeout ({:?}) must be less than the max session duration ({:?})",
+                    idle_timeout, max_duration
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn run_as(name: &str) -> Result<RunAs> {
+    RunAs::from_username(name)
+}
+
+#[cfg(not(unix))]
+fn run_as(_name: &str) -> Result<RunAs> {
+    Err(Error::msg(
+        "running the shell as another user is only supported on unix",
+    ))
+}
+
+/// Looks up the variables by their name without the `TUNSHELL_SHELL_` prefix
+struct Vars<F: Fn(&str) -> Option<String>>(F);
+
+impl<F: Fn(&str) -> Option<String>> Vars<F> {
+    fn get(&self, name: &str) -> Option<String> {
+        (self.0)(&format!("{}{}", VAR_PREFIX, name)).filter(|i| !i.is_empty())
+    }
+
+    fn invalid(&self, name: &str, err: impl Display) -> Error {
+        Error::msg(format!("invalid value for {}{}: {}", VAR_PREFIX, name, err))
+    }
+
+    fn parse<T: FromStr>(&self, name: &str, default: T) -> Result<T>
+    where
+        T::Err: Display,
+    {
+        match self.get(name) {
+            Some(value) => value.parse().map_err(|err| self.invalid(name, err)),
+            None => Ok(default),
+        }
+    }
+
+    fn optional<T: FromStr>(&self, name: &str, default: Option<T>) -> Result<Option<T>>
+    where
+        T::Err: Display,
+    {
+        match self.get(name) {
+            Some(value) if value == "none" => Ok(None),
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|err| self.invalid(name, err)),
+            None => Ok(default),
+        }
+    }
+
+    fn flag(&self, name: &str, default: bool) -> Result<bool> {
+        match self.get(name).as_deref() {
+            Some("1") | Some("true") => Ok(true),
+            Some("0") | Some("false") => Ok(false),
+            Some(value) => Err(self.invalid(name, format!("{:?} is not 0 or 1", value))),
+            None => Ok(default),
+        }
+    }
+
+    fn millis(&self, name: &str, default: Duration) -> Result<Duration> {
+        let millis = self.parse(name, default.as_millis() as u64)?;
+
+        Ok(Duration::from_millis(millis))
+    }
+
+    fn optional_millis(&self, name: &str, default: Option<Duration>) -> Result<Option<Duration>> {
+        let millis = self.optional(name, default.map(|i| i.as_millis() as u64))?;
+
+        Ok(millis.map(Duration::from_millis))
+    }
+
+    fn list(&self, name: &str) -> Option<Vec<String>> {
+        self.get(name)
+            .map(|value| value.split(',').map(|i| i.trim().to_owned()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn vars(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (format!("{}{}", VAR_PREFIX, name), (*value).to_owned()))
+            .collect();
+
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_config_from_no_vars() {
+        assert_eq!(
+            ShellServerConfig::from_vars(vars(&[])).unwrap(),
+            ShellServerConfig::default()
+        );
+    }
+
+    #[test]
+    fn test_config_from_vars() {
+        let config = ShellServerConfig::from_vars(vars(&[
+            ("MAX_SESSION_DURATION_MS", "60000"),
+            ("MAX_CONCURRENT_SESSIONS", "2"),
+            ("AUTH_FAILURE_DELAY_MS", "none"),
+            ("MAX_AUTH_FAILURE_DELAY_MS", "none"),
+            ("UTF8_SAFE_OUTPUT", "0"),
+            ("LOG_PAYLOADS", "1"),
+            ("BANNER", "welcome"),
+            ("NOFILE", "256"),
+            ("UMASK", "027"),
+            ("ALLOWED_SHELLS", "/bin/sh, /bin/bash"),
+            ("PATH", "/bin/sh"),
+            ("DETACH_ON_DISCONNECT", "true"),
+            ("DETACHED_SHELL_TTL_MS", "1000"),
+            ("NODELAY", "always"),
+            ("WRITE_TIMEOUT_MS", "none"),
+            ("PRE_SHELL_HOOK", "/usr/local/bin/setup --quiet"),
+            ("NO_SHELL_ACTION", "message"),
+            ("MAX_MESSAGE_SIZE", "4096"),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            config,
+            ShellServerConfig {
+                max_session_duration: Some(Duration::from_secs(60)),
+                max_concurrent_sessions: Some(2),
+                auth_failure_delay: None,
+                max_auth_failure_delay: None,
+                utf8_safe_output: false,
+                log_payloads: true,
+                banner: Some("welcome".to_owned()),
+                resource_limits: ResourceLimits {
+                    nofile: Some(256),
+                    ..ResourceLimits::default()
+                },
+                umask: Some(0o027),
+                allowed_shells: Some(vec!["/bin/sh".to_owned(), "/bin/bash".to_owned()]),
+                shell_path: Some("/bin/sh".to_owned()),
+                detach_on_disconnect: true,
+                detached_shell_ttl: Duration::from_secs(1),
+                nodelay: NoDelay::Always,
+                write_timeout: None,
+                pre_shell_hook: Some(vec![
+                    "/usr/local/bin/setup".to_owned(),
+                    "--quiet".to_owned()
+                ]),
+                no_shell_action: Some(NoShellAction::MessageOnly),
+                max_message_size: 4096,
+                ..ShellServerConfig::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_config_from_invalid_vars() {
+        let err = ShellServerConfig::from_vars(vars(&[("MAX_DETACHED_SHELLS", "many")]))
+            .expect_err("non-numeric value should be rejected");
+        assert!(err
+            .to_string()
+            .starts_with("invalid value for TUNSHELL_SHELL_MAX_DETACHED_SHELLS"));
+
+        ShellServerConfig::from_vars(vars(&[("CLEAN_ENV", "yes")]))
+            .expect_err("flag should be 0 or 1");
+        ShellServerConfig::from_vars(vars(&[("NODELAY", "sometimes")]))
+            .expect_err("unknown nodelay mode should be rejected");
+        ShellServerConfig::from_vars(vars(&[("UMASK", "999")])).expect_err("umask should be octal");
+        ShellServerConfig::from_vars(vars(&[("HANDSHAKE_TIMEOUT_MS", "0")]))
+            .expect_err("config should be validated");
+    }
+}
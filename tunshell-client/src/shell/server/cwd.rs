@@ -0,0 +1,86 @@
+use anyhow::{Error, Result};
+
+/// Returns the current working directory of the process
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(super) fn process_cwd(pid: u32) -> Result<String> {
+    let path = std::fs::read_link(format!("/proc/{}/cwd", pid))
+        .map_err(|err| Error::new(err).context("failed to read working directory of process"))?;
+
+    path.to_str()
+        .map(|i| i.to_owned())
+        .ok_or_else(|| Error::msg("working directory is not valid UTF-8"))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(super) fn process_cwd(_pid: u32) -> Result<String> {
+    Err(Error::msg(
+        "the working directory of the shell cannot be determined on this platform",
+    ))
+}
+
+/// Returns the command which changes the shell's working directory to the path,
+/// the path is single-quoted so it is not expanded by the shell
+pub(super) fn cd_command(path: &str) -> Result<Vec<u8>> {
+    if path.is_empty() || path.contains(['\n', '\r', '\0']) {
+        return Err(Error::msg(format!("invalid working directory: {:?}", path)));
+    }
+
+    Ok(format!("cd '{}'\n", path.replace('\'', "'\\''")).into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cd_command() {
+        assert_eq!(cd_command("/tmp").unwrap(), b"cd '/tmp'\n".to_vec());
+        assert_eq!(
+            cd_command("/home/it's $HOME").unwrap(),
+            b"cd '/home/it'\\''s $HOME'\n".to_vec()
+        );
+
+        cd_command("").expect_err("empty path should be rejected");
+        cd_command("/tmp\nrm -rf /").expect_err("path containing a newline should be rejected");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_cwd_reported_after_cd() {
+        use super::super::{DefaultShell, PipeShell, Shell};
+        use std::time::Duration;
+        use tokio::runtime::Runtime;
+
+        Runtime::new().unwrap().block_on(async {
+            let mut shell = PipeShell::with_command(
+                DefaultShell {
+                    path: "/bin/sh".to_owned(),
+                    args: vec![],
+                    env: vec![],
+                },
+                true,
+            )
+            .unwrap();
+
+            assert_eq!(
+                shell.cwd().unwrap(),
+                std::env::current_dir().unwrap().to_str().unwrap()
+            );
+
+            shell.write(&cd_command("/").unwrap()).await.unwrap();
+
+            let mut cwd = String::new();
+            for _ in 0..100 {
+                cwd = shell.cwd().unwrap();
+                if cwd == "/" {
+                    break;
+                }
+
+                tokio::time::delay_for(Duration::from_millis(10)).await;
+            }
+
+            assert_eq!(cwd, "/");
+            shell.terminate().unwrap();
+        });
+    }
+}
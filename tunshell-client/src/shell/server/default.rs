@@ -2,23 +2,91 @@ use anyhow::{Context, Error, Result};
 use log::*;
 use std::path::PathBuf;
 
+/// Errors starting the shell program which are reported to the client
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub(super) enum ShellServerError {
+    #[error("shell not found: {path}")]
+    ShellNotFound { path: String },
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub(super) struct DefaultShell {
     pub(super) path: String,
     pub(super) args: Vec<String>,
+    /// Environment variables set for the program in addition to those it inherits
+    pub(super) env: Vec<(String, String)>,
+}
+
+/// Controls whether the shell is started as a login shell, which sources
+/// the user's profile, and whether it is started as an interactive shell
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(super) struct ShellInvocation {
+    pub(super) login: bool,
+    pub(super) interactive: bool,
+}
+
+impl Default for ShellInvocation {
+    fn default() -> Self {
+        Self {
+            login: true,
+            interactive: true,
+        }
+    }
 }
 
 impl DefaultShell {
     pub(super) fn new(path: String) -> Self {
-        Self { path, args: vec![] }
+        Self {
+            path,
+            args: vec![],
+            env: vec![],
+        }
+    }
+
+    /// Creates a program to run in place of the default shell from
+    /// a command and its arguments, such as a key's forced command
+    pub(super) fn from_command(command: &[String]) -> Result<Self> {
+        let (path, args) = command
+            .split_first()
+            .ok_or_else(|| Error::msg("command cannot be empty"))?;
+
+        Ok(Self {
+            path: path.clone(),
+            args: args.to_vec(),
+            env: vec![],
+        })
     }
 
+    /// Returns the arguments used to start the shell with the supplied invocation.
+    /// The login (-l) and interactive (-i) flags are not supported by cmd.exe
+    /// so they are ignored on windows.
+    pub(super) fn invocation_args(&self, invocation: ShellInvocation) -> Vec<String> {
+        let mut args = self.args.clone();
+
+        if self.file_name().map(|i| i == "cmd.exe").unwrap_or(false) {
+            return args;
+        }
+
+        if invocation.login {
+            args.push("-l".to_owned());
+        }
+
+        if invocation.interactive {
+            args.push("-i".to_owned());
+        }
+
+        args
+    }
+
+    /// Returns the lowercased file name of the program. The path is split on both
+    /// separators so that windows paths are recognised whichever platform parses them.
     fn file_name(&self) -> Result<String> {
-        let path = self.path.to_lowercase().parse::<PathBuf>()?;
-        let shell = path.file_name().ok_or_else(|| Error::msg("no file name"))?;
-        let shell = shell
-            .to_str()
-            .ok_or_else(|| Error::msg("invalid file name"))?;
+        let path = self.path.to_lowercase();
+        let shell = path.rsplit(['/', '\\']).next().unwrap_or("");
+
+        if shell.is_empty() {
+            return Err(Error::msg("no file name"));
+        }
 
         Ok(shell.to_owned())
     }
@@ -26,13 +94,13 @@ impl DefaultShell {
     pub(super) fn validate(&self) -> Result<()> {
         if !PathBuf::from(self.path.clone()).exists() {
             debug!("cannot find default shell program: {}", self.path);
-            return Err(Error::msg(format!(
-                "cannot find default shell program: {}",
-                self.path
-            )));
+            return Err(ShellServerError::ShellNotFound {
+                path: self.path.clone(),
+            }
+            .into());
         }
 
-        return Ok(());
+        Ok(())
     }
 
     pub(super) fn can_delegate(&self) -> bool {
@@ -64,25 +132,34 @@ impl DefaultShell {
 
 #[cfg(not(target_os = "windows"))]
 pub(super) fn get_default_shell(shell: Option<&str>) -> Result<DefaultShell> {
+    // A shell which was asked for by path is not substituted when it is missing
+    if let Some(path) = shell {
+        if !path.parse::<PathBuf>()?.exists() {
+            return Err(ShellServerError::ShellNotFound {
+                path: path.to_owned(),
+            }
+            .into());
+        }
+    }
+
     // Copied from portable_pty
-    let mut shell =
-        shell
-            .and_then(|i| Some(i.to_owned()))
-            .unwrap_or(std::env::var("SHELL").or_else(|_| {
-                let ent = unsafe { libc::getpwuid(libc::getuid()) };
-
-                if ent.is_null() {
-                    Ok("/bin/sh".into())
-                } else {
-                    use std::ffi::CStr;
-                    use std::str;
-                    let shell = unsafe { CStr::from_ptr((*ent).pw_shell) };
-                    shell
-                        .to_str()
-                        .map(str::to_owned)
-                        .context("failed to resolve shell")
-                }
-            })?);
+    let mut shell = shell
+        .map(|i| i.to_owned())
+        .unwrap_or(std::env::var("SHELL").or_else(|_| {
+            let ent = unsafe { libc::getpwuid(libc::getuid()) };
+
+            if ent.is_null() {
+                Ok("/bin/sh".into())
+            } else {
+                use std::ffi::CStr;
+                use std::str;
+                let shell = unsafe { CStr::from_ptr((*ent).pw_shell) };
+                shell
+                    .to_str()
+                    .map(str::to_owned)
+                    .context("failed to resolve shell")
+            }
+        })?);
 
     if !shell.parse::<PathBuf>()?.exists() || shell == "nologin" || shell.ends_with("/nologin") {
         shell = "/bin/sh".to_owned();
@@ -129,6 +206,20 @@ mod tests {
     use super::*;
     use std::path::Path;
 
+    #[test]
+    fn test_from_command() {
+        assert_eq!(
+            DefaultShell::from_command(&["top".to_owned(), "-b".to_owned()]).unwrap(),
+            DefaultShell {
+                path: "top".to_owned(),
+                args: vec!["-b".to_owned()],
+                env: vec![]
+            }
+        );
+
+        DefaultShell::from_command(&[]).unwrap_err();
+    }
+
     #[test]
     #[cfg(not(alpine))]
     fn test_new_shell_bash() {
@@ -142,7 +233,8 @@ mod tests {
             cmd,
             DefaultShell {
                 path: "/bin/bash".to_owned(),
-                args: vec!["--norc".to_owned()]
+                args: vec!["--norc".to_owned()],
+                env: vec![]
             }
         );
 
@@ -156,6 +248,18 @@ mod tests {
         cmd.validate().unwrap_err();
     }
 
+    #[test]
+    fn test_new_shell_not_found() {
+        let err = get_default_shell(Some("/nonexistent/shell")).unwrap_err();
+
+        assert_eq!(
+            err.downcast_ref::<ShellServerError>(),
+            Some(&ShellServerError::ShellNotFound {
+                path: "/nonexistent/shell".to_owned()
+            })
+        );
+    }
+
     #[test]
     fn test_new_shell_zsh() {
         if !Path::new("/bin/zsh").exists() {
@@ -168,7 +272,8 @@ mod tests {
             cmd,
             DefaultShell {
                 path: "/bin/zsh".to_owned(),
-                args: vec!["--no-rcs".to_owned()]
+                args: vec!["--no-rcs".to_owned()],
+                env: vec![]
             }
         );
     }
@@ -185,7 +290,8 @@ mod tests {
             cmd,
             DefaultShell {
                 path: "/bin/sh".to_owned(),
-                args: vec![]
+                args: vec![],
+                env: vec![]
             }
         );
     }
@@ -203,14 +309,14 @@ mod tests {
 
         let cmd = get_default_shell(Some("/bin/bash")).unwrap();
 
-        assert_eq!(cmd.can_delegate(), true);
+        assert!(cmd.can_delegate());
     }
 
     #[test]
     fn test_unknown_can_delegate() {
         let cmd = DefaultShell::new("unknown_shell".to_owned());
 
-        assert_eq!(cmd.can_delegate(), false);
+        assert!(!cmd.can_delegate());
     }
 
     #[test]
@@ -243,6 +349,41 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_invocation_args() {
+        let mut cmd = DefaultShell::new("/bin/bash".to_owned());
+        cmd.args.push("--norc".to_owned());
+
+        let invocation = |login, interactive| ShellInvocation { login, interactive };
+
+        assert_eq!(
+            cmd.invocation_args(invocation(true, true)),
+            vec!["--norc", "-l", "-i"]
+        );
+        assert_eq!(
+            cmd.invocation_args(invocation(true, false)),
+            vec!["--norc", "-l"]
+        );
+        assert_eq!(
+            cmd.invocation_args(invocation(false, true)),
+            vec!["--norc", "-i"]
+        );
+        assert_eq!(
+            cmd.invocation_args(invocation(false, false)),
+            vec!["--norc"]
+        );
+    }
+
+    #[test]
+    fn test_invocation_args_cmd() {
+        let cmd = DefaultShell::new("C:\\Windows\\System32\\cmd.exe".to_owned());
+
+        assert_eq!(
+            cmd.invocation_args(ShellInvocation::default()),
+            Vec::<String>::new()
+        );
+    }
+
     #[test]
     fn test_unknown_get_execute_command_args() {
         let cmd = DefaultShell::new("unknown_shell".to_owned());
@@ -0,0 +1,110 @@
+use super::Shell;
+use log::*;
+use ring::constant_time::verify_slices_are_equal;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+use tokio::time;
+
+/// A shell which has been detached from its client
+pub(super) struct DetachedShell {
+    pub(super) shell: Box<dyn Shell + Send>,
+    /// Whether the shell was started in raw mode, its output is passed through
+    /// untouched once reattached
+    pub(super) raw: bool,
+    /// Whether the client requested title messages
+    pub(super) titles: bool,
+}
+
+struct Entry {
+    id: usize,
+    token: String,
+    shell: DetachedShell,
+}
+
+/// Shells which have been detached from their clients, stored under the session's
+/// reconnect token. Detached shells keep running, any output written in the meantime
+/// is buffered by the pipe or pty until the shell is read from again.
+/// Shells which are not reattached within the ttl are terminated.
+/// Clones share the same set of shells.
+#[derive(Clone)]
+pub(super) struct DetachedShells {
+    ttl: Duration,
+    max_shells: usize,
+    next_id: Arc<AtomicUsize>,
+    shells: Arc<Mutex<Vec<Entry>>>,
+}
+
+impl DetachedShells {
+    pub(super) fn new(ttl: Duration, max_shells: usize) -> Self {
+        Self {
+            ttl,
+            max_shells,
+            next_id: Arc::new(AtomicUsize::new(0)),
+            shells: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Stores the shell so it can be reattached using the token until the ttl elapses.
+    /// The shell is returned if the maximum number of shells are already detached.
+    pub(super) fn insert(&self, token: &str, shell: DetachedShell) -> Result<(), DetachedShell> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+
+        {
+            let mut shells = self.shells.lock().unwrap();
+
+            if shells.len() >= self.max_shells {
+                return Err(shell);
+            }
+
+            shells.push(Entry {
+                id,
+                token: token.to_owned(),
+                shell,
+            });
+        }
+
+        let shells = Arc::clone(&self.shells);
+        let ttl = self.ttl;
+        tokio::spawn(async move {
+            time::delay_for(ttl).await;
+
+            let expired = {
+                let mut shells = shells.lock().unwrap();
+                let idx = shells.iter().position(|i| i.id == id);
+                idx.map(|idx| shells.remove(idx).shell)
+            };
+
+            if let Some(mut expired) = expired {
+                info!("detached shell was not reattached within {:?}", ttl);
+                if let Err(err) = expired.shell.terminate() {
+                    warn!("failed to terminate detached shell: {}", err);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Removes and returns the most recently detached shell stored under the token.
+    /// Every stored token is compared in constant time.
+    pub(super) fn take(&self, token: &str) -> Option<DetachedShell> {
+        let mut shells = self.shells.lock().unwrap();
+
+        let mut matched = None;
+        for (idx, entry) in shells.iter().enumerate() {
+            if verify_slices_are_equal(entry.token.as_bytes(), token.as_bytes()).is_ok() {
+                matched = Some(idx);
+            }
+        }
+
+        matched.map(|idx| shells.remove(idx).shell)
+    }
+
+    /// The number of shells currently detached
+    pub(super) fn len(&self) -> usize {
+        self.shells.lock().unwrap().len()
+    }
+}
@@ -0,0 +1,78 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicU8, Ordering};
+use tokio::task;
+
+/// The directions of io between the client and the shell
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum IoDirection {
+    Stdout = 1,
+    Stdin = 2,
+}
+
+/// Alternates priority between the shell output and the client input when both are ready.
+/// `tokio::select!` polls its branches in a random order so under sustained load in both
+/// directions one of them could be served many times in a row. Instead the direction which
+/// was served last yields once before it is polled, letting the other direction go first
+/// if it is ready. A direction which is ready on its own is served after the yield.
+pub(super) struct FairIo {
+    last_served: AtomicU8,
+}
+
+impl FairIo {
+    pub(super) fn new() -> Self {
+        Self {
+            last_served: AtomicU8::new(0),
+        }
+    }
+
+    pub(super) async fn serve<F: Future>(&self, direction: IoDirection, io: F) -> F::Output {
+        if self.last_served.load(Ordering::Relaxed) == direction as u8 {
+            let _ = task::yield_now().await;
+        }
+
+        let output = io.await;
+        self.last_served.store(direction as u8, Ordering::Relaxed);
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn test_alternates_when_both_ready() {
+        Runtime::new().unwrap().block_on(async {
+            let fair = FairIo::new();
+            let mut served = vec![];
+
+            for _ in 0..20 {
+                tokio::select! {
+                    direction = fair.serve(IoDirection::Stdout, future::ready(IoDirection::Stdout)) => served.push(direction),
+                    direction = fair.serve(IoDirection::Stdin, future::ready(IoDirection::Stdin)) => served.push(direction),
+                }
+            }
+
+            for pair in served.windows(2) {
+                assert_ne!(pair[0], pair[1]);
+            }
+        });
+    }
+
+    #[test]
+    fn test_serves_single_ready_direction() {
+        Runtime::new().unwrap().block_on(async {
+            let fair = FairIo::new();
+
+            for _ in 0..3 {
+                tokio::select! {
+                    _ = fair.serve(IoDirection::Stdout, future::ready(())) => {},
+                    _ = fair.serve(IoDirection::Stdin, future::pending::<()>()) => unreachable!(),
+                }
+            }
+        });
+    }
+}
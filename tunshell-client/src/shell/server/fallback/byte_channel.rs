@@ -6,7 +6,7 @@ use std::{
 };
 use tokio::io::AsyncRead;
 
-//// An in-memory buffer with a sync write and an async read half
+/// An in-memory buffer with a sync write and an async read half
 pub(super) struct ByteChannel {
     buff: Vec<u8>,
     read_wakers: Vec<Waker>,
@@ -29,7 +29,7 @@ impl AsyncRead for ByteChannel {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
-        if self.buff.len() == 0 {
+        if self.buff.is_empty() {
             if self.shutdown {
                 return Poll::Ready(Ok(0));
             }
@@ -39,14 +39,14 @@ impl AsyncRead for ByteChannel {
         }
 
         let len = cmp::min(buf.len(), self.buff.len());
-        &buf[..len].copy_from_slice(self.buff.drain(..len).collect::<Vec<u8>>().as_slice());
+        buf[..len].copy_from_slice(self.buff.drain(..len).collect::<Vec<u8>>().as_slice());
         Poll::Ready(Ok(len))
     }
 }
 
 impl Write for ByteChannel {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        if buf.len() == 0 {
+        if buf.is_empty() {
             return Ok(0);
         }
 
@@ -96,7 +96,7 @@ mod tests {
     fn test_write() {
         let mut stream = ByteChannel::new();
 
-        stream.write(&[1, 2, 3]).unwrap();
+        stream.write_all(&[1, 2, 3]).unwrap();
 
         assert_eq!(stream.buff, vec![1, 2, 3]);
     }
@@ -106,7 +106,7 @@ mod tests {
         let mut stream = ByteChannel::new();
 
         let (read, read_buff) = Runtime::new().unwrap().block_on(async {
-            stream.write(&[1, 2, 3]).unwrap();
+            stream.write_all(&[1, 2, 3]).unwrap();
 
             let mut read_buff = [0u8; 1024];
             let read = stream.read(&mut read_buff).await.unwrap();
@@ -180,7 +180,7 @@ mod tests {
             stream.shutdown();
 
             stream
-                .write(&mut [0u8; 1024])
+                .write(&[0u8; 1024])
                 .expect_err("should return error if writing after closing stream");
         });
     }
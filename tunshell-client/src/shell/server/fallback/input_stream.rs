@@ -30,6 +30,9 @@ pub(super) enum Token {
     LeftArrow,
     RightArrow,
     DownArrow,
+    Home,
+    End,
+    Delete,
     Backspace,
     ControlC,
 }
@@ -50,15 +53,33 @@ impl InputStream {
         let mut i = 0;
 
         while i < self.buff.len() {
-            let token = match (self.buff[i], self.buff.get(i + 1), self.buff.get(i + 2)) {
-                (ESC, Some(b'['), Some(b'A')) => Some((Token::UpArrow, 3)),
-                (ESC, Some(b'['), Some(b'B')) => Some((Token::DownArrow, 3)),
-                (ESC, Some(b'['), Some(b'C')) => Some((Token::RightArrow, 3)),
-                (ESC, Some(b'['), Some(b'D')) => Some((Token::LeftArrow, 3)),
-                (ESC, _, None) => break,
-                (CR, _, _) => Some((Token::Enter, 1)),
-                (DEL, _, _) => Some((Token::Backspace, 1)),
-                (EOT, _, _) => Some((Token::ControlC, 1)),
+            let token = match (
+                self.buff[i],
+                self.buff.get(i + 1),
+                self.buff.get(i + 2),
+                self.buff.get(i + 3),
+            ) {
+                (ESC, Some(b'['), Some(b'A'), _) => Some((Token::UpArrow, 3)),
+                (ESC, Some(b'['), Some(b'B'), _) => Some((Token::DownArrow, 3)),
+                (ESC, Some(b'['), Some(b'C'), _) => Some((Token::RightArrow, 3)),
+                (ESC, Some(b'['), Some(b'D'), _) => Some((Token::LeftArrow, 3)),
+                // Home and end are sent as either CSI or SS3 sequences depending on the terminal
+                (ESC, Some(b'['), Some(b'H'), _) | (ESC, Some(b'O'), Some(b'H'), _) => {
+                    Some((Token::Home, 3))
+                }
+                (ESC, Some(b'['), Some(b'F'), _) | (ESC, Some(b'O'), Some(b'F'), _) => {
+                    Some((Token::End, 3))
+                }
+                (ESC, Some(b'['), Some(b'1'), Some(b'~')) => Some((Token::Home, 4)),
+                (ESC, Some(b'['), Some(b'3'), Some(b'~')) => Some((Token::Delete, 4)),
+                (ESC, Some(b'['), Some(b'4'), Some(b'~')) => Some((Token::End, 4)),
+                (ESC, _, None, _) => break,
+                (ESC, Some(b'['), Some(b'1'), None)
+                | (ESC, Some(b'['), Some(b'3'), None)
+                | (ESC, Some(b'['), Some(b'4'), None) => break,
+                (CR, _, _, _) => Some((Token::Enter, 1)),
+                (DEL, _, _, _) => Some((Token::Backspace, 1)),
+                (EOT, _, _, _) => Some((Token::ControlC, 1)),
                 _ => None,
             };
 
@@ -110,11 +131,11 @@ impl Stream for InputStream {
     type Item = Token;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        if self.tokens.len() == 0 && self.buff.len() > 0 {
+        if self.tokens.is_empty() && !self.buff.is_empty() {
             self.parse_buff();
         }
 
-        if self.tokens.len() > 0 {
+        if !self.tokens.is_empty() {
             return Poll::Ready(Some(self.tokens.remove(0)));
         }
 
@@ -129,7 +150,7 @@ impl Stream for InputStream {
 
 impl Write for InputStream {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        if buf.len() == 0 {
+        if buf.is_empty() {
             return Ok(0);
         }
 
@@ -156,6 +177,9 @@ impl Token {
             Token::LeftArrow => &[ESC, b'[', b'D'],
             Token::RightArrow => &[ESC, b'[', b'C'],
             Token::DownArrow => &[ESC, b'[', b'B'],
+            Token::Home => &[ESC, b'[', b'H'],
+            Token::End => &[ESC, b'[', b'F'],
+            Token::Delete => &[ESC, b'[', b'3', b'~'],
             Token::Backspace => &[DEL],
             Token::ControlC => &[EOT],
         }
@@ -193,7 +217,7 @@ mod tests {
         let mut stream = InputStream::new();
 
         let tokens = Runtime::new().unwrap().block_on(async {
-            stream.write("hello world".as_bytes()).unwrap();
+            stream.write_all("hello world".as_bytes()).unwrap();
 
             drain_tokens(&mut stream).await
         });
@@ -209,9 +233,9 @@ mod tests {
         let mut stream = InputStream::new();
 
         let tokens = Runtime::new().unwrap().block_on(async {
-            stream.write("hello ".as_bytes()).unwrap();
-            stream.write("world".as_bytes()).unwrap();
-            stream.write("!!".as_bytes()).unwrap();
+            stream.write_all("hello ".as_bytes()).unwrap();
+            stream.write_all("world".as_bytes()).unwrap();
+            stream.write_all("!!".as_bytes()).unwrap();
 
             drain_tokens(&mut stream).await
         });
@@ -227,7 +251,7 @@ mod tests {
         let mut stream = InputStream::new();
 
         let tokens = Runtime::new().unwrap().block_on(async {
-            stream.write(&[ESC, b'[', b'A']).unwrap();
+            stream.write_all(&[ESC, b'[', b'A']).unwrap();
 
             drain_tokens(&mut stream).await
         });
@@ -240,7 +264,7 @@ mod tests {
         let mut stream = InputStream::new();
 
         let tokens = Runtime::new().unwrap().block_on(async {
-            stream.write(&[ESC, b'[', b'B']).unwrap();
+            stream.write_all(&[ESC, b'[', b'B']).unwrap();
 
             drain_tokens(&mut stream).await
         });
@@ -253,7 +277,7 @@ mod tests {
         let mut stream = InputStream::new();
 
         let tokens = Runtime::new().unwrap().block_on(async {
-            stream.write(&[ESC, b'[', b'C']).unwrap();
+            stream.write_all(&[ESC, b'[', b'C']).unwrap();
 
             drain_tokens(&mut stream).await
         });
@@ -266,7 +290,7 @@ mod tests {
         let mut stream = InputStream::new();
 
         let tokens = Runtime::new().unwrap().block_on(async {
-            stream.write(&[ESC, b'[', b'D']).unwrap();
+            stream.write_all(&[ESC, b'[', b'D']).unwrap();
 
             drain_tokens(&mut stream).await
         });
@@ -274,12 +298,59 @@ mod tests {
         assert_eq!(tokens, vec![Token::LeftArrow]);
     }
 
+    #[test]
+    fn test_parsing_home_end_and_delete() {
+        let mut stream = InputStream::new();
+
+        let tokens = Runtime::new().unwrap().block_on(async {
+            stream
+                .write_all(&[
+                    ESC, b'[', b'H', ESC, b'O', b'F', ESC, b'[', b'1', b'~', ESC, b'[', b'4', b'~',
+                    ESC, b'[', b'3', b'~',
+                ])
+                .unwrap();
+
+            drain_tokens(&mut stream).await
+        });
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Home,
+                Token::End,
+                Token::Home,
+                Token::End,
+                Token::Delete
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parsing_partial_delete_sequence() {
+        let mut stream = InputStream::new();
+
+        let tokens = Runtime::new().unwrap().block_on(async {
+            stream.write_all(&[b'a', ESC, b'[', b'3']).unwrap();
+
+            let tokens = drain_tokens(&mut stream).await;
+
+            stream.write_all(b"~").unwrap();
+
+            [tokens, drain_tokens(&mut stream).await].concat()
+        });
+
+        assert_eq!(
+            tokens,
+            vec![Token::Bytes("a".as_bytes().to_vec()), Token::Delete]
+        );
+    }
+
     #[test]
     fn test_parsing_del() {
         let mut stream = InputStream::new();
 
         let tokens = Runtime::new().unwrap().block_on(async {
-            stream.write(&[DEL]).unwrap();
+            stream.write_all(&[DEL]).unwrap();
 
             drain_tokens(&mut stream).await
         });
@@ -292,7 +363,7 @@ mod tests {
         let mut stream = InputStream::new();
 
         let tokens = Runtime::new().unwrap().block_on(async {
-            stream.write(&[EOT]).unwrap();
+            stream.write_all(&[EOT]).unwrap();
 
             drain_tokens(&mut stream).await
         });
@@ -305,7 +376,7 @@ mod tests {
         let mut stream = InputStream::new();
 
         let tokens = Runtime::new().unwrap().block_on(async {
-            stream.write(&[CR]).unwrap();
+            stream.write_all(&[CR]).unwrap();
 
             drain_tokens(&mut stream).await
         });
@@ -318,7 +389,7 @@ mod tests {
         let mut stream = InputStream::new();
 
         let tokens = Runtime::new().unwrap().block_on(async {
-            stream.write(&[ESC, b'[']).unwrap();
+            stream.write_all(&[ESC, b'[']).unwrap();
 
             drain_tokens(&mut stream).await
         });
@@ -333,7 +404,7 @@ mod tests {
 
         let tokens = Runtime::new().unwrap().block_on(async {
             stream
-                .write(&[
+                .write_all(&[
                     b'h', b'i', ESC, b'[', b'A', b't', b'e', b's', b't', DEL, ESC, b'[', b'C', b'a',
                 ])
                 .unwrap();
@@ -359,17 +430,17 @@ mod tests {
         let mut stream = InputStream::new();
 
         let tokens = Runtime::new().unwrap().block_on(async {
-            stream.write(&[b'h', b'i', ESC]).unwrap();
+            stream.write_all(&[b'h', b'i', ESC]).unwrap();
 
             let tokens = drain_tokens(&mut stream).await;
 
             stream
-                .write(&[b'[', b'A', b't', b'e', b's', b't', DEL, ESC, b'['])
+                .write_all(&[b'[', b'A', b't', b'e', b's', b't', DEL, ESC, b'['])
                 .unwrap();
 
             let tokens = [tokens, drain_tokens(&mut stream).await].concat();
 
-            stream.write(&[b'C', b'a']).unwrap();
+            stream.write_all(b"Ca").unwrap();
 
             let tokens = [tokens, drain_tokens(&mut stream).await].concat();
 
@@ -394,7 +465,7 @@ mod tests {
         let mut stream = InputStream::new();
 
         let tokens = Runtime::new().unwrap().block_on(async {
-            stream.write("echo test\rexit\r".as_bytes()).unwrap();
+            stream.write_all("echo test\rexit\r".as_bytes()).unwrap();
 
             drain_tokens(&mut stream).await
         });
@@ -13,6 +13,8 @@ use tokio::{
 pub(super) struct Interpreter {
     state: SharedState,
     line_buff: Vec<u8>,
+    // The line being edited before navigating into the history
+    pending_line: Vec<u8>,
     history: Vec<String>,
     cursor_pos: usize,
     history_pos: usize,
@@ -24,6 +26,7 @@ impl Interpreter {
         let interpreter = Self {
             state,
             line_buff: vec![],
+            pending_line: vec![],
             history: vec![],
             cursor_pos: 0,
             history_pos: 0,
@@ -55,6 +58,9 @@ impl Interpreter {
                 Token::LeftArrow => self.retract_cursor(1)?,
                 Token::RightArrow => self.advance_cursor(1)?,
                 Token::DownArrow => self.next_in_history()?,
+                Token::Home => self.retract_cursor(self.cursor_pos)?,
+                Token::End => self.advance_cursor(self.line_buff.len() - self.cursor_pos)?,
+                Token::Delete => self.delete()?,
                 Token::Backspace => self.backspace()?,
                 Token::ControlC => self.interrupt_line()?,
                 Token::Enter => break self.handle_new_line()?,
@@ -63,7 +69,12 @@ impl Interpreter {
 
         let cmd = String::from_utf8(line)?;
 
-        self.history.push(cmd.clone());
+        // Blank lines and repeats of the previous command are not added to the history
+        if !cmd.trim().is_empty() && self.history.last() != Some(&cmd) {
+            self.history.push(cmd.clone());
+        }
+
+        self.pending_line = vec![];
         self.cursor_pos = 0;
         self.history_pos = self.history.len();
 
@@ -154,7 +165,7 @@ impl Interpreter {
             state.output.write_all(
                 [
                     Token::LeftArrow.to_bytes(),
-                    &[b' '],
+                    b" ",
                     Token::LeftArrow.to_bytes(),
                 ]
                 .concat()
@@ -171,12 +182,26 @@ impl Interpreter {
         Ok(())
     }
 
+    fn delete(&mut self) -> Result<()> {
+        if self.cursor_pos == self.line_buff.len() {
+            return Ok(());
+        }
+
+        self.refresh_line(|this| {
+            this.line_buff.remove(this.cursor_pos);
+        })
+    }
+
     fn previous_in_history(&mut self) -> Result<()> {
-        if self.history_pos == 0 || self.history.len() == 0 {
+        if self.history_pos == 0 || self.history.is_empty() {
             return Ok(());
         }
 
         self.refresh_line(|this| {
+            if this.history_pos == this.history.len() {
+                this.pending_line = this.line_buff.clone();
+            }
+
             this.history_pos -= 1;
             this.line_buff = this.history[this.history_pos].as_bytes().to_vec();
             this.cursor_pos = this.line_buff.len();
@@ -196,7 +221,7 @@ impl Interpreter {
             this.history_pos += 1;
 
             this.line_buff = if this.history_pos == this.history.len() {
-                vec![]
+                this.pending_line.drain(..).collect()
             } else {
                 this.history[this.history_pos].as_bytes().to_vec()
             };
@@ -209,7 +234,7 @@ impl Interpreter {
         Ok(())
     }
 
-    fn refresh_line(&mut self, mut update_cb: impl FnMut(&mut Self) -> ()) -> Result<()> {
+    fn refresh_line(&mut self, mut update_cb: impl FnMut(&mut Self)) -> Result<()> {
         let original_len = self.line_buff.len();
         let original_pos = self.cursor_pos;
 
@@ -254,13 +279,13 @@ impl Interpreter {
     fn write_prompt(&mut self) -> Result<()> {
         let mut state = self.state.inner.lock().unwrap();
         let pwd = state.pwd.clone();
-        state.output.write(
+        state.output.write_all(
             pwd.file_name()
                 .map(|i| i.to_string_lossy().to_string())
                 .unwrap_or("/".to_owned())
                 .as_bytes(),
         )?;
-        state.output.write("> ".as_bytes())?;
+        state.output.write_all("> ".as_bytes())?;
         Ok(())
     }
 
@@ -269,7 +294,7 @@ impl Interpreter {
             return Err(Error::msg("shell has exited"));
         }
 
-        if cmd.len() == 0 {
+        if cmd.trim().is_empty() {
             return Ok(());
         }
 
@@ -296,7 +321,7 @@ impl Interpreter {
         if !new_pwd.exists() {
             state
                 .output
-                .write(format!("no such directory: {}", dir).as_bytes())?;
+                .write_all(format!("no such directory: {}", dir).as_bytes())?;
             return Ok(());
         }
 
@@ -345,7 +370,7 @@ impl Interpreter {
             // to that shell for execution to enable near full shell command capability
             let path = delegate_shell.path.clone();
             let args = delegate_shell
-                .get_execute_command_args(program, args.iter().cloned().collect())
+                .get_execute_command_args(program, args.to_vec())
                 .unwrap();
             let mut cmd = Command::new(path.clone());
             cmd.args(args.clone());
@@ -356,7 +381,7 @@ impl Interpreter {
         } else {
             // In the case of no shells available on the system we fallback to executing
             // the programs directly
-            let mut cmd = Command::new(program.to_owned());
+            let mut cmd = Command::new(program);
             cmd.args(args.clone());
 
             debug!("created command: {} {:?}", program.to_owned(), args);
@@ -364,11 +389,15 @@ impl Interpreter {
             cmd
         };
 
-        let (pwd, env) = {
+        let (pwd, env, inherit_env) = {
             let state = self.state.inner.lock().unwrap();
-            (state.pwd.clone(), state.env.clone())
+            (state.pwd.clone(), state.env.clone(), state.inherit_env)
         };
 
+        if !inherit_env {
+            cmd.env_clear();
+        }
+
         cmd.current_dir(pwd.clone())
             .envs(env.iter())
             .stdin(Stdio::piped())
@@ -394,7 +423,7 @@ impl Interpreter {
                             process.kill()?; // TODO: replace with SIGTERM on unix
                             debug!("killed child process");
                         },
-                        data @ _ => {
+                        data => {
                             let data = data.to_bytes();
                             stdin.write_all(data).await?;
                             debug!("wrote {} bytes to process stdin", data.len());
@@ -630,7 +659,7 @@ mod tests {
 
             let echo_output = output
                 .split("\r\n")
-                .filter(|i| !i.starts_with("/>") && i.len() > 0)
+                .filter(|i| !i.starts_with("/>") && !i.is_empty())
                 .collect::<Vec<&str>>();
 
             assert_eq!(echo_output, vec!["first", "second", "first"]);
@@ -666,10 +695,111 @@ mod tests {
 
             let echo_output = output
                 .split("\r\n")
-                .filter(|i| !i.starts_with("/>") && i.len() > 0)
+                .filter(|i| !i.starts_with("/>") && !i.is_empty())
                 .collect::<Vec<&str>>();
 
             assert_eq!(echo_output, vec!["first", "second", "second"]);
         });
     }
+
+    #[test]
+    fn test_home_end_and_delete() {
+        Runtime::new().unwrap().block_on(async {
+            let mut state = init_interpreter();
+
+            write_input(&mut state, "cho helllo".as_bytes());
+            write_input(&mut state, Token::Home.to_bytes());
+            write_input(&mut state, "e".as_bytes());
+            write_input(&mut state, Token::End.to_bytes());
+            write_input(&mut state, Token::LeftArrow.to_bytes().repeat(3).as_slice());
+            write_input(&mut state, Token::Delete.to_bytes());
+            write_input(&mut state, Token::Enter.to_bytes());
+
+            // Let process execute
+            delay_for(Duration::from_millis(100)).await;
+
+            write_input(&mut state, "exit\r".as_bytes());
+
+            let output = read_to_end(&mut state).await;
+            let output = String::from_utf8(output).unwrap();
+
+            let echo_output = output.split("\r\n").collect::<Vec<&str>>()[1];
+
+            assert_eq!(echo_output, "hello");
+        });
+    }
+
+    #[test]
+    fn test_history_skips_blank_and_repeated_lines() {
+        Runtime::new().unwrap().block_on(async {
+            let mut state = init_interpreter();
+
+            write_input(&mut state, "echo first\r".as_bytes());
+
+            // Wait for process to finish
+            delay_for(Duration::from_millis(100)).await;
+
+            write_input(&mut state, "echo second\r".as_bytes());
+
+            // Wait for process to finish
+            delay_for(Duration::from_millis(100)).await;
+
+            write_input(&mut state, "echo second\r".as_bytes());
+
+            // Wait for process to finish
+            delay_for(Duration::from_millis(100)).await;
+
+            write_input(&mut state, "  \r".as_bytes());
+
+            // Navigate past the blank and repeated lines to the first command
+            write_input(&mut state, Token::UpArrow.to_bytes().repeat(2).as_slice());
+            write_input(&mut state, Token::Enter.to_bytes());
+
+            delay_for(Duration::from_millis(100)).await;
+
+            write_input(&mut state, "exit\r".as_bytes());
+
+            let output = read_to_end(&mut state).await;
+            let output = String::from_utf8(output).unwrap();
+
+            let echo_output = output
+                .split("\r\n")
+                .filter(|i| !i.starts_with("/>") && !i.is_empty())
+                .collect::<Vec<&str>>();
+
+            assert_eq!(echo_output, vec!["first", "second", "second", "first"]);
+        });
+    }
+
+    #[test]
+    fn test_in_progress_line_restored_from_history() {
+        Runtime::new().unwrap().block_on(async {
+            let mut state = init_interpreter();
+
+            write_input(&mut state, "echo first\r".as_bytes());
+
+            // Wait for process to finish
+            delay_for(Duration::from_millis(100)).await;
+
+            // Browse the history and return to the partially typed line
+            write_input(&mut state, "echo dra".as_bytes());
+            write_input(&mut state, Token::UpArrow.to_bytes());
+            write_input(&mut state, Token::DownArrow.to_bytes());
+            write_input(&mut state, "ft\r".as_bytes());
+
+            delay_for(Duration::from_millis(100)).await;
+
+            write_input(&mut state, "exit\r".as_bytes());
+
+            let output = read_to_end(&mut state).await;
+            let output = String::from_utf8(output).unwrap();
+
+            let echo_output = output
+                .split("\r\n")
+                .filter(|i| !i.starts_with("/>") && !i.is_empty())
+                .collect::<Vec<&str>>();
+
+            assert_eq!(echo_output, vec!["first", "draft"]);
+        });
+    }
 }
@@ -42,7 +42,7 @@ impl AsyncRead for OutputStream {
 
 impl Write for OutputStream {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        if buf.len() == 0 {
+        if buf.is_empty() {
             return Ok(0);
         }
 
@@ -54,7 +54,8 @@ impl Write for OutputStream {
             written += 1;
         }
 
-        for i in written..buf.len() {
+        let start = written;
+        for i in start..buf.len() {
             if i > 0 && buf[i] == LF && buf[i - 1] != CR {
                 self.inner.write_all(&buf[written..i])?;
                 self.inner.write_all(&[CR, LF])?;
@@ -90,7 +91,7 @@ mod tests {
         let mut stream = OutputStream::new();
 
         let (read, read_buff) = Runtime::new().unwrap().block_on(async {
-            stream.write(&[1, 2, 3]).unwrap();
+            stream.write_all(&[1, 2, 3]).unwrap();
 
             let mut read_buff = [0u8; 1024];
             let read = stream.read(&mut read_buff).await.unwrap();
@@ -107,7 +108,7 @@ mod tests {
         let mut stream = OutputStream::new();
 
         let (read, read_buff) = Runtime::new().unwrap().block_on(async {
-            stream.write(&[LF, 2, 3, LF, 5, 6, CR, LF, LF]).unwrap();
+            stream.write_all(&[LF, 2, 3, LF, 5, 6, CR, LF, LF]).unwrap();
 
             let mut read_buff = [0u8; 1024];
             let read = stream.read(&mut read_buff).await.unwrap();
@@ -126,10 +127,10 @@ mod tests {
         let mut stream = OutputStream::new();
 
         let (read, read_buff) = Runtime::new().unwrap().block_on(async {
-            stream.write(&[LF]).unwrap();
-            stream.write(&[1, 2, 3]).unwrap();
-            stream.write(&[LF, 4, 5, 6, CR]).unwrap();
-            stream.write(&[LF, 7, 8, 9]).unwrap();
+            stream.write_all(&[LF]).unwrap();
+            stream.write_all(&[1, 2, 3]).unwrap();
+            stream.write_all(&[LF, 4, 5, 6, CR]).unwrap();
+            stream.write_all(&[LF, 7, 8, 9]).unwrap();
 
             let mut read_buff = [0u8; 1024];
             let read = stream.read(&mut read_buff).await.unwrap();
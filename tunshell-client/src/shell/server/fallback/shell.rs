@@ -1,5 +1,8 @@
 use super::{InputStream, Interpreter, OutputStream, Token};
-use crate::shell::{proto::WindowSize, server::shell::Shell};
+use crate::shell::{
+    proto::{ExitStatus, WindowSize},
+    server::{curated_env, shell::Shell},
+};
 use anyhow::{Error, Result};
 use async_trait::async_trait;
 use futures::{Future, Stream};
@@ -30,14 +33,24 @@ pub(super) struct Inner {
     pub(super) output: OutputStream,
     pub(super) pwd: PathBuf,
     pub(super) env: HashMap<String, String>,
+    /// Whether commands inherit the environment of this process in addition to `env`
+    pub(super) inherit_env: bool,
     pub(super) size: WindowSize,
     pub(super) exit_code: Option<u8>,
 }
 
 impl FallbackShell {
-    pub(in super::super) fn new(_term: &str, size: WindowSize) -> Self {
+    /// When `clean_env` is set commands are run with only the curated environment
+    pub(in super::super) fn new(term: &str, size: WindowSize, clean_env: bool) -> Self {
         let state = SharedState::new(size);
 
+        if clean_env {
+            let mut inner = state.inner.lock().unwrap();
+            inner.inherit_env = false;
+            inner.env = curated_env().into_iter().collect();
+            inner.env.insert("TERM".to_owned(), term.to_owned());
+        }
+
         let mut shell = Self {
             _interpreter_task: Interpreter::start(state.clone()),
             state,
@@ -51,12 +64,12 @@ impl FallbackShell {
     fn write_notice(&mut self) -> Result<()> {
         let mut state = self.state.inner.lock().unwrap();
 
-        state.output.write("\r\n".as_bytes())?;
-        state.output.write("NOTICE: Tunshell is running in a limited environment and is unable to allocate a pty for a real shell. ".as_bytes())?;
-        state.output.write(
+        state.output.write_all("\r\n".as_bytes())?;
+        state.output.write_all("NOTICE: Tunshell is running in a limited environment and is unable to allocate a pty for a real shell. ".as_bytes())?;
+        state.output.write_all(
             "Falling back to a built-in pseudo-shell with very limited functionality".as_bytes(),
         )?;
-        state.output.write("\r\n\r\n".as_bytes())?;
+        state.output.write_all("\r\n\r\n".as_bytes())?;
 
         Ok(())
     }
@@ -65,7 +78,7 @@ impl FallbackShell {
 #[async_trait]
 impl Shell for FallbackShell {
     async fn read(&mut self, buff: &mut [u8]) -> Result<usize> {
-        if self.exit_code().is_ok() {
+        if self.exit_status().is_ok() {
             return Ok(0);
         }
 
@@ -73,7 +86,7 @@ impl Shell for FallbackShell {
     }
 
     async fn write(&mut self, buff: &[u8]) -> Result<()> {
-        if self.exit_code().is_ok() {
+        if self.exit_status().is_ok() {
             return Err(Error::msg("shell has exited"));
         }
 
@@ -83,18 +96,52 @@ impl Shell for FallbackShell {
         Ok(())
     }
 
+    async fn close_stdin(&mut self) -> Result<()> {
+        Err(Error::msg(
+            "closing stdin is not supported by the fallback shell",
+        ))
+    }
+
     fn resize(&mut self, size: WindowSize) -> Result<()> {
         let mut state = self.state.inner.lock().unwrap();
         state.size = size;
         Ok(())
     }
 
-    fn exit_code(&self) -> Result<u8> {
+    fn exit_status(&self) -> Result<ExitStatus> {
         let state = self.state.inner.lock().unwrap();
         state
             .exit_code
+            .map(|code| ExitStatus::exited(code as i32))
             .ok_or_else(|| Error::msg("shell has not closed"))
     }
+
+    fn terminate(&mut self) -> Result<()> {
+        let mut state = self.state.inner.lock().unwrap();
+
+        if state.exit_code.is_none() {
+            state.exit_code = Some(1);
+        }
+
+        Ok(())
+    }
+
+    fn signal(&mut self, signal: u8) -> Result<()> {
+        Err(Error::msg(format!(
+            "signal {} is not supported by the fallback shell",
+            signal
+        )))
+    }
+
+    fn cwd(&self) -> Result<String> {
+        let state = self.state.inner.lock().unwrap();
+
+        state
+            .pwd
+            .to_str()
+            .map(|i| i.to_owned())
+            .ok_or_else(|| Error::msg("working directory is not valid UTF-8"))
+    }
 }
 
 impl Drop for FallbackShell {
@@ -110,6 +157,7 @@ impl SharedState {
                 output: OutputStream::new(),
                 pwd: std::env::current_dir().unwrap(),
                 env: HashMap::new(),
+                inherit_env: true,
                 exit_code: None,
             })),
         }
@@ -0,0 +1,83 @@
+use anyhow::{Context, Error, Result};
+use log::*;
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// The result of running the pre-shell hook to completion
+#[derive(Debug, PartialEq)]
+pub(super) struct HookOutput {
+    /// The hook's stdout followed by its stderr
+    pub(super) output: Vec<u8>,
+    /// `None` if the hook was killed by a signal
+    pub(super) exit_code: Option<i32>,
+}
+
+impl HookOutput {
+    pub(super) fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+/// Runs the hook command to completion with stdin closed, capturing its output
+pub(super) async fn run_pre_shell_hook(command: &[String]) -> Result<HookOutput> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| Error::msg("pre-shell hook command cannot be empty"))?;
+
+    info!("running pre-shell hook: {:?}", command);
+    let result = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .with_context(|| format!("failed to run pre-shell hook {}", program))?;
+
+    let mut output = result.stdout;
+    output.extend_from_slice(&result.stderr);
+
+    let hook_output = HookOutput {
+        output,
+        exit_code: result.status.code(),
+    };
+    info!("pre-shell hook exited with {:?}", hook_output.exit_code);
+
+    Ok(hook_output)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn test_run_pre_shell_hook() {
+        let output = Runtime::new()
+            .unwrap()
+            .block_on(run_pre_shell_hook(&[
+                "/bin/sh".to_owned(),
+                "-c".to_owned(),
+                "echo out; echo err >&2; exit 3".to_owned(),
+            ]))
+            .unwrap();
+
+        assert_eq!(
+            output,
+            HookOutput {
+                output: b"out\nerr\n".to_vec(),
+                exit_code: Some(3),
+            }
+        );
+        assert!(!output.success());
+    }
+
+    #[test]
+    fn test_run_missing_pre_shell_hook() {
+        Runtime::new()
+            .unwrap()
+            .block_on(run_pre_shell_hook(&[
+                "/bin/tunshell-missing-hook".to_owned()
+            ]))
+            .unwrap_err();
+    }
+}
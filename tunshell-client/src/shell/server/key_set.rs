@@ -0,0 +1,127 @@
+use crate::ShellKey;
+use ring::constant_time::verify_slices_are_equal;
+
+/// A set of keys which are accepted by the shell server.
+/// Multiple keys are supported to allow for rotation of credentials
+pub(crate) struct KeySet {
+    keys: Vec<ShellKey>,
+}
+
+impl KeySet {
+    pub(crate) fn new(keys: Vec<ShellKey>) -> Self {
+        Self { keys }
+    }
+
+    /// Returns the index of the key matching the supplied key, if any.
+    /// Every key in the set is compared in constant time so the
+    /// position of the matching key is not leaked through timing.
+    pub(crate) fn find(&self, received: &str) -> Option<usize> {
+        let mut matched = None;
+
+        for (idx, key) in self.keys.iter().enumerate() {
+            let is_match =
+                verify_slices_are_equal(received.as_bytes(), key.key().as_bytes()).is_ok();
+
+            if is_match && matched.is_none() {
+                matched = Some(idx);
+            }
+        }
+
+        matched
+    }
+
+    /// Returns the index of the key which the proof of the nonce was created from, if any.
+    /// As with `find` every key is checked so the matching position is not leaked.
+    pub(crate) fn find_proof(&self, nonce: &[u8], proof: &[u8]) -> Option<usize> {
+        let mut matched = None;
+
+        for (idx, key) in self.keys.iter().enumerate() {
+            if key.verify_proof(nonce, proof) && matched.is_none() {
+                matched = Some(idx);
+            }
+        }
+
+        matched
+    }
+
+    pub(crate) fn get(&self, idx: usize) -> Option<&ShellKey> {
+        self.keys.get(idx)
+    }
+}
+
+impl From<ShellKey> for KeySet {
+    fn from(key: ShellKey) -> Self {
+        Self::new(vec![key])
+    }
+}
+
+impl From<Vec<ShellKey>> for KeySet {
+    fn from(keys: Vec<ShellKey>) -> Self {
+        Self::new(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_set() -> KeySet {
+        KeySet::new(vec![
+            ShellKey::new("PrimaryKey"),
+            ShellKey::new("SecondaryKey"),
+        ])
+    }
+
+    #[test]
+    fn test_find_primary_key() {
+        assert_eq!(key_set().find("PrimaryKey"), Some(0));
+    }
+
+    #[test]
+    fn test_find_secondary_key() {
+        assert_eq!(key_set().find("SecondaryKey"), Some(1));
+    }
+
+    #[test]
+    fn test_find_unknown_key() {
+        assert_eq!(key_set().find("UnknownKey"), None);
+        assert_eq!(key_set().find(""), None);
+    }
+
+    #[test]
+    fn test_find_proof() {
+        let nonce = [1u8; 32];
+        let proof = ShellKey::new("SecondaryKey").proof(&nonce);
+
+        assert_eq!(key_set().find_proof(&nonce, &proof), Some(1));
+    }
+
+    #[test]
+    fn test_find_proof_of_unknown_key() {
+        let nonce = [1u8; 32];
+        let proof = ShellKey::new("UnknownKey").proof(&nonce);
+
+        assert_eq!(key_set().find_proof(&nonce, &proof), None);
+        assert_eq!(key_set().find_proof(&nonce, &[]), None);
+    }
+
+    #[test]
+    fn test_find_proof_of_other_nonce() {
+        let proof = ShellKey::new("PrimaryKey").proof(&[1u8; 32]);
+
+        assert_eq!(key_set().find_proof(&[2u8; 32], &proof), None);
+    }
+
+    #[test]
+    fn test_get_key() {
+        assert_eq!(key_set().get(1).map(|i| i.key()), Some("SecondaryKey"));
+        assert_eq!(key_set().get(2).map(|i| i.key()), None);
+    }
+
+    #[test]
+    fn test_from_single_key() {
+        let keys = KeySet::from(ShellKey::new("Key"));
+
+        assert_eq!(keys.find("Key"), Some(0));
+    }
+}
@@ -0,0 +1,144 @@
+use super::DefaultShell;
+#[cfg(not(unix))]
+use log::*;
+
+/// Limits on the resources used by the shell, which are also inherited by
+/// any processes it starts
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResourceLimits {
+    /// The CPU time in seconds, the shell is killed once this is exceeded
+    pub cpu_seconds: Option<u64>,
+    /// The size of the shell's virtual address space in bytes
+    pub address_space: Option<u64>,
+    /// The number of files the shell can have open
+    pub nofile: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.cpu_seconds.is_none() && self.address_space.is_none() && self.nofile.is_none()
+    }
+
+    /// Wraps the program so that the limits are set using setrlimit (via the `ulimit`
+    /// builtin of /bin/sh) in the child before the program is exec'd.
+    /// portable-pty has no pre-exec hook so the limits cannot be set directly.
+    /// If any of the limits cannot be set the child exits without running the program.
+    #[cfg(unix)]
+    pub(super) fn apply(&self, program: DefaultShell) -> DefaultShell {
+        if self.is_empty() {
+            return program;
+        }
+
+        let mut script = "set -e;".to_owned();
+
+        if let Some(cpu_seconds) = self.cpu_seconds {
+            script.push_str(&format!(" ulimit -t {};", cpu_seconds));
+        }
+
+        if let Some(address_space) = self.address_space {
+            // ulimit accepts the address space in KiB
+            script.push_str(&format!(" ulimit -v {};", (address_space / 1024).max(1)));
+        }
+
+        if let Some(nofile) = self.nofile {
+            script.push_str(&format!(" ulimit -n {};", nofile));
+        }
+
+        script.push_str(" exec \"$@\"");
+
+        let mut args = vec!["-c".to_owned(), script, "tunshell".to_owned(), program.path];
+        args.extend(program.args);
+
+        DefaultShell {
+            path: "/bin/sh".to_owned(),
+            args,
+            env: program.env,
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub(super) fn apply(&self, program: DefaultShell) -> DefaultShell {
+        if !self.is_empty() {
+            warn!("resource limits are not supported on this platform, ignoring");
+        }
+
+        program
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::super::{PipeShell, Shell};
+    use super::*;
+    use std::time::Duration;
+    use tokio::runtime::Runtime;
+    use tokio::time::timeout;
+
+    #[test]
+    fn test_apply_no_limits() {
+        let program = DefaultShell::new("/bin/bash".to_owned());
+
+        assert_eq!(ResourceLimits::default().apply(program.clone()), program);
+    }
+
+    #[test]
+    fn test_apply_limits() {
+        let program = DefaultShell {
+            path: "/bin/bash".to_owned(),
+            args: vec!["-l".to_owned()],
+            env: vec![],
+        };
+        let limits = ResourceLimits {
+            cpu_seconds: Some(10),
+            address_space: Some(1024 * 1024 * 1024),
+            nofile: Some(64),
+        };
+
+        assert_eq!(
+            limits.apply(program),
+            DefaultShell {
+                path: "/bin/sh".to_owned(),
+                args: vec![
+                    "-c".to_owned(),
+                    "set -e; ulimit -t 10; ulimit -v 1048576; ulimit -n 64; exec \"$@\"".to_owned(),
+                    "tunshell".to_owned(),
+                    "/bin/bash".to_owned(),
+                    "-l".to_owned(),
+                ],
+                env: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_cpu_limit_kills_shell() {
+        Runtime::new().unwrap().block_on(async {
+            let limits = ResourceLimits {
+                cpu_seconds: Some(1),
+                ..ResourceLimits::default()
+            };
+            let program = DefaultShell::from_command(&[
+                "/bin/sh".to_owned(),
+                "-c".to_owned(),
+                "while :; do :; done".to_owned(),
+            ])
+            .unwrap();
+            let mut shell = PipeShell::with_command(limits.apply(program), true).unwrap();
+            let mut buff = [0u8; 1024];
+
+            timeout(Duration::from_secs(10), async {
+                while shell.read(&mut buff).await.unwrap() > 0 {}
+            })
+            .await
+            .expect("shell should be killed once the cpu limit is exceeded");
+
+            let code = shell.exit_status().unwrap().process_exit_code();
+
+            assert!(
+                code == 128 + libc::SIGXCPU as u8 || code == 128 + libc::SIGKILL as u8,
+                "unexpected exit code {}",
+                code
+            );
+        });
+    }
+}
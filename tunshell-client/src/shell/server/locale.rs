@@ -0,0 +1,115 @@
+use crate::shell::proto::StartShellPayload;
+use log::*;
+
+/// Longer values are rejected, real locale and timezone names are far shorter
+const MAX_LEN: usize = 64;
+
+/// Returns the environment variables setting the locale and timezone of the shell to
+/// those requested by the client. The values are passed to every program the shell runs
+/// so any which are not plausible names are ignored rather than set as arbitrary strings.
+pub(super) fn locale_env(request: &StartShellPayload) -> Vec<(String, String)> {
+    let mut env = vec![];
+
+    match request.locale.as_ref() {
+        Some(locale) if is_valid_locale(locale) => {
+            env.push(("LANG".to_owned(), locale.clone()));
+            env.push(("LC_ALL".to_owned(), locale.clone()));
+        }
+        Some(locale) => warn!("ignoring invalid locale requested by client: {:?}", locale),
+        None => {}
+    }
+
+    match request.timezone.as_ref() {
+        Some(timezone) if is_valid_timezone(timezone) => {
+            env.push(("TZ".to_owned(), timezone.clone()));
+        }
+        Some(timezone) => warn!(
+            "ignoring invalid timezone requested by client: {:?}",
+            timezone
+        ),
+        None => {}
+    }
+
+    env
+}
+
+/// Matches names such as "C", "en_GB.UTF-8" and "sr_RS@latin"
+fn is_valid_locale(locale: &str) -> bool {
+    locale.len() <= MAX_LEN
+        && locale.starts_with(|c: char| c.is_ascii_alphabetic())
+        && locale
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_.-@".contains(c))
+}
+
+/// Matches zone names such as "Europe/London" and "Etc/GMT+5" and POSIX rules such as
+/// "EST5EDT". Paths to zone files, which start with ":" or "/", are not accepted.
+fn is_valid_timezone(timezone: &str) -> bool {
+    timezone.len() <= MAX_LEN
+        && timezone.starts_with(|c: char| c.is_ascii_alphabetic())
+        && !timezone.contains("..")
+        && timezone
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/_+-.".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::proto::WindowSize;
+
+    #[test]
+    fn test_invalid_values_ignored() {
+        let request = StartShellPayload {
+            term: "xterm".to_owned(),
+            size: Some(WindowSize(80, 24)),
+            pty: true,
+            login: true,
+            interactive: true,
+            raw: false,
+            shell_path: None,
+            colors: None,
+            truecolor: false,
+            line_mode: false,
+            locale: Some("en_GB.UTF-8".to_owned()),
+            timezone: Some(":/etc/shadow".to_owned()),
+            titles: false,
+        };
+
+        assert_eq!(
+            locale_env(&request),
+            vec![
+                ("LANG".to_owned(), "en_GB.UTF-8".to_owned()),
+                ("LC_ALL".to_owned(), "en_GB.UTF-8".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_valid_locale() {
+        assert!(is_valid_locale("C"));
+        assert!(is_valid_locale("en_GB.UTF-8"));
+        assert!(is_valid_locale("sr_RS@latin"));
+
+        assert!(!is_valid_locale(""));
+        assert!(!is_valid_locale("en_GB.UTF-8\nLD_PRELOAD=/tmp/x.so"));
+        assert!(!is_valid_locale("$(reboot)"));
+        assert!(!is_valid_locale("../../tmp"));
+        assert!(!is_valid_locale(&"a".repeat(65)));
+    }
+
+    #[test]
+    fn test_is_valid_timezone() {
+        assert!(is_valid_timezone("UTC"));
+        assert!(is_valid_timezone("Europe/London"));
+        assert!(is_valid_timezone("America/Argentina/Buenos_Aires"));
+        assert!(is_valid_timezone("Etc/GMT+5"));
+        assert!(is_valid_timezone("EST5EDT"));
+
+        assert!(!is_valid_timezone(""));
+        assert!(!is_valid_timezone(":/etc/shadow"));
+        assert!(!is_valid_timezone("/etc/shadow"));
+        assert!(!is_valid_timezone("Europe/../../etc/shadow"));
+        assert!(!is_valid_timezone("UTC; reboot"));
+    }
+}
@@ -1,342 +1,5484 @@
-use super::{ShellClientMessage, ShellServerMessage, ShellServerStream};
-use crate::{ShellKey, TunnelStream};
+use super::{
+    negotiate_protocol_version, ExitStatus, HelloAckPayload, HelloPayload, ReattachPayload,
+    ShellClientMessage, ShellServerMessage, ShellServerStream, StartShellPayload, WindowSize,
+    DEFAULT_WINDOW_SIZE, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use crate::TunnelStream;
 use anyhow::{Error, Result};
+use bytes::{Bytes, BytesMut};
 use futures::stream::StreamExt;
 use log::*;
+use ring::constant_time::verify_slices_are_equal;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
 use std::time::Duration;
-use tokio::time;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::Semaphore,
+    time,
+};
 use tokio_util::compat::*;
+use tunshell_shared::{Capabilities, KeyGenConfig, MessageFormat};
+
+mod auth_observer;
+pub use auth_observer::*;
+
+mod auth_backoff;
+use auth_backoff::*;
+
+mod banner;
+use banner::*;
+
+mod report;
+pub(crate) use report::*;
+
+mod builder;
+pub use builder::*;
+
+mod config;
+pub use config::*;
 
 mod fallback;
 use fallback::*;
 
+mod key_set;
+pub(crate) use key_set::*;
+
+mod limits;
+pub use limits::*;
+
+mod umask;
+use umask::*;
+
+mod clean_env;
+use clean_env::*;
+
+mod unavailable;
+pub use unavailable::*;
+
+mod run_as;
+pub use run_as::*;
+
+mod sandbox;
+pub use sandbox::*;
+
 mod default;
-pub(self) use default::*;
+use default::*;
+
+mod detached;
+use detached::*;
 
 mod shell;
 use shell::*;
 
+mod pipe;
+use pipe::*;
+
+mod shell_io;
+use shell_io::*;
+
+mod signal;
+use signal::*;
+
+mod cwd;
+use cwd::*;
+
+mod paste;
+use paste::*;
+
+mod colors;
+use colors::*;
+
+mod locale;
+use locale::*;
+
+mod system_info;
+use system_info::*;
+
+mod hook;
+use hook::*;
+
+mod shells;
+use shells::*;
+
+mod utf8;
+use utf8::*;
+
+mod osc;
+use osc::*;
+
+mod output;
+use output::*;
+
+mod rate_limit;
+use rate_limit::*;
+
+mod fairness;
+use fairness::*;
+
 #[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
 mod pty;
 #[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
 use pty::*;
 
+#[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
+mod pty_pool;
+#[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
+use pty_pool::*;
+
+#[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
+mod pty_factory;
+#[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
+use pty_factory::*;
+
 type ShellStream = ShellServerStream<Compat<Box<dyn TunnelStream>>>;
 
-pub(crate) struct ShellServer {}
+/// The number of times to check for the shell's exit code after it has closed
+const EXIT_CODE_ATTEMPTS: u32 = 10;
+/// The delay between checks for the shell's exit code
+const EXIT_CODE_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// The delay before the first retry of a transient pty failure, doubled for each subsequent retry
+const PTY_SPAWN_RETRY_DELAY: Duration = Duration::from_millis(20);
+
+/// Coalesced stdin is written to the shell once it reaches this size rather than waiting
+/// for the window to end. The write completes before the next message is read from the
+/// client, so large inputs are held back by the shell instead of buffered in memory.
+const MAX_PENDING_STDIN: usize = 64 * 1024;
+
+/// Resizes received within this period of the last applied resize are coalesced and only
+/// the latest is applied once it ends, so dragging a window does not flood the shell with
+/// SIGWINCH. The first resize after a quiet period is applied immediately.
+const RESIZE_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// The length of the random nonce sent to clients which prove their key
+const CHALLENGE_NONCE_LEN: usize = 32;
+
+/// How the shell's io is streamed to the client, chosen when the shell is started
+/// and kept with the shell while it is detached
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ShellIoMode {
+    raw: bool,
+    titles: bool,
+}
+
+/// Clones of the server share the same session limit, count, detached shells
+/// and record of authentication failures
+#[derive(Clone)]
+pub struct ShellServer {
+    config: ShellServerConfig,
+    session_permits: Option<Arc<Semaphore>>,
+    active_sessions: Arc<AtomicUsize>,
+    auth_observer: Arc<dyn AuthObserver + Send + Sync>,
+    auth_failures: AuthFailures,
+    detached_shells: DetachedShells,
+    peer_addr: Option<String>,
+    reconnect_token: Option<String>,
+    #[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
+    pty_factory: Arc<dyn PtyFactory + Send + Sync>,
+}
 
 impl ShellServer {
-    pub(crate) fn new() -> Result<ShellServer> {
-        Ok(ShellServer {})
+    pub fn new() -> Result<ShellServer> {
+        Self::with_config(ShellServerConfig::default())
+    }
+
+    pub(crate) fn with_config(config: ShellServerConfig) -> Result<ShellServer> {
+        config.validate()?;
+
+        let session_permits = config
+            .max_concurrent_sessions
+            .map(|max| Arc::new(Semaphore::new(max)));
+        let detached_shells =
+            DetachedShells::new(config.detached_shell_ttl, config.max_detached_shells);
+        #[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
+        let pty_factory = Arc::new(NativePtyFactory::new(config.pty_pool_size));
+
+        Ok(ShellServer {
+            config,
+            session_permits,
+            active_sessions: Arc::new(AtomicUsize::new(0)),
+            auth_observer: Arc::new(NoopAuthObserver),
+            auth_failures: AuthFailures::default(),
+            detached_shells,
+            peer_addr: None,
+            reconnect_token: None,
+            #[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
+            pty_factory,
+        })
     }
 
-    pub(crate) async fn run(self, stream: Box<dyn TunnelStream>, key: ShellKey) -> Result<()> {
+    /// Returns a builder for configuring the server, `ShellServer::new()` uses the defaults
+    pub fn builder() -> ShellServerBuilder {
+        ShellServerBuilder::new()
+    }
+
+    /// Sets the address of the peer this server is running a session for,
+    /// which is substituted into the banner
+    pub(crate) fn with_peer_addr(mut self, peer_addr: &str) -> Self {
+        self.peer_addr = Some(peer_addr.to_owned());
+        self
+    }
+
+    /// Sets the session's reconnect token, shells can only be detached when it is set
+    /// and are reattached by clients which send the same token.
+    /// An empty token, sent by the relay when reconnecting is not permitted, is ignored.
+    pub(crate) fn with_reconnect_token(mut self, token: &str) -> Self {
+        self.reconnect_token = Some(token.to_owned()).filter(|i| !i.is_empty());
+        self
+    }
+
+    /// Registers an observer to be notified when a client's key is accepted or rejected
+    pub fn with_auth_observer(mut self, observer: Arc<dyn AuthObserver + Send + Sync>) -> Self {
+        self.auth_observer = observer;
+        self
+    }
+
+    /// The number of sessions currently being run by this server
+    pub(crate) fn active_sessions(&self) -> usize {
+        self.active_sessions.load(Ordering::SeqCst)
+    }
+
+    /// The number of shells waiting to be reattached by a client
+    pub(crate) fn detached_shell_count(&self) -> usize {
+        self.detached_shells.len()
+    }
+
+    /// How long detached shells are kept for the client to reattach to
+    pub(crate) fn detached_shell_ttl(&self) -> Duration {
+        self.config.detached_shell_ttl
+    }
+
+    pub(crate) async fn run(
+        self,
+        stream: Box<dyn TunnelStream>,
+        keys: impl Into<KeySet>,
+    ) -> Result<SessionReport> {
         let mut stream = ShellStream::new(stream.compat());
+        stream.set_log_payloads(self.config.log_payloads);
+        stream.set_max_message_size(self.config.max_message_size);
+
+        let result = self.run_session(&mut stream, keys.into()).await;
+
+        let reason = match result.as_ref() {
+            Ok(report) if report.detached => "shell detached".to_owned(),
+            Ok(_) => "shell exited".to_owned(),
+            Err(err) => format!("{:#}", err),
+        };
+
+        // The client may have already disconnected
+        if let Err(err) = stream.write(&ShellServerMessage::Close { reason }).await {
+            debug!("failed to send close to client: {}", err);
+        }
+
+        if result.is_ok() {
+            // We keep the connection alive for some time to allow the receive
+            // of any acknowledgement packets and so the client can continue to receive
+            // the last message
+            // Improvement: add trait method to TunnelStream wait for ack'd connection state
+            time::delay_for(Duration::from_millis(500)).await;
+        }
+
+        result
+    }
+
+    async fn run_session(&self, stream: &mut ShellStream, keys: KeySet) -> Result<SessionReport> {
+        let session_permits = self.session_permits.clone();
+        let _permit = match session_permits.as_ref().map(|i| i.try_acquire()) {
+            Some(Err(_)) => {
+                warn!("rejecting session as server is at capacity");
+                stream
+                    .write(&ShellServerMessage::fatal("server at capacity"))
+                    .await?;
+                return Err(Error::msg("server at capacity"));
+            }
+            permit => permit,
+        };
+
+        let _session = ActiveSession::new(&self.active_sessions);
+        let session_id = KeyGenConfig::default().generate();
+        let started_at = time::Instant::now();
+        let mut report = SessionReport::new(&session_id);
+        info!("starting session {}", session_id);
+        info!("active sessions: {}", self.active_sessions());
+
+        info!("waiting for hello");
+        let hello = self.wait_for_hello(stream).await?;
+        info!("negotiated protocol version {}", hello.protocol_version);
 
         info!("waiting for key");
-        self.wait_for_key(&mut stream, key).await?;
-        info!("successfully authenticated client");
+        let key_idx = if hello.key_proof {
+            self.wait_for_key_proof(stream, &keys).await?
+        } else {
+            self.wait_for_key(stream, &keys).await?
+        };
+        info!("successfully authenticated client using key #{}", key_idx);
 
         info!("waiting for shell request");
-        let shell = self.start_shell(&mut stream).await?;
+        let forced_command = keys.get(key_idx).and_then(|i| i.forced_command());
+        let (shell, mode) = self.start_shell(stream, forced_command).await?;
         info!("shell started");
 
-        self.steam_shell_io(&mut stream, shell).await?;
+        let nodelay = self.config.nodelay.enabled(mode.raw);
+        debug!("setting nodelay to {} on the connection", nodelay);
+        if let Err(err) = stream.inner_mut().get_mut().set_nodelay(nodelay) {
+            warn!("failed to set nodelay on the connection: {}", err);
+        }
+
+        // Raw mode output must be passed through untouched
+        if !mode.raw {
+            self.send_banner(stream, &session_id).await?;
+        }
+
+        let result = self
+            .steam_shell_io(stream, shell, mode.raw, mode.titles, &mut report)
+            .await;
+
+        #[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
+        self.pty_factory.release();
+
+        result?;
+        report.duration_ms = started_at.elapsed().as_millis() as u64;
+
+        Ok(report)
+    }
+
+    /// The optional features supported by shells on this platform,
+    /// detaching is only supported when the server has a reconnect token
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::PIPE | Capabilities::RAW;
+
+        #[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
+        {
+            capabilities |= Capabilities::PTY;
+        }
+
+        #[cfg(unix)]
+        {
+            capabilities |= Capabilities::SIGNALS;
+        }
+
+        if self.reconnect_token.is_some() {
+            capabilities |= Capabilities::DETACH;
+        }
+
+        capabilities |=
+            Capabilities::KEY_PROOF | Capabilities::SEQUENCE_NUMBERS | Capabilities::FRAME_V2;
+
+        capabilities
+    }
+
+    /// Waits for the client's hello, returning it with the negotiated protocol version
+    async fn wait_for_hello(&self, stream: &mut ShellStream) -> Result<HelloPayload> {
+        let hello = tokio::select! {
+            message = stream.next() => match message {
+                Some(Ok(ShellClientMessage::Hello(hello))) => hello,
+                // Clients predating the hello start by sending their key
+                Some(Ok(ShellClientMessage::Key(_))) => return self.reject_pre_hello_client(stream).await,
+                Some(Ok(message)) => return Err(Error::msg(format!("received unexpected message from client: {:?}", message))),
+                Some(Err(err)) => return Err(err.context("received invalid message from client")),
+                None => return Err(Error::msg("client did not send hello"))
+            },
+            _ = time::delay_for(self.config.handshake_timeout) => return Err(Error::msg("timed out while waiting for hello"))
+        };
+
+        if let Some(version) = negotiate_protocol_version(hello.protocol_version) {
+            stream
+                .write(&ShellServerMessage::HelloAck(HelloAckPayload {
+                    protocol_version: version,
+                    accepted: true,
+                    capabilities: self.capabilities(),
+                    format: hello.format,
+                }))
+                .await?;
+
+            // Every message after the ack is sent in the format requested by the client
+            stream.set_format(hello.format);
+            stream.set_sequence_numbers(hello.sequence_numbers);
+            stream.set_frame_version(hello.frame_version);
+
+            return Ok(HelloPayload {
+                protocol_version: version,
+                ..hello
+            });
+        }
+
+        let message = format!(
+            "client protocol version {} is not supported by server (supported versions {} to {})",
+            hello.protocol_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        );
+
+        stream
+            .write(&ShellServerMessage::HelloAck(HelloAckPayload {
+                protocol_version: PROTOCOL_VERSION,
+                accepted: false,
+                capabilities: self.capabilities(),
+                format: MessageFormat::Binary,
+            }))
+            .await?;
+        stream
+            .write(&ShellServerMessage::fatal(message.clone()))
+            .await?;
+
+        Err(Error::msg(message))
+    }
+
+    /// Tells a client which does not send a hello that it must be upgraded
+    async fn reject_pre_hello_client<T>(&self, stream: &mut ShellStream) -> Result<T> {
+        let message = format!(
+            "client does not support the shell protocol, version {} to {} is required",
+            MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        );
+
+        stream
+            .write(&ShellServerMessage::fatal(message.clone()))
+            .await?;
+
+        Err(Error::msg(message))
+    }
+
+    /// Waits for the client to send its key, returning the index of the matched key
+    async fn wait_for_key(&self, stream: &mut ShellStream, keys: &KeySet) -> Result<usize> {
+        let received_key = tokio::select! {
+            message = stream.next() => match message {
+                Some(Ok(ShellClientMessage::Key(key))) => key,
+                Some(Ok(message)) => return Err(Error::msg(format!("received unexpected message from client: {:?}", message))),
+                Some(Err(err)) => return Err(err.context("received invalid message from client")),
+                None => return Err(Error::msg("client did not sent key"))
+            },
+            _ = time::delay_for(self.config.handshake_timeout) => return Err(Error::msg("timed out while waiting for key"))
+        };
+
+        self.respond_to_key(stream, keys.find(&received_key)).await
+    }
+
+    /// Sends the client a random nonce and waits for the proof of its key,
+    /// returning the index of the matched key.
+    /// A fresh nonce is issued for every session so a captured proof cannot be replayed.
+    async fn wait_for_key_proof(&self, stream: &mut ShellStream, keys: &KeySet) -> Result<usize> {
+        let nonce = generate_nonce()?;
+        stream
+            .write(&ShellServerMessage::Challenge(nonce.clone()))
+            .await?;
+
+        self.verify_key_proof(stream, keys, &nonce).await
+    }
+
+    async fn verify_key_proof(
+        &self,
+        stream: &mut ShellStream,
+        keys: &KeySet,
+        nonce: &[u8],
+    ) -> Result<usize> {
+        let proof = tokio::select! {
+            message = stream.next() => match message {
+                Some(Ok(ShellClientMessage::KeyProof(proof))) => proof,
+                Some(Ok(message)) => return Err(Error::msg(format!("received unexpected message from client: {:?}", message))),
+                Some(Err(err)) => return Err(err.context("received invalid message from client")),
+                None => return Err(Error::msg("client did not send key proof"))
+            },
+            _ = time::delay_for(self.config.handshake_timeout) => return Err(Error::msg("timed out while waiting for key proof"))
+        };
+
+        self.respond_to_key(stream, keys.find_proof(nonce, &proof))
+            .await
+    }
+
+    /// Notifies the client and the auth observer whether a key was matched
+    async fn respond_to_key(
+        &self,
+        stream: &mut ShellStream,
+        key_index: Option<usize>,
+    ) -> Result<usize> {
+        let peer = AuthPeer {
+            key_index,
+            peer_addr: self.peer_addr.clone(),
+        };
+
+        if let Some(idx) = key_index {
+            self.auth_observer.on_accepted(&peer);
+            if let Some(peer_addr) = self.peer_addr.as_ref() {
+                self.auth_failures.clear(peer_addr);
+            }
+            stream.write(&ShellServerMessage::KeyAccepted).await?;
+            Ok(idx)
+        } else {
+            self.auth_observer.on_rejected(&peer);
+            if let Some(delay) = self.rejection_delay() {
+                // Only this connection waits, other sessions continue to be served
+                info!("delaying key rejection by {:?}", delay);
+                time::delay_for(delay).await;
+            }
+            stream.write(&ShellServerMessage::KeyRejected).await?;
+            Err(Error::msg("client key rejected"))
+        }
+    }
+
+    /// Records the failure against the peer, returning how long to wait before rejecting it
+    fn rejection_delay(&self) -> Option<Duration> {
+        let delay = self.config.auth_failure_delay?;
+        let failures = match self.peer_addr.as_ref() {
+            Some(peer_addr) => self.auth_failures.record(peer_addr),
+            None => 1,
+        };
+
+        Some(auth_failure_delay(
+            delay,
+            self.config.max_auth_failure_delay,
+            failures,
+        ))
+    }
+
+    /// Starts the shell requested by the client, returning the shell and how its io is streamed.
+    /// When the client's key has a forced command it is run in place of the requested shell.
+    async fn start_shell(
+        &self,
+        stream: &mut ShellStream,
+        forced_command: Option<&[String]>,
+    ) -> Result<(Box<dyn Shell + Send>, ShellIoMode)> {
+        let deadline = time::Instant::now() + self.config.handshake_timeout;
+
+        // The client may list the available shells before choosing one
+        let request = loop {
+            tokio::select! {
+                message = stream.next() => match message {
+                    Some(Ok(ShellClientMessage::StartShell(request))) => break request,
+                    Some(Ok(ShellClientMessage::Reattach(payload))) => {
+                        return self.reattach_shell(stream, payload, forced_command).await;
+                    }
+                    Some(Ok(ShellClientMessage::ListShells)) => {
+                        let shells = available_shells(self.config.allowed_shells.as_deref());
+                        stream.write(&ShellServerMessage::Shells(shells)).await?;
+                    }
+                    Some(Ok(ShellClientMessage::SystemInfo)) => {
+                        stream.write(&ShellServerMessage::SystemInfo(system_info())).await?;
+                    }
+                    Some(Ok(message)) => return Err(Error::msg(format!("received unexpected message from client: {:?}", message))),
+                    Some(Err(err)) => return Err(err.context("received invalid message from client")),
+                    None => return Err(Error::msg("client did not send start shell message"))
+                },
+                _ = time::delay_until(deadline) => return Err(Error::msg("timed out while waiting for shell request"))
+            }
+        };
+
+        if let Some(shell_path) = request.shell_path.as_ref() {
+            let shells = available_shells(self.config.allowed_shells.as_deref());
+
+            if !shells.contains(shell_path) {
+                return Err(Error::msg(format!(
+                    "requested shell {} is not available",
+                    shell_path
+                )));
+            }
+        }
+
+        if let Some(hook) = self.config.pre_shell_hook.as_ref() {
+            self.run_pre_shell_hook(stream, hook).await?;
+        }
+
+        let size = match request.size.as_ref().filter(|i| **i != WindowSize(0, 0)) {
+            Some(size) => size.clamped(),
+            None => {
+                info!(
+                    "client did not supply a window size, using default of {}x{}",
+                    DEFAULT_WINDOW_SIZE.0, DEFAULT_WINDOW_SIZE.1
+                );
+                DEFAULT_WINDOW_SIZE
+            }
+        };
+        let shell = match forced_command {
+            Some(command) => {
+                self.create_forced_command_shell(&request, size.clone(), command)
+                    .await
+            }
+            None => self.create_shell(&request, size.clone()).await,
+        };
+        let shell = match shell {
+            Ok(shell) => shell,
+            Err(err) => {
+                if let Some(unavailable) = err.downcast_ref::<ShellUnavailable>() {
+                    stream
+                        .write(&ShellServerMessage::fatal(unavailable.to_string()))
+                        .await?;
+                }
+
+                if let Some(err) = err.downcast_ref::<ShellServerError>() {
+                    stream
+                        .write(&ShellServerMessage::fatal(err.to_string()))
+                        .await?;
+                }
+
+                return Err(err);
+            }
+        };
+        stream.write(&ShellServerMessage::SizeApplied(size)).await?;
+
+        let mode = ShellIoMode {
+            raw: request.raw,
+            titles: request.titles,
+        };
+
+        Ok((shell, mode))
+    }
+
+    /// Resumes the shell detached under the session's reconnect token, which the client
+    /// sends in place of a shell request.
+    /// Keys with a forced command cannot reattach as the detached shell may be running
+    /// something else.
+    async fn reattach_shell(
+        &self,
+        stream: &mut ShellStream,
+        payload: ReattachPayload,
+        forced_command: Option<&[String]>,
+    ) -> Result<(Box<dyn Shell + Send>, ShellIoMode)> {
+        let token = match self.reconnect_token.as_ref() {
+            Some(token) if forced_command.is_none() => token,
+            _ => {
+                return self
+                    .reject_reattach(stream, "reattaching is not permitted")
+                    .await
+            }
+        };
+
+        if verify_slices_are_equal(token.as_bytes(), payload.token.as_bytes()).is_err() {
+            warn!("client sent an invalid reconnect token");
+            return self
+                .reject_reattach(stream, "invalid reconnect token")
+                .await;
+        }
+
+        let detached = match self.detached_shells.take(&payload.token) {
+            Some(detached) => detached,
+            None => {
+                return self
+                    .reject_reattach(stream, "no detached shell to reattach")
+                    .await
+            }
+        };
+        info!("reattaching detached shell");
+        info!("detached shells: {}", self.detached_shells.len());
+
+        let size = match payload.size.filter(|i| *i != WindowSize(0, 0)) {
+            Some(size) => size.clamped(),
+            None => DEFAULT_WINDOW_SIZE,
+        };
+        let mut shell = detached.shell;
+        if let Err(err) = shell.resize(size.clone()) {
+            if let Err(terminate_err) = shell.terminate() {
+                warn!("failed to terminate shell: {}", terminate_err);
+            }
+            return Err(err);
+        }
+        stream.write(&ShellServerMessage::SizeApplied(size)).await?;
+
+        let mode = ShellIoMode {
+            raw: detached.raw,
+            titles: detached.titles,
+        };
+
+        Ok((shell, mode))
+    }
+
+    async fn reject_reattach<T>(&self, stream: &mut ShellStream, reason: &str) -> Result<T> {
+        stream.write(&ShellServerMessage::fatal(reason)).await?;
+        Err(Error::msg(reason.to_owned()))
+    }
+
+    async fn create_shell(
+        &self,
+        request: &StartShellPayload,
+        size: WindowSize,
+    ) -> Result<Box<dyn Shell + Send>> {
+        if request.raw {
+            // Raw mode requires byte-exact output so there is no pty and stderr is not
+            // interleaved with stdout, nor can we fall back to the in-built shell
+            debug!("initialising raw pipe shell");
+            return Ok(Box::new(PipeShell::with_command(
+                self.shell_program(request, None)?,
+                false,
+            )?));
+        }
+
+        // The reason the last shell could not be started
+        let mut failure = None;
+
+        // In line mode the client echoes its input so a pty, which would echo it again, is not used
+        let pty = request.pty && !request.line_mode;
+
+        if !pty {
+            debug!("initialising pipe shell");
+            let pipe_shell = self
+                .shell_program(request, None)
+                .and_then(|program| PipeShell::with_command(program, true));
+
+            match pipe_shell {
+                Ok(pipe_shell) => return Ok(Box::new(pipe_shell)),
+                Err(err) => {
+                    warn!("failed to init pipe shell: {:?}", err);
+                    failure = Some(err);
+                }
+            }
+        }
+
+        #[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
+        {
+            if pty {
+                debug!("initialising pty shell");
+                let invocation = ShellInvocation {
+                    login: request.login,
+                    interactive: request.interactive,
+                };
+                let pty_shell = match self.shell_program(request, Some(invocation)) {
+                    Ok(program) => {
+                        self.spawn_pty_shell(request.term.as_ref(), program, size.clone())
+                            .await
+                    }
+                    Err(err) => Err(err),
+                };
+
+                match pty_shell {
+                    Ok(pty_shell) => return Ok(pty_shell),
+                    Err(err) => {
+                        warn!("failed to init pty shell: {:?}", err);
+                        failure = Some(err);
+                    }
+                }
+            }
+        }
+
+        if !self.config.fallback_on_missing_shell {
+            if let Some(err) = failure
+                .as_ref()
+                .and_then(|i| i.downcast_ref::<ShellServerError>())
+            {
+                warn!("not falling back to in-built shell: {}", err);
+                return Err(Error::new(err.clone()));
+            }
+        }
+
+        if let Some(action) = self.config.no_shell_action {
+            let unavailable = ShellUnavailable(match failure {
+                Some(err) => format!("{:#}", err),
+                None => "ptys are not supported on this platform".to_owned(),
+            });
+            warn!("not falling back to in-built shell: {}", unavailable);
+
+            return match action {
+                NoShellAction::Error => Err(Error::new(unavailable)),
+                NoShellAction::MessageOnly => Ok(Box::new(UnavailableShell::new(unavailable))),
+            };
+        }
+
+        if !self.config.resource_limits.is_empty() {
+            return Err(Error::msg(
+                "cannot fall back to the in-built shell as resource limits cannot be applied to it",
+            ));
+        }
+
+        if self.config.umask.is_some() {
+            // Commands run by the in-built shell inherit the umask of this process
+            return Err(Error::msg(
+                "cannot fall back to the in-built shell as the umask cannot be applied to it",
+            ));
+        }
+
+        if self.config.run_as.is_some() {
+            // The in-built shell runs in this process so would not drop privileges
+            return Err(Error::msg(
+                "cannot fall back to the in-built shell as it cannot be run as another user",
+            ));
+        }
+
+        if self.config.sandbox.is_some() {
+            return Err(Error::msg(
+                "cannot fall back to the in-built shell as it cannot be sandboxed",
+            ));
+        }
+
+        debug!("falling back to in-built shell");
+        let fallback_shell = FallbackShell::new(request.term.as_ref(), size, self.config.clean_env);
+
+        Ok(Box::new(fallback_shell))
+    }
+
+    /// Runs the forced command of the client's key, the client's requested shell is ignored.
+    /// This never falls back to the in-built shell as that would not be restricted to the command.
+    async fn create_forced_command_shell(
+        &self,
+        request: &StartShellPayload,
+        size: WindowSize,
+        command: &[String],
+    ) -> Result<Box<dyn Shell + Send>> {
+        info!(
+            "ignoring requested shell, running forced command: {:?}",
+            command
+        );
+        let mut program = DefaultShell::from_command(command)?;
+        program.env.extend(color_env(request));
+        program.env.extend(locale_env(request));
+        let program = self.restrict_program(program)?;
+
+        #[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
+        {
+            if request.pty && !request.raw && !request.line_mode {
+                debug!("initialising pty for forced command");
+                return self
+                    .spawn_pty_shell(request.term.as_ref(), program, size)
+                    .await;
+            }
+        }
+
+        debug!("initialising pipe for forced command");
+        let pipe_shell = PipeShell::with_command(program, !request.raw)?;
+
+        Ok(Box::new(pipe_shell))
+    }
+
+    /// Runs the pre-shell hook, returning an error after reporting it to the client
+    /// if the hook could not be run or exited unsuccessfully
+    async fn run_pre_shell_hook(&self, stream: &mut ShellStream, hook: &[String]) -> Result<()> {
+        let result = run_pre_shell_hook(hook).await;
+
+        let err = match result {
+            Ok(hook_output) => {
+                if self.config.stream_pre_shell_hook_output {
+                    let chunk_size = stream.inner().get_ref().preferred_chunk_size().max(1);
+                    for chunk in hook_output.output.chunks(chunk_size) {
+                        stream
+                            .write(&ShellServerMessage::Stdout(Bytes::copy_from_slice(chunk)))
+                            .await?;
+                    }
+                } else if !hook_output.output.is_empty() {
+                    debug!(
+                        "pre-shell hook output: {}",
+                        String::from_utf8_lossy(&hook_output.output)
+                    );
+                }
+
+                if hook_output.success() {
+                    return Ok(());
+                }
+
+                match hook_output.exit_code {
+                    Some(code) => {
+                        Error::msg(format!("pre-shell hook failed with exit status {}", code))
+                    }
+                    None => Error::msg("pre-shell hook was terminated by a signal"),
+                }
+            }
+            Err(err) => err,
+        };
+
+        warn!("aborting session: {:#}", err);
+        stream
+            .write(&ShellServerMessage::fatal(format!("{:#}", err)))
+            .await?;
+
+        Err(err)
+    }
+
+    /// Runs the program in a pty, retrying with a backoff when the pty
+    /// could not be allocated due to a transient failure
+    #[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
+    async fn spawn_pty_shell(
+        &self,
+        term: &str,
+        program: DefaultShell,
+        size: WindowSize,
+    ) -> Result<Box<dyn Shell + Send>> {
+        let mut attempt = 0;
+        let mut delay = PTY_SPAWN_RETRY_DELAY;
+
+        loop {
+            let err = match self.pty_factory.spawn(term, program.clone(), size.clone()) {
+                Ok(shell) => return Ok(shell),
+                Err(err) => err,
+            };
+
+            if attempt >= self.config.pty_spawn_retries || !is_transient_pty_error(&err) {
+                return Err(err);
+            }
+
+            attempt += 1;
+            warn!(
+                "failed to allocate pty, retrying in {:?} ({}/{}): {:?}",
+                delay, attempt, self.config.pty_spawn_retries, err
+            );
+            time::delay_for(delay).await;
+            delay *= 2;
+        }
+    }
+
+    /// The program used to start the requested or default shell, restricted as configured
+    fn shell_program(
+        &self,
+        request: &StartShellPayload,
+        invocation: Option<ShellInvocation>,
+    ) -> Result<DefaultShell> {
+        let shell_path = request
+            .shell_path
+            .as_deref()
+            .or(self.config.shell_path.as_deref());
+        let shell = get_default_shell(shell_path)?;
+        let args = match invocation {
+            Some(invocation) => shell.invocation_args(invocation),
+            None => shell.args.clone(),
+        };
+
+        let mut env = shell.env.clone();
+        env.extend(color_env(request));
+        env.extend(locale_env(request));
+
+        self.restrict_program(DefaultShell { args, env, ..shell })
+    }
+
+    /// Clears the inherited environment, applies the configured umask and resource limits,
+    /// drops privileges to the configured user and sandboxes the shell. Each step wraps the
+    /// program returned by the previous one, so the steps run in the reverse of the order they
+    /// are applied: the sandbox, applied last, is created first while the process still has
+    /// the privileges to do so, then privileges are dropped before the limits are set.
+    /// Fails rather than returning a program which would run with elevated privileges.
+    fn restrict_program(&self, program: DefaultShell) -> Result<DefaultShell> {
+        let program = apply_clean_env(self.config.clean_env, program);
+        let program = apply_umask(self.config.umask, program);
+        let program = self.config.resource_limits.apply(program);
+
+        let program = match self.config.run_as.as_ref() {
+            Some(run_as) => run_as.apply(program)?,
+            None => program,
+        };
+
+        match self.config.sandbox.as_ref() {
+            Some(sandbox) => sandbox.apply(program),
+            None => Ok(program),
+        }
+    }
+
+    /// Sends the configured banner to the client, if any
+    async fn send_banner(&self, stream: &mut ShellStream, session_id: &str) -> Result<()> {
+        let template = match self.config.banner.as_ref() {
+            Some(template) => template,
+            None => return Ok(()),
+        };
+
+        let banner = render_banner(
+            template,
+            &BannerContext {
+                session_id,
+                peer_addr: self.peer_addr.as_deref(),
+            },
+        );
+
+        stream
+            .write(&ShellServerMessage::Stdout(Bytes::from(banner)))
+            .await
+    }
+
+    /// Streams io between the client and the shell, recording the transfer in the report.
+    /// In raw mode the output is passed through without any processing.
+    /// When `titles` is set a `Title` message follows output which sets the terminal title.
+    /// When both output and input are ready they are served alternately, see `FairIo`.
+    /// If the client disconnects the shell is detached when `detach_on_disconnect` is enabled
+    /// and the session has a reconnect token, otherwise it is terminated along with any
+    /// other error.
+    async fn steam_shell_io(
+        &self,
+        stream: &mut ShellStream,
+        shell: Box<dyn Shell + Send>,
+        raw: bool,
+        titles: bool,
+        report: &mut SessionReport,
+    ) -> Result<()> {
+        let mut shell_io = ShellIo::new(shell);
+        let result = self
+            .steam_shell_io_loop(stream, &mut shell_io, raw, titles, report)
+            .await;
+        let mut shell = shell_io.into_inner();
+
+        match result {
+            Ok(false) => Ok(()),
+            Ok(true) => match self.detach_shell(shell, raw, titles) {
+                Ok(token) => {
+                    report.detached = true;
+                    self.write_to_client(stream, &ShellServerMessage::Detached(token))
+                        .await
+                }
+                Err(err) => {
+                    self.write_to_client(stream, &ShellServerMessage::fatal(err.to_string()))
+                        .await?;
+                    Err(err)
+                }
+            },
+            Err(err)
+                if self.config.detach_on_disconnect
+                    && self.reconnect_token.is_some()
+                    && is_disconnect(&err) =>
+            {
+                warn!("lost connection to client, detaching shell: {:#}", err);
+                if let Err(detach_err) = self.detach_shell(shell, raw, titles) {
+                    warn!("{}", detach_err);
+                } else {
+                    report.detached = true;
+                }
+                Err(err)
+            }
+            Err(err) => {
+                // Otherwise the shell could be left running without a client
+                if let Err(terminate_err) = shell.terminate() {
+                    warn!("failed to terminate shell: {}", terminate_err);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Stores the shell under the session's reconnect token, returning the token.
+    /// The shell is terminated if it cannot be detached.
+    fn detach_shell(
+        &self,
+        shell: Box<dyn Shell + Send>,
+        raw: bool,
+        titles: bool,
+    ) -> Result<String> {
+        let token = match self.reconnect_token.as_ref() {
+            Some(token) => token,
+            None => return Err(Error::msg("detaching is not supported by this session")),
+        };
+
+        if let Err(mut refused) = self
+            .detached_shells
+            .insert(token, DetachedShell { shell, raw, titles })
+        {
+            if let Err(err) = refused.shell.terminate() {
+                warn!("failed to terminate shell: {}", err);
+            }
+            return Err(Error::msg(
+                "too many detached shells, the shell has been terminated",
+            ));
+        }
+        info!("detached shells: {}", self.detached_shells.len());
+
+        Ok(token.clone())
+    }
+
+    /// Returns whether the client detached from the shell
+    async fn steam_shell_io_loop(
+        &self,
+        stream: &mut ShellStream,
+        shell: &mut ShellIo,
+        raw: bool,
+        titles: bool,
+        report: &mut SessionReport,
+    ) -> Result<bool> {
+        // Output is read in chunks of the size preferred by the underlying transport
+        let chunk_size = stream.inner().get_ref().preferred_chunk_size().max(1);
+        // Each chunk of output is split off from this buffer and sent without copying,
+        // the allocation is reclaimed once the sent chunk has been dropped
+        let mut buff = BytesMut::with_capacity(chunk_size);
+        let deadline = self
+            .config
+            .max_session_duration
+            .map(|duration| time::Instant::now() + duration);
+        let idle_deadline_from_now = || {
+            self.config
+                .idle_timeout
+                .map(|timeout| time::Instant::now() + timeout)
+        };
+        let mut idle_deadline = idle_deadline_from_now();
+        let keepalive_deadline_from_now = || {
+            self.config
+                .keepalive_interval
+                .map(|interval| time::Instant::now() + interval)
+        };
+        let mut keepalive_deadline = keepalive_deadline_from_now();
+        let mut read_deadline = self
+            .config
+            .read_timeout
+            .map(|timeout| time::Instant::now() + timeout);
+        let mut chunker = if raw {
+            None
+        } else {
+            OutputChunker::from_config(&self.config)
+        };
+        let mut output_flush_deadline = None;
+        let mut rate_limiter = RateLimiter::new(self.config.max_bytes_per_sec);
+        let mut pending_stdin = vec![];
+        let mut stdin_flush_deadline = None;
+        let mut stdin_closed = false;
+        let mut pending_resize = None;
+        let mut resize_deadline = None;
+        let mut detached = false;
+        let fair_io = FairIo::new();
+        let payload_log_level = if self.config.log_payloads {
+            Level::Info
+        } else {
+            Level::Debug
+        };
+
+        loop {
+            if self
+                .config
+                .max_output_bytes
+                .is_some_and(|max| report.stdout_bytes > max)
+            {
+                warn!("output limit exceeded, terminating shell");
+                self.write_to_client(stream, &ShellServerMessage::fatal("output limit exceeded"))
+                    .await?;
+                shell.shell().await.terminate()?;
+                break;
+            }
+
+            buff.resize(chunk_size, 0);
+
+            trace!("waiting for shell message");
+            tokio::select! {
+                result = fair_io.serve(IoDirection::Stdout, shell.read(&mut buff)) => match result {
+                    Ok(0) => {
+                        if let Some(pending) = chunker.as_mut().map(|i| i.flush()).filter(|i| !i.is_empty()) {
+                            report.stdout_bytes += pending.len() as u64;
+                            self.write_to_client(stream, &ShellServerMessage::Stdout(pending)).await?;
+                        }
+
+                        let status = wait_for_exit_status(shell.shell().await.as_mut()).await;
+                        info!("shell has exited with status {:?}", status);
+                        report.exit_code = Some(status.process_exit_code());
+                        self.write_to_client(stream, &ShellServerMessage::Exited(status)).await?;
+                        info!("send exit code status");
+                        break;
+                    },
+                    Ok(read) => {
+                        log!(payload_log_level, "read {} bytes from stdout", read);
+                        idle_deadline = idle_deadline_from_now();
+                        keepalive_deadline = keepalive_deadline_from_now();
+                        let output = buff.split_to(read).freeze();
+                        let output = match chunker.as_mut() {
+                            Some(chunker) => {
+                                let output = chunker.push(output);
+                                output_flush_deadline = if chunker.has_pending() {
+                                    output_flush_deadline.or_else(|| Some(time::Instant::now() + self.config.output_flush_interval))
+                                } else {
+                                    None
+                                };
+                                output
+                            }
+                            None => output,
+                        };
+
+                        if !output.is_empty() {
+                            let len = output.len();
+                            let title = if titles { last_title(&output) } else { None };
+                            // Delaying here holds back further reads from the shell
+                            if let Some(limiter) = rate_limiter.as_mut() {
+                                limiter.acquire(len).await;
+                            }
+                            report.stdout_bytes += len as u64;
+                            self.write_to_client(stream, &ShellServerMessage::Stdout(output)).await?;
+                            log!(payload_log_level, "sent {} bytes to client shell", len);
+                            if let Some(title) = title {
+                                debug!("shell set terminal title: {}", title);
+                                self.write_to_client(stream, &ShellServerMessage::Title(title)).await?;
+                            }
+                        }
+                    },
+                    Err(err) => {
+                        error!("error while reading from stdout: {}", err);
+                        return Err(err.into());
+                    }
+                },
+                message = fair_io.serve(IoDirection::Stdin, next_message(stream, &mut read_deadline, self.config.read_timeout)) => match message? {
+                    Some(Ok(ShellClientMessage::Stdin(payload))) | Some(Ok(ShellClientMessage::Paste(payload))) if stdin_closed => {
+                        warn!("ignoring {} bytes received after stdin was closed", payload.len());
+                    }
+                    Some(Ok(ShellClientMessage::Stdin(payload))) => {
+                        log!(payload_log_level, "received {} bytes from client shell", payload.len());
+                        idle_deadline = idle_deadline_from_now();
+                        keepalive_deadline = keepalive_deadline_from_now();
+                        report.stdin_bytes += payload.len() as u64;
+                        pending_stdin.extend_from_slice(payload.as_slice());
+
+                        match self.config.stdin_coalesce_window {
+                            Some(window) if pending_stdin.len() < MAX_PENDING_STDIN => {
+                                stdin_flush_deadline = stdin_flush_deadline.or_else(|| Some(time::Instant::now() + window));
+                            }
+                            _ => {
+                                stdin_flush_deadline = None;
+                                write_stdin(shell, &mut pending_stdin).await?;
+                            }
+                        }
+                    }
+                    Some(Ok(ShellClientMessage::Paste(payload))) => {
+                        log!(payload_log_level, "received {} bytes of pasted input", payload.len());
+                        idle_deadline = idle_deadline_from_now();
+                        keepalive_deadline = keepalive_deadline_from_now();
+                        report.stdin_bytes += payload.len() as u64;
+                        stdin_flush_deadline = None;
+                        write_stdin(shell, &mut pending_stdin).await?;
+                        // Without a pty there is no terminal to interpret the paste markers
+                        if shell.shell().await.is_pty() {
+                            shell.write_all(&bracketed_paste(&payload)).await?;
+                        } else {
+                            shell.write_all(&payload).await?;
+                        }
+                    }
+                    Some(Ok(ShellClientMessage::Resize(size))) => {
+                        info!("received window resize: {:?}", size);
+                        stdin_flush_deadline = None;
+                        write_stdin(shell, &mut pending_stdin).await?;
+                        if resize_deadline.is_some() {
+                            debug!("coalescing window resize");
+                            pending_resize = Some(size);
+                        } else {
+                            self.apply_resize(shell.shell().await.as_mut(), stream, size).await?;
+                            resize_deadline = Some(time::Instant::now() + RESIZE_DEBOUNCE);
+                        }
+                    }
+                    Some(Ok(ShellClientMessage::Signal(signal))) => {
+                        info!("received signal: {}", signal);
+                        stdin_flush_deadline = None;
+                        write_stdin(shell, &mut pending_stdin).await?;
+                        if let Err(err) = shell.shell().await.signal(signal) {
+                            warn!("failed to send signal to shell: {}", err);
+                            self.write_to_client(stream, &ShellServerMessage::warning(format!("failed to send signal: {}", err))).await?;
+                        }
+                    }
+                    Some(Ok(ShellClientMessage::StdinClose)) => {
+                        info!("client closed stdin");
+                        stdin_flush_deadline = None;
+                        write_stdin(shell, &mut pending_stdin).await?;
+                        stdin_closed = true;
+                        if let Err(err) = shell.shutdown().await {
+                            warn!("failed to close shell stdin: {}", err);
+                            self.write_to_client(stream, &ShellServerMessage::warning(format!("failed to close stdin: {}", err))).await?;
+                        }
+                    }
+                    Some(Ok(ShellClientMessage::GetCwd)) => {
+                        info!("client requested working directory");
+                        let cwd = shell.shell().await.cwd();
+                        match cwd {
+                            Ok(cwd) => self.write_to_client(stream, &ShellServerMessage::Cwd(cwd)).await?,
+                            Err(err) => {
+                                warn!("failed to get working directory of shell: {}", err);
+                                self.write_to_client(stream, &ShellServerMessage::warning(format!("failed to get working directory: {}", err))).await?;
+                            }
+                        }
+                    }
+                    Some(Ok(ShellClientMessage::SystemInfo)) => {
+                        info!("client requested system info");
+                        self.write_to_client(stream, &ShellServerMessage::SystemInfo(system_info())).await?;
+                    }
+                    Some(Ok(ShellClientMessage::SetCwd(path))) => {
+                        info!("client changed working directory");
+                        stdin_flush_deadline = None;
+                        write_stdin(shell, &mut pending_stdin).await?;
+                        match cd_command(&path) {
+                            Ok(command) => shell.write_all(&command).await?,
+                            Err(err) => self.write_to_client(stream, &ShellServerMessage::warning(format!("failed to change working directory: {}", err))).await?,
+                        }
+                    }
+                    Some(Ok(ShellClientMessage::Detach)) if self.reconnect_token.is_none() => {
+                        warn!("client tried to detach without a reconnect token");
+                        self.write_to_client(stream, &ShellServerMessage::warning("detaching is not supported by this session")).await?;
+                    }
+                    Some(Ok(ShellClientMessage::Detach)) => {
+                        info!("client detached from shell");
+                        write_stdin(shell, &mut pending_stdin).await?;
+                        if let Some(size) = pending_resize.take() {
+                            self.apply_resize(shell.shell().await.as_mut(), stream, size).await?;
+                        }
+                        detached = true;
+                        break;
+                    }
+                    Some(Ok(ShellClientMessage::Unknown(type_id))) => {
+                        warn!("ignoring unknown message type {} from shell client", type_id);
+                    }
+                    Some(Ok(message)) => {
+                        return Err(Error::msg(format!("received unexpected message from shell client {:?}", message)));
+                    }
+                    Some(Err(err)) => {
+                        let err = err.context("received invalid message from shell client");
+                        // This is only attempted once as the stream may be unusable
+                        if let Err(write_err) = self.write_to_client(stream, &ShellServerMessage::fatal(format!("{:#}", err))).await {
+                            warn!("failed to send error to client: {}", write_err);
+                        }
+                        return Err(err);
+                    }
+                    None => {
+                        warn!("client shell stream ended");
+                        write_stdin(shell, &mut pending_stdin).await?;
+                        // The stream cannot be written to once it has ended so the final size
+                        // is applied to the shell without being reported
+                        if let Some(size) = pending_resize.take() {
+                            shell.shell().await.resize(size.clamped())?;
+                        }
+                        if self.config.detach_on_disconnect {
+                            return Err(Error::new(ClientDisconnected));
+                        }
+                        break;
+                    }
+                },
+                _ = wait_until(resize_deadline) => {
+                    resize_deadline = None;
+                    if let Some(size) = pending_resize.take() {
+                        self.apply_resize(shell.shell().await.as_mut(), stream, size).await?;
+                        resize_deadline = Some(time::Instant::now() + RESIZE_DEBOUNCE);
+                    }
+                }
+                _ = wait_until(stdin_flush_deadline) => {
+                    stdin_flush_deadline = None;
+                    write_stdin(shell, &mut pending_stdin).await?;
+                }
+                _ = wait_until(output_flush_deadline) => {
+                    output_flush_deadline = None;
+                    let pending = chunker.as_mut().map(|i| i.flush()).unwrap_or_default();
+                    debug!("flushing {} bytes of incomplete output", pending.len());
+                    report.stdout_bytes += pending.len() as u64;
+                    self.write_to_client(stream, &ShellServerMessage::Stdout(pending)).await?;
+                }
+                _ = wait_until(keepalive_deadline) => {
+                    debug!("no traffic within keepalive interval, sending ping");
+                    keepalive_deadline = keepalive_deadline_from_now();
+                    self.write_to_client(stream, &ShellServerMessage::Ping).await?;
+                }
+                _ = wait_until(idle_deadline) => {
+                    warn!("session idle timeout reached, terminating shell");
+                    self.write_to_client(stream, &ShellServerMessage::fatal("session idle timeout reached")).await?;
+                    shell.shell().await.terminate()?;
+                    break;
+                }
+                _ = wait_until(deadline) => {
+                    warn!("max session duration reached, terminating shell");
+                    self.write_to_client(stream, &ShellServerMessage::fatal("max session duration reached")).await?;
+                    shell.shell().await.terminate()?;
+                    break;
+                }
+            }
+        }
+
+        if detached {
+            if let Some(pending) = chunker
+                .as_mut()
+                .map(|i| i.flush())
+                .filter(|i| !i.is_empty())
+            {
+                report.stdout_bytes += pending.len() as u64;
+                self.write_to_client(stream, &ShellServerMessage::Stdout(pending))
+                    .await?;
+            }
+        }
+
+        Ok(detached)
+    }
+
+    /// Writes the message to the client, marking a failure with `ClientWriteError`.
+    /// A write which does not complete within the write timeout fails with `TransportStalled`.
+    async fn write_to_client(
+        &self,
+        stream: &mut ShellStream,
+        message: &ShellServerMessage,
+    ) -> Result<()> {
+        let result = match self.config.write_timeout {
+            Some(timeout) => match time::timeout(timeout, stream.write(message)).await {
+                Ok(result) => result,
+                Err(_) => Err(TransportStalled::Write(timeout).into()),
+            },
+            None => stream.write(message).await,
+        };
+
+        result.map_err(|err| err.context(ClientWriteError))
+    }
+
+    /// Resizes the shell to the requested size, clamped to the supported range,
+    /// and informs the client of the size applied
+    async fn apply_resize(
+        &self,
+        shell: &mut (dyn Shell + Send + '_),
+        stream: &mut ShellStream,
+        size: WindowSize,
+    ) -> Result<()> {
+        let clamped = size.clamped();
+        shell.resize(clamped.clone())?;
+
+        if clamped != size {
+            self.write_to_client(
+                stream,
+                &ShellServerMessage::warning(format!(
+                    "window size {}x{} is out of range, resized to {}x{}",
+                    size.0, size.1, clamped.0, clamped.1
+                )),
+            )
+            .await?;
+        }
+
+        self.write_to_client(stream, &ShellServerMessage::SizeApplied(clamped))
+            .await
+    }
+}
+
+/// Tracks a running session in the server's active session count
+struct ActiveSession {
+    count: Arc<AtomicUsize>,
+}
+
+impl ActiveSession {
+    fn new(count: &Arc<AtomicUsize>) -> Self {
+        count.fetch_add(1, Ordering::SeqCst);
+
+        Self {
+            count: Arc::clone(count),
+        }
+    }
+}
+
+impl Drop for ActiveSession {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Writes any pending stdin to the shell in a single write
+async fn write_stdin(shell: &mut ShellIo, pending: &mut Vec<u8>) -> Result<()> {
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    shell.write_all(pending.as_slice()).await?;
+    debug!("wrote {} bytes to shell", pending.len());
+    pending.clear();
+
+    Ok(())
+}
+
+/// Waits a bounded amount of time for the shell's exit status to become available,
+/// returning an unknown status if it cannot be determined
+async fn wait_for_exit_status(shell: &mut (dyn Shell + Send + '_)) -> ExitStatus {
+    for _ in 0..EXIT_CODE_ATTEMPTS {
+        if let Ok(status) = shell.exit_status() {
+            return status;
+        }
+
+        time::delay_for(EXIT_CODE_RETRY_DELAY).await;
+    }
+
+    match shell.exit_status() {
+        Ok(status) => status,
+        Err(err) => {
+            warn!("could not determine exit status of shell: {}", err);
+            ExitStatus::unknown()
+        }
+    }
+}
+
+/// Returned when the client can no longer be written to, such as when it has disconnected
+#[derive(thiserror::Error, Debug)]
+#[error("failed to write to client")]
+struct ClientWriteError;
+
+/// Returned when the client's stream ends without the session ending, when the shell
+/// would otherwise be detached on disconnect
+#[derive(thiserror::Error, Debug)]
+#[error("client disconnected")]
+struct ClientDisconnected;
+
+/// Whether the error was caused by losing the connection to the client
+fn is_disconnect(err: &Error) -> bool {
+    err.downcast_ref::<ClientWriteError>().is_some()
+        || err.downcast_ref::<ClientDisconnected>().is_some()
+}
+
+/// Returned when the transport to the client makes no progress within the configured
+/// timeout, such as over a wedged connection, as opposed to the client closing it
+#[derive(thiserror::Error, Debug, PartialEq)]
+enum TransportStalled {
+    #[error("timed out after {0:?} writing to client")]
+    Write(Duration),
+    #[error("no message received from client within {0:?}")]
+    Read(Duration),
+}
+
+/// Waits for the next message from the client, failing with `TransportStalled` if none is
+/// received by the deadline, which is pushed back each time a message is received
+async fn next_message(
+    stream: &mut ShellStream,
+    deadline: &mut Option<time::Instant>,
+    timeout: Option<Duration>,
+) -> Result<Option<Result<ShellClientMessage>>> {
+    tokio::select! {
+        message = stream.next() => {
+            *deadline = timeout.map(|timeout| time::Instant::now() + timeout);
+            Ok(message)
+        }
+        _ = wait_until(*deadline) => {
+            Err(TransportStalled::Read(timeout.unwrap_or_default()).into())
+        }
+    }
+}
+
+/// Resolves at the supplied deadline or never if there is no deadline
+async fn wait_until(deadline: Option<time::Instant>) {
+    match deadline {
+        Some(deadline) => time::delay_until(deadline).await,
+        None => futures::future::pending().await,
+    }
+}
+
+/// Generates a random nonce for the client to prove its key against
+fn generate_nonce() -> Result<Vec<u8>> {
+    use ring::rand::{SecureRandom, SystemRandom};
+
+    let mut nonce = vec![0u8; CHALLENGE_NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| Error::msg("failed to generate challenge nonce"))?;
+
+    Ok(nonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::proto::{ErrorSeverity, ShellClientStream, MAX_WINDOW_DIMENSION};
+    use crate::ShellKey;
+    use async_trait::async_trait;
+    use futures::io::Cursor;
+    use std::{
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+    };
+    use tokio::runtime::Runtime;
+    use tokio::time::timeout;
+    use tunshell_shared::{FrameVersion, Message, RawMessage};
+
+    fn hello() -> ShellClientMessage {
+        hello_with_version(PROTOCOL_VERSION)
+    }
+
+    fn hello_with_version(protocol_version: u16) -> ShellClientMessage {
+        ShellClientMessage::Hello(HelloPayload {
+            protocol_version,
+            format: MessageFormat::Binary,
+            key_proof: false,
+            sequence_numbers: false,
+            frame_version: FrameVersion::V1,
+        })
+    }
+
+    /// Mock tunnel stream which reads the supplied input and captures
+    /// everything written by the server
+    struct MockStream {
+        input: Vec<u8>,
+        pos: usize,
+        output: Arc<Mutex<Vec<u8>>>,
+        hold_open: bool,
+        chunk_size: usize,
+        fail_writes_after: Option<usize>,
+        stall_writes_after: Option<usize>,
+        nodelay: Arc<Mutex<Option<bool>>>,
+    }
+
+    impl MockStream {
+        /// When `hold_open` is set the stream remains open after the input
+        /// has been exhausted, rather than signalling EOF
+        fn new(messages: Vec<ShellClientMessage>, hold_open: bool) -> (Self, Arc<Mutex<Vec<u8>>>) {
+            let output = Arc::new(Mutex::new(vec![]));
+
+            let stream = Self {
+                input: messages
+                    .iter()
+                    .flat_map(|i| i.serialise().unwrap().to_vec())
+                    .collect(),
+                pos: 0,
+                output: Arc::clone(&output),
+                hold_open,
+                chunk_size: crate::DEFAULT_CHUNK_SIZE,
+                fail_writes_after: None,
+                stall_writes_after: None,
+                nodelay: Arc::new(Mutex::new(None)),
+            };
+
+            (stream, output)
+        }
+
+        /// Fails writes once the number of bytes has been written, as if the client disconnected
+        fn fail_writes_after(mut self, bytes: usize) -> Self {
+            self.fail_writes_after = Some(bytes);
+            self
+        }
+
+        /// Never completes writes once the number of bytes has been written, as if the
+        /// connection to the client had wedged
+        fn stall_writes_after(mut self, bytes: usize) -> Self {
+            self.stall_writes_after = Some(bytes);
+            self
+        }
+
+        /// The last nodelay setting applied to the stream
+        fn nodelay(&self) -> Arc<Mutex<Option<bool>>> {
+            Arc::clone(&self.nodelay)
+        }
+
+        fn into_shell_stream(self) -> ShellStream {
+            ShellStream::new((Box::new(self) as Box<dyn TunnelStream>).compat())
+        }
+    }
+
+    impl tokio::io::AsyncRead for MockStream {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buff: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let this = self.get_mut();
+            let remaining = this.input.len() - this.pos;
+
+            if remaining == 0 {
+                if this.hold_open {
+                    return Poll::Pending;
+                }
+
+                return Poll::Ready(Ok(0));
+            }
+
+            let len = std::cmp::min(buff.len(), remaining);
+            buff[..len].copy_from_slice(&this.input[this.pos..this.pos + len]);
+            this.pos += len;
+
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    impl tokio::io::AsyncWrite for MockStream {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buff: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let mut output = self.output.lock().unwrap();
+
+            if let Some(limit) = self.fail_writes_after {
+                if output.len() + buff.len() > limit {
+                    return Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()));
+                }
+            }
+
+            if let Some(limit) = self.stall_writes_after {
+                if output.len() + buff.len() > limit {
+                    return Poll::Pending;
+                }
+            }
+
+            output.extend_from_slice(buff);
+            Poll::Ready(Ok(buff.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl TunnelStream for MockStream {
+        fn preferred_chunk_size(&self) -> usize {
+            self.chunk_size
+        }
+
+        fn set_nodelay(&mut self, enabled: bool) -> std::io::Result<()> {
+            self.nodelay.lock().unwrap().replace(enabled);
+            Ok(())
+        }
+    }
+
+    async fn parse_server_messages(output: &Arc<Mutex<Vec<u8>>>) -> Vec<ShellServerMessage> {
+        let data = output.lock().unwrap().clone();
+
+        ShellClientStream::new(Cursor::new(data))
+            .map(|i| i.unwrap())
+            .collect()
+            .await
+    }
+
+    /// Mock shell which continuously outputs data until terminated
+    struct MockShell {
+        terminated: Arc<Mutex<bool>>,
+    }
+
+    impl MockShell {
+        fn new() -> (Self, Arc<Mutex<bool>>) {
+            let terminated = Arc::new(Mutex::new(false));
+
+            (
+                Self {
+                    terminated: Arc::clone(&terminated),
+                },
+                terminated,
+            )
+        }
+    }
+
+    #[async_trait]
+    impl Shell for MockShell {
+        async fn read(&mut self, buff: &mut [u8]) -> Result<usize> {
+            if *self.terminated.lock().unwrap() {
+                return Ok(0);
+            }
+
+            time::delay_for(Duration::from_millis(1)).await;
+            buff[0] = b'.';
+
+            Ok(1)
+        }
+
+        async fn write(&mut self, _buff: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        async fn close_stdin(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn resize(&mut self, _size: WindowSize) -> Result<()> {
+            Ok(())
+        }
+
+        fn exit_status(&self) -> Result<ExitStatus> {
+            if *self.terminated.lock().unwrap() {
+                Ok(ExitStatus::exited(1))
+            } else {
+                Err(Error::msg("shell has not exited"))
+            }
+        }
+
+        fn terminate(&mut self) -> Result<()> {
+            *self.terminated.lock().unwrap() = true;
+            Ok(())
+        }
+
+        fn signal(&mut self, _signal: u8) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Mock shell which outputs the supplied chunks then exits
+    struct ScriptedShell {
+        chunks: Vec<Vec<u8>>,
+    }
+
+    #[async_trait]
+    impl Shell for ScriptedShell {
+        async fn read(&mut self, buff: &mut [u8]) -> Result<usize> {
+            if self.chunks.is_empty() {
+                return Ok(0);
+            }
+
+            let mut chunk = self.chunks.remove(0);
+
+            // Chunks larger than the buffer are returned over multiple reads
+            if chunk.len() > buff.len() {
+                self.chunks.insert(0, chunk.split_off(buff.len()));
+            }
+
+            buff[..chunk.len()].copy_from_slice(&chunk);
+
+            Ok(chunk.len())
+        }
+
+        async fn write(&mut self, _buff: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        async fn close_stdin(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn resize(&mut self, _size: WindowSize) -> Result<()> {
+            Ok(())
+        }
+
+        fn exit_status(&self) -> Result<ExitStatus> {
+            Ok(ExitStatus::exited(0))
+        }
+
+        fn terminate(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn signal(&mut self, _signal: u8) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Mock shell which outputs the supplied chunks then waits for input that never comes
+    struct StalledShell {
+        chunks: Vec<Vec<u8>>,
+    }
+
+    #[async_trait]
+    impl Shell for StalledShell {
+        async fn read(&mut self, buff: &mut [u8]) -> Result<usize> {
+            if self.chunks.is_empty() {
+                futures::future::pending::<()>().await;
+            }
+
+            let chunk = self.chunks.remove(0);
+            buff[..chunk.len()].copy_from_slice(&chunk);
+
+            Ok(chunk.len())
+        }
+
+        async fn write(&mut self, _buff: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        async fn close_stdin(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn resize(&mut self, _size: WindowSize) -> Result<()> {
+            Ok(())
+        }
+
+        fn exit_status(&self) -> Result<ExitStatus> {
+            Err(Error::msg("shell has not exited"))
+        }
+
+        fn terminate(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn signal(&mut self, _signal: u8) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Mock shell which always has output ready, recording the
+    /// order in which its output is read and its input is written
+    struct SaturatedShell {
+        events: Arc<Mutex<Vec<IoDirection>>>,
+    }
+
+    #[async_trait]
+    impl Shell for SaturatedShell {
+        async fn read(&mut self, buff: &mut [u8]) -> Result<usize> {
+            self.events.lock().unwrap().push(IoDirection::Stdout);
+            buff[0] = b'y';
+
+            Ok(1)
+        }
+
+        async fn write(&mut self, _buff: &[u8]) -> Result<()> {
+            self.events.lock().unwrap().push(IoDirection::Stdin);
+            Ok(())
+        }
+
+        async fn close_stdin(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn resize(&mut self, _size: WindowSize) -> Result<()> {
+            Ok(())
+        }
+
+        fn exit_status(&self) -> Result<ExitStatus> {
+            Ok(ExitStatus::exited(0))
+        }
+
+        fn terminate(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn signal(&mut self, _signal: u8) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Mock shell which exits immediately but only reports its
+    /// exit code after it has been queried a number of times
+    struct DelayedExitCodeShell {
+        exit_code_after: usize,
+        queries: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Shell for DelayedExitCodeShell {
+        async fn read(&mut self, _buff: &mut [u8]) -> Result<usize> {
+            Ok(0)
+        }
+
+        async fn write(&mut self, _buff: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        async fn close_stdin(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn resize(&mut self, _size: WindowSize) -> Result<()> {
+            Ok(())
+        }
+
+        fn exit_status(&self) -> Result<ExitStatus> {
+            if self.queries.fetch_add(1, Ordering::SeqCst) < self.exit_code_after {
+                Err(Error::msg("shell has not exited"))
+            } else {
+                Ok(ExitStatus::exited(3))
+            }
+        }
+
+        fn terminate(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn signal(&mut self, _signal: u8) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Mock shell which records each write and never outputs data
+    struct RecordingShell {
+        writes: Arc<Mutex<Vec<Vec<u8>>>>,
+        pty: bool,
+    }
+
+    #[async_trait]
+    impl Shell for RecordingShell {
+        async fn read(&mut self, _buff: &mut [u8]) -> Result<usize> {
+            futures::future::pending().await
+        }
+
+        async fn write(&mut self, buff: &[u8]) -> Result<()> {
+            self.writes.lock().unwrap().push(buff.to_vec());
+            Ok(())
+        }
+
+        async fn close_stdin(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn resize(&mut self, _size: WindowSize) -> Result<()> {
+            Ok(())
+        }
+
+        fn exit_status(&self) -> Result<ExitStatus> {
+            Err(Error::msg("shell has not exited"))
+        }
+
+        fn terminate(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn signal(&mut self, _signal: u8) -> Result<()> {
+            Ok(())
+        }
+
+        fn is_pty(&self) -> bool {
+            self.pty
+        }
+
+        fn cwd(&self) -> Result<String> {
+            Ok("/home/test".to_owned())
+        }
+    }
+
+    /// Mock shell which records the sizes it is resized to
+    struct ResizeRecordingShell {
+        sizes: Arc<Mutex<Vec<WindowSize>>>,
+    }
+
+    #[async_trait]
+    impl Shell for ResizeRecordingShell {
+        async fn read(&mut self, _buff: &mut [u8]) -> Result<usize> {
+            futures::future::pending().await
+        }
+
+        async fn write(&mut self, _buff: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        async fn close_stdin(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn resize(&mut self, size: WindowSize) -> Result<()> {
+            self.sizes.lock().unwrap().push(size);
+            Ok(())
+        }
+
+        fn exit_status(&self) -> Result<ExitStatus> {
+            Err(Error::msg("shell has not exited"))
+        }
+
+        fn terminate(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn signal(&mut self, _signal: u8) -> Result<()> {
+            Ok(())
+        }
+
+        fn is_pty(&self) -> bool {
+            true
+        }
+
+        fn cwd(&self) -> Result<String> {
+            Ok("/home/test".to_owned())
+        }
+    }
+
+    /// Mock shell which only outputs its chunks once stdin has been closed
+    struct HalfCloseShell {
+        stdin_closed: bool,
+        chunks: Vec<Vec<u8>>,
+    }
+
+    #[async_trait]
+    impl Shell for HalfCloseShell {
+        async fn read(&mut self, buff: &mut [u8]) -> Result<usize> {
+            if !self.stdin_closed {
+                return futures::future::pending().await;
+            }
+
+            if self.chunks.is_empty() {
+                return Ok(0);
+            }
+
+            let chunk = self.chunks.remove(0);
+            buff[..chunk.len()].copy_from_slice(&chunk);
+
+            Ok(chunk.len())
+        }
+
+        async fn write(&mut self, _buff: &[u8]) -> Result<()> {
+            if self.stdin_closed {
+                return Err(Error::msg("stdin has been closed"));
+            }
+
+            Ok(())
+        }
+
+        async fn close_stdin(&mut self) -> Result<()> {
+            self.stdin_closed = true;
+            Ok(())
+        }
+
+        fn resize(&mut self, _size: WindowSize) -> Result<()> {
+            Ok(())
+        }
+
+        fn exit_status(&self) -> Result<ExitStatus> {
+            Ok(ExitStatus::exited(0))
+        }
+
+        fn terminate(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn signal(&mut self, _signal: u8) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Records the authentication events it receives
+    #[derive(Default)]
+    struct RecordingAuthObserver {
+        accepted: Mutex<Vec<AuthPeer>>,
+        rejected: Mutex<Vec<AuthPeer>>,
+    }
+
+    impl AuthObserver for RecordingAuthObserver {
+        fn on_accepted(&self, peer: &AuthPeer) {
+            self.accepted.lock().unwrap().push(peer.clone());
+        }
+
+        fn on_rejected(&self, peer: &AuthPeer) {
+            self.rejected.lock().unwrap().push(peer.clone());
+        }
+    }
+
+    #[test]
+    fn test_new_shell_server() {
+        ShellServer::new().unwrap();
+    }
+
+    #[test]
+    fn test_rejected_key() {
+        Runtime::new().unwrap().block_on(async {
+            let mut mock_data = hello().serialise().unwrap().to_vec();
+
+            mock_data.extend_from_slice(
+                ShellClientMessage::Key("Invalid".to_owned())
+                    .serialise()
+                    .unwrap()
+                    .to_vec()
+                    .as_slice(),
+            );
+
+            let mock_stream = Cursor::new(mock_data).compat();
+            ShellServer::new()
+                .unwrap()
+                .run(Box::new(mock_stream), ShellKey::new("MyKey"))
+                .await
+                .expect_err("client key should be rejected");
+        });
+    }
+
+    #[test]
+    fn test_message_exceeding_max_message_size() {
+        Runtime::new().unwrap().block_on(async {
+            let mock_data = hello().serialise().unwrap().to_vec();
+
+            let mock_stream = Cursor::new(mock_data).compat();
+            let err = ShellServer::builder()
+                .max_message_size(8)
+                .build()
+                .unwrap()
+                .run(Box::new(mock_stream), ShellKey::new("CorrectKey"))
+                .await
+                .expect_err("oversized hello should be rejected");
+
+            assert_eq!(err.to_string(), "received invalid message from client");
+        });
+    }
+
+    #[test]
+    fn test_key_timeout() {
+        Runtime::new().unwrap().block_on(async {
+            let mock_data = Vec::<u8>::new();
+
+            let mock_stream = Cursor::new(mock_data).compat();
+
+            timeout(
+                Duration::from_millis(5000),
+                ShellServer::new()
+                    .unwrap()
+                    .run(Box::new(mock_stream), ShellKey::new("CorrectKey")),
+            )
+            .await
+            .unwrap()
+            .expect_err("should timeout");
+        });
+    }
+
+    #[test]
+    fn test_start_shell_timeout() {
+        Runtime::new().unwrap().block_on(async {
+            let mut mock_data = hello().serialise().unwrap().to_vec();
+
+            mock_data.extend_from_slice(
+                ShellClientMessage::Key("CorrectKey".to_owned())
+                    .serialise()
+                    .unwrap()
+                    .to_vec()
+                    .as_slice(),
+            );
+
+            let mock_stream = Cursor::new(mock_data).compat();
+
+            timeout(
+                Duration::from_millis(5000),
+                ShellServer::new()
+                    .unwrap()
+                    .run(Box::new(mock_stream), ShellKey::new("CorrectKey")),
+            )
+            .await
+            .unwrap()
+            .expect_err("should timeout");
+        });
+    }
+
+    #[test]
+    fn test_start_connect_to_shell() {
+        Runtime::new().unwrap().block_on(async {
+            let mut mock_data = hello().serialise().unwrap().to_vec();
+
+            mock_data.extend_from_slice(
+                ShellClientMessage::Key("CorrectKey".to_owned())
+                    .serialise()
+                    .unwrap()
+                    .to_vec()
+                    .as_slice(),
+            );
+
+            mock_data.extend_from_slice(
+                ShellClientMessage::StartShell(StartShellPayload {
+                    term: "TERM".to_owned(),
+                    size: Some(WindowSize(50, 50)),
+                    pty: true,
+                    login: true,
+                    interactive: true,
+                    raw: false,
+                    shell_path: None,
+                    colors: None,
+                    truecolor: false,
+                    line_mode: false,
+                    locale: None,
+                    timezone: None,
+                    titles: false,
+                })
+                .serialise()
+                .unwrap()
+                .to_vec()
+                .as_slice(),
+            );
+
+            mock_data.extend_from_slice(
+                ShellClientMessage::Stdin("echo \"hello\"\n".as_bytes().to_vec())
+                    .serialise()
+                    .unwrap()
+                    .to_vec()
+                    .as_slice(),
+            );
+
+            mock_data.extend_from_slice(
+                ShellClientMessage::Resize(WindowSize(100, 80))
+                    .serialise()
+                    .unwrap()
+                    .to_vec()
+                    .as_slice(),
+            );
+
+            mock_data.extend_from_slice(
+                ShellClientMessage::Stdin("exit\n".as_bytes().to_vec())
+                    .serialise()
+                    .unwrap()
+                    .to_vec()
+                    .as_slice(),
+            );
+
+            let mock_stream = Cursor::new(mock_data).compat();
+            let server = ShellServer::new().unwrap();
+
+            server
+                .run(Box::new(mock_stream), ShellKey::new("CorrectKey"))
+                .await
+                .unwrap();
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_payloads_redacted_from_logs() {
+        use lazy_static::lazy_static;
+        use std::sync::Once;
+
+        lazy_static! {
+            static ref RECORDS: Mutex<Vec<String>> = Mutex::new(vec![]);
+        }
+
+        /// Captures every log record, including those from other tests running concurrently
+        struct CapturingLogger;
+
+        impl Log for CapturingLogger {
+            fn enabled(&self, _metadata: &Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &Record) {
+                RECORDS.lock().unwrap().push(record.args().to_string());
+            }
+
+            fn flush(&self) {}
+        }
+
+        static LOGGER: CapturingLogger = CapturingLogger;
+        static INIT: Once = Once::new();
+
+        INIT.call_once(|| {
+            log::set_logger(&LOGGER).unwrap();
+            log::set_max_level(LevelFilter::Trace);
+        });
+
+        Runtime::new().unwrap().block_on(async {
+            let (stream, _output) = MockStream::new(
+                vec![
+                    hello(),
+                    ShellClientMessage::Key("CorrectKey".to_owned()),
+                    ShellClientMessage::StartShell(StartShellPayload {
+                        term: "TERM".to_owned(),
+                        size: Some(WindowSize(50, 50)),
+                        pty: false,
+                        login: false,
+                        interactive: false,
+                        raw: false,
+                        shell_path: None,
+                        colors: None,
+                        truecolor: false,
+                        line_mode: false,
+                        locale: None,
+                        timezone: None,
+                        titles: false,
+                    }),
+                    ShellClientMessage::Stdin("#s3cr3t-passw0rd\n".as_bytes().to_vec()),
+                    ShellClientMessage::Stdin("exit\n".as_bytes().to_vec()),
+                ],
+                true,
+            );
+
+            timeout(
+                Duration::from_millis(5000),
+                ShellServer::new()
+                    .unwrap()
+                    .run(Box::new(stream), ShellKey::new("CorrectKey")),
+            )
+            .await
+            .expect("shell should exit")
+            .unwrap();
+        });
+
+        let records = RECORDS.lock().unwrap();
+
+        assert!(records
+            .iter()
+            .any(|i| i.contains("received 17 bytes from client shell")));
+        assert!(records.iter().all(|i| !i.contains("s3cr3t")));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_nodelay_set_for_interactive_sessions() {
+        Runtime::new().unwrap().block_on(async {
+            for (raw, expected) in [(false, true), (true, false)] {
+                let (stream, _) = MockStream::new(
+                    vec![
+                        hello(),
+                        ShellClientMessage::Key("CorrectKey".to_owned()),
+                        ShellClientMessage::StartShell(StartShellPayload {
+                            term: "TERM".to_owned(),
+                            size: Some(WindowSize(50, 50)),
+                            pty: false,
+                            login: true,
+                            interactive: true,
+                            raw,
+                            shell_path: None,
+                            colors: None,
+                            truecolor: false,
+                            line_mode: false,
+                            locale: None,
+                            timezone: None,
+                            titles: false,
+                        }),
+                    ],
+                    true,
+                );
+                let nodelay = stream.nodelay();
+
+                let key = ShellKey::new("CorrectKey")
+                    .with_forced_command(vec!["echo".to_owned(), "output".to_owned()]);
+
+                timeout(
+                    Duration::from_millis(5000),
+                    ShellServer::new().unwrap().run(Box::new(stream), key),
+                )
+                .await
+                .expect("forced command should exit")
+                .unwrap();
+
+                assert_eq!(*nodelay.lock().unwrap(), Some(expected));
+            }
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_banner_sent_before_shell_output() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(
+                vec![
+                    hello(),
+                    ShellClientMessage::Key("CorrectKey".to_owned()),
+                    ShellClientMessage::StartShell(StartShellPayload {
+                        term: "TERM".to_owned(),
+                        size: Some(WindowSize(50, 50)),
+                        pty: false,
+                        login: true,
+                        interactive: true,
+                        raw: false,
+                        shell_path: None,
+                        colors: None,
+                        truecolor: false,
+                        line_mode: false,
+                        locale: None,
+                        timezone: None,
+                        titles: false,
+                    }),
+                ],
+                true,
+            );
+
+            let key = ShellKey::new("CorrectKey")
+                .with_forced_command(vec!["echo".to_owned(), "output".to_owned()]);
+            let server = ShellServer::builder()
+                .banner("Authorized use only\nconnected from {peer_addr}")
+                .build()
+                .unwrap()
+                .with_peer_addr("1.2.3.4");
+
+            timeout(
+                Duration::from_millis(5000),
+                server.run(Box::new(stream), key),
+            )
+            .await
+            .expect("forced command should exit")
+            .unwrap();
+
+            let stdout = parse_server_messages(&output)
+                .await
+                .into_iter()
+                .filter_map(|i| match i {
+                    ShellServerMessage::Stdout(data) => Some(data.to_vec()),
+                    _ => None,
+                })
+                .collect::<Vec<Vec<u8>>>();
+
+            assert_eq!(
+                stdout.first().map(|i| i.as_slice()),
+                Some("Authorized use only\r\nconnected from 1.2.3.4\r\n".as_bytes())
+            );
+            assert_eq!(
+                String::from_utf8(stdout.concat()).unwrap(),
+                "Authorized use only\r\nconnected from 1.2.3.4\r\noutput\n"
+            );
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_forced_command_runs_in_place_of_requested_shell() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(
+                vec![
+                    hello(),
+                    ShellClientMessage::Key("CorrectKey".to_owned()),
+                    ShellClientMessage::StartShell(StartShellPayload {
+                        term: "TERM".to_owned(),
+                        size: Some(WindowSize(50, 50)),
+                        pty: false,
+                        login: true,
+                        interactive: true,
+                        raw: false,
+                        shell_path: None,
+                        colors: None,
+                        truecolor: false,
+                        line_mode: false,
+                        locale: None,
+                        timezone: None,
+                        titles: false,
+                    }),
+                ],
+                true,
+            );
+
+            let key = ShellKey::new("CorrectKey")
+                .with_forced_command(vec!["echo".to_owned(), "forced".to_owned()]);
+
+            timeout(
+                Duration::from_millis(5000),
+                ShellServer::new().unwrap().run(Box::new(stream), key),
+            )
+            .await
+            .expect("forced command should exit")
+            .unwrap();
+
+            let messages = parse_server_messages(&output).await;
+            let stdout = messages
+                .iter()
+                .filter_map(|i| match i {
+                    ShellServerMessage::Stdout(data) => Some(data.to_vec()),
+                    _ => None,
+                })
+                .flatten()
+                .collect::<Vec<u8>>();
+
+            assert_eq!(String::from_utf8(stdout).unwrap(), "forced\n");
+            assert_eq!(
+                messages[messages.len() - 2..],
+                [
+                    ShellServerMessage::Exited(ExitStatus::exited(0)),
+                    ShellServerMessage::Close {
+                        reason: "shell exited".to_owned()
+                    }
+                ]
+            );
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_returns_session_report() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(
+                vec![
+                    hello(),
+                    ShellClientMessage::Key("CorrectKey".to_owned()),
+                    ShellClientMessage::StartShell(StartShellPayload {
+                        term: "TERM".to_owned(),
+                        size: Some(WindowSize(50, 50)),
+                        pty: false,
+                        login: true,
+                        interactive: true,
+                        raw: false,
+                        shell_path: None,
+                        colors: None,
+                        truecolor: false,
+                        line_mode: false,
+                        locale: None,
+                        timezone: None,
+                        titles: false,
+                    }),
+                    ShellClientMessage::Stdin("hello world".as_bytes().to_vec()),
+                ],
+                true,
+            );
+
+            let key = ShellKey::new("CorrectKey").with_forced_command(vec![
+                "head".to_owned(),
+                "-c".to_owned(),
+                "5".to_owned(),
+            ]);
+
+            let report = timeout(
+                Duration::from_millis(5000),
+                ShellServer::new().unwrap().run(Box::new(stream), key),
+            )
+            .await
+            .expect("forced command should exit")
+            .unwrap();
+
+            let messages = parse_server_messages(&output).await;
+            assert_eq!(
+                messages[messages.len() - 2..],
+                [
+                    ShellServerMessage::Exited(ExitStatus::exited(0)),
+                    ShellServerMessage::Close {
+                        reason: "shell exited".to_owned()
+                    }
+                ]
+            );
+
+            assert_eq!(report.session_id.len(), 22);
+            assert_eq!(report.exit_code, Some(0));
+            assert!(!report.detached);
+            assert_eq!(report.stdin_bytes, 11);
+            assert_eq!(report.stdout_bytes, 5);
+            assert!(report.duration_ms < 5000);
+        });
+    }
+
+    #[test]
+    fn test_close_sent_with_error_reason() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(
+                vec![hello(), ShellClientMessage::Key("Invalid".to_owned())],
+                false,
+            );
+
+            ShellServer::new()
+                .unwrap()
+                .run(Box::new(stream), ShellKey::new("CorrectKey"))
+                .await
+                .expect_err("client key should be rejected");
+
+            let messages = parse_server_messages(&output).await;
+            assert_eq!(
+                messages[messages.len() - 2..],
+                [
+                    ShellServerMessage::KeyRejected,
+                    ShellServerMessage::Close {
+                        reason: "client key rejected".to_owned()
+                    }
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_start_connect_to_shell_then_error() {
+        Runtime::new().unwrap().block_on(async {
+            let mut mock_data = hello().serialise().unwrap().to_vec();
+
+            mock_data.extend_from_slice(
+                ShellClientMessage::Key("CorrectKey".to_owned())
+                    .serialise()
+                    .unwrap()
+                    .to_vec()
+                    .as_slice(),
+            );
+
+            mock_data.extend_from_slice(
+                ShellClientMessage::StartShell(StartShellPayload {
+                    term: "TERM".to_owned(),
+                    size: Some(WindowSize(50, 50)),
+                    pty: true,
+                    login: true,
+                    interactive: true,
+                    raw: false,
+                    shell_path: None,
+                    colors: None,
+                    truecolor: false,
+                    line_mode: false,
+                    locale: None,
+                    timezone: None,
+                    titles: false,
+                })
+                .serialise()
+                .unwrap()
+                .to_vec()
+                .as_slice(),
+            );
+
+            mock_data.extend_from_slice(
+                ShellClientMessage::Error("some error occurred".to_owned())
+                    .serialise()
+                    .unwrap()
+                    .to_vec()
+                    .as_slice(),
+            );
+
+            let mock_stream = Cursor::new(mock_data).compat();
+            let server = ShellServer::new().unwrap();
+
+            server
+                .run(Box::new(mock_stream), ShellKey::new("CorrectKey"))
+                .await
+                .expect_err("should return error");
+        });
+    }
+
+    #[test]
+    fn test_max_session_duration() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(vec![], true);
+            let mut stream = stream.into_shell_stream();
+            let (shell, terminated) = MockShell::new();
+
+            let config = ShellServerConfig {
+                max_session_duration: Some(Duration::from_millis(100)),
+                ..ShellServerConfig::default()
+            };
+            let server = ShellServer::with_config(config).unwrap();
+
+            timeout(
+                Duration::from_millis(2000),
+                server.steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                ),
+            )
+            .await
+            .expect("session should be terminated")
+            .unwrap();
+
+            assert!(*terminated.lock().unwrap());
+
+            let messages = parse_server_messages(&output).await;
+
+            assert!(messages.len() > 1);
+            assert_eq!(
+                messages.last().unwrap(),
+                &ShellServerMessage::fatal("max session duration reached")
+            );
+        });
+    }
+
+    #[test]
+    fn test_stdin_and_stdout_served_fairly_under_load() {
+        Runtime::new().unwrap().block_on(async {
+            let messages = (0..100)
+                .map(|_| ShellClientMessage::Stdin(vec![b'x']))
+                .collect::<Vec<_>>();
+            let (stream, _) = MockStream::new(messages, false);
+            let mut stream = stream.into_shell_stream();
+            let events = Arc::new(Mutex::new(vec![]));
+            let shell = SaturatedShell {
+                events: Arc::clone(&events),
+            };
+            let server = ShellServer::builder()
+                .stdin_coalesce_window(None)
+                .build()
+                .unwrap();
+
+            timeout(
+                Duration::from_millis(2000),
+                server.steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    true,
+                    false,
+                    &mut SessionReport::new("test"),
+                ),
+            )
+            .await
+            .expect("session should end once the client stream ends")
+            .unwrap();
+
+            let events = events.lock().unwrap();
+            let writes = events.iter().filter(|i| **i == IoDirection::Stdin).count();
+            assert_eq!(writes, 100);
+
+            // While both directions are saturated neither is served more than twice in a row,
+            // a ready direction can briefly be pending once the task's tokio budget is spent
+            let last_write = events
+                .iter()
+                .rposition(|i| *i == IoDirection::Stdin)
+                .unwrap();
+            for run in events[..=last_write].windows(3) {
+                assert!(
+                    run[0] != run[1] || run[1] != run[2],
+                    "events: {:?}",
+                    &events[..]
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn test_shell_terminated_after_output_limit() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(vec![], true);
+            let mut stream = stream.into_shell_stream();
+            let shell = SaturatedShell {
+                events: Arc::new(Mutex::new(vec![])),
+            };
+            let server = ShellServer::builder()
+                .max_output_bytes(100)
+                .build()
+                .unwrap();
+            let mut report = SessionReport::new("test");
+
+            timeout(
+                Duration::from_millis(2000),
+                server.steam_shell_io(&mut stream, Box::new(shell), true, false, &mut report),
+            )
+            .await
+            .expect("session should end once the output limit is exceeded")
+            .unwrap();
+
+            assert_eq!(report.stdout_bytes, 101);
+
+            let messages = parse_server_messages(&output).await;
+            assert_eq!(messages.len(), 102);
+            assert_eq!(
+                messages.last(),
+                Some(&ShellServerMessage::fatal("output limit exceeded"))
+            );
+        });
+    }
+
+    #[test]
+    fn test_shell_terminated_when_client_write_fails() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, _) = MockStream::new(vec![], true);
+            let mut stream = stream.fail_writes_after(100).into_shell_stream();
+            let (shell, terminated) = MockShell::new();
+            let server = ShellServer::new().unwrap();
+            let mut report = SessionReport::new("test");
+
+            let err = timeout(
+                Duration::from_millis(2000),
+                server.steam_shell_io(&mut stream, Box::new(shell), true, false, &mut report),
+            )
+            .await
+            .expect("session should end once the client cannot be written to")
+            .expect_err("write failure should be returned");
+
+            assert!(err.downcast_ref::<ClientWriteError>().is_some());
+            assert!(*terminated.lock().unwrap());
+            assert!(!report.detached);
+            assert_eq!(server.detached_shells.len(), 0);
+        });
+    }
+
+    #[test]
+    fn test_shell_detached_when_client_write_fails() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, _) = MockStream::new(vec![], true);
+            let mut stream = stream.fail_writes_after(100).into_shell_stream();
+            let (shell, terminated) = MockShell::new();
+            let server = ShellServer::builder()
+                .detach_on_disconnect(true)
+                .build()
+                .unwrap()
+                .with_reconnect_token("token");
+            let mut report = SessionReport::new("test");
+
+            timeout(
+                Duration::from_millis(2000),
+                server.steam_shell_io(&mut stream, Box::new(shell), true, false, &mut report),
+            )
+            .await
+            .expect("session should end once the client cannot be written to")
+            .expect_err("write failure should be returned");
+
+            assert!(!(*terminated.lock().unwrap()));
+            assert!(report.detached);
+            assert_eq!(server.detached_shells.len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_shell_terminated_on_disconnect_without_reconnect_token() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, _) = MockStream::new(vec![], true);
+            let mut stream = stream.fail_writes_after(100).into_shell_stream();
+            let (shell, terminated) = MockShell::new();
+            let server = ShellServer::builder()
+                .detach_on_disconnect(true)
+                .build()
+                .unwrap();
+            let mut report = SessionReport::new("test");
+
+            timeout(
+                Duration::from_millis(2000),
+                server.steam_shell_io(&mut stream, Box::new(shell), true, false, &mut report),
+            )
+            .await
+            .expect("session should end once the client cannot be written to")
+            .expect_err("write failure should be returned");
+
+            assert!(*terminated.lock().unwrap());
+            assert!(!report.detached);
+            assert_eq!(server.detached_shells.len(), 0);
+        });
+    }
+
+    #[test]
+    fn test_shell_detached_when_client_stream_ends() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, _) = MockStream::new(vec![], false);
+            let mut stream = stream.into_shell_stream();
+            let (shell, terminated) = MockShell::new();
+            let server = ShellServer::builder()
+                .detach_on_disconnect(true)
+                .build()
+                .unwrap()
+                .with_reconnect_token("token");
+            let mut report = SessionReport::new("test");
+
+            let err = timeout(
+                Duration::from_millis(2000),
+                server.steam_shell_io(&mut stream, Box::new(shell), true, false, &mut report),
+            )
+            .await
+            .expect("session should end once the client stream ends")
+            .expect_err("disconnect should be returned");
+
+            assert!(err.downcast_ref::<ClientDisconnected>().is_some());
+            assert!(!(*terminated.lock().unwrap()));
+            assert!(report.detached);
+            assert_eq!(server.detached_shells.len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_shell_terminated_when_client_write_stalls() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, _) = MockStream::new(vec![], true);
+            let mut stream = stream.stall_writes_after(100).into_shell_stream();
+            let (shell, terminated) = MockShell::new();
+            let server = ShellServer::builder()
+                .write_timeout(Some(Duration::from_millis(100)))
+                .build()
+                .unwrap();
+
+            let err = timeout(
+                Duration::from_millis(2000),
+                server.steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    true,
+                    false,
+                    &mut SessionReport::new("test"),
+                ),
+            )
+            .await
+            .expect("session should end once writes to the client stall")
+            .expect_err("stalled write should be returned");
+
+            assert_eq!(
+                err.downcast_ref::<TransportStalled>(),
+                Some(&TransportStalled::Write(Duration::from_millis(100)))
+            );
+            assert!(*terminated.lock().unwrap());
+            assert_eq!(server.detached_shells.len(), 0);
+        });
+    }
+
+    #[test]
+    fn test_shell_terminated_when_client_read_stalls() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, _) = MockStream::new(vec![ShellClientMessage::Stdin(vec![1])], true);
+            let mut stream = stream.into_shell_stream();
+            let writes = Arc::new(Mutex::new(vec![]));
+            let shell = RecordingShell {
+                writes: Arc::clone(&writes),
+                pty: false,
+            };
+            let server = ShellServer::builder()
+                .read_timeout(Duration::from_millis(100))
+                .build()
+                .unwrap();
+
+            let err = timeout(
+                Duration::from_millis(2000),
+                server.steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                ),
+            )
+            .await
+            .expect("session should end once reads from the client stall")
+            .expect_err("stalled read should be returned");
+
+            assert_eq!(
+                err.downcast_ref::<TransportStalled>(),
+                Some(&TransportStalled::Read(Duration::from_millis(100)))
+            );
+            assert_eq!(writes.lock().unwrap().concat(), vec![1]);
+        });
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(vec![], true);
+            let mut stream = stream.into_shell_stream();
+            let shell = HalfCloseShell {
+                stdin_closed: false,
+                chunks: vec![],
+            };
+
+            let server = ShellServer::builder()
+                .idle_timeout(Duration::from_millis(100))
+                .build()
+                .unwrap();
+
+            timeout(
+                Duration::from_millis(2000),
+                server.steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                ),
+            )
+            .await
+            .expect("idle session should be terminated")
+            .unwrap();
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![ShellServerMessage::fatal("session idle timeout reached")]
+            );
+        });
+    }
+
+    #[test]
+    fn test_keepalive_sent_while_silent() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(vec![], true);
+            let mut stream = stream.into_shell_stream();
+            let shell = HalfCloseShell {
+                stdin_closed: false,
+                chunks: vec![],
+            };
+
+            let server = ShellServer::builder()
+                .keepalive_interval(Duration::from_millis(100))
+                .idle_timeout(Duration::from_millis(550))
+                .build()
+                .unwrap();
+
+            timeout(
+                Duration::from_millis(2000),
+                server.steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                ),
+            )
+            .await
+            .expect("idle session should be terminated")
+            .unwrap();
+
+            let mut messages = parse_server_messages(&output).await;
+
+            assert_eq!(
+                messages.pop(),
+                Some(ShellServerMessage::fatal("session idle timeout reached"))
+            );
+            assert!(messages.iter().all(|i| *i == ShellServerMessage::Ping));
+            // Pings are due at each 100ms of silence before the idle timeout at 550ms
+            assert!(messages.len() >= 3 && messages.len() <= 5);
+        });
+    }
+
+    #[test]
+    fn test_max_concurrent_sessions() {
+        Runtime::new().unwrap().block_on(async {
+            let config = ShellServerConfig {
+                max_concurrent_sessions: Some(1),
+                ..ShellServerConfig::default()
+            };
+            let server = ShellServer::with_config(config).unwrap();
+
+            let (stream, _) = MockStream::new(vec![], true);
+            let running = tokio::spawn(
+                server
+                    .clone()
+                    .run(Box::new(stream), ShellKey::new("CorrectKey")),
+            );
+
+            time::delay_for(Duration::from_millis(100)).await;
+            assert_eq!(server.active_sessions(), 1);
+
+            let (stream, output) = MockStream::new(vec![hello()], false);
+
+            server
+                .clone()
+                .run(Box::new(stream), ShellKey::new("CorrectKey"))
+                .await
+                .expect_err("session should be rejected");
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![
+                    ShellServerMessage::fatal("server at capacity"),
+                    ShellServerMessage::Close {
+                        reason: "server at capacity".to_owned()
+                    }
+                ]
+            );
+
+            running
+                .await
+                .unwrap()
+                .expect_err("first session should time out waiting for hello");
+
+            assert_eq!(server.active_sessions(), 0);
+        });
+    }
+
+    #[test]
+    fn test_utf8_safe_output() {
+        Runtime::new().unwrap().block_on(async {
+            let mut data = vec![b'a'; 1023];
+            data.extend_from_slice("é€b".as_bytes());
+
+            let (stream, output) = MockStream::new(vec![], true);
+            let mut stream = stream.into_shell_stream();
+            let shell = ScriptedShell {
+                chunks: data.chunks(1024).map(|i| i.to_vec()).collect(),
+            };
+
+            ShellServer::new()
+                .unwrap()
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .unwrap();
+
+            let mut received = vec![];
+
+            for message in parse_server_messages(&output).await {
+                if let ShellServerMessage::Stdout(chunk) = message {
+                    std::str::from_utf8(&chunk).expect("chunk should be valid utf-8");
+                    received.extend_from_slice(&chunk);
+                }
+            }
+
+            assert_eq!(received, data);
+        });
+    }
+
+    #[test]
+    fn test_title_sent_out_of_band() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(vec![], true);
+            let mut stream = stream.into_shell_stream();
+            let shell = ScriptedShell {
+                chunks: vec![b"\x1b]0;user@host: ~\x07$ ".to_vec(), b"ls\r\n".to_vec()],
+            };
+
+            ShellServer::new()
+                .unwrap()
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    true,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .unwrap();
+
+            // The sequence is still passed through for terminals which handle it
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![
+                    ShellServerMessage::Stdout(
+                        "\x1b]0;user@host: ~\x07$ ".as_bytes().to_vec().into()
+                    ),
+                    ShellServerMessage::Title("user@host: ~".to_owned()),
+                    ShellServerMessage::Stdout("ls\r\n".as_bytes().to_vec().into()),
+                    ShellServerMessage::Exited(ExitStatus::exited(0))
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_title_not_sent_unless_requested() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(vec![], true);
+            let mut stream = stream.into_shell_stream();
+            let shell = ScriptedShell {
+                chunks: vec![b"\x1b]2;title\x07".to_vec()],
+            };
+
+            ShellServer::new()
+                .unwrap()
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .unwrap();
+
+            assert!(!parse_server_messages(&output)
+                .await
+                .iter()
+                .any(|i| matches!(i, ShellServerMessage::Title(_))));
+        });
+    }
+
+    #[test]
+    fn test_osc52_sequence_sent_intact() {
+        Runtime::new().unwrap().block_on(async {
+            let sequence = b"\x1b]52;c;aGVsbG8=\x07".to_vec();
+
+            let (stream, output) = MockStream::new(vec![], true);
+            let mut stream = stream.into_shell_stream();
+            let shell = ScriptedShell {
+                chunks: vec![
+                    b"before\x1b]52;c;aG".to_vec(),
+                    b"VsbG8".to_vec(),
+                    b"=\x07after".to_vec(),
+                ],
+            };
+
+            ShellServer::new()
+                .unwrap()
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .unwrap();
+
+            let messages = parse_server_messages(&output).await;
+
+            assert!(messages.iter().any(|i| match i {
+                ShellServerMessage::Stdout(chunk) => chunk
+                    .windows(sequence.len())
+                    .any(|i| i == sequence.as_slice()),
+                _ => false,
+            }));
+            assert_eq!(
+                messages,
+                vec![
+                    ShellServerMessage::Stdout("before".as_bytes().to_vec().into()),
+                    ShellServerMessage::Stdout(
+                        "\x1b]52;c;aGVsbG8=\x07after".as_bytes().to_vec().into()
+                    ),
+                    ShellServerMessage::Exited(ExitStatus::exited(0))
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_large_output_stream() {
+        Runtime::new().unwrap().block_on(async {
+            let data = (0..100_000).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+
+            let (stream, output) = MockStream::new(vec![], true);
+            let mut stream = stream.into_shell_stream();
+            let shell = ScriptedShell {
+                chunks: data
+                    .chunks(crate::DEFAULT_CHUNK_SIZE)
+                    .map(|i| i.to_vec())
+                    .collect(),
+            };
+
+            let config = ShellServerConfig {
+                utf8_safe_output: false,
+                osc_safe_output: false,
+                ..ShellServerConfig::default()
+            };
+
+            ShellServer::with_config(config)
+                .unwrap()
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .unwrap();
+
+            let messages = parse_server_messages(&output).await;
+            let mut received = vec![];
+
+            for message in messages.iter() {
+                if let ShellServerMessage::Stdout(chunk) = message {
+                    assert!(chunk.len() <= crate::DEFAULT_CHUNK_SIZE);
+                    received.extend_from_slice(chunk);
+                }
+            }
+
+            assert_eq!(received, data);
+            assert_eq!(
+                messages.last().unwrap(),
+                &ShellServerMessage::Exited(ExitStatus::exited(0))
+            );
+        });
+    }
+
+    #[test]
+    fn test_output_throttled_to_max_bytes_per_sec() {
+        Runtime::new().unwrap().block_on(async {
+            let data = vec![b'a'; 3000];
+
+            let (stream, output) = MockStream::new(vec![], true);
+            let mut stream = stream.into_shell_stream();
+            let shell = ScriptedShell {
+                chunks: data.chunks(500).map(|i| i.to_vec()).collect(),
+            };
+
+            let server = ShellServer::builder()
+                .max_bytes_per_sec(2000)
+                .build()
+                .unwrap();
+
+            let started_at = time::Instant::now();
+            server
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .unwrap();
+
+            // The first 2000 bytes are sent as a burst, the remainder at 2000 bytes per second
+            assert!(started_at.elapsed() >= Duration::from_millis(500));
+
+            let received = parse_server_messages(&output)
+                .await
+                .into_iter()
+                .filter_map(|i| match i {
+                    ShellServerMessage::Stdout(data) => Some(data.to_vec()),
+                    _ => None,
+                })
+                .flatten()
+                .collect::<Vec<u8>>();
+            assert_eq!(received, data);
+        });
+    }
+
+    #[test]
+    fn test_invalid_message_reported_to_client() {
+        Runtime::new().unwrap().block_on(async {
+            let (mut stream, output) = MockStream::new(vec![], true);
+            stream.input.extend(
+                RawMessage::new(4, "not json".as_bytes().to_vec())
+                    .unwrap()
+                    .to_vec(),
+            );
+            let mut stream = stream.into_shell_stream();
+            let (shell, _) = MockShell::new();
+
+            let err = timeout(
+                Duration::from_millis(2000),
+                ShellServer::new().unwrap().steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                ),
+            )
+            .await
+            .expect("session should end")
+            .unwrap_err();
+
+            let error = parse_server_messages(&output)
+                .await
+                .into_iter()
+                .find_map(|i| match i {
+                    ShellServerMessage::Error { severity, message } => Some((severity, message)),
+                    _ => None,
+                })
+                .expect("error should be sent to client");
+
+            assert_eq!(error, (ErrorSeverity::Fatal, format!("{:#}", err)));
+            assert!(error
+                .1
+                .starts_with("received invalid message from shell client"));
+        });
+    }
+
+    #[test]
+    fn test_output_continues_after_stdin_close() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(
+                vec![
+                    ShellClientMessage::Stdin("input".as_bytes().to_vec()),
+                    ShellClientMessage::StdinClose,
+                    ShellClientMessage::Stdin("ignored".as_bytes().to_vec()),
+                ],
+                true,
+            );
+            let mut stream = stream.into_shell_stream();
+            let shell = HalfCloseShell {
+                stdin_closed: false,
+                chunks: vec!["first".as_bytes().to_vec(), "second".as_bytes().to_vec()],
+            };
+
+            timeout(
+                Duration::from_millis(2000),
+                ShellServer::new().unwrap().steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                ),
+            )
+            .await
+            .expect("shell should exit")
+            .unwrap();
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![
+                    ShellServerMessage::Stdout("first".as_bytes().to_vec().into()),
+                    ShellServerMessage::Stdout("second".as_bytes().to_vec().into()),
+                    ShellServerMessage::Exited(ExitStatus::exited(0))
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_get_and_set_cwd() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(
+                vec![
+                    ShellClientMessage::SetCwd("/it's here".to_owned()),
+                    ShellClientMessage::SetCwd("/tmp\nexit".to_owned()),
+                    ShellClientMessage::GetCwd,
+                    ShellClientMessage::Detach,
+                ],
+                true,
+            );
+            let mut stream = stream.into_shell_stream();
+            let writes = Arc::new(Mutex::new(vec![]));
+            let shell = RecordingShell {
+                writes: Arc::clone(&writes),
+                pty: false,
+            };
+
+            timeout(
+                Duration::from_millis(2000),
+                ShellServer::new()
+                    .unwrap()
+                    .with_reconnect_token("token")
+                    .steam_shell_io(
+                        &mut stream,
+                        Box::new(shell),
+                        false,
+                    false,
+                    &mut SessionReport::new("test"),
+                ),
+            )
+            .await
+            .expect("shell should detach")
+            .unwrap();
+
+            assert_eq!(
+                *writes.lock().unwrap(),
+                vec!["cd '/it'\\''s here'\n".as_bytes().to_vec()]
+            );
+
+            let messages = parse_server_messages(&output).await;
+            assert_eq!(
+                messages[..2],
+                [
+                    ShellServerMessage::warning(
+                        "failed to change working directory: invalid working directory: \"/tmp\\nexit\""
+                    ),
+                    ShellServerMessage::Cwd("/home/test".to_owned())
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_cwd_unavailable() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(vec![ShellClientMessage::GetCwd], false);
+            let mut stream = stream.into_shell_stream();
+            let shell = HalfCloseShell {
+                stdin_closed: false,
+                chunks: vec![],
+            };
+
+            timeout(
+                Duration::from_millis(2000),
+                ShellServer::new().unwrap().steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                ),
+            )
+            .await
+            .expect("session should end")
+            .unwrap();
+
+            assert_eq!(
+                parse_server_messages(&output).await[0],
+                ShellServerMessage::warning(
+                    "failed to get working directory: the working directory of this shell cannot be determined"
+                )
+            );
+        });
+    }
+
+    #[test]
+    fn test_system_info_requested() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(vec![ShellClientMessage::SystemInfo], false);
+            let mut stream = stream.into_shell_stream();
+            let shell = HalfCloseShell {
+                stdin_closed: false,
+                chunks: vec![],
+            };
+
+            timeout(
+                Duration::from_millis(2000),
+                ShellServer::new().unwrap().steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                ),
+            )
+            .await
+            .expect("session should end")
+            .unwrap();
+
+            match &parse_server_messages(&output).await[0] {
+                ShellServerMessage::SystemInfo(info) => {
+                    assert_eq!(info.os, std::env::consts::OS)
+                }
+                message => panic!("expected system info, got {:?}", message),
+            }
+        });
+    }
+
+    #[test]
+    fn test_detach_leaves_shell_running() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(
+                vec![
+                    ShellClientMessage::Stdin("input".as_bytes().to_vec()),
+                    ShellClientMessage::Detach,
+                ],
+                true,
+            );
+            let mut stream = stream.into_shell_stream();
+            let (shell, terminated) = MockShell::new();
+            let server = ShellServer::new().unwrap().with_reconnect_token("token");
+
+            timeout(
+                Duration::from_millis(2000),
+                server.steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                ),
+            )
+            .await
+            .expect("io loop should exit on detach")
+            .unwrap();
+
+            let token = match parse_server_messages(&output).await.last() {
+                Some(ShellServerMessage::Detached(token)) => token.clone(),
+                message => panic!("unexpected last message: {:?}", message),
+            };
+
+            assert_eq!(token, "token");
+            assert!(!(*terminated.lock().unwrap()));
+            assert_eq!(server.detached_shells.len(), 1);
+
+            let detached = server.detached_shells.take(&token).unwrap();
+
+            assert!(!detached.raw);
+            assert!(detached.shell.exit_status().is_err());
+            assert!(server.detached_shells.take(&token).is_none());
+        });
+    }
+
+    #[test]
+    fn test_detach_without_reconnect_token() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(vec![ShellClientMessage::Detach], true);
+            let mut stream = stream.into_shell_stream();
+            let (shell, terminated) = MockShell::new();
+            let server = ShellServer::new().unwrap();
+            let mut report = SessionReport::new("test");
+
+            timeout(
+                Duration::from_millis(200),
+                server.steam_shell_io(&mut stream, Box::new(shell), true, false, &mut report),
+            )
+            .await
+            .expect_err("session should continue");
+
+            assert!(parse_server_messages(&output)
+                .await
+                .contains(&ShellServerMessage::warning(
+                    "detaching is not supported by this session"
+                )));
+            assert!(!(*terminated.lock().unwrap()));
+            assert!(!report.detached);
+            assert_eq!(server.detached_shells.len(), 0);
+            assert!(!server.capabilities().contains(Capabilities::DETACH));
+        });
+    }
+
+    /// Detaches a mock shell from a client, returning whether the shell has been terminated
+    async fn detach_mock_shell(server: &ShellServer) -> Arc<Mutex<bool>> {
+        let (stream, _) = MockStream::new(vec![ShellClientMessage::Detach], true);
+        let mut stream = stream.into_shell_stream();
+        let (shell, terminated) = MockShell::new();
+
+        server
+            .steam_shell_io(
+                &mut stream,
+                Box::new(shell),
+                false,
+                true,
+                &mut SessionReport::new("test"),
+            )
+            .await
+            .unwrap();
+
+        terminated
+    }
+
+    #[test]
+    fn test_reattach_with_reconnect_token() {
+        Runtime::new().unwrap().block_on(async {
+            let server = ShellServer::new().unwrap().with_reconnect_token("token");
+            let terminated = detach_mock_shell(&server).await;
+
+            assert!(server.capabilities().contains(Capabilities::DETACH));
+
+            let (stream, output) = MockStream::new(
+                vec![ShellClientMessage::Reattach(ReattachPayload {
+                    token: "token".to_owned(),
+                    size: Some(WindowSize(100, 50)),
+                })],
+                true,
+            );
+            let mut stream = stream.into_shell_stream();
+
+            let (shell, mode) = server.start_shell(&mut stream, None).await.unwrap();
+
+            assert_eq!(
+                mode,
+                ShellIoMode {
+                    raw: false,
+                    titles: true
+                }
+            );
+            assert_eq!(server.detached_shells.len(), 0);
+
+            timeout(
+                Duration::from_millis(200),
+                server.steam_shell_io(
+                    &mut stream,
+                    shell,
+                    mode.raw,
+                    mode.titles,
+                    &mut SessionReport::new("test"),
+                ),
+            )
+            .await
+            .expect_err("reattached shell should keep running");
+
+            let messages = parse_server_messages(&output).await;
+            assert_eq!(
+                messages[0],
+                ShellServerMessage::SizeApplied(WindowSize(100, 50))
+            );
+            assert!(messages
+                .iter()
+                .any(|i| matches!(i, ShellServerMessage::Stdout(_))));
+            assert!(!(*terminated.lock().unwrap()));
+        });
+    }
+
+    #[test]
+    fn test_reattach_with_invalid_token() {
+        Runtime::new().unwrap().block_on(async {
+            let server = ShellServer::new().unwrap().with_reconnect_token("token");
+            let terminated = detach_mock_shell(&server).await;
+
+            let (stream, output) = MockStream::new(
+                vec![ShellClientMessage::Reattach(ReattachPayload {
+                    token: "invalid".to_owned(),
+                    size: None,
+                })],
+                true,
+            );
+            let mut stream = stream.into_shell_stream();
+
+            let err = server
+                .start_shell(&mut stream, None)
+                .await
+                .err()
+                .expect("reattach should be rejected");
+
+            assert_eq!(err.to_string(), "invalid reconnect token");
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![ShellServerMessage::fatal("invalid reconnect token")]
+            );
+            assert_eq!(server.detached_shells.len(), 1);
+            assert!(!(*terminated.lock().unwrap()));
+        });
+    }
+
+    #[test]
+    fn test_reattach_rejected_for_forced_command() {
+        Runtime::new().unwrap().block_on(async {
+            let server = ShellServer::new().unwrap().with_reconnect_token("token");
+            detach_mock_shell(&server).await;
+
+            let (stream, _) = MockStream::new(
+                vec![ShellClientMessage::Reattach(ReattachPayload {
+                    token: "token".to_owned(),
+                    size: None,
+                })],
+                true,
+            );
+            let mut stream = stream.into_shell_stream();
+            let command = vec!["/bin/true".to_owned()];
+
+            let err = server
+                .start_shell(&mut stream, Some(&command))
+                .await
+                .err()
+                .expect("reattach should be rejected");
+
+            assert_eq!(err.to_string(), "reattaching is not permitted");
+            assert_eq!(server.detached_shells.len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_detached_shell_terminated_after_ttl() {
+        Runtime::new().unwrap().block_on(async {
+            let server = ShellServer::builder()
+                .detached_shell_ttl(Duration::from_millis(100))
+                .build()
+                .unwrap()
+                .with_reconnect_token("token");
+            let terminated = detach_mock_shell(&server).await;
+
+            assert_eq!(server.detached_shells.len(), 1);
+            assert!(!(*terminated.lock().unwrap()));
+
+            time::delay_for(Duration::from_millis(300)).await;
+
+            assert_eq!(server.detached_shells.len(), 0);
+            assert!(*terminated.lock().unwrap());
+        });
+    }
+
+    #[test]
+    fn test_shell_terminated_when_too_many_detached() {
+        Runtime::new().unwrap().block_on(async {
+            let server = ShellServer::builder()
+                .max_detached_shells(1)
+                .build()
+                .unwrap()
+                .with_reconnect_token("token");
+            detach_mock_shell(&server).await;
+
+            let (stream, output) = MockStream::new(vec![ShellClientMessage::Detach], true);
+            let mut stream = stream.into_shell_stream();
+            let (shell, terminated) = MockShell::new();
+            let mut report = SessionReport::new("test");
+
+            server
+                .steam_shell_io(&mut stream, Box::new(shell), false, false, &mut report)
+                .await
+                .expect_err("shell should not be detached");
+
+            assert_eq!(
+                parse_server_messages(&output).await.last(),
+                Some(&ShellServerMessage::fatal(
+                    "too many detached shells, the shell has been terminated"
+                ))
+            );
+            assert!(*terminated.lock().unwrap());
+            assert!(!report.detached);
+            assert_eq!(server.detached_shells.len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_output_read_in_preferred_chunk_size() {
+        Runtime::new().unwrap().block_on(async {
+            let (mut stream, output) = MockStream::new(vec![], true);
+            stream.chunk_size = 16;
+            let mut stream = stream.into_shell_stream();
+            let shell = ScriptedShell {
+                chunks: vec![vec![b'a'; 40]],
+            };
+
+            ShellServer::new()
+                .unwrap()
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![
+                    ShellServerMessage::Stdout(vec![b'a'; 16].into()),
+                    ShellServerMessage::Stdout(vec![b'a'; 16].into()),
+                    ShellServerMessage::Stdout(vec![b'a'; 8].into()),
+                    ShellServerMessage::Exited(ExitStatus::exited(0))
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_partial_output_flushed_within_interval() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(vec![], true);
+            let mut stream = stream.into_shell_stream();
+            // A prompt ending in the first byte of "é", which is held back by the utf8 chunker
+            let shell = StalledShell {
+                chunks: vec![b"password \xc3".to_vec()],
+            };
+            let server = ShellServer::builder()
+                .output_flush_interval(Duration::from_millis(20))
+                .build()
+                .unwrap();
+
+            timeout(
+                Duration::from_millis(200),
+                server.steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                ),
+            )
+            .await
+            .expect_err("shell should not exit");
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![
+                    ShellServerMessage::Stdout(Bytes::from_static(b"password ")),
+                    ShellServerMessage::Stdout(Bytes::from_static(b"\xc3")),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_raw_mode_bypasses_utf8_chunking() {
+        Runtime::new().unwrap().block_on(async {
+            let data = "é".as_bytes();
+
+            let (stream, output) = MockStream::new(vec![], true);
+            let mut stream = stream.into_shell_stream();
+            let shell = ScriptedShell {
+                chunks: vec![data[..1].to_vec(), data[1..].to_vec()],
+            };
+
+            ShellServer::new()
+                .unwrap()
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    true,
+                    false,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![
+                    ShellServerMessage::Stdout(data[..1].to_vec().into()),
+                    ShellServerMessage::Stdout(data[1..].to_vec().into()),
+                    ShellServerMessage::Exited(ExitStatus::exited(0))
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_binary_output_bypasses_utf8_chunking() {
+        Runtime::new().unwrap().block_on(async {
+            let data = "é".as_bytes();
+
+            let (stream, output) = MockStream::new(vec![], true);
+            let mut stream = stream.into_shell_stream();
+            let shell = ScriptedShell {
+                chunks: vec![data[..1].to_vec(), data[1..].to_vec()],
+            };
+
+            let config = ShellServerConfig {
+                utf8_safe_output: false,
+                ..ShellServerConfig::default()
+            };
+
+            ShellServer::with_config(config)
+                .unwrap()
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![
+                    ShellServerMessage::Stdout(data[..1].to_vec().into()),
+                    ShellServerMessage::Stdout(data[1..].to_vec().into()),
+                    ShellServerMessage::Exited(ExitStatus::exited(0))
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_exit_code_available_after_delay() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(vec![], true);
+            let mut stream = stream.into_shell_stream();
+            let shell = DelayedExitCodeShell {
+                exit_code_after: 3,
+                queries: AtomicUsize::new(0),
+            };
+
+            ShellServer::new()
+                .unwrap()
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![ShellServerMessage::Exited(ExitStatus::exited(3))]
+            );
+        });
+    }
+
+    #[test]
+    fn test_exit_code_unavailable() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(vec![], true);
+            let mut stream = stream.into_shell_stream();
+            let shell = DelayedExitCodeShell {
+                exit_code_after: usize::MAX,
+                queries: AtomicUsize::new(0),
+            };
+
+            ShellServer::new()
+                .unwrap()
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![ShellServerMessage::Exited(ExitStatus::unknown())]
+            );
+        });
+    }
+
+    #[test]
+    fn test_unknown_message_is_ignored() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, _) = MockStream::new(
+                vec![
+                    ShellClientMessage::Unknown(100),
+                    ShellClientMessage::Stdin(vec![1, 2, 3]),
+                ],
+                false,
+            );
+            let mut stream = stream.into_shell_stream();
+            let (shell, terminated) = MockShell::new();
+
+            ShellServer::new()
+                .unwrap()
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .expect("session should continue after unknown message");
+
+            assert!(!(*terminated.lock().unwrap()));
+        });
+    }
+
+    #[test]
+    fn test_unknown_message_during_handshake() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, _) = MockStream::new(vec![ShellClientMessage::Unknown(100)], false);
+            let mut stream = stream.into_shell_stream();
+
+            ShellServer::new()
+                .unwrap()
+                .wait_for_hello(&mut stream)
+                .await
+                .expect_err("unknown message should be rejected during handshake");
+        });
+    }
+
+    fn stdin_burst() -> (Vec<ShellClientMessage>, Vec<u8>) {
+        let data = (0..100u8).collect::<Vec<u8>>();
+        let messages = data
+            .iter()
+            .map(|i| ShellClientMessage::Stdin(vec![*i]))
+            .collect();
+
+        (messages, data)
+    }
+
+    #[test]
+    fn test_stdin_is_coalesced() {
+        Runtime::new().unwrap().block_on(async {
+            let (messages, data) = stdin_burst();
+            let (stream, _) = MockStream::new(messages, false);
+            let mut stream = stream.into_shell_stream();
+            let writes = Arc::new(Mutex::new(vec![]));
+            let shell = RecordingShell {
+                writes: Arc::clone(&writes),
+                pty: false,
+            };
+
+            let config = ShellServerConfig {
+                stdin_coalesce_window: Some(Duration::from_millis(50)),
+                ..ShellServerConfig::default()
+            };
+
+            ShellServer::with_config(config)
+                .unwrap()
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .unwrap();
+
+            let writes = writes.lock().unwrap();
+
+            assert!(writes.len() < data.len());
+            assert_eq!(writes.concat(), data);
+        });
+    }
+
+    #[test]
+    fn test_large_stdin_is_streamed_in_bounded_writes() {
+        Runtime::new().unwrap().block_on(async {
+            let chunk_size = 1024;
+            let data = (0..4 * 1024 * 1024)
+                .map(|i| (i % 251) as u8)
+                .collect::<Vec<u8>>();
+            let messages = data
+                .chunks(chunk_size)
+                .map(|i| ShellClientMessage::Stdin(i.to_vec()))
+                .collect();
+            let (stream, _) = MockStream::new(messages, false);
+            let mut stream = stream.into_shell_stream();
+            let writes = Arc::new(Mutex::new(vec![]));
+            let shell = RecordingShell {
+                writes: Arc::clone(&writes),
+                pty: false,
+            };
+
+            // The window outlasts the input so without a bound it would all be buffered
+            let config = ShellServerConfig {
+                stdin_coalesce_window: Some(Duration::from_secs(60)),
+                ..ShellServerConfig::default()
+            };
+
+            ShellServer::with_config(config)
+                .unwrap()
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .unwrap();
+
+            let writes = writes.lock().unwrap();
+
+            assert!(writes.len() > 1);
+            assert!(writes
+                .iter()
+                .all(|i| i.len() < MAX_PENDING_STDIN + chunk_size));
+            assert_eq!(writes.concat(), data);
+        });
+    }
+
+    #[test]
+    fn test_stdin_without_coalescing() {
+        Runtime::new().unwrap().block_on(async {
+            let (messages, data) = stdin_burst();
+            let (stream, _) = MockStream::new(messages, false);
+            let mut stream = stream.into_shell_stream();
+            let writes = Arc::new(Mutex::new(vec![]));
+            let shell = RecordingShell {
+                writes: Arc::clone(&writes),
+                pty: false,
+            };
+
+            let config = ShellServerConfig {
+                stdin_coalesce_window: None,
+                ..ShellServerConfig::default()
+            };
+
+            ShellServer::with_config(config)
+                .unwrap()
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .unwrap();
+
+            let writes = writes.lock().unwrap();
+
+            assert_eq!(writes.len(), data.len());
+            assert_eq!(writes.concat(), data);
+        });
+    }
+
+    #[test]
+    fn test_paste_is_bracketed_in_pty() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, _) = MockStream::new(
+                vec![ShellClientMessage::Paste(b"echo one\necho two\n".to_vec())],
+                false,
+            );
+            let mut stream = stream.into_shell_stream();
+            let writes = Arc::new(Mutex::new(vec![]));
+            let shell = RecordingShell {
+                writes: Arc::clone(&writes),
+                pty: true,
+            };
+
+            ShellServer::new()
+                .unwrap()
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(
+                writes.lock().unwrap().concat(),
+                b"\x1b[200~echo one\necho two\n\x1b[201~".to_vec()
+            );
+        });
+    }
+
+    #[test]
+    fn test_paste_is_raw_without_pty() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, _) = MockStream::new(
+                vec![ShellClientMessage::Paste(b"echo one\necho two\n".to_vec())],
+                false,
+            );
+            let mut stream = stream.into_shell_stream();
+            let writes = Arc::new(Mutex::new(vec![]));
+            let shell = RecordingShell {
+                writes: Arc::clone(&writes),
+                pty: false,
+            };
+
+            ShellServer::new()
+                .unwrap()
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(
+                writes.lock().unwrap().concat(),
+                b"echo one\necho two\n".to_vec()
+            );
+        });
+    }
+
+    #[test]
+    fn test_resize_sends_applied_size() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(
+                vec![
+                    ShellClientMessage::Resize(WindowSize(100, 50)),
+                    ShellClientMessage::Resize(WindowSize(0, u16::MAX)),
+                ],
+                true,
+            );
+            let mut stream = stream.into_shell_stream();
+            let shell = RecordingShell {
+                writes: Arc::new(Mutex::new(vec![])),
+                pty: false,
+            };
+
+            // The second resize is applied once the debounce period has passed
+            timeout(
+                RESIZE_DEBOUNCE * 4,
+                ShellServer::new().unwrap().steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                ),
+            )
+            .await
+            .expect_err("shell should not exit");
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![
+                    ShellServerMessage::SizeApplied(WindowSize(100, 50)),
+                    ShellServerMessage::warning(format!(
+                        "window size 0x{} is out of range, resized to 1x{}",
+                        u16::MAX,
+                        MAX_WINDOW_DIMENSION
+                    )),
+                    ShellServerMessage::SizeApplied(WindowSize(1, MAX_WINDOW_DIMENSION)),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_pending_resize_applied_when_stream_ends() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(
+                vec![
+                    ShellClientMessage::Resize(WindowSize(100, 50)),
+                    ShellClientMessage::Resize(WindowSize(0, u16::MAX)),
+                ],
+                false,
+            );
+            let mut stream = stream.into_shell_stream();
+            let sizes = Arc::new(Mutex::new(vec![]));
+            let shell = ResizeRecordingShell {
+                sizes: Arc::clone(&sizes),
+            };
+
+            ShellServer::new()
+                .unwrap()
+                .steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(
+                sizes.lock().unwrap().clone(),
+                vec![WindowSize(100, 50), WindowSize(1, MAX_WINDOW_DIMENSION)]
+            );
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![ShellServerMessage::SizeApplied(WindowSize(100, 50))]
+            );
+        });
+    }
+
+    #[test]
+    fn test_rapid_resizes_are_coalesced() {
+        Runtime::new().unwrap().block_on(async {
+            let messages = (1..=50)
+                .map(|i| ShellClientMessage::Resize(WindowSize(80 + i, 24 + i)))
+                .collect::<Vec<_>>();
+            let (stream, output) = MockStream::new(messages, true);
+            let mut stream = stream.into_shell_stream();
+            let sizes = Arc::new(Mutex::new(vec![]));
+            let shell = ResizeRecordingShell {
+                sizes: Arc::clone(&sizes),
+            };
+
+            timeout(
+                RESIZE_DEBOUNCE * 4,
+                ShellServer::new().unwrap().steam_shell_io(
+                    &mut stream,
+                    Box::new(shell),
+                    false,
+                    false,
+                    &mut SessionReport::new("test"),
+                ),
+            )
+            .await
+            .expect_err("shell should not exit");
+
+            // The first resize is applied immediately and the last once the burst has settled
+            assert_eq!(
+                sizes.lock().unwrap().clone(),
+                vec![WindowSize(81, 25), WindowSize(130, 74)]
+            );
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![
+                    ShellServerMessage::SizeApplied(WindowSize(81, 25)),
+                    ShellServerMessage::SizeApplied(WindowSize(130, 74)),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_auth_observer_accepted() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, _) =
+                MockStream::new(vec![ShellClientMessage::Key("NewKey".to_owned())], false);
+            let mut stream = stream.into_shell_stream();
+            let observer = Arc::new(RecordingAuthObserver::default());
+
+            ShellServer::new()
+                .unwrap()
+                .with_auth_observer(observer.clone())
+                .with_peer_addr("1.2.3.4")
+                .wait_for_key(
+                    &mut stream,
+                    &vec![ShellKey::new("OldKey"), ShellKey::new("NewKey")].into(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(
+                *observer.accepted.lock().unwrap(),
+                vec![AuthPeer {
+                    key_index: Some(1),
+                    peer_addr: Some("1.2.3.4".to_owned())
+                }]
+            );
+            assert_eq!(*observer.rejected.lock().unwrap(), vec![]);
+        });
+    }
+
+    #[test]
+    fn test_auth_observer_rejected() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, _) =
+                MockStream::new(vec![ShellClientMessage::Key("Unknown".to_owned())], false);
+            let mut stream = stream.into_shell_stream();
+            let observer = Arc::new(RecordingAuthObserver::default());
+
+            ShellServer::new()
+                .unwrap()
+                .with_auth_observer(observer.clone())
+                .wait_for_key(&mut stream, &ShellKey::new("CorrectKey").into())
+                .await
+                .unwrap_err();
+
+            assert_eq!(*observer.accepted.lock().unwrap(), vec![]);
+            assert_eq!(
+                *observer.rejected.lock().unwrap(),
+                vec![AuthPeer {
+                    key_index: None,
+                    peer_addr: None
+                }]
+            );
+        });
+    }
+
+    #[test]
+    fn test_rejected_key_is_delayed() {
+        Runtime::new().unwrap().block_on(async {
+            let server = ShellServer::builder()
+                .auth_failure_delay(Some(Duration::from_millis(100)))
+                .max_auth_failure_delay(None)
+                .build()
+                .unwrap();
+
+            let (stream, output) =
+                MockStream::new(vec![ShellClientMessage::Key("Unknown".to_owned())], false);
+            let mut stream = stream.into_shell_stream();
+            let (accepted_stream, _) = MockStream::new(
+                vec![ShellClientMessage::Key("CorrectKey".to_owned())],
+                false,
+            );
+            let mut accepted_stream = accepted_stream.into_shell_stream();
+            let started_at = time::Instant::now();
+
+            let rejected = async {
+                let result = server
+                    .wait_for_key(&mut stream, &ShellKey::new("CorrectKey").into())
+                    .await;
+                (result, started_at.elapsed())
+            };
+            let accepted = async {
+                let result = server
+                    .wait_for_key(&mut accepted_stream, &ShellKey::new("CorrectKey").into())
+                    .await;
+                (result, started_at.elapsed())
+            };
+
+            let ((rejected, rejected_after), (accepted, accepted_after)) =
+                futures::future::join(rejected, accepted).await;
+
+            rejected.unwrap_err();
+            assert!(rejected_after >= Duration::from_millis(100));
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![ShellServerMessage::KeyRejected]
+            );
+
+            // Other sessions are not held up by the delay
+            assert_eq!(accepted.unwrap(), 0);
+            assert!(accepted_after < Duration::from_millis(100));
+        });
+    }
+
+    #[test]
+    fn test_rejected_key_delay_grows_per_peer() {
+        Runtime::new().unwrap().block_on(async {
+            let server = ShellServer::builder()
+                .auth_failure_delay(Some(Duration::from_millis(50)))
+                .max_auth_failure_delay(Some(Duration::from_secs(1)))
+                .build()
+                .unwrap()
+                .with_peer_addr("1.2.3.4");
+
+            let mut elapsed = vec![];
+            for _ in 0..3 {
+                let (stream, _) =
+                    MockStream::new(vec![ShellClientMessage::Key("Unknown".to_owned())], false);
+                let mut stream = stream.into_shell_stream();
+                let started_at = time::Instant::now();
+
+                server
+                    .clone()
+                    .wait_for_key(&mut stream, &ShellKey::new("CorrectKey").into())
+                    .await
+                    .unwrap_err();
+                elapsed.push(started_at.elapsed());
+            }
+
+            assert!(elapsed[0] >= Duration::from_millis(50));
+            assert!(elapsed[1] >= Duration::from_millis(100));
+            assert!(elapsed[2] >= Duration::from_millis(200));
+        });
+    }
+
+    #[test]
+    fn test_key_proof_accepted() {
+        Runtime::new().unwrap().block_on(async {
+            let nonce = [1u8; CHALLENGE_NONCE_LEN];
+            let proof = ShellKey::new("CorrectKey").proof(&nonce);
+            let (stream, output) =
+                MockStream::new(vec![ShellClientMessage::KeyProof(proof)], false);
+            let mut stream = stream.into_shell_stream();
+
+            let key_idx = ShellServer::new()
+                .unwrap()
+                .verify_key_proof(&mut stream, &ShellKey::new("CorrectKey").into(), &nonce)
+                .await
+                .unwrap();
+
+            assert_eq!(key_idx, 0);
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![ShellServerMessage::KeyAccepted]
+            );
+        });
+    }
+
+    #[test]
+    fn test_key_proof_rejected() {
+        Runtime::new().unwrap().block_on(async {
+            let nonce = [1u8; CHALLENGE_NONCE_LEN];
+            let proof = ShellKey::new("Invalid").proof(&nonce);
+            let (stream, output) =
+                MockStream::new(vec![ShellClientMessage::KeyProof(proof)], false);
+            let mut stream = stream.into_shell_stream();
+
+            ShellServer::new()
+                .unwrap()
+                .verify_key_proof(&mut stream, &ShellKey::new("CorrectKey").into(), &nonce)
+                .await
+                .expect_err("key proof should be rejected");
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![ShellServerMessage::KeyRejected]
+            );
+        });
+    }
+
+    #[test]
+    fn test_replayed_key_proof_rejected() {
+        Runtime::new().unwrap().block_on(async {
+            // A valid proof captured from a previous session's challenge
+            let captured_nonce = [1u8; CHALLENGE_NONCE_LEN];
+            let proof = ShellKey::new("CorrectKey").proof(&captured_nonce);
+            let (stream, output) =
+                MockStream::new(vec![ShellClientMessage::KeyProof(proof)], false);
+            let mut stream = stream.into_shell_stream();
+
+            ShellServer::new()
+                .unwrap()
+                .wait_for_key_proof(&mut stream, &ShellKey::new("CorrectKey").into())
+                .await
+                .expect_err("replayed key proof should be rejected");
+
+            let messages = parse_server_messages(&output).await;
+
+            match &messages[0] {
+                ShellServerMessage::Challenge(nonce) => {
+                    assert_eq!(nonce.len(), CHALLENGE_NONCE_LEN);
+                    assert_ne!(nonce.as_slice(), &captured_nonce[..]);
+                }
+                message => panic!("expected challenge, got {:?}", message),
+            }
+            assert_eq!(messages[1], ShellServerMessage::KeyRejected);
+        });
+    }
+
+    #[test]
+    fn test_challenge_nonce_is_unique() {
+        assert_ne!(generate_nonce().unwrap(), generate_nonce().unwrap());
+    }
+
+    #[test]
+    fn test_accepts_secondary_key() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(
+                vec![hello(), ShellClientMessage::Key("NewKey".to_owned())],
+                false,
+            );
+
+            ShellServer::new()
+                .unwrap()
+                .run(
+                    Box::new(stream),
+                    vec![ShellKey::new("OldKey"), ShellKey::new("NewKey")],
+                )
+                .await
+                .expect_err("client should not send start shell message");
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![
+                    ShellServerMessage::HelloAck(HelloAckPayload {
+                        protocol_version: PROTOCOL_VERSION,
+                        accepted: true,
+                        capabilities: ShellServer::new().unwrap().capabilities(),
+                        format: MessageFormat::Binary,
+                    }),
+                    ShellServerMessage::KeyAccepted
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_negotiates_json_format() {
+        Runtime::new().unwrap().block_on(async {
+            let (mut stream, output) = MockStream::new(
+                vec![ShellClientMessage::Hello(HelloPayload {
+                    protocol_version: PROTOCOL_VERSION,
+                    format: MessageFormat::Json,
+                    key_proof: false,
+                    sequence_numbers: false,
+                    frame_version: FrameVersion::V1,
+                })],
+                false,
+            );
+            stream.input.extend(
+                ShellClientMessage::Key("Key".to_owned())
+                    .serialise_json()
+                    .unwrap()
+                    .to_vec(),
+            );
+
+            ShellServer::new()
+                .unwrap()
+                .run(Box::new(stream), vec![ShellKey::new("Key")])
+                .await
+                .expect_err("client should not send start shell message");
+
+            let data = output.lock().unwrap().clone();
+            let mut client = ShellClientStream::new(Cursor::new(data));
+
+            assert_eq!(
+                client.next().await.unwrap().unwrap(),
+                ShellServerMessage::HelloAck(HelloAckPayload {
+                    protocol_version: PROTOCOL_VERSION,
+                    accepted: true,
+                    capabilities: ShellServer::new().unwrap().capabilities(),
+                    format: MessageFormat::Json,
+                })
+            );
+
+            client.set_format(MessageFormat::Json);
+
+            assert_eq!(
+                client.next().await.unwrap().unwrap(),
+                ShellServerMessage::KeyAccepted
+            );
+        });
+    }
+
+    #[test]
+    fn test_negotiates_sequence_numbers() {
+        Runtime::new().unwrap().block_on(async {
+            let (mut stream, output) = MockStream::new(
+                vec![ShellClientMessage::Hello(HelloPayload {
+                    protocol_version: PROTOCOL_VERSION,
+                    format: MessageFormat::Binary,
+                    key_proof: false,
+                    sequence_numbers: true,
+                    frame_version: FrameVersion::V1,
+                })],
+                false,
+            );
+
+            let mut key = ShellClientStream::new(Cursor::new(vec![]));
+            key.set_sequence_numbers(true);
+            key.write(&ShellClientMessage::Key("Key".to_owned()))
+                .await
+                .unwrap();
+            stream.input.extend(key.into_inner().into_inner());
+
+            ShellServer::new()
+                .unwrap()
+                .run(Box::new(stream), vec![ShellKey::new("Key")])
+                .await
+                .expect_err("client should not send start shell message");
+
+            let data = output.lock().unwrap().clone();
+            let mut client = ShellClientStream::new(Cursor::new(data));
+
+            assert_eq!(
+                client.next().await.unwrap().unwrap(),
+                ShellServerMessage::HelloAck(HelloAckPayload {
+                    protocol_version: PROTOCOL_VERSION,
+                    accepted: true,
+                    capabilities: ShellServer::new().unwrap().capabilities(),
+                    format: MessageFormat::Binary,
+                })
+            );
+
+            client.set_sequence_numbers(true);
+
+            assert_eq!(
+                client.next().await.unwrap().unwrap(),
+                ShellServerMessage::KeyAccepted
+            );
+            assert_eq!(client.sequence_anomalies(), 0);
+        });
+    }
+
+    #[test]
+    fn test_negotiates_frame_version() {
+        Runtime::new().unwrap().block_on(async {
+            let (mut stream, output) = MockStream::new(
+                vec![ShellClientMessage::Hello(HelloPayload {
+                    protocol_version: PROTOCOL_VERSION,
+                    format: MessageFormat::Binary,
+                    key_proof: false,
+                    sequence_numbers: false,
+                    frame_version: FrameVersion::V2,
+                })],
+                false,
+            );
+
+            let mut key = ShellClientStream::new(Cursor::new(vec![]));
+            key.set_frame_version(FrameVersion::V2);
+            key.write(&ShellClientMessage::Key("Key".to_owned()))
+                .await
+                .unwrap();
+            stream.input.extend(key.into_inner().into_inner());
+
+            ShellServer::new()
+                .unwrap()
+                .run(Box::new(stream), vec![ShellKey::new("Key")])
+                .await
+                .expect_err("client should not send start shell message");
+
+            let data = output.lock().unwrap().clone();
+            let mut client = ShellClientStream::new(Cursor::new(data));
+
+            // The ack is sent in the legacy framing, which the client has not yet switched from
+            match client.next().await.unwrap().unwrap() {
+                ShellServerMessage::HelloAck(ack) => {
+                    assert!(ack.capabilities.contains(Capabilities::FRAME_V2))
+                }
+                message => panic!("unexpected message: {:?}", message),
+            }
+
+            client.set_frame_version(FrameVersion::V2);
+
+            assert_eq!(
+                client.next().await.unwrap().unwrap(),
+                ShellServerMessage::KeyAccepted
+            );
+        });
+    }
+
+    #[test]
+    fn test_rejects_key_not_in_set() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) = MockStream::new(
+                vec![hello(), ShellClientMessage::Key("Unknown".to_owned())],
+                false,
+            );
+
+            ShellServer::new()
+                .unwrap()
+                .run(
+                    Box::new(stream),
+                    vec![ShellKey::new("OldKey"), ShellKey::new("NewKey")],
+                )
+                .await
+                .expect_err("client key should be rejected");
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![
+                    ShellServerMessage::HelloAck(HelloAckPayload {
+                        protocol_version: PROTOCOL_VERSION,
+                        accepted: true,
+                        capabilities: ShellServer::new().unwrap().capabilities(),
+                        format: MessageFormat::Binary,
+                    }),
+                    ShellServerMessage::KeyRejected,
+                    ShellServerMessage::Close {
+                        reason: "client key rejected".to_owned()
+                    }
+                ]
+            );
+        });
+    }
+
+    /// Pty factory which fails with each of the errors in turn before spawning a mock shell
+    #[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
+    struct MockPtyFactory {
+        errors: Mutex<Vec<Error>>,
+        attempts: Arc<Mutex<usize>>,
+    }
+
+    #[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
+    impl PtyFactory for MockPtyFactory {
+        fn spawn(
+            &self,
+            _term: &str,
+            _program: DefaultShell,
+            _size: WindowSize,
+        ) -> Result<Box<dyn Shell + Send>> {
+            *self.attempts.lock().unwrap() += 1;
+
+            let mut errors = self.errors.lock().unwrap();
+            if !errors.is_empty() {
+                return Err(errors.remove(0));
+            }
+
+            Ok(Box::new(HalfCloseShell {
+                stdin_closed: true,
+                chunks: vec!["from pty".as_bytes().to_vec()],
+            }))
+        }
+    }
+
+    #[cfg(unix)]
+    fn create_shell_with_pty_errors(errors: Vec<Error>) -> (Box<dyn Shell + Send>, usize) {
+        let (shell, attempts) =
+            try_create_shell_with_pty_errors(ShellServerConfig::default(), errors);
+
+        (shell.unwrap(), attempts)
+    }
+
+    #[cfg(unix)]
+    fn try_create_shell_with_pty_errors(
+        config: ShellServerConfig,
+        errors: Vec<Error>,
+    ) -> (Result<Box<dyn Shell + Send>>, usize) {
+        let attempts = Arc::new(Mutex::new(0));
+        let mut server = ShellServer::with_config(config).unwrap();
+        server.pty_factory = Arc::new(MockPtyFactory {
+            errors: Mutex::new(errors),
+            attempts: Arc::clone(&attempts),
+        });
+
+        let request = StartShellPayload {
+            term: "TERM".to_owned(),
+            size: Some(WindowSize(80, 24)),
+            pty: true,
+            login: true,
+            interactive: true,
+            raw: false,
+            shell_path: None,
+            colors: None,
+            truecolor: false,
+            line_mode: false,
+            locale: None,
+            timezone: None,
+            titles: false,
+        };
+
+        let shell = Runtime::new()
+            .unwrap()
+            .block_on(server.create_shell(&request, WindowSize(80, 24)));
+        let attempts = *attempts.lock().unwrap();
+
+        (shell, attempts)
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_pty_spawn_retried_after_transient_error() {
+        let enospc = || Error::new(std::io::Error::from_raw_os_error(libc::ENOSPC));
+        let (mut shell, attempts) = create_shell_with_pty_errors(vec![enospc(), enospc()]);
+
+        assert_eq!(attempts, 3);
+
+        let mut buff = [0u8; 1024];
+        let read = Runtime::new()
+            .unwrap()
+            .block_on(shell.read(&mut buff))
+            .unwrap();
+        assert_eq!(&buff[..read], "from pty".as_bytes());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_pty_spawn_not_retried_after_permanent_error() {
+        let enoent = Error::new(std::io::Error::from_raw_os_error(libc::ENOENT));
+        let (_, attempts) = create_shell_with_pty_errors(vec![enoent]);
+
+        // Falls back to the in-built shell without retrying
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_no_shell_action_error() {
+        let config = ShellServerConfig {
+            no_shell_action: Some(NoShellAction::Error),
+            ..ShellServerConfig::default()
+        };
+        let enoent = Error::new(std::io::Error::from_raw_os_error(libc::ENOENT));
+
+        let (shell, _) = try_create_shell_with_pty_errors(config, vec![enoent]);
+        let err = shell
+            .err()
+            .expect("should not fall back to the in-built shell");
+
+        assert!(err.downcast_ref::<ShellUnavailable>().is_some());
+        assert!(err
+            .to_string()
+            .starts_with("shell unavailable on this host: "));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_no_shell_action_message_only() {
+        let config = ShellServerConfig {
+            no_shell_action: Some(NoShellAction::MessageOnly),
+            ..ShellServerConfig::default()
+        };
+        let enoent = Error::new(std::io::Error::from_raw_os_error(libc::ENOENT));
+
+        let (shell, _) = try_create_shell_with_pty_errors(config, vec![enoent]);
+        let mut shell = shell.unwrap();
+
+        Runtime::new().unwrap().block_on(async {
+            let mut output = vec![];
+            let mut buff = [0u8; 1024];
+            loop {
+                match shell.read(&mut buff).await.unwrap() {
+                    0 => break,
+                    read => output.extend_from_slice(&buff[..read]),
+                }
+            }
+
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.starts_with("shell unavailable on this host: "));
+            assert!(output.ends_with("\r\n"));
+            assert_eq!(shell.exit_status().unwrap(), ExitStatus::exited(1));
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_locale_and_timezone_set() {
+        Runtime::new().unwrap().block_on(async {
+            let request = StartShellPayload {
+                term: "TERM".to_owned(),
+                size: Some(WindowSize(80, 24)),
+                pty: false,
+                login: false,
+                interactive: false,
+                raw: false,
+                shell_path: Some("/bin/sh".to_owned()),
+                colors: None,
+                truecolor: false,
+                line_mode: false,
+                locale: Some("C".to_owned()),
+                // A POSIX rule, which does not depend on the host's zone database
+                timezone: Some("JST-9".to_owned()),
+                titles: false,
+            };
+
+            let mut shell = ShellServer::new()
+                .unwrap()
+                .create_shell(&request, WindowSize(80, 24))
+                .await
+                .unwrap();
+
+            shell
+                .write(b"echo \"$LANG $LC_ALL $TZ $(date +%Z)\"\n")
+                .await
+                .unwrap();
+            shell.close_stdin().await.unwrap();
+
+            let mut output = vec![];
+            let mut buff = [0u8; 1024];
+
+            loop {
+                match shell.read(&mut buff).await.unwrap() {
+                    0 => break,
+                    read => output.extend_from_slice(&buff[..read]),
+                }
+            }
+
+            assert_eq!(String::from_utf8(output).unwrap(), "C C JST-9 JST\n");
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_truecolor_sets_colorterm() {
+        Runtime::new().unwrap().block_on(async {
+            let request = StartShellPayload {
+                term: "TERM".to_owned(),
+                size: Some(WindowSize(80, 24)),
+                pty: false,
+                login: false,
+                interactive: false,
+                raw: false,
+                shell_path: Some("/bin/sh".to_owned()),
+                colors: Some(256),
+                truecolor: true,
+                line_mode: false,
+                locale: None,
+                timezone: None,
+                titles: false,
+            };
+
+            let mut shell = ShellServer::new()
+                .unwrap()
+                .create_shell(&request, WindowSize(80, 24))
+                .await
+                .unwrap();
+
+            shell
+                .write(b"echo \"COLORTERM=$COLORTERM\"\n")
+                .await
+                .unwrap();
+            shell.close_stdin().await.unwrap();
+
+            let mut output = vec![];
+            let mut buff = [0u8; 1024];
+
+            loop {
+                match shell.read(&mut buff).await.unwrap() {
+                    0 => break,
+                    read => output.extend_from_slice(&buff[..read]),
+                }
+            }
+
+            assert_eq!(String::from_utf8(output).unwrap(), "COLORTERM=truecolor\n");
+        });
+    }
 
-        // We keep the connection alive for some time to allow the receive
-        // of any acknowledgement packets and so the client can continue to receive
-        // the last message
-        // Improvement: add trait method to TunnelStream wait for ack'd connection state
-        time::delay_for(Duration::from_millis(500)).await;
+    #[test]
+    #[cfg(unix)]
+    fn test_clean_env_hides_inherited_secrets() {
+        Runtime::new().unwrap().block_on(async {
+            std::env::set_var("TUNSHELL_TEST_DB_PASSWORD", "hunter2");
 
-        Ok(())
-    }
+            let request = StartShellPayload {
+                term: "xterm".to_owned(),
+                size: Some(WindowSize(80, 24)),
+                pty: false,
+                login: false,
+                interactive: false,
+                raw: false,
+                shell_path: Some("/bin/sh".to_owned()),
+                colors: None,
+                truecolor: false,
+                line_mode: false,
+                locale: None,
+                timezone: None,
+                titles: false,
+            };
 
-    async fn wait_for_key(&self, stream: &mut ShellStream, key: ShellKey) -> Result<()> {
-        let received_key = tokio::select! {
-            message = stream.next() => match message {
-                Some(Ok(ShellClientMessage::Key(key))) => key,
-                Some(Ok(message)) => return Err(Error::msg(format!("received unexpected message from client: {:?}", message))),
-                Some(Err(err)) => return Err(Error::from(err).context("received invalid message from client")),
-                None => return Err(Error::msg("client did not sent key"))
-            },
-            _ = time::delay_for(Duration::from_millis(3000)) => return Err(Error::msg("timed out while waiting for key"))
-        };
+            for (clean_env, expected) in [(true, "[]\n"), (false, "[hunter2]\n")] {
+                let mut shell = ShellServer::builder()
+                    .clean_env(clean_env)
+                    .build()
+                    .unwrap()
+                    .create_shell(&request, WindowSize(80, 24))
+                    .await
+                    .unwrap();
 
-        // TODO: timing safe comparison
-        if received_key == key.key() {
-            stream.write(&ShellServerMessage::KeyAccepted).await?;
-            return Ok(());
-        } else {
-            stream.write(&ShellServerMessage::KeyRejected).await?;
-            return Err(Error::msg("client key rejected"));
-        }
-    }
+                shell
+                    .write(b"echo \"[$TUNSHELL_TEST_DB_PASSWORD]\"\n")
+                    .await
+                    .unwrap();
+                shell.close_stdin().await.unwrap();
 
-    async fn start_shell(&self, stream: &mut ShellStream) -> Result<Box<dyn Shell + Send>> {
-        let request = tokio::select! {
-            message = stream.next() => match message {
-                Some(Ok(ShellClientMessage::StartShell(request))) => request,
-                Some(Ok(message)) => return Err(Error::msg(format!("received unexpected message from client: {:?}", message))),
-                Some(Err(err)) => return Err(Error::from(err).context("received invalid message from client")),
-                None => return Err(Error::msg("client did not send start shell message"))
-            },
-            _ = time::delay_for(Duration::from_millis(3000)) => return Err(Error::msg("timed out while waiting for shell request"))
-        };
+                let mut output = vec![];
+                let mut buff = [0u8; 1024];
 
-        #[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
-        {
-            debug!("initialising pty shell");
-            let pty_shell = PtyShell::new(request.term.as_ref(), None, request.size.clone());
+                loop {
+                    match shell.read(&mut buff).await.unwrap() {
+                        0 => break,
+                        read => output.extend_from_slice(&buff[..read]),
+                    }
+                }
 
-            if let Ok(pty_shell) = pty_shell {
-                return Ok(Box::new(pty_shell));
+                assert_eq!(String::from_utf8(output).unwrap(), expected);
             }
+        });
+    }
 
-            warn!("failed to init pty shell: {:?}", pty_shell.err().unwrap());
-        }
+    #[test]
+    #[cfg(unix)]
+    fn test_line_mode_runs_shell_without_pty() {
+        Runtime::new().unwrap().block_on(async {
+            let request = StartShellPayload {
+                term: "TERM".to_owned(),
+                size: Some(WindowSize(80, 24)),
+                pty: true,
+                login: false,
+                interactive: false,
+                raw: false,
+                shell_path: Some("/bin/sh".to_owned()),
+                colors: None,
+                truecolor: false,
+                line_mode: true,
+                locale: None,
+                timezone: None,
+                titles: false,
+            };
 
-        debug!("falling back to in-built shell");
-        let fallback_shell = FallbackShell::new(request.term.as_ref(), request.size.clone());
+            let mut shell = ShellServer::new()
+                .unwrap()
+                .create_shell(&request, WindowSize(80, 24))
+                .await
+                .unwrap();
 
-        Ok(Box::new(fallback_shell))
-    }
+            assert!(!shell.is_pty());
 
-    async fn steam_shell_io<'a>(
-        &self,
-        stream: &mut ShellStream,
-        mut shell: Box<dyn Shell + Send + 'a>,
-    ) -> Result<()> {
-        let mut buff = [0u8; 1024];
+            shell.write(b"echo line\n").await.unwrap();
+            shell.close_stdin().await.unwrap();
 
-        loop {
-            info!("waiting for shell message");
-            tokio::select! {
-                result = shell.read(&mut buff) => match result {
-                    Ok(0) => {
-                        let code = shell.exit_code().unwrap();
-                        info!("shell has exited with status {}", code);
-                        stream.write(&ShellServerMessage::Exited(code)).await?;
-                        info!("send exit code status");
-                        break;
-                    },
-                    Ok(read) => {
-                        info!("read {} bytes from stdout", read);
-                        stream.write(&ShellServerMessage::Stdout(buff[..read].to_vec())).await?;
-                        info!("sent {} bytes to client shell", read);
-                    },
-                    Err(err) => {
-                        error!("error while reading from stdout: {}", err);
-                        return Err(err);
-                    }
-                },
-                message = stream.next() => match message {
-                    Some(Ok(ShellClientMessage::Stdin(payload))) => {
-                        info!("received {} bytes from client shell", payload.len());
-                        shell.write(payload.as_slice()).await?;
-                        info!("wrote {} bytes to shell", payload.len());
-                    }
-                    Some(Ok(ShellClientMessage::Resize(size))) => {
-                        info!("received window resize: {:?}", size);
-                        shell.resize(size)?;
-                    }
-                    Some(Ok(message)) => {
-                        return Err(Error::msg(format!("received unexpected message from shell client {:?}", message)));
-                    }
-                    Some(Err(err)) => {
-                        return Err(Error::from(err).context("received invalid message from shell client"));
-                    }
-                    None => {
-                        warn!("client shell stream ended");
-                        break;
-                    }
+            let mut output = vec![];
+            let mut buff = [0u8; 1024];
+
+            loop {
+                match shell.read(&mut buff).await.unwrap() {
+                    0 => break,
+                    read => output.extend_from_slice(&buff[..read]),
                 }
             }
-        }
 
-        Ok(())
+            // The input is not echoed back to the client
+            assert_eq!(String::from_utf8(output).unwrap(), "line\n");
+        });
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::shell::proto::{StartShellPayload, WindowSize};
-    use futures::io::Cursor;
-    use tokio::runtime::Runtime;
-    use tokio::time::timeout;
-    use tunshell_shared::Message;
+    fn start_pipe_shell() -> ShellClientMessage {
+        ShellClientMessage::StartShell(StartShellPayload {
+            term: "TERM".to_owned(),
+            size: Some(WindowSize(80, 24)),
+            pty: false,
+            login: false,
+            interactive: false,
+            raw: false,
+            shell_path: None,
+            colors: None,
+            truecolor: false,
+            line_mode: false,
+            locale: None,
+            timezone: None,
+            titles: false,
+        })
+    }
 
     #[test]
-    fn test_new_shell_server() {
-        ShellServer::new().unwrap();
+    #[cfg(unix)]
+    fn test_start_shell_without_size_uses_default_until_resized() {
+        Runtime::new().unwrap().block_on(async {
+            let mut request = match start_pipe_shell() {
+                ShellClientMessage::StartShell(request) => request,
+                _ => unreachable!(),
+            };
+            request.size = None;
+
+            let (stream, output) = MockStream::new(
+                vec![
+                    ShellClientMessage::StartShell(request),
+                    ShellClientMessage::Resize(WindowSize(120, 40)),
+                ],
+                true,
+            );
+            let mut stream = stream.into_shell_stream();
+            let server = ShellServer::new().unwrap();
+
+            let (shell, request) = server.start_shell(&mut stream, None).await.unwrap();
+
+            timeout(
+                RESIZE_DEBOUNCE * 4,
+                server.steam_shell_io(
+                    &mut stream,
+                    shell,
+                    request.raw,
+                    request.titles,
+                    &mut SessionReport::new("test"),
+                ),
+            )
+            .await
+            .expect_err("shell should not exit");
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![
+                    ShellServerMessage::SizeApplied(WindowSize(80, 24)),
+                    ShellServerMessage::SizeApplied(WindowSize(120, 40)),
+                ]
+            );
+        });
     }
 
     #[test]
-    fn test_rejected_key() {
+    #[cfg(unix)]
+    fn test_start_shell_with_zero_size_uses_default() {
         Runtime::new().unwrap().block_on(async {
-            let mut mock_data = Vec::<u8>::new();
+            let mut request = match start_pipe_shell() {
+                ShellClientMessage::StartShell(request) => request,
+                _ => unreachable!(),
+            };
+            request.size = Some(WindowSize(0, 0));
 
-            mock_data.extend_from_slice(
-                ShellClientMessage::Key("Invalid".to_owned())
-                    .serialise()
-                    .unwrap()
-                    .to_vec()
-                    .as_slice(),
-            );
+            let (stream, output) =
+                MockStream::new(vec![ShellClientMessage::StartShell(request)], true);
+            let mut stream = stream.into_shell_stream();
 
-            let mock_stream = Cursor::new(mock_data).compat();
-            ShellServer::new()
+            let (mut shell, _) = ShellServer::new()
                 .unwrap()
-                .run(Box::new(mock_stream), ShellKey::new("MyKey"))
+                .start_shell(&mut stream, None)
                 .await
-                .expect_err("client key should be rejected");
+                .unwrap();
+            shell.terminate().unwrap();
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![ShellServerMessage::SizeApplied(DEFAULT_WINDOW_SIZE)]
+            );
         });
     }
 
     #[test]
-    fn test_key_timeout() {
+    #[cfg(unix)]
+    fn test_missing_shell_reported_to_client() {
         Runtime::new().unwrap().block_on(async {
-            let mock_data = Vec::<u8>::new();
+            let server = ShellServer::builder()
+                .shell_path("/nonexistent/shell")
+                .fallback_on_missing_shell(false)
+                .build()
+                .unwrap();
 
-            let mock_stream = Cursor::new(mock_data).compat();
+            let (stream, output) = MockStream::new(vec![start_pipe_shell()], true);
+            let mut stream = stream.into_shell_stream();
 
-            timeout(
-                Duration::from_millis(5000),
-                ShellServer::new()
-                    .unwrap()
-                    .run(Box::new(mock_stream), ShellKey::new("CorrectKey")),
-            )
-            .await
-            .unwrap()
-            .expect_err("should timeout");
+            let err = server
+                .start_shell(&mut stream, None)
+                .await
+                .err()
+                .expect("shell should not start when its program is missing");
+
+            assert_eq!(
+                err.downcast_ref::<ShellServerError>(),
+                Some(&ShellServerError::ShellNotFound {
+                    path: "/nonexistent/shell".to_owned()
+                })
+            );
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![ShellServerMessage::fatal(
+                    "shell not found: /nonexistent/shell"
+                )]
+            );
         });
     }
 
     #[test]
-    fn test_start_shell_timeout() {
+    #[cfg(unix)]
+    fn test_missing_shell_falls_back_by_default() {
         Runtime::new().unwrap().block_on(async {
-            let mut mock_data = Vec::<u8>::new();
+            let server = ShellServer::builder()
+                .shell_path("/nonexistent/shell")
+                .build()
+                .unwrap();
 
-            mock_data.extend_from_slice(
-                ShellClientMessage::Key("CorrectKey".to_owned())
-                    .serialise()
-                    .unwrap()
-                    .to_vec()
-                    .as_slice(),
+            let (stream, output) = MockStream::new(vec![start_pipe_shell()], true);
+            let mut stream = stream.into_shell_stream();
+
+            let (mut shell, _) = server.start_shell(&mut stream, None).await.unwrap();
+            shell.terminate().unwrap();
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![ShellServerMessage::SizeApplied(WindowSize(80, 24))]
             );
+        });
+    }
 
-            let mock_stream = Cursor::new(mock_data).compat();
+    #[test]
+    #[cfg(unix)]
+    fn test_pre_shell_hook_succeeds() {
+        Runtime::new().unwrap().block_on(async {
+            let server = ShellServer::builder()
+                .pre_shell_hook(vec![
+                    "/bin/sh".to_owned(),
+                    "-c".to_owned(),
+                    "echo hook ran".to_owned(),
+                ])
+                .stream_pre_shell_hook_output(true)
+                .build()
+                .unwrap();
 
-            timeout(
-                Duration::from_millis(5000),
-                ShellServer::new()
-                    .unwrap()
-                    .run(Box::new(mock_stream), ShellKey::new("CorrectKey")),
-            )
-            .await
-            .unwrap()
-            .expect_err("should timeout");
+            let (stream, output) = MockStream::new(vec![start_pipe_shell()], true);
+            let mut stream = stream.into_shell_stream();
+
+            let (mut shell, _) = server.start_shell(&mut stream, None).await.unwrap();
+            shell.terminate().unwrap();
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![
+                    ShellServerMessage::Stdout(Bytes::from("hook ran\n")),
+                    ShellServerMessage::SizeApplied(WindowSize(80, 24))
+                ]
+            );
         });
     }
 
     #[test]
-    fn test_start_connect_to_shell() {
+    #[cfg(unix)]
+    fn test_pre_shell_hook_failure_aborts_session() {
         Runtime::new().unwrap().block_on(async {
-            let mut mock_data = Vec::<u8>::new();
+            let server = ShellServer::builder()
+                .pre_shell_hook(vec![
+                    "/bin/sh".to_owned(),
+                    "-c".to_owned(),
+                    "echo hook failed; exit 3".to_owned(),
+                ])
+                .build()
+                .unwrap();
 
-            mock_data.extend_from_slice(
-                ShellClientMessage::Key("CorrectKey".to_owned())
-                    .serialise()
-                    .unwrap()
-                    .to_vec()
-                    .as_slice(),
+            let (stream, output) = MockStream::new(vec![start_pipe_shell()], true);
+            let mut stream = stream.into_shell_stream();
+
+            server
+                .start_shell(&mut stream, None)
+                .await
+                .err()
+                .expect("shell should not start after the hook fails");
+
+            // The hook's output is not sent unless enabled
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![ShellServerMessage::fatal(
+                    "pre-shell hook failed with exit status 3"
+                )]
             );
+        });
+    }
 
-            mock_data.extend_from_slice(
+    #[test]
+    #[cfg(unix)]
+    fn test_list_shells_then_start_selected_shell() {
+        Runtime::new().unwrap().block_on(async {
+            let start_shell = |shell_path: &str| {
                 ShellClientMessage::StartShell(StartShellPayload {
                     term: "TERM".to_owned(),
-                    size: WindowSize(50, 50),
+                    size: Some(WindowSize(80, 24)),
+                    pty: false,
+                    login: false,
+                    interactive: false,
+                    raw: false,
+                    shell_path: Some(shell_path.to_owned()),
+                    colors: None,
+                    truecolor: false,
+                    line_mode: false,
+                    locale: None,
+                    timezone: None,
+                    titles: false,
                 })
-                .serialise()
-                .unwrap()
-                .to_vec()
-                .as_slice(),
-            );
+            };
+            let server = ShellServer::builder()
+                .allowed_shells(vec![
+                    "/bin/sh".to_owned(),
+                    "/bin/tunshell-missing-shell".to_owned(),
+                ])
+                .build()
+                .unwrap();
 
-            mock_data.extend_from_slice(
-                ShellClientMessage::Stdin("echo \"hello\"\n".as_bytes().to_vec())
-                    .serialise()
-                    .unwrap()
-                    .to_vec()
-                    .as_slice(),
+            let (stream, output) = MockStream::new(
+                vec![ShellClientMessage::ListShells, start_shell("/bin/sh")],
+                true,
             );
+            let mut stream = stream.into_shell_stream();
 
-            mock_data.extend_from_slice(
-                ShellClientMessage::Resize(WindowSize(100, 80))
-                    .serialise()
-                    .unwrap()
-                    .to_vec()
-                    .as_slice(),
-            );
+            let (mut shell, _) = server.start_shell(&mut stream, None).await.unwrap();
+            shell.terminate().unwrap();
 
-            mock_data.extend_from_slice(
-                ShellClientMessage::Stdin("exit\n".as_bytes().to_vec())
-                    .serialise()
-                    .unwrap()
-                    .to_vec()
-                    .as_slice(),
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![
+                    ShellServerMessage::Shells(vec!["/bin/sh".to_owned()]),
+                    ShellServerMessage::SizeApplied(WindowSize(80, 24))
+                ]
             );
 
-            let mock_stream = Cursor::new(mock_data).compat();
-            let server = ShellServer::new().unwrap();
+            let (stream, _) =
+                MockStream::new(vec![start_shell("/bin/tunshell-missing-shell")], true);
+            let mut stream = stream.into_shell_stream();
 
             server
-                .run(Box::new(mock_stream), ShellKey::new("CorrectKey"))
+                .start_shell(&mut stream, None)
                 .await
-                .unwrap();
+                .err()
+                .expect("shell which is not available should be rejected");
         });
     }
 
     #[test]
-    fn test_start_connect_to_shell_then_error() {
+    fn test_hello_matching_version() {
         Runtime::new().unwrap().block_on(async {
-            let mut mock_data = Vec::<u8>::new();
+            let (stream, output) = MockStream::new(vec![hello()], false);
+            let mut stream = stream.into_shell_stream();
 
-            mock_data.extend_from_slice(
-                ShellClientMessage::Key("CorrectKey".to_owned())
-                    .serialise()
-                    .unwrap()
-                    .to_vec()
-                    .as_slice(),
+            let hello = ShellServer::new()
+                .unwrap()
+                .wait_for_hello(&mut stream)
+                .await
+                .unwrap();
+
+            assert_eq!(hello.protocol_version, PROTOCOL_VERSION);
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![ShellServerMessage::HelloAck(HelloAckPayload {
+                    protocol_version: PROTOCOL_VERSION,
+                    accepted: true,
+                    capabilities: ShellServer::new().unwrap().capabilities(),
+                    format: MessageFormat::Binary,
+                })]
             );
+        });
+    }
 
-            mock_data.extend_from_slice(
-                ShellClientMessage::StartShell(StartShellPayload {
-                    term: "TERM".to_owned(),
-                    size: WindowSize(50, 50),
-                })
-                .serialise()
+    #[test]
+    fn test_hello_client_too_old() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) =
+                MockStream::new(vec![hello_with_version(MIN_PROTOCOL_VERSION - 1)], false);
+            let mut stream = stream.into_shell_stream();
+
+            ShellServer::new()
                 .unwrap()
-                .to_vec()
-                .as_slice(),
+                .wait_for_hello(&mut stream)
+                .await
+                .expect_err("client version should be rejected");
+
+            let messages = parse_server_messages(&output).await;
+
+            assert_eq!(
+                messages[0],
+                ShellServerMessage::HelloAck(HelloAckPayload {
+                    protocol_version: PROTOCOL_VERSION,
+                    accepted: false,
+                    capabilities: ShellServer::new().unwrap().capabilities(),
+                    format: MessageFormat::Binary,
+                })
+            );
+            assert_eq!(
+                messages[1],
+                ShellServerMessage::fatal(format!(
+                    "client protocol version {} is not supported by server (supported versions {} to {})",
+                    MIN_PROTOCOL_VERSION - 1,
+                    MIN_PROTOCOL_VERSION,
+                    PROTOCOL_VERSION
+                ))
             );
+        });
+    }
 
-            mock_data.extend_from_slice(
-                ShellClientMessage::Error("some error occurred".to_owned())
-                    .serialise()
-                    .unwrap()
-                    .to_vec()
-                    .as_slice(),
+    #[test]
+    fn test_client_without_hello_told_required_version() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) =
+                MockStream::new(vec![ShellClientMessage::Key("key".to_owned())], false);
+            let mut stream = stream.into_shell_stream();
+
+            ShellServer::new()
+                .unwrap()
+                .wait_for_hello(&mut stream)
+                .await
+                .expect_err("client without hello should be rejected");
+
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![ShellServerMessage::fatal(format!(
+                    "client does not support the shell protocol, version {} to {} is required",
+                    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+                ))]
             );
+        });
+    }
 
-            let mock_stream = Cursor::new(mock_data).compat();
-            let server = ShellServer::new().unwrap();
+    #[test]
+    fn test_hello_client_too_new() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, output) =
+                MockStream::new(vec![hello_with_version(PROTOCOL_VERSION + 1)], false);
+            let mut stream = stream.into_shell_stream();
 
-            server
-                .run(Box::new(mock_stream), ShellKey::new("CorrectKey"))
+            let hello = ShellServer::new()
+                .unwrap()
+                .wait_for_hello(&mut stream)
                 .await
-                .expect_err("should return error");
+                .unwrap();
+
+            // Newer clients are downgraded to the common protocol version
+            assert_eq!(hello.protocol_version, PROTOCOL_VERSION);
+            assert_eq!(
+                parse_server_messages(&output).await,
+                vec![ShellServerMessage::HelloAck(HelloAckPayload {
+                    protocol_version: PROTOCOL_VERSION,
+                    accepted: true,
+                    capabilities: ShellServer::new().unwrap().capabilities(),
+                    format: MessageFormat::Binary,
+                })]
+            );
         });
     }
 }
@@ -0,0 +1,190 @@
+use bytes::Bytes;
+use std::mem;
+
+/// The largest operating system command which will be held back, larger sequences
+/// are sent as is. Clipboard (OSC 52) payloads are base64 so allow for a generous size.
+const MAX_PENDING_OSC_LEN: usize = 256 * 1024;
+
+const ESC: u8 = 0x1b;
+const OSC_INTRODUCER: u8 = b']';
+const BEL: u8 = 0x07;
+const ST_FINAL: u8 = b'\\';
+
+/// Buffers a trailing unterminated operating system command (OSC) escape sequence,
+/// such as an OSC 52 clipboard sequence, so that it is never split across chunks of output
+pub(super) struct OscChunker {
+    pending: Vec<u8>,
+}
+
+impl OscChunker {
+    pub(super) fn new() -> Self {
+        Self { pending: vec![] }
+    }
+
+    /// Returns any pending bytes followed by the supplied data, holding back
+    /// a trailing unterminated sequence until the next call.
+    /// The data is only copied when there are pending bytes to prepend.
+    pub(super) fn push(&mut self, data: Bytes) -> Bytes {
+        let data = if self.pending.is_empty() {
+            data
+        } else {
+            let mut joined = mem::take(&mut self.pending);
+            joined.extend_from_slice(&data);
+            Bytes::from(joined)
+        };
+
+        let boundary = unterminated_osc_start(&data);
+        self.pending.extend_from_slice(&data[boundary..]);
+
+        data.slice(..boundary)
+    }
+
+    pub(super) fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Returns the pending bytes regardless of whether the sequence has been terminated
+    pub(super) fn flush(&mut self) -> Bytes {
+        Bytes::from(mem::take(&mut self.pending))
+    }
+}
+
+/// Returns the index at which a trailing unterminated OSC sequence starts,
+/// or the length of the data if it does not end with one
+fn unterminated_osc_start(data: &[u8]) -> usize {
+    // A trailing escape may be the start of a sequence
+    if data.last() == Some(&ESC) {
+        return data.len() - 1;
+    }
+
+    let start = match data
+        .windows(2)
+        .rposition(|i| i[0] == ESC && i[1] == OSC_INTRODUCER)
+    {
+        Some(start) => start,
+        None => return data.len(),
+    };
+
+    let body = &data[start + 2..];
+    let terminated = body
+        .iter()
+        .enumerate()
+        .any(|(idx, byte)| *byte == BEL || (*byte == ST_FINAL && idx > 0 && body[idx - 1] == ESC));
+
+    if terminated || data.len() - start > MAX_PENDING_OSC_LEN {
+        data.len()
+    } else {
+        start
+    }
+}
+
+/// Returns the title set by the last complete OSC 0 (icon name and title) or
+/// OSC 2 (title) sequence in the data, sequences split across chunks are not seen
+pub(super) fn last_title(data: &[u8]) -> Option<String> {
+    let mut title = None;
+    let mut rest = data;
+
+    while let Some(start) = rest
+        .windows(2)
+        .position(|i| i[0] == ESC && i[1] == OSC_INTRODUCER)
+    {
+        let body = &rest[start + 2..];
+        let (len, terminator_len) = match osc_end(body) {
+            Some(end) => end,
+            None => break,
+        };
+
+        let command = &body[..len];
+        if command.starts_with(b"0;") || command.starts_with(b"2;") {
+            title = Some(String::from_utf8_lossy(&command[2..]).into_owned());
+        }
+
+        rest = &body[len + terminator_len..];
+    }
+
+    title
+}
+
+/// Returns the length of the body of an OSC sequence and of its terminator, BEL or ST
+fn osc_end(body: &[u8]) -> Option<(usize, usize)> {
+    body.iter().enumerate().find_map(|(idx, byte)| match *byte {
+        BEL => Some((idx, 1)),
+        ESC if body.get(idx + 1) == Some(&ST_FINAL) => Some((idx, 2)),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unterminated_osc_start() {
+        assert_eq!(unterminated_osc_start(b""), 0);
+        assert_eq!(unterminated_osc_start(b"abc"), 3);
+        assert_eq!(unterminated_osc_start(b"abc\x1b"), 3);
+        assert_eq!(unterminated_osc_start(b"abc\x1b]52;c;aGVs"), 3);
+        assert_eq!(unterminated_osc_start(b"abc\x1b]52;c;aGVsbG8=\x07"), 19);
+        assert_eq!(
+            unterminated_osc_start(b"abc\x1b]52;c;aGVsbG8=\x1b\\def"),
+            23
+        );
+        assert_eq!(unterminated_osc_start(b"\x1b[31mred"), 8);
+    }
+
+    #[test]
+    fn test_push_split_sequence() {
+        let mut chunker = OscChunker::new();
+
+        assert_eq!(
+            chunker.push(Bytes::from_static(b"before\x1b]52;c;aGVs")),
+            Bytes::from_static(b"before")
+        );
+        assert!(chunker.has_pending());
+        assert_eq!(
+            chunker.push(Bytes::from_static(b"bG8=\x07after")),
+            Bytes::from_static(b"\x1b]52;c;aGVsbG8=\x07after")
+        );
+        assert!(!chunker.has_pending());
+    }
+
+    #[test]
+    fn test_push_oversized_sequence() {
+        let mut chunker = OscChunker::new();
+        let mut data = b"\x1b]52;c;".to_vec();
+        data.resize(MAX_PENDING_OSC_LEN + 1, b'A');
+
+        assert_eq!(chunker.push(Bytes::from(data.clone())), Bytes::from(data));
+        assert!(!chunker.has_pending());
+    }
+
+    #[test]
+    fn test_flush() {
+        let mut chunker = OscChunker::new();
+
+        chunker.push(Bytes::from_static(b"\x1b]0;title"));
+
+        assert_eq!(chunker.flush(), Bytes::from_static(b"\x1b]0;title"));
+        assert!(!chunker.has_pending());
+    }
+
+    #[test]
+    fn test_last_title() {
+        assert_eq!(last_title(b""), None);
+        assert_eq!(last_title(b"plain output"), None);
+        assert_eq!(
+            last_title(b"before\x1b]0;user@host: ~\x07after"),
+            Some("user@host: ~".to_owned())
+        );
+        assert_eq!(
+            last_title(b"\x1b]2;first\x1b\\\x1b]2;second\x1b\\"),
+            Some("second".to_owned())
+        );
+        // Other commands and unterminated sequences are ignored
+        assert_eq!(last_title(b"\x1b]52;c;aGVsbG8=\x07"), None);
+        assert_eq!(
+            last_title(b"\x1b]2;title\x07\x1b]2;unterminated"),
+            Some("title".to_owned())
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use super::{OscChunker, ShellServerConfig, Utf8Chunker};
+use bytes::{Bytes, BytesMut};
+
+/// Chains the enabled output chunkers so that each chunk of output
+/// sent to the client ends on a character and escape sequence boundary
+pub(super) struct OutputChunker {
+    utf8: Option<Utf8Chunker>,
+    osc: Option<OscChunker>,
+}
+
+impl OutputChunker {
+    /// Returns `None` if no chunking is enabled in the config
+    pub(super) fn from_config(config: &ShellServerConfig) -> Option<Self> {
+        if !config.utf8_safe_output && !config.osc_safe_output {
+            return None;
+        }
+
+        Some(Self {
+            utf8: if config.utf8_safe_output {
+                Some(Utf8Chunker::new())
+            } else {
+                None
+            },
+            osc: if config.osc_safe_output {
+                Some(OscChunker::new())
+            } else {
+                None
+            },
+        })
+    }
+
+    pub(super) fn push(&mut self, data: Bytes) -> Bytes {
+        let data = match self.utf8.as_mut() {
+            Some(utf8) => utf8.push(data),
+            None => data,
+        };
+
+        match self.osc.as_mut() {
+            Some(osc) => osc.push(data),
+            None => data,
+        }
+    }
+
+    pub(super) fn has_pending(&self) -> bool {
+        self.utf8.as_ref().is_some_and(|i| i.has_pending())
+            || self.osc.as_ref().is_some_and(|i| i.has_pending())
+    }
+
+    /// Returns all pending bytes in the order they were received
+    pub(super) fn flush(&mut self) -> Bytes {
+        // The osc chunker holds output which was received before that held by the utf8 chunker
+        let osc = self.osc.as_mut().map(|i| i.flush()).unwrap_or_default();
+        let utf8 = self.utf8.as_mut().map(|i| i.flush()).unwrap_or_default();
+
+        if osc.is_empty() {
+            return utf8;
+        }
+
+        if utf8.is_empty() {
+            return osc;
+        }
+
+        let mut joined = BytesMut::with_capacity(osc.len() + utf8.len());
+        joined.extend_from_slice(&osc);
+        joined.extend_from_slice(&utf8);
+        joined.freeze()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_config() {
+        let mut config = ShellServerConfig::default();
+
+        assert!(OutputChunker::from_config(&config).is_some());
+
+        config.utf8_safe_output = false;
+        config.osc_safe_output = false;
+
+        assert!(OutputChunker::from_config(&config).is_none());
+    }
+
+    #[test]
+    fn test_flush_preserves_order() {
+        let mut chunker = OutputChunker::from_config(&ShellServerConfig::default()).unwrap();
+
+        // "é" is 0xC3 0xA9, the first byte is held back by the utf8 chunker
+        // after the unterminated sequence is held back by the osc chunker
+        assert_eq!(
+            chunker.push(Bytes::from_static(b"a\x1b]0;title\xc3")),
+            Bytes::from_static(b"a")
+        );
+        assert!(chunker.has_pending());
+        assert_eq!(chunker.flush(), Bytes::from_static(b"\x1b]0;title\xc3"));
+        assert!(!chunker.has_pending());
+    }
+}
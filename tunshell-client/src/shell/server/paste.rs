@@ -0,0 +1,48 @@
+/// Marks the start of a bracketed paste
+const PASTE_START: &[u8] = b"\x1b[200~";
+/// Marks the end of a bracketed paste
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Wraps the pasted input in bracketed paste markers. Any end markers within the input
+/// are removed, otherwise the paste could be ended early and the rest of it executed.
+pub(super) fn bracketed_paste(input: &[u8]) -> Vec<u8> {
+    let mut input = input.to_vec();
+
+    // Removing a marker can join the bytes either side into another marker
+    while let Some(pos) = input.windows(PASTE_END.len()).position(|i| i == PASTE_END) {
+        input.drain(pos..pos + PASTE_END.len());
+    }
+
+    let mut output = Vec::with_capacity(PASTE_START.len() + input.len() + PASTE_END.len());
+    output.extend_from_slice(PASTE_START);
+    output.extend_from_slice(&input);
+    output.extend_from_slice(PASTE_END);
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bracketed_paste() {
+        assert_eq!(
+            bracketed_paste(b"echo one\necho two\n"),
+            b"\x1b[200~echo one\necho two\n\x1b[201~".to_vec()
+        );
+        assert_eq!(bracketed_paste(b""), b"\x1b[200~\x1b[201~".to_vec());
+    }
+
+    #[test]
+    fn test_bracketed_paste_strips_end_markers() {
+        assert_eq!(
+            bracketed_paste(b"safe\x1b[201~rm -rf ~\n"),
+            b"\x1b[200~saferm -rf ~\n\x1b[201~".to_vec()
+        );
+        assert_eq!(
+            bracketed_paste(b"\x1b[20\x1b[201~1~ls\n"),
+            b"\x1b[200~ls\n\x1b[201~".to_vec()
+        );
+    }
+}
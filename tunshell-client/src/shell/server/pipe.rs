@@ -0,0 +1,334 @@
+use super::{process_cwd, send_signal, shell::Shell, DefaultShell};
+use crate::shell::proto::{ExitStatus, WindowSize};
+use anyhow::{Context, Error, Result};
+use async_trait::async_trait;
+use log::*;
+use std::process::Stdio;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+/// A shell which is run with plain pipes rather than a pty.
+/// There is no terminal echo or line editing, so the output is
+/// suitable for capturing by scripts.
+pub(super) struct PipeShell {
+    child: Child,
+    /// Dropped once the client has closed stdin so the shell receives EOF
+    stdin: Option<ChildStdin>,
+    output_rx: Receiver<Vec<u8>>,
+    recv_buff: Vec<u8>,
+    exit_status: Option<ExitStatus>,
+}
+
+impl PipeShell {
+    /// Runs the supplied program in place of the default shell. When `forward_stderr`
+    /// is false the program's stderr is logged rather than being interleaved with its stdout
+    pub(super) fn with_command(program: DefaultShell, forward_stderr: bool) -> Result<Self> {
+        info!("creating pipe shell");
+
+        let mut child = Command::new(&program.path)
+            .args(&program.args)
+            .env("TERM", "dumb")
+            .envs(program.env.iter().cloned())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| "Failed to open system shell")?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+
+        let (tx, output_rx) = channel(10);
+
+        if forward_stderr {
+            Self::start_reader_task(stderr, tx.clone());
+        } else {
+            Self::start_stderr_log_task(stderr);
+        }
+
+        Self::start_reader_task(stdout, tx);
+
+        info!("created pipe shell");
+        Ok(Self {
+            child,
+            stdin: Some(stdin),
+            output_rx,
+            recv_buff: vec![],
+            exit_status: None,
+        })
+    }
+
+    fn start_reader_task<R>(mut reader: R, mut tx: Sender<Vec<u8>>)
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        tokio::spawn(async move {
+            let mut buff = [0u8; 1024];
+
+            loop {
+                let read = match reader.read(&mut buff).await {
+                    Ok(0) => break,
+                    Ok(read) => read,
+                    Err(err) => {
+                        warn!("failed to read from shell: {}", err);
+                        break;
+                    }
+                };
+
+                if let Err(err) = tx.send(buff[..read].to_vec()).await {
+                    warn!("error while sending to channel: {}", err);
+                    break;
+                }
+            }
+        });
+    }
+
+    fn start_stderr_log_task<R>(mut reader: R)
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        tokio::spawn(async move {
+            let mut buff = [0u8; 1024];
+
+            while let Ok(read) = reader.read(&mut buff).await {
+                if read == 0 {
+                    break;
+                }
+
+                debug!("shell stderr: {}", String::from_utf8_lossy(&buff[..read]));
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl Shell for PipeShell {
+    async fn read(&mut self, buff: &mut [u8]) -> Result<usize> {
+        if self.recv_buff.is_empty() {
+            match self.output_rx.recv().await {
+                Some(data) => self.recv_buff.extend_from_slice(data.as_slice()),
+                None => {
+                    // Both stdout and stderr have closed so we wait for the shell to exit
+                    let status = (&mut self.child).await?;
+                    debug!("status: {:?}", status);
+                    self.exit_status.replace(exit_status_from_process(status));
+                    info!("shell exited");
+                    return Ok(0);
+                }
+            }
+        }
+
+        let read = std::cmp::min(buff.len(), self.recv_buff.len());
+        buff[..read].copy_from_slice(&self.recv_buff[..read]);
+        self.recv_buff.drain(..read);
+
+        Ok(read)
+    }
+
+    async fn write(&mut self, buff: &[u8]) -> Result<()> {
+        let stdin = self
+            .stdin
+            .as_mut()
+            .ok_or_else(|| Error::msg("stdin has been closed"))?;
+
+        stdin.write_all(buff).await.map_err(Error::from)
+    }
+
+    async fn close_stdin(&mut self) -> Result<()> {
+        self.stdin.take();
+        Ok(())
+    }
+
+    fn resize(&mut self, size: WindowSize) -> Result<()> {
+        debug!("ignoring resize of pipe shell to {:?}", size);
+        Ok(())
+    }
+
+    fn exit_status(&self) -> Result<ExitStatus> {
+        self.exit_status
+            .ok_or_else(|| Error::msg("shell has not exited"))
+    }
+
+    fn terminate(&mut self) -> Result<()> {
+        if self.exit_status.is_some() {
+            return Ok(());
+        }
+
+        self.child.kill()?;
+        self.exit_status.replace(ExitStatus::exited(1));
+
+        Ok(())
+    }
+
+    fn signal(&mut self, signal: u8) -> Result<()> {
+        if self.exit_status.is_some() {
+            return Err(Error::msg("shell has exited"));
+        }
+
+        send_signal(self.child.id(), signal)
+    }
+
+    fn cwd(&self) -> Result<String> {
+        if self.exit_status.is_some() {
+            return Err(Error::msg("shell has exited"));
+        }
+
+        process_cwd(self.child.id())
+    }
+}
+
+fn exit_status_from_process(status: std::process::ExitStatus) -> ExitStatus {
+    if let Some(code) = status.code() {
+        return ExitStatus::exited(code);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+
+        if let Some(signal) = status.signal() {
+            return ExitStatus::signalled(signal);
+        }
+    }
+
+    ExitStatus::unknown()
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::super::get_default_shell;
+    use super::*;
+    use rand::{thread_rng, Rng};
+    use tokio::runtime::Runtime;
+
+    async fn read_to_end(shell: &mut dyn Shell) -> Vec<u8> {
+        let mut output = vec![];
+        let mut buff = [0u8; 1024];
+
+        loop {
+            match shell.read(&mut buff).await.unwrap() {
+                0 => break,
+                read => output.extend_from_slice(&buff[..read]),
+            }
+        }
+
+        output
+    }
+
+    #[test]
+    fn test_pipe_shell_exit_code() {
+        Runtime::new().unwrap().block_on(async {
+            let mut shell =
+                PipeShell::with_command(get_default_shell(Some("/bin/sh")).unwrap(), true).unwrap();
+
+            shell.write("exit 3\n".as_bytes()).await.unwrap();
+
+            assert_eq!(read_to_end(&mut shell).await, Vec::<u8>::new());
+            assert_eq!(shell.exit_status().unwrap(), ExitStatus::exited(3));
+        });
+    }
+
+    #[test]
+    fn test_pipe_shell_close_stdin() {
+        Runtime::new().unwrap().block_on(async {
+            let mut shell =
+                PipeShell::with_command(get_default_shell(Some("/bin/sh")).unwrap(), true).unwrap();
+
+            shell.write("echo hello\n".as_bytes()).await.unwrap();
+            shell.close_stdin().await.unwrap();
+
+            assert_eq!(read_to_end(&mut shell).await, "hello\n".as_bytes());
+            assert_eq!(shell.exit_status().unwrap(), ExitStatus::exited(0));
+            shell.write("echo again\n".as_bytes()).await.unwrap_err();
+        });
+    }
+
+    #[test]
+    fn test_pipe_shell_raw_round_trip() {
+        Runtime::new().unwrap().block_on(async {
+            let mut shell =
+                PipeShell::with_command(get_default_shell(Some("/bin/sh")).unwrap(), false)
+                    .unwrap();
+
+            shell.write("exec cat\n".as_bytes()).await.unwrap();
+            tokio::time::delay_for(std::time::Duration::from_millis(100)).await;
+
+            let mut blob = vec![0u8; 64 * 1024];
+            thread_rng().fill(blob.as_mut_slice());
+
+            let mut received = vec![];
+            let mut buff = [0u8; 1024];
+
+            let mut written = 0;
+
+            // Drain the echoed output as we go so cat never blocks on a full pipe
+            for chunk in blob.chunks(4096) {
+                shell.write(chunk).await.unwrap();
+                written += chunk.len();
+
+                while received.len() < written {
+                    let read = shell.read(&mut buff).await.unwrap();
+                    assert_ne!(read, 0);
+                    received.extend_from_slice(&buff[..read]);
+                }
+            }
+
+            assert_eq!(received, blob);
+            shell.terminate().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_pipe_shell_signal() {
+        Runtime::new().unwrap().block_on(async {
+            let mut shell =
+                PipeShell::with_command(get_default_shell(Some("/bin/sh")).unwrap(), true).unwrap();
+
+            // Replace the shell process with a long running command
+            shell.write("exec sleep 10\n".as_bytes()).await.unwrap();
+            tokio::time::delay_for(std::time::Duration::from_millis(100)).await;
+
+            shell.signal(libc::SIGTERM as u8).unwrap();
+
+            assert_eq!(read_to_end(&mut shell).await, Vec::<u8>::new());
+            assert_eq!(
+                shell.exit_status().unwrap(),
+                ExitStatus::signalled(libc::SIGTERM)
+            );
+        });
+    }
+
+    #[test]
+    #[cfg(all(not(target_os = "ios"), not(target_os = "android")))]
+    fn test_pipe_shell_output_compared_to_pty() {
+        use super::super::{PtyShell, ShellInvocation};
+
+        let sh = get_default_shell(Some("/bin/sh")).unwrap();
+        let login_sh = DefaultShell {
+            args: sh.invocation_args(ShellInvocation::default()),
+            ..sh.clone()
+        };
+
+        Runtime::new().unwrap().block_on(async {
+            let mut pipe_shell = PipeShell::with_command(sh, true).unwrap();
+            pipe_shell
+                .write("echo hi\nexit\n".as_bytes())
+                .await
+                .unwrap();
+            let pipe_output = read_to_end(&mut pipe_shell).await;
+
+            let mut pty_shell = PtyShell::with_command("", login_sh, WindowSize(80, 80)).unwrap();
+            pty_shell.write("echo hi\nexit\n".as_bytes()).await.unwrap();
+            let pty_output =
+                String::from_utf8_lossy(&read_to_end(&mut pty_shell).await).to_string();
+
+            assert_eq!(pipe_output, b"hi\n".to_vec());
+            assert!(pty_output.contains("echo hi"));
+            assert!(pty_output.contains("\r\n"));
+        });
+    }
+}
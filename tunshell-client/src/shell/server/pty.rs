@@ -1,14 +1,11 @@
-use super::{get_default_shell, shell::Shell, DefaultShell};
-use crate::shell::proto::WindowSize;
+use super::{open_pty, process_cwd, send_signal, shell::Shell, DefaultShell};
+use crate::shell::proto::{ExitStatus, WindowSize};
 use anyhow::{Context, Error, Result};
 use async_trait::async_trait;
 use log::*;
-use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+use portable_pty::{CommandBuilder, PtyPair, PtySize};
 use std::io::{Read, Write};
-use std::{
-    panic,
-    sync::{Arc, Mutex},
-};
+use std::sync::{Arc, Mutex};
 use tokio::runtime::Runtime;
 use tokio::sync::mpsc::{
     channel,
@@ -17,45 +14,56 @@ use tokio::sync::mpsc::{
 };
 use tokio::task::JoinHandle;
 
+/// The default EOF character (Ctrl-D) of a terminal
+const PTY_EOF: u8 = 0x04;
+
 pub struct PtyShell {
     state: ShellState,
     master_pty: Box<dyn portable_pty::MasterPty + Send>,
     reader_rx: Receiver<Vec<u8>>,
     recv_buff: Vec<u8>,
     writer_tx: Sender<Option<Vec<u8>>>,
+    /// Whether input has been written since the end of the last line
+    line_pending: bool,
 }
 
 #[derive(Clone)]
 struct ShellState {
     shell: Arc<Mutex<Box<dyn portable_pty::Child + Send>>>,
-    exit_status: Arc<Mutex<Option<portable_pty::ExitStatus>>>,
+    exit_status: Arc<Mutex<Option<ExitStatus>>>,
 }
 
 impl PtyShell {
-    pub(super) fn new(term: &str, shell: Option<&str>, size: WindowSize) -> Result<Self> {
+    /// Runs the supplied program in place of the default shell
+    pub(super) fn with_command(
+        term: &str,
+        command: DefaultShell,
+        size: WindowSize,
+    ) -> Result<Self> {
+        Self::spawn(term, command, size)
+    }
+
+    fn spawn(term: &str, program: DefaultShell, size: WindowSize) -> Result<Self> {
         info!("creating pty shell");
-        let pty = panic::catch_unwind(|| {
-            let pty_system = native_pty_system();
+        let pty = open_pty(size.into())?;
 
-            pty_system
-                .openpty(size.into())
-                .with_context(|| "could not open pty")
-        });
+        Self::with_pty(pty, term, program)
+    }
 
-        if let Err(_) = pty {
-            return Err(Error::msg("failed to init pty system"));
+    /// Runs the program in an already opened pty, such as one taken from a `PtyPool`
+    pub(super) fn with_pty(pty: PtyPair, term: &str, program: DefaultShell) -> Result<Self> {
+        let mut cmd = CommandBuilder::new(&program.path);
+        cmd.args(&program.args);
+        cmd.env("TERM", term);
+        for (key, value) in program.env.iter() {
+            cmd.env(key, value);
         }
 
-        let pty = pty.unwrap();
-
-        if let Err(_) = pty {
-            return Err(Error::msg("failed to init pty"));
+        // portable-pty starts the program in the home directory unless told otherwise
+        if let Ok(cwd) = std::env::current_dir() {
+            cmd.cwd(cwd);
         }
 
-        let pty = pty.unwrap();
-        let mut cmd: CommandBuilder = get_default_shell(shell)?.into();
-        cmd.env("TERM", term);
-
         let shell = pty
             .slave
             .spawn_command(cmd)
@@ -85,6 +93,7 @@ impl PtyShell {
             reader_rx,
             recv_buff: vec![],
             writer_tx,
+            line_pending: false,
         })
     }
 
@@ -109,7 +118,12 @@ impl PtyShell {
                     }
                     Ok(read) => read,
                     Err(err) => {
+                        // On some platforms reading from the pty fails once the child
+                        // has exited or was killed, so we reap the child to record its status
                         warn!("failed to read from pty: {}", err);
+                        state
+                            .exit_shell(true)
+                            .unwrap_or_else(|err| error!("Failed to exit shell: {}", err));
                         break;
                     }
                 };
@@ -160,7 +174,7 @@ impl PtyShell {
 #[async_trait]
 impl Shell for PtyShell {
     async fn read(&mut self, buff: &mut [u8]) -> Result<usize> {
-        while self.recv_buff.len() == 0 {
+        while self.recv_buff.is_empty() {
             let stdout = match self.reader_rx.recv().await {
                 Some(data) => data,
                 None => {
@@ -187,39 +201,89 @@ impl Shell for PtyShell {
     }
 
     async fn write(&mut self, buff: &[u8]) -> Result<()> {
+        if let Some(last) = buff.last() {
+            self.line_pending = !matches!(*last, b'\n' | b'\r' | PTY_EOF);
+        }
+
         self.writer_tx
             .send(Some(buff.to_vec()))
             .await
             .map_err(Error::from)
     }
 
+    /// A pty has no separate stdin stream so the EOF character is written,
+    /// which the terminal reports as end of input to the foreground process.
+    /// The session continues so the process can exit and report its status.
+    async fn close_stdin(&mut self) -> Result<()> {
+        // An EOF after a partial line only passes the line to the reader,
+        // a second is needed for the reader to see the end of input
+        if self.line_pending {
+            self.write(&[PTY_EOF, PTY_EOF]).await
+        } else {
+            self.write(&[PTY_EOF]).await
+        }
+    }
+
     fn resize(&mut self, size: WindowSize) -> Result<()> {
         self.master_pty
             .resize(size.into())
             .with_context(|| "Failed to resize pty")
     }
 
-    fn exit_code(&self) -> Result<u8> {
-        let status = self.state.exit_status.lock().unwrap();
+    fn exit_status(&self) -> Result<ExitStatus> {
+        self.state
+            .exit_status
+            .lock()
+            .unwrap()
+            .ok_or_else(|| Error::msg("shell has not exited"))
+    }
 
-        let status = match status.as_ref() {
-            Some(status) => status,
-            None => return Err(Error::msg("shell has not exited")),
-        };
+    fn terminate(&mut self) -> Result<()> {
+        self.exit_sync()
+    }
 
-        if status.success() {
-            Ok(0)
-        } else {
-            Ok(1)
+    fn signal(&mut self, signal: u8) -> Result<()> {
+        if !self.state.is_running() {
+            return Err(Error::msg("shell has exited"));
         }
+
+        let pid = self
+            .state
+            .shell
+            .lock()
+            .unwrap()
+            .process_id()
+            .ok_or_else(|| Error::msg("could not get pid of shell"))?;
+
+        send_signal(pid, signal)
+    }
+
+    fn is_pty(&self) -> bool {
+        true
+    }
+
+    fn cwd(&self) -> Result<String> {
+        if !self.state.is_running() {
+            return Err(Error::msg("shell has exited"));
+        }
+
+        let pid = self
+            .state
+            .shell
+            .lock()
+            .unwrap()
+            .process_id()
+            .ok_or_else(|| Error::msg("could not get pid of shell"))?;
+
+        process_cwd(pid)
     }
 }
 
-impl Into<PtySize> for WindowSize {
-    fn into(self) -> PtySize {
+impl From<WindowSize> for PtySize {
+    fn from(val: WindowSize) -> Self {
         PtySize {
-            cols: self.0,
-            rows: self.1,
+            cols: val.0,
+            rows: val.1,
             pixel_width: 0,
             pixel_height: 0,
         }
@@ -280,22 +344,19 @@ impl ShellState {
         let mut shell = self.shell.lock().unwrap();
 
         let status = if wait_for_exit {
-            match shell.wait() {
-                Ok(status) => status,
-                Err(err) => return Err(Error::new(err)),
-            }
+            wait_for_exit_status(&mut shell, true)?
+                .ok_or_else(|| Error::msg("shell did not exit"))?
         } else {
-            match shell.try_wait() {
-                Ok(None) => {
+            match wait_for_exit_status(&mut shell, false)? {
+                Some(status) => status,
+                None => {
                     shell.kill().expect("Failed to shutdown shell");
-                    portable_pty::ExitStatus::with_exit_code(1)
+                    ExitStatus::exited(1)
                 }
-                Ok(Some(status)) => status,
-                Err(err) => return Err(Error::new(err)),
             }
         };
 
-        debug!("status: {:?}", status);
+        debug!("exit status: {:?}", status);
         self.exit_status.lock().unwrap().replace(status);
         info!("shell exited");
 
@@ -303,31 +364,85 @@ impl ShellState {
     }
 }
 
-impl Drop for PtyShell {
-    fn drop(&mut self) {
-        self.exit_sync().expect("Failed to exit shell");
+/// Waits for the child process to exit, returning its exit code or
+/// the signal which terminated it.
+#[cfg(unix)]
+fn wait_for_exit_status(
+    shell: &mut Box<dyn portable_pty::Child + Send>,
+    block: bool,
+) -> Result<Option<ExitStatus>> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let pid = shell
+        .process_id()
+        .ok_or_else(|| Error::msg("could not get pid of shell"))?;
+
+    let mut raw_status = 0;
+    let flags = if block { 0 } else { libc::WNOHANG };
+    let result = unsafe { libc::waitpid(pid as libc::pid_t, &mut raw_status, flags) };
+
+    if result < 0 {
+        return Err(Error::new(std::io::Error::last_os_error()));
+    }
+
+    if result == 0 {
+        return Ok(None);
     }
+
+    let status = std::process::ExitStatus::from_raw(raw_status);
+
+    let status = match (status.code(), status.signal()) {
+        (Some(code), _) => ExitStatus::exited(code),
+        (None, Some(signal)) => ExitStatus::signalled(signal),
+        (None, None) => ExitStatus::unknown(),
+    };
+
+    Ok(Some(status))
+}
+
+#[cfg(not(unix))]
+fn wait_for_exit_status(
+    shell: &mut Box<dyn portable_pty::Child + Send>,
+    block: bool,
+) -> Result<Option<ExitStatus>> {
+    let status = if block {
+        Some(shell.wait().map_err(Error::new)?)
+    } else {
+        shell.try_wait().map_err(Error::new)?
+    };
+
+    Ok(status.map(|status| ExitStatus::exited(if status.success() { 0 } else { 1 })))
 }
 
-impl Into<CommandBuilder> for DefaultShell {
-    fn into(self) -> CommandBuilder {
-        let mut cmd = CommandBuilder::new(self.path);
-        cmd.args(self.args);
-        cmd
+impl Drop for PtyShell {
+    fn drop(&mut self) {
+        self.exit_sync().expect("Failed to exit shell");
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::super::{get_default_shell, ShellInvocation};
     use super::*;
     use std::time::Duration;
 
+    /// The shell at the path, invoked as an interactive login shell as it is by the server
+    fn login_shell(path: &str) -> DefaultShell {
+        let shell = get_default_shell(Some(path)).unwrap();
+
+        DefaultShell {
+            args: shell.invocation_args(ShellInvocation::default()),
+            ..shell
+        }
+    }
+
     #[test]
     #[cfg(unix)]
     fn test_shell_pty_exit_on_error() {
         Runtime::new().unwrap().block_on(async {
-            let mut pty: PtyShell = PtyShell::new("", Some("/bin/bash"), WindowSize(80, 80))
-                .expect("Failed to initialise ShellPty");
+            let mut pty: PtyShell =
+                PtyShell::with_command("", login_shell("/bin/bash"), WindowSize(80, 80))
+                    .expect("Failed to initialise ShellPty");
 
             tokio::time::delay_for(Duration::from_millis(10)).await;
 
@@ -337,9 +452,89 @@ mod tests {
 
             pty.exit_sync().unwrap();
 
-            assert_eq!(pty.state.is_running(), false);
-            assert_eq!(pty.exit_code().unwrap(), 1);
+            assert!(!pty.state.is_running());
+            assert_eq!(pty.exit_status().unwrap(), ExitStatus::exited(1));
             assert_eq!(pty.exit_sync().unwrap(), ());
         });
     }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_shell_pty_close_stdin() {
+        Runtime::new().unwrap().block_on(async {
+            let path =
+                std::env::temp_dir().join(format!("tunshell-pty-eof-{}", std::process::id()));
+            let mut pty = PtyShell::with_command(
+                "",
+                DefaultShell {
+                    path: "/bin/sh".to_owned(),
+                    args: vec![
+                        "-c".to_owned(),
+                        format!("cat > '{}'", path.to_string_lossy()),
+                    ],
+                    env: vec![],
+                },
+                WindowSize(80, 80),
+            )
+            .expect("Failed to initialise ShellPty");
+
+            pty.write(b"line one\n").await.unwrap();
+            pty.write(b"no newline").await.unwrap();
+            pty.close_stdin().await.unwrap();
+
+            let mut buff = [0u8; 1024];
+
+            loop {
+                let read = tokio::time::timeout(Duration::from_secs(5), pty.read(&mut buff))
+                    .await
+                    .expect("timed out waiting for command to exit")
+                    .unwrap();
+
+                if read == 0 {
+                    break;
+                }
+            }
+
+            let written = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(pty.exit_status().unwrap(), ExitStatus::exited(0));
+            assert_eq!(written, "line one\nno newline");
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_shell_pty_killed_externally() {
+        Runtime::new().unwrap().block_on(async {
+            let mut pty: PtyShell =
+                PtyShell::with_command("", login_shell("/bin/sh"), WindowSize(80, 80))
+                    .expect("Failed to initialise ShellPty");
+
+            let pid = pty.state.shell.lock().unwrap().process_id().unwrap();
+
+            unsafe {
+                libc::kill(pid as libc::pid_t, libc::SIGKILL);
+            }
+
+            let mut buff = [0u8; 1024];
+
+            loop {
+                let read = tokio::time::timeout(Duration::from_secs(5), pty.read(&mut buff))
+                    .await
+                    .expect("timed out waiting for shell to exit")
+                    .expect("read should not fail when the shell is killed");
+
+                if read == 0 {
+                    break;
+                }
+            }
+
+            assert!(!pty.state.is_running());
+            assert_eq!(
+                pty.exit_status().unwrap(),
+                ExitStatus::signalled(libc::SIGKILL)
+            );
+        });
+    }
 }
@@ -0,0 +1,99 @@
+use super::{DefaultShell, PtyPool, PtyShell, Shell};
+use crate::shell::proto::WindowSize;
+use anyhow::{Error, Result};
+use log::*;
+use std::io;
+
+/// Allocates a pty and runs the program in it
+pub(super) trait PtyFactory {
+    fn spawn(
+        &self,
+        term: &str,
+        program: DefaultShell,
+        size: WindowSize,
+    ) -> Result<Box<dyn Shell + Send>>;
+
+    /// Called once a session has ended
+    fn release(&self) {}
+}
+
+/// Spawns shells in ptys taken from the pool, if configured, otherwise in newly opened ptys
+pub(super) struct NativePtyFactory {
+    pool: Option<PtyPool>,
+}
+
+impl NativePtyFactory {
+    pub(super) fn new(pool_size: Option<usize>) -> Self {
+        Self {
+            pool: pool_size.map(PtyPool::new),
+        }
+    }
+}
+
+impl PtyFactory for NativePtyFactory {
+    fn spawn(
+        &self,
+        term: &str,
+        program: DefaultShell,
+        size: WindowSize,
+    ) -> Result<Box<dyn Shell + Send>> {
+        let shell = match self.pool.as_ref() {
+            Some(pool) => PtyShell::with_pty(pool.take(size)?, term, program)?,
+            None => PtyShell::with_command(term, program, size)?,
+        };
+
+        Ok(Box::new(shell))
+    }
+
+    fn release(&self) {
+        if let Some(pool) = self.pool.as_ref() {
+            pool.fill();
+            debug!("pty pool has {} ptys available", pool.len());
+        }
+    }
+}
+
+/// Whether the pty could be allocated if tried again shortly, such as when
+/// the system has temporarily run out of ptys or file descriptors
+pub(super) fn is_transient_pty_error(err: &Error) -> bool {
+    err.chain()
+        .filter_map(|i| i.downcast_ref::<io::Error>())
+        .any(|err| {
+            if let io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted = err.kind() {
+                return true;
+            }
+
+            #[cfg(unix)]
+            {
+                if let Some(code) = err.raw_os_error() {
+                    return [libc::EAGAIN, libc::ENOSPC, libc::EMFILE, libc::ENFILE]
+                        .contains(&code);
+                }
+            }
+
+            false
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_is_transient_pty_error() {
+        let transient =
+            Error::new(io::Error::from_raw_os_error(libc::ENOSPC)).context("failed to init pty");
+        assert!(is_transient_pty_error(&transient));
+
+        let transient = Error::new(io::Error::from_raw_os_error(libc::EAGAIN));
+        assert!(is_transient_pty_error(&transient));
+
+        let permanent = Error::new(io::Error::from_raw_os_error(libc::ENOENT))
+            .context("Failed to open system shell");
+        assert!(!is_transient_pty_error(&permanent));
+
+        let permanent = Error::msg("failed to init pty system");
+        assert!(!is_transient_pty_error(&permanent));
+    }
+}
@@ -0,0 +1,150 @@
+use crate::shell::proto::WindowSize;
+use anyhow::{Context, Error, Result};
+use log::*;
+use portable_pty::{native_pty_system, PtyPair, PtySize};
+use std::{
+    panic,
+    sync::{Arc, Mutex},
+};
+
+/// Ptys opened ahead of time so starting a pty shell does not wait for one to be allocated.
+/// A pty is never handed out twice: portable-pty does not expose the file descriptors needed
+/// to reset the terminal attributes and flush its queues, and the shell's exit is detected
+/// by the pty hanging up once the slave is closed. Instead the pool is topped back up with
+/// freshly opened ptys when a session ends, so no state can leak between sessions.
+/// Clones share the same pool.
+#[derive(Clone)]
+pub(super) struct PtyPool {
+    ptys: Arc<Mutex<Vec<PtyPair>>>,
+    capacity: usize,
+}
+
+impl PtyPool {
+    pub(super) fn new(capacity: usize) -> Self {
+        let pool = Self {
+            ptys: Arc::new(Mutex::new(Vec::with_capacity(capacity))),
+            capacity,
+        };
+
+        pool.fill();
+        pool
+    }
+
+    /// Takes a pty from the pool, resized to the supplied size.
+    /// When the pool is empty a pty is opened on demand.
+    pub(super) fn take(&self, size: WindowSize) -> Result<PtyPair> {
+        let pty = self.ptys.lock().unwrap().pop();
+
+        match pty {
+            Some(pty) => {
+                debug!("using pty from pool");
+                pty.master
+                    .resize(size.into())
+                    .with_context(|| "Failed to resize pty")?;
+                Ok(pty)
+            }
+            None => {
+                debug!("pty pool is empty, opening pty on demand");
+                open_pty(size.into())
+            }
+        }
+    }
+
+    /// Opens ptys until the pool is at capacity
+    pub(super) fn fill(&self) {
+        let mut ptys = self.ptys.lock().unwrap();
+
+        while ptys.len() < self.capacity {
+            match open_pty(PtySize::default()) {
+                Ok(pty) => ptys.push(pty),
+                Err(err) => {
+                    warn!("failed to open pty for pool: {:?}", err);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// The number of ptys available in the pool
+    pub(super) fn len(&self) -> usize {
+        self.ptys.lock().unwrap().len()
+    }
+}
+
+pub(super) fn open_pty(size: PtySize) -> Result<PtyPair> {
+    let pty = panic::catch_unwind(|| {
+        let pty_system = native_pty_system();
+
+        pty_system
+            .openpty(size)
+            .with_context(|| "could not open pty")
+    });
+
+    match pty {
+        Ok(Ok(pty)) => Ok(pty),
+        Ok(Err(err)) => Err(err.context("failed to init pty")),
+        Err(_) => Err(Error::msg("failed to init pty system")),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::super::{DefaultShell, PtyShell, Shell};
+    use super::*;
+    use tokio::runtime::Runtime;
+
+    async fn run_in_pty(pool: &PtyPool, command: &str) -> String {
+        let pty = pool.take(WindowSize(80, 24)).unwrap();
+        let program = DefaultShell {
+            path: "/bin/sh".to_owned(),
+            args: vec!["-c".to_owned(), command.to_owned()],
+            env: vec![],
+        };
+        let mut shell = PtyShell::with_pty(pty, "", program).unwrap();
+
+        let mut output = vec![];
+        let mut buff = [0u8; 1024];
+
+        loop {
+            match shell.read(&mut buff).await.unwrap() {
+                0 => break,
+                read => output.extend_from_slice(&buff[..read]),
+            }
+        }
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_take_falls_back_when_empty() {
+        let pool = PtyPool::new(1);
+        assert_eq!(pool.len(), 1);
+
+        let pty = pool.take(WindowSize(100, 30)).unwrap();
+        assert_eq!(pool.len(), 0);
+        assert_eq!(pty.master.get_size().unwrap().cols, 100);
+        assert_eq!(pty.master.get_size().unwrap().rows, 30);
+
+        let pty = pool.take(WindowSize(100, 30)).unwrap();
+        assert_eq!(pool.len(), 0);
+        assert_eq!(pty.master.get_size().unwrap().cols, 100);
+
+        pool.fill();
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn test_sessions_do_not_share_terminal_state() {
+        Runtime::new().unwrap().block_on(async {
+            let pool = PtyPool::new(1);
+
+            let output = run_in_pty(&pool, "stty -echo; stty -a").await;
+            assert!(output.contains("-echo "), "{}", output);
+            pool.fill();
+            assert_eq!(pool.len(), 1);
+
+            let output = run_in_pty(&pool, "stty -a").await;
+            assert!(!output.contains("-echo "), "{}", output);
+        });
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+/// A token bucket limiting the rate at which bytes are sent.
+/// Up to one second's worth of bytes can be sent in a burst, after which
+/// the sender is delayed until enough tokens have accumulated. A chunk larger
+/// than the bucket is allowed through, the delay before the next chunk makes up for it.
+pub(super) struct RateLimiter {
+    bytes_per_sec: u64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// Returns `None` when the rate is unlimited
+    pub(super) fn new(bytes_per_sec: Option<u64>) -> Option<Self> {
+        match bytes_per_sec {
+            Some(0) | None => None,
+            Some(bytes_per_sec) => Some(Self {
+                bytes_per_sec,
+                tokens: bytes_per_sec as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until the bytes may be sent
+    pub(super) async fn acquire(&mut self, bytes: usize) {
+        let delay = self.take(bytes, Instant::now());
+
+        if delay > Duration::from_secs(0) {
+            time::delay_for(delay).await;
+        }
+    }
+
+    /// Consumes the tokens for the bytes, returning how long to wait before sending them
+    fn take(&mut self, bytes: usize, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let capacity = self.bytes_per_sec as f64;
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.bytes_per_sec as f64).min(capacity);
+        self.last_refill = now;
+
+        self.tokens -= bytes as f64;
+
+        if self.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.bytes_per_sec as f64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited() {
+        assert!(RateLimiter::new(None).is_none());
+        assert!(RateLimiter::new(Some(0)).is_none());
+    }
+
+    #[test]
+    fn test_take() {
+        let mut limiter = RateLimiter::new(Some(1000)).unwrap();
+        let start = limiter.last_refill;
+
+        // The initial burst is allowed through immediately
+        assert_eq!(limiter.take(1000, start), Duration::from_secs(0));
+        assert_eq!(limiter.take(500, start), Duration::from_millis(500));
+
+        // Once the delay has elapsed the bucket is empty again
+        assert_eq!(
+            limiter.take(100, start + Duration::from_millis(500)),
+            Duration::from_millis(100)
+        );
+
+        // The bucket does not refill beyond its capacity
+        assert_eq!(
+            limiter.take(1000, start + Duration::from_secs(10)),
+            Duration::from_secs(0)
+        );
+    }
+}
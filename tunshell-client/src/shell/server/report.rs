@@ -0,0 +1,77 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{fs::OpenOptions, io::Write};
+
+/// A summary of a completed session, written as a line of JSON to a side channel
+/// so scripts wrapping the client do not need to parse the terminal output
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct SessionReport {
+    pub(crate) session_id: String,
+    /// The exit code of the shell, not set if the shell was detached or terminated
+    pub(crate) exit_code: Option<u8>,
+    pub(crate) detached: bool,
+    /// The number of bytes received from the client and written to the shell
+    pub(crate) stdin_bytes: u64,
+    /// The number of bytes of shell output sent to the client
+    pub(crate) stdout_bytes: u64,
+    pub(crate) duration_ms: u64,
+}
+
+impl SessionReport {
+    pub(super) fn new(session_id: &str) -> Self {
+        Self {
+            session_id: session_id.to_owned(),
+            exit_code: None,
+            detached: false,
+            stdin_bytes: 0,
+            stdout_bytes: 0,
+            duration_ms: 0,
+        }
+    }
+
+    /// Appends the report as a single line of JSON to the file at the supplied path
+    pub(crate) fn write_to(&self, path: &str) -> Result<()> {
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(line.as_slice()))
+            .with_context(|| format!("failed to write session report to {}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    #[test]
+    fn test_write_to() {
+        let path = env::temp_dir().join(format!("tunshell-report-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        let report = SessionReport {
+            session_id: "abc".to_owned(),
+            exit_code: Some(3),
+            detached: false,
+            stdin_bytes: 5,
+            stdout_bytes: 10,
+            duration_ms: 1500,
+        };
+
+        report.write_to(path).unwrap();
+        report.write_to(path).unwrap();
+
+        let line = r#"{"session_id":"abc","exit_code":3,"detached":false,"stdin_bytes":5,"stdout_bytes":10,"duration_ms":1500}"#;
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            format!("{}\n{}\n", line, line)
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+use super::DefaultShell;
+use anyhow::{Error, Result};
+#[cfg(unix)]
+use std::{ffi::CString, io, mem, ptr};
+
+/// The locations setpriv is looked up in, it is part of util-linux
+#[cfg(target_os = "linux")]
+const SETPRIV_PATHS: &[&str] = &["/usr/bin/setpriv", "/bin/setpriv"];
+
+/// The user the shell is run as, allowing the client to run as root
+/// while the shell itself runs unprivileged
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunAs {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl RunAs {
+    /// Resolves the uid and primary gid of the user with the supplied name
+    #[cfg(unix)]
+    pub(crate) fn from_username(name: &str) -> Result<Self> {
+        let c_name = CString::new(name).map_err(|_| Error::msg("invalid user name"))?;
+        let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+        let mut buff = vec![0 as libc::c_char; 16 * 1024];
+        let mut result: *mut libc::passwd = ptr::null_mut();
+
+        let ret = unsafe {
+            libc::getpwnam_r(
+                c_name.as_ptr(),
+                &mut passwd,
+                buff.as_mut_ptr(),
+                buff.len(),
+                &mut result,
+            )
+        };
+
+        if ret != 0 {
+            return Err(Error::new(io::Error::from_raw_os_error(ret))
+                .context(format!("failed to look up user {}", name)));
+        }
+
+        if result.is_null() {
+            return Err(Error::msg(format!("user {} does not exist", name)));
+        }
+
+        Ok(Self {
+            uid: passwd.pw_uid as u32,
+            gid: passwd.pw_gid as u32,
+        })
+    }
+
+    /// Wraps the program so it is exec'd by setpriv after switching to the user's
+    /// gid and uid and clearing the supplementary groups.
+    /// portable-pty has no pre-exec hook so setuid cannot be called in the child directly.
+    /// If the privileges cannot be dropped setpriv exits without running the program.
+    #[cfg(target_os = "linux")]
+    pub(super) fn apply(&self, program: DefaultShell) -> Result<DefaultShell> {
+        let setpriv = SETPRIV_PATHS
+            .iter()
+            .find(|i| std::path::Path::new(i).exists())
+            .ok_or_else(|| Error::msg("setpriv is required to run the shell as another user"))?;
+
+        let mut args = vec![
+            format!("--regid={}", self.gid),
+            format!("--reuid={}", self.uid),
+            "--clear-groups".to_owned(),
+            "--".to_owned(),
+            program.path,
+        ];
+        args.extend(program.args);
+
+        Ok(DefaultShell {
+            path: (*setpriv).to_owned(),
+            args,
+            env: program.env,
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(super) fn apply(&self, _program: DefaultShell) -> Result<DefaultShell> {
+        Err(Error::msg(
+            "running the shell as another user is only supported on linux",
+        ))
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::super::{PipeShell, Shell};
+    use super::*;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn test_from_username() {
+        assert_eq!(
+            RunAs::from_username("root").unwrap(),
+            RunAs { uid: 0, gid: 0 }
+        );
+
+        RunAs::from_username("tunshell-missing-user").expect_err("missing user should not resolve");
+        RunAs::from_username("nul\0byte")
+            .expect_err("user name containing a nul byte should not resolve");
+    }
+
+    #[test]
+    fn test_apply() {
+        let run_as = RunAs {
+            uid: 1000,
+            gid: 100,
+        };
+        let program = run_as
+            .apply(DefaultShell {
+                path: "/bin/bash".to_owned(),
+                args: vec!["-l".to_owned()],
+                env: vec![],
+            })
+            .unwrap();
+
+        assert!(program.path.ends_with("/setpriv"));
+        assert_eq!(
+            program.args,
+            vec![
+                "--regid=100",
+                "--reuid=1000",
+                "--clear-groups",
+                "--",
+                "/bin/bash",
+                "-l"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shell_runs_as_user() {
+        // Dropping privileges requires root
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        let run_as = match RunAs::from_username("nobody") {
+            Ok(run_as) => run_as,
+            Err(_) => return,
+        };
+
+        Runtime::new().unwrap().block_on(async {
+            let program = run_as
+                .apply(DefaultShell {
+                    path: "/bin/sh".to_owned(),
+                    args: vec!["-c".to_owned(), "id -u; id -g".to_owned()],
+                    env: vec![],
+                })
+                .unwrap();
+            let mut shell = PipeShell::with_command(program, true).unwrap();
+
+            let mut output = vec![];
+            let mut buff = [0u8; 1024];
+
+            loop {
+                match shell.read(&mut buff).await.unwrap() {
+                    0 => break,
+                    read => output.extend_from_slice(&buff[..read]),
+                }
+            }
+
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                format!("{}\n{}\n", run_as.uid, run_as.gid)
+            );
+            assert_eq!(shell.exit_status().unwrap().code, Some(0));
+        });
+    }
+}
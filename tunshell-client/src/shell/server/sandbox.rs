@@ -0,0 +1,249 @@
+use super::DefaultShell;
+use anyhow::{Error, Result};
+use std::str::FromStr;
+
+/// The locations unshare is looked up in, it is part of util-linux
+#[cfg(target_os = "linux")]
+const UNSHARE_PATHS: &[&str] = &["/usr/bin/unshare", "/bin/unshare"];
+
+/// The namespaces the shell is isolated in, so it cannot see or signal processes
+/// on the host. Parsed from a comma-separated list of namespaces, eg `pid,mount`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SandboxConfig {
+    /// Runs the shell as pid 1 of a new pid namespace
+    pub pid_namespace: bool,
+    /// Runs the shell in a new mount namespace, /proc is remounted within it
+    /// when the shell is also in a new pid namespace
+    pub mount_namespace: bool,
+    /// Runs the shell in a new user namespace, mapped to root within it, which
+    /// allows the other namespaces to be created without privileges
+    pub user_namespace: bool,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            pid_namespace: true,
+            mount_namespace: true,
+            user_namespace: false,
+        }
+    }
+}
+
+impl FromStr for SandboxConfig {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let mut config = Self {
+            pid_namespace: false,
+            mount_namespace: false,
+            user_namespace: false,
+        };
+
+        for namespace in value.split(',').map(|i| i.trim()) {
+            match namespace {
+                "pid" => config.pid_namespace = true,
+                "mount" => config.mount_namespace = true,
+                "user" => config.user_namespace = true,
+                _ => {
+                    return Err(Error::msg(format!(
+                        "unknown sandbox namespace: {:?}",
+                        namespace
+                    )))
+                }
+            }
+        }
+
+        config.validate()?;
+
+        Ok(config)
+    }
+}
+
+impl SandboxConfig {
+    pub(super) fn validate(&self) -> Result<()> {
+        if !self.pid_namespace && !self.mount_namespace {
+            return Err(Error::msg("sandbox must include a pid or mount namespace"));
+        }
+
+        // Without remounting /proc the host's processes would still be visible
+        if self.pid_namespace && !self.mount_namespace {
+            return Err(Error::msg(
+                "sandbox pid namespace requires a mount namespace",
+            ));
+        }
+
+        if !cfg!(target_os = "linux") {
+            return Err(Error::msg(
+                "sandboxing the shell is only supported on linux",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Wraps the program so it is exec'd by unshare in the new namespaces.
+    /// In a pid namespace unshare forks and waits for the shell, so signals
+    /// and working directory queries are received by unshare rather than the shell.
+    #[cfg(target_os = "linux")]
+    pub(super) fn apply(&self, program: DefaultShell) -> Result<DefaultShell> {
+        let unshare = UNSHARE_PATHS
+            .iter()
+            .find(|i| std::path::Path::new(i).exists())
+            .ok_or_else(|| Error::msg("unshare is required to sandbox the shell"))?;
+
+        let mut args = vec![];
+
+        if self.user_namespace {
+            args.push("--user".to_owned());
+            args.push("--map-root-user".to_owned());
+        }
+
+        if self.mount_namespace {
+            args.push("--mount".to_owned());
+        }
+
+        if self.pid_namespace {
+            args.push("--pid".to_owned());
+            args.push("--fork".to_owned());
+            args.push("--kill-child".to_owned());
+            args.push("--mount-proc".to_owned());
+        }
+
+        args.push("--".to_owned());
+        args.push(program.path);
+        args.extend(program.args);
+
+        Ok(DefaultShell {
+            path: (*unshare).to_owned(),
+            args,
+            env: program.env,
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(super) fn apply(&self, _program: DefaultShell) -> Result<DefaultShell> {
+        Err(Error::msg(
+            "sandboxing the shell is only supported on linux",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_parse() {
+        assert_eq!(
+            "pid,mount".parse::<SandboxConfig>().unwrap(),
+            SandboxConfig::default()
+        );
+        assert_eq!(
+            "mount, user".parse::<SandboxConfig>().unwrap(),
+            SandboxConfig {
+                pid_namespace: false,
+                mount_namespace: true,
+                user_namespace: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        "pid,net"
+            .parse::<SandboxConfig>()
+            .expect_err("unknown namespace should be rejected");
+        "pid"
+            .parse::<SandboxConfig>()
+            .expect_err("pid namespace without mount namespace should be rejected");
+        "user"
+            .parse::<SandboxConfig>()
+            .expect_err("user namespace alone should be rejected");
+        "".parse::<SandboxConfig>()
+            .expect_err("empty sandbox should be rejected");
+    }
+
+    #[test]
+    #[cfg(not(target_os = "linux"))]
+    fn test_unsupported_platform() {
+        "pid,mount"
+            .parse::<SandboxConfig>()
+            .err()
+            .expect("sandbox should be rejected on this platform");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_apply() {
+        let program = SandboxConfig::default()
+            .apply(DefaultShell {
+                path: "/bin/bash".to_owned(),
+                args: vec!["-l".to_owned()],
+                env: vec![],
+            })
+            .unwrap();
+
+        assert!(program.path.ends_with("/unshare"));
+        assert_eq!(
+            program.args,
+            vec![
+                "--mount",
+                "--pid",
+                "--fork",
+                "--kill-child",
+                "--mount-proc",
+                "--",
+                "/bin/bash",
+                "-l"
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_shell_cannot_see_host_processes() {
+        use super::super::{PipeShell, Shell};
+        use tokio::runtime::Runtime;
+
+        // Creating namespaces requires root
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        Runtime::new().unwrap().block_on(async {
+            let program = SandboxConfig::default()
+                .apply(DefaultShell {
+                    path: "/bin/sh".to_owned(),
+                    args: vec![
+                        "-c".to_owned(),
+                        format!(
+                            "echo $$; test -e /proc/{} && echo visible || echo hidden",
+                            std::process::id()
+                        ),
+                    ],
+                    env: vec![],
+                })
+                .unwrap();
+            let mut shell = PipeShell::with_command(program, true).unwrap();
+
+            let mut output = vec![];
+            let mut buff = [0u8; 1024];
+
+            loop {
+                match shell.read(&mut buff).await.unwrap() {
+                    0 => break,
+                    read => output.extend_from_slice(&buff[..read]),
+                }
+            }
+
+            // Namespaces may be unavailable, such as within a container
+            if shell.exit_status().unwrap().code != Some(0) {
+                return;
+            }
+
+            assert_eq!(String::from_utf8(output).unwrap(), "1\nhidden\n");
+        });
+    }
+}
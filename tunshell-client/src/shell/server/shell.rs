@@ -1,5 +1,5 @@
-use crate::shell::proto::WindowSize;
-use anyhow::Result;
+use crate::shell::proto::{ExitStatus, WindowSize};
+use anyhow::{Error, Result};
 use async_trait::async_trait;
 
 #[async_trait]
@@ -8,7 +8,33 @@ pub(super) trait Shell {
 
     async fn write(&mut self, buff: &[u8]) -> Result<()>;
 
+    /// Signals that no more input will be written, output can
+    /// still be read until the shell exits
+    async fn close_stdin(&mut self) -> Result<()>;
+
     fn resize(&mut self, size: WindowSize) -> Result<()>;
 
-    fn exit_code(&self) -> Result<u8>;
+    /// Returns how the shell exited, with its exit code or the signal which terminated it.
+    /// This is expected to be available once `read` has returned `Ok(0)` or
+    /// `terminate` has been called, although implementations which reap the
+    /// process in the background may take a short time for it to become available.
+    fn exit_status(&self) -> Result<ExitStatus>;
+
+    /// Forcefully terminates the shell if it is still running
+    fn terminate(&mut self) -> Result<()>;
+
+    /// Sends the signal to the shell process
+    fn signal(&mut self, signal: u8) -> Result<()>;
+
+    /// Whether the shell is attached to a pty, rather than pipes
+    fn is_pty(&self) -> bool {
+        false
+    }
+
+    /// Returns the current working directory of the shell process
+    fn cwd(&self) -> Result<String> {
+        Err(Error::msg(
+            "the working directory of this shell cannot be determined",
+        ))
+    }
 }
@@ -0,0 +1,257 @@
+use super::Shell;
+use anyhow::Result;
+use futures::{future::BoxFuture, FutureExt};
+use std::{
+    cmp, io, mem,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::{Mutex, MutexGuard},
+};
+
+/// Adapts a `Shell` to tokio's `AsyncRead` and `AsyncWrite` so it can be driven by the
+/// standard io utilities and combinators, such as `tokio::io::copy`.
+/// Shutting down the writer closes the shell's stdin. The shell's other methods,
+/// such as `resize` and `exit_status`, are reached through `shell`.
+pub(super) struct ShellIo {
+    shell: Arc<Mutex<Box<dyn Shell + Send>>>,
+    /// A read from the shell which has not completed, it is abandoned when the shell
+    /// is needed for anything else as the shell may be waiting on that input
+    read: Option<BoxFuture<'static, Result<Vec<u8>>>>,
+    read_waker: Option<Waker>,
+    /// Output read from the shell which did not fit in the caller's buffer
+    read_buff: Vec<u8>,
+    write: Option<BoxFuture<'static, Result<usize>>>,
+    shutdown: Option<BoxFuture<'static, Result<()>>>,
+}
+
+impl ShellIo {
+    pub(super) fn new(shell: Box<dyn Shell + Send>) -> Self {
+        Self {
+            shell: Arc::new(Mutex::new(shell)),
+            read: None,
+            read_waker: None,
+            read_buff: vec![],
+            write: None,
+            shutdown: None,
+        }
+    }
+
+    /// Returns the shell once any write in progress has completed, a read in progress
+    /// is abandoned and restarted when the reader is next polled
+    pub(super) async fn shell(&mut self) -> MutexGuard<'_, Box<dyn Shell + Send>> {
+        self.abandon_read();
+        self.shell.lock().await
+    }
+
+    /// Returns the shell, abandoning any io in progress
+    pub(super) fn into_inner(mut self) -> Box<dyn Shell + Send> {
+        self.read = None;
+        self.write = None;
+        self.shutdown = None;
+
+        match Arc::try_unwrap(self.shell) {
+            Ok(shell) => shell.into_inner(),
+            Err(_) => unreachable!("the shell is only shared with the io futures"),
+        }
+    }
+
+    fn abandon_read(&mut self) {
+        if self.read.take().is_some() {
+            if let Some(waker) = self.read_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    fn poll_in_flight_write(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let write = match self.write.as_mut() {
+            Some(write) => write,
+            None => return Poll::Ready(Ok(0)),
+        };
+
+        let result = futures::ready!(write.as_mut().poll(cx));
+        self.write = None;
+
+        Poll::Ready(result.map_err(to_io_error))
+    }
+}
+
+impl AsyncRead for ShellIo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buff: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if buff.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        if this.read_buff.is_empty() {
+            if this.read.is_none() {
+                let shell = Arc::clone(&this.shell);
+                let len = buff.len();
+                // The allocation of the previous read is reused
+                let mut output = mem::take(&mut this.read_buff);
+
+                this.read = Some(
+                    async move {
+                        output.resize(len, 0);
+                        let read = shell.lock().await.read(&mut output).await?;
+                        output.truncate(read);
+                        Ok(output)
+                    }
+                    .boxed(),
+                );
+            }
+
+            let result = match this.read.as_mut().unwrap().as_mut().poll(cx) {
+                Poll::Ready(result) => result,
+                Poll::Pending => {
+                    this.read_waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            };
+
+            this.read = None;
+            this.read_waker = None;
+            this.read_buff = result.map_err(to_io_error)?;
+        }
+
+        let read = cmp::min(buff.len(), this.read_buff.len());
+        buff[..read].copy_from_slice(&this.read_buff[..read]);
+        this.read_buff.drain(..read);
+
+        Poll::Ready(Ok(read))
+    }
+}
+
+impl AsyncWrite for ShellIo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buff: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if this.write.is_none() {
+            // The shell may be blocked waiting for this input so a pending read must not hold it
+            this.abandon_read();
+
+            let shell = Arc::clone(&this.shell);
+            let data = buff.to_vec();
+
+            this.write = Some(
+                async move {
+                    shell.lock().await.write(&data).await?;
+                    Ok(data.len())
+                }
+                .boxed(),
+            );
+        }
+
+        this.poll_in_flight_write(cx)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        futures::ready!(this.poll_in_flight_write(cx))?;
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        futures::ready!(this.poll_in_flight_write(cx))?;
+
+        if this.shutdown.is_none() {
+            this.abandon_read();
+
+            let shell = Arc::clone(&this.shell);
+            this.shutdown = Some(async move { shell.lock().await.close_stdin().await }.boxed());
+        }
+
+        let result = futures::ready!(this.shutdown.as_mut().unwrap().as_mut().poll(cx));
+        this.shutdown = None;
+
+        Poll::Ready(result.map_err(to_io_error))
+    }
+}
+
+fn to_io_error(err: anyhow::Error) -> io::Error {
+    io::Error::other(format!("{:#}", err))
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::super::{DefaultShell, PipeShell};
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::runtime::Runtime;
+
+    fn cat_shell() -> ShellIo {
+        let program = DefaultShell::from_command(&["/bin/cat".to_owned()]).unwrap();
+
+        ShellIo::new(Box::new(PipeShell::with_command(program, false).unwrap()))
+    }
+
+    #[test]
+    fn test_write_then_read_to_end() {
+        Runtime::new().unwrap().block_on(async {
+            let mut shell = cat_shell();
+
+            shell.write_all(b"hello\n").await.unwrap();
+            shell.shutdown().await.unwrap();
+
+            let mut output = vec![];
+            shell.read_to_end(&mut output).await.unwrap();
+
+            assert_eq!(output, b"hello\n");
+        });
+    }
+
+    #[test]
+    fn test_read_exact_in_small_chunks() {
+        Runtime::new().unwrap().block_on(async {
+            let mut shell = cat_shell();
+
+            shell.write_all(b"hello world\n").await.unwrap();
+
+            let mut first = [0u8; 6];
+            shell.read_exact(&mut first).await.unwrap();
+            assert_eq!(&first, b"hello ");
+
+            let mut rest = [0u8; 6];
+            shell.read_exact(&mut rest).await.unwrap();
+            assert_eq!(&rest, b"world\n");
+
+            shell.shell().await.terminate().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_copy_into_shell() {
+        Runtime::new().unwrap().block_on(async {
+            let mut shell = cat_shell();
+
+            let copied = tokio::io::copy(&mut &b"copied input"[..], &mut shell)
+                .await
+                .unwrap();
+            shell.shutdown().await.unwrap();
+            assert_eq!(copied, 12);
+
+            let mut output = String::new();
+            shell.read_to_string(&mut output).await.unwrap();
+
+            assert_eq!(output, "copied input");
+        });
+    }
+}
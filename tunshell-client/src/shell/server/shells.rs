@@ -0,0 +1,128 @@
+use log::*;
+use std::path::Path;
+
+/// The file listing the valid login shells of the host
+#[cfg(unix)]
+const SHELLS_FILE: &str = "/etc/shells";
+
+/// Returns the shells which the client may select, from the allowlist if configured,
+/// otherwise from /etc/shells. Only shells which exist and are executable are returned.
+pub(super) fn available_shells(allowlist: Option<&[String]>) -> Vec<String> {
+    match allowlist {
+        Some(allowlist) => filter_executable(allowlist.iter().map(|i| i.as_str())),
+        None => filter_executable(parse_shells_file(&read_shells_file()).into_iter()),
+    }
+}
+
+#[cfg(unix)]
+fn read_shells_file() -> String {
+    read_shells_file_at(Path::new(SHELLS_FILE))
+}
+
+#[cfg(not(unix))]
+fn read_shells_file() -> String {
+    String::new()
+}
+
+#[cfg(unix)]
+fn read_shells_file_at(path: &Path) -> String {
+    std::fs::read_to_string(path).unwrap_or_else(|err| {
+        warn!("failed to read {}: {}", path.display(), err);
+        String::new()
+    })
+}
+
+/// Parses the paths from the contents of a shells file, skipping blank lines and comments
+fn parse_shells_file(contents: &str) -> Vec<&str> {
+    contents
+        .lines()
+        .map(|i| i.trim())
+        .filter(|i| !i.is_empty() && !i.starts_with('#'))
+        .collect()
+}
+
+fn filter_executable<'a>(shells: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut available = vec![];
+
+    for shell in shells {
+        if !is_executable(Path::new(shell)) {
+            debug!("shell {} is not available", shell);
+            continue;
+        }
+
+        if !available.iter().any(|i| i == shell) {
+            available.push(shell.to_owned());
+        }
+    }
+
+    available
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .map(|i| i.is_file() && i.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_shells_file() {
+        let contents = "# /etc/shells: valid login shells\n/bin/sh\n\n  /bin/bash  \n#/bin/zsh\n";
+
+        assert_eq!(parse_shells_file(contents), vec!["/bin/sh", "/bin/bash"]);
+    }
+
+    #[test]
+    fn test_only_existing_executable_shells_returned() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("tunshell-shells-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let executable = dir.join("executable");
+        let not_executable = dir.join("not-executable");
+        for (path, mode) in &[(&executable, 0o755), (&not_executable, 0o644)] {
+            std::fs::write(path, "").unwrap();
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(*mode)).unwrap();
+        }
+
+        let shells_file = dir.join("shells");
+        std::fs::write(
+            &shells_file,
+            format!(
+                "{0}\n{1}\n{2}\n{0}\n",
+                executable.display(),
+                dir.join("missing").display(),
+                not_executable.display()
+            ),
+        )
+        .unwrap();
+
+        let available =
+            filter_executable(parse_shells_file(&read_shells_file_at(&shells_file)).into_iter());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(available, vec![executable.to_str().unwrap()]);
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let allowlist = vec![
+            "/bin/sh".to_owned(),
+            "/bin/tunshell-missing-shell".to_owned(),
+        ];
+
+        assert_eq!(available_shells(Some(&allowlist)), vec!["/bin/sh"]);
+    }
+}
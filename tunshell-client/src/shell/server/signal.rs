@@ -0,0 +1,58 @@
+use anyhow::{Error, Result};
+use log::*;
+
+/// The signals which clients are permitted to send to the shell process
+#[cfg(unix)]
+const ALLOWED_SIGNALS: &[libc::c_int] = &[
+    libc::SIGINT,
+    libc::SIGTERM,
+    libc::SIGQUIT,
+    libc::SIGHUP,
+    libc::SIGKILL,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+];
+
+/// Sends the signal to the process, returning an error if
+/// the signal is not in the allowlist
+#[cfg(unix)]
+pub(super) fn send_signal(pid: u32, signal: u8) -> Result<()> {
+    let signal = signal as libc::c_int;
+
+    if !ALLOWED_SIGNALS.contains(&signal) {
+        return Err(Error::msg(format!("signal {} is not permitted", signal)));
+    }
+
+    debug!("sending signal {} to process {}", signal, pid);
+    let result = unsafe { libc::kill(pid as libc::pid_t, signal) };
+
+    if result != 0 {
+        return Err(Error::new(std::io::Error::last_os_error()));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(super) fn send_signal(_pid: u32, signal: u8) -> Result<()> {
+    Err(Error::msg(format!(
+        "signal {} is not supported on this platform",
+        signal
+    )))
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_signal_not_in_allowlist() {
+        let err = send_signal(std::process::id(), libc::SIGSTOP as u8).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!("signal {} is not permitted", libc::SIGSTOP)
+        );
+    }
+}
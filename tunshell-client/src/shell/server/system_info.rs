@@ -0,0 +1,112 @@
+use crate::shell::proto::SystemInfoPayload;
+use std::env::consts;
+
+/// Returns information about the host, gathered when it is requested.
+/// Fields which cannot be determined on this platform are `None`.
+pub(super) fn system_info() -> SystemInfoPayload {
+    let (hostname, kernel_version) = uname();
+
+    SystemInfoPayload {
+        hostname,
+        os: consts::OS.to_owned(),
+        arch: consts::ARCH.to_owned(),
+        kernel_version,
+        load_average: load_average(),
+        uptime_secs: uptime_secs(),
+    }
+}
+
+/// Returns the hostname and kernel release
+#[cfg(unix)]
+fn uname() -> (Option<String>, Option<String>) {
+    use std::ffi::CStr;
+
+    let mut name: libc::utsname = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::uname(&mut name) } != 0 {
+        return (None, None);
+    }
+
+    let field = |value: &[libc::c_char]| {
+        unsafe { CStr::from_ptr(value.as_ptr()) }
+            .to_str()
+            .ok()
+            .map(|i| i.to_owned())
+    };
+
+    (field(&name.nodename[..]), field(&name.release[..]))
+}
+
+#[cfg(not(unix))]
+fn uname() -> (Option<String>, Option<String>) {
+    (std::env::var("COMPUTERNAME").ok(), None)
+}
+
+/// Returns the 1, 5 and 15 minute load averages
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+fn load_average() -> Option<(f64, f64, f64)> {
+    let mut loads = [0f64; 3];
+
+    if unsafe { libc::getloadavg(loads.as_mut_ptr(), 3) } != 3 {
+        return None;
+    }
+
+    Some((loads[0], loads[1], loads[2]))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "freebsd")))]
+fn load_average() -> Option<(f64, f64, f64)> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn uptime_secs() -> Option<u64> {
+    let uptime = std::fs::read_to_string("/proc/uptime").ok()?;
+    let secs = uptime.split_whitespace().next()?.parse::<f64>().ok()?;
+
+    Some(secs as u64)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn uptime_secs() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::proto::ShellServerMessage;
+    use tunshell_shared::Message;
+
+    #[test]
+    fn test_system_info() {
+        let info = system_info();
+
+        assert_eq!(info.os, consts::OS);
+        assert_eq!(info.arch, consts::ARCH);
+
+        #[cfg(unix)]
+        {
+            assert!(!info.hostname.unwrap().is_empty());
+            assert!(!info.kernel_version.unwrap().is_empty());
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            let (one, five, fifteen) = info.load_average.unwrap();
+            assert!(one >= 0.0 && five >= 0.0 && fifteen >= 0.0);
+            assert!(info.uptime_secs.is_some());
+        }
+    }
+
+    #[test]
+    fn test_system_info_round_trip() {
+        let message = ShellServerMessage::SystemInfo(system_info());
+        let serialised = message.serialise().unwrap();
+
+        assert_eq!(
+            ShellServerMessage::deserialise(&serialised).unwrap(),
+            message
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use super::DefaultShell;
+#[cfg(not(unix))]
+use log::*;
+
+/// Wraps the program so that the umask is set (via the `umask` builtin of /bin/sh)
+/// in the child before the program is exec'd, rather than inheriting the umask of
+/// this process. Files created during the session get their default permissions from it.
+#[cfg(unix)]
+pub(super) fn apply_umask(umask: Option<u32>, program: DefaultShell) -> DefaultShell {
+    let umask = match umask {
+        Some(umask) => umask,
+        None => return program,
+    };
+
+    let mut args = vec![
+        "-c".to_owned(),
+        format!("umask {:03o} && exec \"$@\"", umask),
+        "tunshell".to_owned(),
+        program.path,
+    ];
+    args.extend(program.args);
+
+    DefaultShell {
+        path: "/bin/sh".to_owned(),
+        args,
+        env: program.env,
+    }
+}
+
+#[cfg(not(unix))]
+pub(super) fn apply_umask(umask: Option<u32>, program: DefaultShell) -> DefaultShell {
+    if umask.is_some() {
+        warn!("umask is not supported on this platform, ignoring");
+    }
+
+    program
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::super::{PipeShell, Shell};
+    use super::*;
+    use std::{env, fs, os::unix::fs::PermissionsExt};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn test_apply_no_umask() {
+        let program = DefaultShell::new("/bin/bash".to_owned());
+
+        assert_eq!(apply_umask(None, program.clone()), program);
+    }
+
+    #[test]
+    fn test_apply_umask() {
+        let program = DefaultShell {
+            path: "/bin/bash".to_owned(),
+            args: vec!["-l".to_owned()],
+            env: vec![],
+        };
+
+        assert_eq!(
+            apply_umask(Some(0o027), program),
+            DefaultShell {
+                path: "/bin/sh".to_owned(),
+                args: vec![
+                    "-c".to_owned(),
+                    "umask 027 && exec \"$@\"".to_owned(),
+                    "tunshell".to_owned(),
+                    "/bin/bash".to_owned(),
+                    "-l".to_owned(),
+                ],
+                env: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_files_created_with_umask() {
+        Runtime::new().unwrap().block_on(async {
+            let path = env::temp_dir().join(format!("tunshell-umask-{}", std::process::id()));
+            let program = DefaultShell::from_command(&[
+                "/bin/sh".to_owned(),
+                "-c".to_owned(),
+                "echo secret > \"$1\"".to_owned(),
+                "sh".to_owned(),
+                path.to_string_lossy().into_owned(),
+            ])
+            .unwrap();
+            let mut shell =
+                PipeShell::with_command(apply_umask(Some(0o077), program), true).unwrap();
+            let mut buff = [0u8; 1024];
+
+            while shell.read(&mut buff).await.unwrap() > 0 {}
+
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            fs::remove_file(&path).unwrap();
+
+            assert_eq!(shell.exit_status().unwrap().code, Some(0));
+            assert_eq!(mode & 0o777, 0o600);
+        });
+    }
+}
@@ -0,0 +1,89 @@
+use super::Shell;
+use crate::shell::proto::{ExitStatus, WindowSize};
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+use std::{cmp, str::FromStr};
+
+/// What the server does when neither a pty nor a pipe shell can be started,
+/// in place of falling back to the in-built shell
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoShellAction {
+    /// Ends the session with a fatal error describing why the shell is unavailable
+    Error,
+    /// Writes why the shell is unavailable to the client's terminal as the output of
+    /// a shell which exits immediately, so the session ends without an error
+    MessageOnly,
+}
+
+impl FromStr for NoShellAction {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "error" => Ok(Self::Error),
+            "message" => Ok(Self::MessageOnly),
+            _ => Err(Error::msg(format!(
+                "unknown no shell action: {:?}, expected error or message",
+                value
+            ))),
+        }
+    }
+}
+
+/// No shell could be started on this host for the reason given
+#[derive(thiserror::Error, Debug)]
+#[error("shell unavailable on this host: {0}")]
+pub(super) struct ShellUnavailable(pub(super) String);
+
+/// Stands in for a shell which could not be started, its only output is the reason
+pub(super) struct UnavailableShell {
+    output: Vec<u8>,
+}
+
+impl UnavailableShell {
+    pub(super) fn new(reason: ShellUnavailable) -> Self {
+        Self {
+            output: format!("{}\r\n", reason).into_bytes(),
+        }
+    }
+}
+
+#[async_trait]
+impl Shell for UnavailableShell {
+    async fn read(&mut self, buff: &mut [u8]) -> Result<usize> {
+        let len = cmp::min(buff.len(), self.output.len());
+        buff[..len].copy_from_slice(&self.output[..len]);
+        self.output.drain(..len);
+
+        Ok(len)
+    }
+
+    async fn write(&mut self, _buff: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    async fn close_stdin(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn resize(&mut self, _size: WindowSize) -> Result<()> {
+        Ok(())
+    }
+
+    fn exit_status(&self) -> Result<ExitStatus> {
+        if !self.output.is_empty() {
+            return Err(Error::msg("shell has not exited"));
+        }
+
+        Ok(ExitStatus::exited(1))
+    }
+
+    fn terminate(&mut self) -> Result<()> {
+        self.output.clear();
+        Ok(())
+    }
+
+    fn signal(&mut self, _signal: u8) -> Result<()> {
+        Ok(())
+    }
+}
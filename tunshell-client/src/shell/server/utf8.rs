@@ -0,0 +1,118 @@
+use bytes::Bytes;
+use std::mem;
+
+/// Buffers a trailing partial UTF-8 sequence so that each chunk of
+/// output ends on a character boundary
+pub(super) struct Utf8Chunker {
+    pending: Vec<u8>,
+}
+
+impl Utf8Chunker {
+    pub(super) fn new() -> Self {
+        Self { pending: vec![] }
+    }
+
+    /// Returns any pending bytes followed by the supplied data, holding back
+    /// a trailing incomplete sequence until the next call.
+    /// The data is only copied when there are pending bytes to prepend.
+    pub(super) fn push(&mut self, data: Bytes) -> Bytes {
+        let data = if self.pending.is_empty() {
+            data
+        } else {
+            let mut joined = mem::take(&mut self.pending);
+            joined.extend_from_slice(&data);
+            Bytes::from(joined)
+        };
+
+        let boundary = last_char_boundary(&data);
+        self.pending.extend_from_slice(&data[boundary..]);
+
+        data.slice(..boundary)
+    }
+
+    pub(super) fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Returns the pending bytes regardless of whether they form a complete character
+    pub(super) fn flush(&mut self) -> Bytes {
+        Bytes::from(mem::take(&mut self.pending))
+    }
+}
+
+/// Returns the index at which a trailing incomplete UTF-8 sequence starts,
+/// or the length of the data if it does not end with one.
+/// Invalid UTF-8 is passed through untouched.
+fn last_char_boundary(data: &[u8]) -> usize {
+    // A sequence is at most 4 bytes so only the last 3 bytes can be incomplete
+    for back in 1..=data.len().min(3) {
+        let idx = data.len() - back;
+        let byte = data[idx];
+
+        // Continuation byte
+        if byte & 0b1100_0000 == 0b1000_0000 {
+            continue;
+        }
+
+        let len = if byte & 0b1110_0000 == 0b1100_0000 {
+            2
+        } else if byte & 0b1111_0000 == 0b1110_0000 {
+            3
+        } else if byte & 0b1111_1000 == 0b1111_0000 {
+            4
+        } else {
+            1
+        };
+
+        return if len > back { idx } else { data.len() };
+    }
+
+    data.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_char_boundary() {
+        assert_eq!(last_char_boundary(&[]), 0);
+        assert_eq!(last_char_boundary(b"abc"), 3);
+        assert_eq!(last_char_boundary("aé".as_bytes()), 3);
+        assert_eq!(last_char_boundary(&"aé".as_bytes()[..2]), 1);
+        assert_eq!(last_char_boundary(&"a€".as_bytes()[..3]), 1);
+        assert_eq!(last_char_boundary(&"a😀".as_bytes()[..4]), 1);
+        assert_eq!(last_char_boundary(&[0x80, 0x80, 0x80, 0x80]), 4);
+    }
+
+    #[test]
+    fn test_push_defers_partial_sequence() {
+        let mut chunker = Utf8Chunker::new();
+        let data = Bytes::from("a😀b");
+
+        assert_eq!(chunker.push(data.slice(..3)), b"a".to_vec());
+        assert!(chunker.has_pending());
+        assert_eq!(chunker.push(data.slice(3..)), "😀b".as_bytes().to_vec());
+        assert!(!chunker.has_pending());
+    }
+
+    #[test]
+    fn test_push_complete_data_is_not_copied() {
+        let mut chunker = Utf8Chunker::new();
+        let data = Bytes::from("aé");
+
+        let output = chunker.push(data.clone());
+
+        assert_eq!(output.as_ptr(), data.as_ptr());
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn test_flush_pending() {
+        let mut chunker = Utf8Chunker::new();
+
+        assert_eq!(chunker.push(Bytes::from(vec![b'a', 0xE2])), b"a".to_vec());
+        assert_eq!(chunker.flush(), vec![0xE2]);
+        assert!(!chunker.has_pending());
+    }
+}
@@ -22,19 +22,19 @@ pub(super) struct EncryptedMessage {
 
 impl Message for EncryptedMessage {
     fn type_id(&self) -> u8 {
-        return 0;
+        0
     }
 
     fn serialise(&self) -> Result<RawMessage> {
         let mut cursor = Cursor::new(vec![]);
 
         cursor.write_u8(self.nonce.len() as u8).unwrap();
-        cursor.write(self.nonce.as_slice()).unwrap();
+        cursor.write_all(self.nonce.as_slice()).unwrap();
 
         cursor
             .write_u16::<BigEndian>(self.ciphertext.len() as u16)
             .unwrap();
-        cursor.write(self.ciphertext.as_slice()).unwrap();
+        cursor.write_all(self.ciphertext.as_slice()).unwrap();
 
         RawMessage::new(self.type_id(), cursor.into_inner())
     }
@@ -64,6 +64,8 @@ pub struct AesStream<S: futures::AsyncRead + futures::AsyncWrite + Unpin + Send>
     write_buff: Option<EncryptedMessage>,
 }
 
+// The key is moved out of the state for each operation so it isn't boxed
+#[allow(clippy::large_enum_variant)]
 enum CryptoState<R> {
     Swapping,
     Pending(Key),
@@ -94,7 +96,7 @@ impl<S: futures::AsyncRead + futures::AsyncWrite + Unpin + Send> AsyncRead for A
     ) -> Poll<io::Result<usize>> {
         debug!("reading from aes stream");
 
-        while self.read_buff.len() == 0 {
+        while self.read_buff.is_empty() {
             match std::mem::replace(&mut self.decrypt, CryptoState::Swapping) {
                 CryptoState::Crypting(mut fut) => {
                     let result = match fut.as_mut().poll(cx) {
@@ -149,7 +151,7 @@ impl<S: futures::AsyncRead + futures::AsyncWrite + Unpin + Send> AsyncWrite for
         cx: &mut Context<'_>,
         buff: &[u8],
     ) -> Poll<Result<usize, io::Error>> {
-        if buff.len() > 0 {
+        if !buff.is_empty() {
             debug!("sending {} bytes", buff.len());
         }
 
@@ -187,19 +189,19 @@ impl<S: futures::AsyncRead + futures::AsyncWrite + Unpin + Send> AsyncWrite for
         let message = self.write_buff.take().unwrap();
         match Pin::new(&mut self.inner).poll_write(cx, &message) {
             Poll::Ready(Ok(_)) => {
-                return if buff.len() == 0 {
+                if buff.is_empty() {
                     Poll::Ready(Ok(0))
                 } else {
-                    return poll;
+                    poll
                 }
             }
             Poll::Ready(Err(err)) => {
                 warn!("encountered error while writing to inner stream: {}", err);
-                return Poll::Ready(Err(io::Error::from(io::ErrorKind::BrokenPipe)));
+                Poll::Ready(Err(io::Error::from(io::ErrorKind::BrokenPipe)))
             }
             Poll::Pending => {
                 self.write_buff.replace(message);
-                return poll;
+                poll
             }
         }
     }
@@ -268,7 +270,7 @@ mod tests {
             let (message, _) = encrypt(plaintext.to_vec(), key.clone()).await.unwrap();
 
             assert_eq!(message.nonce.len(), 12);
-            assert_eq!(message.ciphertext != plaintext, true);
+            assert!(message.ciphertext != plaintext);
         });
     }
 
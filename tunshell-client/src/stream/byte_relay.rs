@@ -0,0 +1,145 @@
+use crate::stream::TunnelStream;
+use anyhow::Result;
+use log::*;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Relays raw bytes between the two streams in both directions, without parsing
+/// any of the messages passing through them.
+/// When one stream reaches EOF the other is shut down for writing so the half-close
+/// is propagated, while the opposite direction continues until it also reaches EOF.
+/// Returns the number of bytes copied from `a` to `b` and from `b` to `a`.
+pub async fn relay(a: Box<dyn TunnelStream>, b: Box<dyn TunnelStream>) -> Result<(u64, u64)> {
+    // Each direction uses a single buffer sized for the stream being written to
+    let a_chunk_size = a.preferred_chunk_size();
+    let b_chunk_size = b.preferred_chunk_size();
+
+    let (a_read, a_write) = io::split(a);
+    let (b_read, b_write) = io::split(b);
+
+    futures::future::try_join(
+        copy_until_eof(a_read, b_write, b_chunk_size),
+        copy_until_eof(b_read, a_write, a_chunk_size),
+    )
+    .await
+}
+
+async fn copy_until_eof<R, W>(mut reader: R, mut writer: W, buff_size: usize) -> Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buff = vec![0u8; buff_size.max(1)];
+    let mut copied = 0u64;
+
+    loop {
+        let read = reader.read(&mut buff).await?;
+
+        if read == 0 {
+            break;
+        }
+
+        writer.write_all(&buff[..read]).await?;
+        copied += read as u64;
+    }
+
+    debug!("relay reached eof after {} bytes, closing writer", copied);
+    writer.flush().await?;
+    writer.shutdown().await?;
+
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{thread_rng, Rng};
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+    use tokio::runtime::Runtime;
+
+    type Shared<T> = Arc<Mutex<T>>;
+
+    /// Mock stream which reads its input in small chunks and
+    /// captures everything written to it
+    struct MockStream {
+        input: Vec<u8>,
+        pos: usize,
+        output: Arc<Mutex<Vec<u8>>>,
+        shutdown: Arc<Mutex<bool>>,
+    }
+
+    impl MockStream {
+        fn new(input: Vec<u8>) -> (Self, Shared<Vec<u8>>, Shared<bool>) {
+            let output = Arc::new(Mutex::new(vec![]));
+            let shutdown = Arc::new(Mutex::new(false));
+
+            let stream = Self {
+                input,
+                pos: 0,
+                output: Arc::clone(&output),
+                shutdown: Arc::clone(&shutdown),
+            };
+
+            (stream, output, shutdown)
+        }
+    }
+
+    impl AsyncRead for MockStream {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buff: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let this = self.get_mut();
+            let len = std::cmp::min(std::cmp::min(buff.len(), 100), this.input.len() - this.pos);
+
+            buff[..len].copy_from_slice(&this.input[this.pos..this.pos + len]);
+            this.pos += len;
+
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    impl AsyncWrite for MockStream {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buff: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.output.lock().unwrap().extend_from_slice(buff);
+            Poll::Ready(Ok(buff.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            *self.shutdown.lock().unwrap() = true;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl TunnelStream for MockStream {}
+
+    #[test]
+    fn test_relay_both_directions() {
+        Runtime::new().unwrap().block_on(async {
+            let mut a_input = vec![0u8; 10_000];
+            thread_rng().fill(a_input.as_mut_slice());
+            let b_input = "hello from b".as_bytes().to_vec();
+
+            let (a, a_output, a_shutdown) = MockStream::new(a_input.clone());
+            let (b, b_output, b_shutdown) = MockStream::new(b_input.clone());
+
+            let copied = relay(Box::new(a), Box::new(b)).await.unwrap();
+
+            assert_eq!(copied, (a_input.len() as u64, b_input.len() as u64));
+            assert_eq!(*b_output.lock().unwrap(), a_input);
+            assert_eq!(*a_output.lock().unwrap(), b_input);
+            assert!(*a_shutdown.lock().unwrap());
+            assert!(*b_shutdown.lock().unwrap());
+        });
+    }
+}
@@ -1,13 +1,32 @@
 use tokio::io::{AsyncRead, AsyncWrite};
 
 mod aes_stream;
+mod byte_relay;
 mod crypto;
+mod probe;
 mod relay_stream;
 
 pub use aes_stream::*;
+pub use byte_relay::*;
+pub use probe::*;
 pub use relay_stream::*;
 
-pub trait TunnelStream: AsyncRead + AsyncWrite + Send + Unpin {}
+/// The chunk size used for streams which do not specify a preference
+pub const DEFAULT_CHUNK_SIZE: usize = 1024;
+
+pub trait TunnelStream: AsyncRead + AsyncWrite + Send + Unpin {
+    /// The preferred number of bytes written to the stream per chunk,
+    /// allowing each transport to use its optimal frame size
+    fn preferred_chunk_size(&self) -> usize {
+        DEFAULT_CHUNK_SIZE
+    }
+
+    /// Sets whether small writes are sent immediately rather than being coalesced
+    /// (Nagle's algorithm on TCP), streams without such buffering ignore this
+    fn set_nodelay(&mut self, _enabled: bool) -> std::io::Result<()> {
+        Ok(())
+    }
+}
 
 
 #[cfg(test)]
@@ -0,0 +1,233 @@
+use crate::stream::TunnelStream;
+use anyhow::{Error, Result};
+use futures::{future, FutureExt};
+use log::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Sent by the master side over every candidate
+const PROBE: u8 = 0x01;
+/// Sent by the other side in response to each probe
+const PROBE_ACK: u8 = 0x02;
+/// Sent by the master side over the chosen candidate only
+const PROBE_SELECT: u8 = 0x03;
+
+/// Races a small handshake over each of the connected candidates, returning the
+/// first to complete and closing the others.
+/// The master side chooses the candidate whose acknowledgement arrives first and
+/// informs the other side of its choice, so both peers select the same stream.
+pub async fn probe_transport(
+    candidates: Vec<Box<dyn TunnelStream>>,
+    master_side: bool,
+) -> Result<Box<dyn TunnelStream>> {
+    if candidates.is_empty() {
+        return Err(Error::msg("no transports to probe"));
+    }
+
+    debug!("probing {} transports", candidates.len());
+
+    let probes = candidates.into_iter().enumerate().map(|(idx, stream)| {
+        if master_side {
+            probe_as_master(stream)
+                .map(move |i| i.map(|stream| (idx, stream)))
+                .boxed()
+        } else {
+            probe_as_slave(stream)
+                .map(move |i| i.map(|stream| (idx, stream)))
+                .boxed()
+        }
+    });
+
+    // The remaining probes are dropped along with the streams of the losing candidates
+    let ((idx, mut stream), _) = future::select_ok(probes).await?;
+    debug!("selected transport #{}", idx);
+
+    if master_side {
+        write_byte(&mut stream, PROBE_SELECT).await?;
+    }
+
+    Ok(stream)
+}
+
+async fn probe_as_master(mut stream: Box<dyn TunnelStream>) -> Result<Box<dyn TunnelStream>> {
+    write_byte(&mut stream, PROBE).await?;
+    expect_byte(&mut stream, PROBE_ACK).await?;
+
+    Ok(stream)
+}
+
+async fn probe_as_slave(mut stream: Box<dyn TunnelStream>) -> Result<Box<dyn TunnelStream>> {
+    expect_byte(&mut stream, PROBE).await?;
+    write_byte(&mut stream, PROBE_ACK).await?;
+    expect_byte(&mut stream, PROBE_SELECT).await?;
+
+    Ok(stream)
+}
+
+async fn write_byte(stream: &mut Box<dyn TunnelStream>, byte: u8) -> Result<()> {
+    stream.write_all(&[byte]).await?;
+    stream.flush().await?;
+
+    Ok(())
+}
+
+async fn expect_byte(stream: &mut Box<dyn TunnelStream>, expected: u8) -> Result<()> {
+    let received = stream.read_u8().await?;
+
+    if received != expected {
+        return Err(Error::msg(format!(
+            "unexpected transport probe byte {} (expected {})",
+            received, expected
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use tokio::io::{AsyncRead, AsyncWrite};
+    use tokio::runtime::Runtime;
+    use tokio::time::{delay_for, timeout, Delay};
+
+    /// Mock stream which delays its first read to simulate the latency of the transport,
+    /// once its input is exhausted reads remain pending
+    struct LatencyStream {
+        input: Vec<u8>,
+        latency: Option<Pin<Box<Delay>>>,
+        output: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl LatencyStream {
+        fn new(input: &[u8], latency: Duration) -> (Self, Arc<Mutex<Vec<u8>>>) {
+            let output = Arc::new(Mutex::new(vec![]));
+
+            let stream = Self {
+                input: input.to_vec(),
+                latency: Some(Box::pin(delay_for(latency))),
+                output: Arc::clone(&output),
+            };
+
+            (stream, output)
+        }
+    }
+
+    impl AsyncRead for LatencyStream {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buff: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let this = self.get_mut();
+
+            if let Some(latency) = this.latency.as_mut() {
+                if latency.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+
+                this.latency = None;
+            }
+
+            if this.input.is_empty() {
+                return Poll::Pending;
+            }
+
+            let len = std::cmp::min(buff.len(), this.input.len());
+            buff[..len].copy_from_slice(&this.input[..len]);
+            this.input.drain(..len);
+
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    impl AsyncWrite for LatencyStream {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buff: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.output.lock().unwrap().extend_from_slice(buff);
+            Poll::Ready(Ok(buff.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl TunnelStream for LatencyStream {}
+
+    #[test]
+    fn test_master_selects_fastest_transport() {
+        Runtime::new().unwrap().block_on(async {
+            let (slow, slow_output) = LatencyStream::new(&[PROBE_ACK], Duration::from_millis(200));
+            let (fast, fast_output) = LatencyStream::new(&[PROBE_ACK], Duration::from_millis(10));
+
+            timeout(
+                Duration::from_millis(1000),
+                probe_transport(vec![Box::new(slow), Box::new(fast)], true),
+            )
+            .await
+            .expect("probe should complete")
+            .unwrap();
+
+            assert_eq!(*slow_output.lock().unwrap(), vec![PROBE]);
+            assert_eq!(*fast_output.lock().unwrap(), vec![PROBE, PROBE_SELECT]);
+        });
+    }
+
+    #[test]
+    fn test_slave_uses_transport_selected_by_master() {
+        Runtime::new().unwrap().block_on(async {
+            let (unselected, unselected_output) =
+                LatencyStream::new(&[PROBE], Duration::from_millis(10));
+            let (selected, selected_output) =
+                LatencyStream::new(&[PROBE, PROBE_SELECT], Duration::from_millis(50));
+
+            let mut stream = timeout(
+                Duration::from_millis(1000),
+                probe_transport(vec![Box::new(unselected), Box::new(selected)], false),
+            )
+            .await
+            .expect("probe should complete")
+            .unwrap();
+
+            assert_eq!(*unselected_output.lock().unwrap(), vec![PROBE_ACK]);
+            assert_eq!(*selected_output.lock().unwrap(), vec![PROBE_ACK]);
+
+            stream.write_all(&[1, 2, 3]).await.unwrap();
+            assert_eq!(*selected_output.lock().unwrap(), vec![PROBE_ACK, 1, 2, 3]);
+        });
+    }
+
+    #[test]
+    fn test_unexpected_probe_byte() {
+        Runtime::new().unwrap().block_on(async {
+            let (stream, _) = LatencyStream::new(&[0xff], Duration::from_millis(0));
+
+            probe_transport(vec![Box::new(stream)], true)
+                .await
+                .err()
+                .expect("unexpected byte should fail the probe");
+        });
+    }
+
+    #[test]
+    fn test_no_candidates() {
+        Runtime::new().unwrap().block_on(async {
+            probe_transport(vec![], true)
+                .await
+                .err()
+                .expect("probe should fail without candidates");
+        });
+    }
+}
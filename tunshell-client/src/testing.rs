@@ -0,0 +1,215 @@
+//! Utilities for writing integration tests against the client, enabled by the `test-support` feature
+
+use crate::TunnelStream;
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// The number of bytes which can be buffered in each direction before writes wait for the peer to read
+pub const TUNNEL_PAIR_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Returns a pair of connected in-memory streams, bytes written to one can be read from the other.
+/// Dropping or shutting down one side signals EOF to the other.
+pub fn tunnel_pair() -> (Box<dyn TunnelStream>, Box<dyn TunnelStream>) {
+    let (a, b) = memory_stream_pair(TUNNEL_PAIR_BUFFER_SIZE);
+
+    (Box::new(a), Box::new(b))
+}
+
+/// A unidirectional buffer between the streams
+struct Pipe {
+    buff: VecDeque<u8>,
+    max_size: usize,
+    closed: bool,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+impl Pipe {
+    fn new(max_size: usize) -> Self {
+        Self {
+            buff: VecDeque::new(),
+            max_size,
+            closed: false,
+            read_waker: None,
+            write_waker: None,
+        }
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// One end of an in-memory stream pair, such as returned by `tunnel_pair`
+pub struct MemoryStream {
+    read: Arc<Mutex<Pipe>>,
+    write: Arc<Mutex<Pipe>>,
+}
+
+/// Returns a pair of connected in-memory streams, buffering up to `max_size` bytes in each direction
+pub fn memory_stream_pair(max_size: usize) -> (MemoryStream, MemoryStream) {
+    let a_to_b = Arc::new(Mutex::new(Pipe::new(max_size)));
+    let b_to_a = Arc::new(Mutex::new(Pipe::new(max_size)));
+
+    (
+        MemoryStream {
+            read: Arc::clone(&b_to_a),
+            write: Arc::clone(&a_to_b),
+        },
+        MemoryStream {
+            read: a_to_b,
+            write: b_to_a,
+        },
+    )
+}
+
+impl AsyncRead for MemoryStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buff: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut pipe = self.read.lock().unwrap();
+
+        if pipe.buff.is_empty() {
+            if pipe.closed {
+                return Poll::Ready(Ok(0));
+            }
+
+            pipe.read_waker.replace(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let len = buff.len().min(pipe.buff.len());
+
+        for (i, byte) in pipe.buff.drain(..len).enumerate() {
+            buff[i] = byte;
+        }
+
+        if let Some(waker) = pipe.write_waker.take() {
+            waker.wake();
+        }
+
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl AsyncWrite for MemoryStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buff: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut pipe = self.write.lock().unwrap();
+
+        if pipe.closed {
+            return Poll::Ready(Err(io::Error::from(io::ErrorKind::BrokenPipe)));
+        }
+
+        let available = pipe.max_size - pipe.buff.len();
+
+        if available == 0 {
+            pipe.write_waker.replace(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let len = buff.len().min(available);
+        pipe.buff.extend(&buff[..len]);
+
+        if let Some(waker) = pipe.read_waker.take() {
+            waker.wake();
+        }
+
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.write.lock().unwrap().close();
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        self.write.lock().unwrap().close();
+        self.read.lock().unwrap().close();
+    }
+}
+
+impl TunnelStream for MemoryStream {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn test_tunnel_pair_relays_both_directions() {
+        Runtime::new().unwrap().block_on(async {
+            let (mut a, mut b) = tunnel_pair();
+            let mut buff = [0u8; 1024];
+
+            a.write_all(b"hello from a").await.unwrap();
+            let read = b.read(&mut buff).await.unwrap();
+            assert_eq!(&buff[..read], b"hello from a");
+
+            b.write_all(b"hello from b").await.unwrap();
+            let read = a.read(&mut buff).await.unwrap();
+            assert_eq!(&buff[..read], b"hello from b");
+        });
+    }
+
+    #[test]
+    fn test_tunnel_pair_eof_on_drop() {
+        Runtime::new().unwrap().block_on(async {
+            let (mut a, mut b) = tunnel_pair();
+
+            a.write_all(b"last words").await.unwrap();
+            drop(a);
+
+            let mut received = vec![];
+            b.read_to_end(&mut received).await.unwrap();
+            assert_eq!(received, b"last words".to_vec());
+
+            b.write_all(b"anyone there?")
+                .await
+                .expect_err("writing to a dropped stream should fail");
+        });
+    }
+
+    #[test]
+    fn test_memory_stream_backpressure() {
+        Runtime::new().unwrap().block_on(async {
+            let (mut a, mut b) = memory_stream_pair(4);
+
+            let writer = tokio::spawn(async move {
+                a.write_all(b"more than four bytes").await.unwrap();
+                a.shutdown().await.unwrap();
+            });
+
+            let mut received = vec![];
+            b.read_to_end(&mut received).await.unwrap();
+            writer.await.unwrap();
+
+            assert_eq!(received, b"more than four bytes".to_vec());
+        });
+    }
+}
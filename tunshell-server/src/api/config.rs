@@ -0,0 +1,196 @@
+use anyhow::{Context, Result};
+use std::env;
+use tunshell_shared::{Capabilities, KeyGenConfig};
+
+const DEFAULT_RELAY_HOST: &str = "relay.tunshell.com";
+const DEFAULT_CORS_ORIGINS: &[&str] = &["https://tunshell.com", "http://localhost:3003"];
+const DEFAULT_CORS_METHODS: &[&str] = &["GET", "POST", "PUT", "PATCH", "DELETE"];
+const DEFAULT_CORS_HEADERS: &[&str] = &[
+    "Host",
+    "User-Agent",
+    "Accept",
+    "Content-Type",
+    "Sec-Fetch-Mode",
+    "Referer",
+    "Origin",
+    "Authority",
+    "Access-Control-Request-Method",
+    "Access-Control-Request-Headers",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Config {
+    /// The origins permitted to make cross-origin requests, "*" allows any origin
+    pub(crate) allowed_origins: Vec<String>,
+    pub(crate) allowed_methods: Vec<String>,
+    pub(crate) allowed_headers: Vec<String>,
+    /// The host clients should connect to for sessions created by this server
+    pub(crate) relay_host: String,
+    /// Controls the length and character set of generated session keys
+    pub(crate) key_gen: KeyGenConfig,
+}
+
+impl Config {
+    pub(crate) fn from_env() -> Result<Self> {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    /// Parses the config using `var` to look up each variable by name
+    pub(crate) fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let key_gen = parse_key_gen_vars(
+            &var,
+            "TUNSHELL_SESSION_KEY_LENGTH",
+            "TUNSHELL_SESSION_KEY_ALPHABET",
+        )?;
+
+        Ok(Self {
+            allowed_origins: parse_list_var(
+                &var,
+                "TUNSHELL_API_CORS_ORIGINS",
+                DEFAULT_CORS_ORIGINS,
+            ),
+            allowed_methods: parse_list_var(
+                &var,
+                "TUNSHELL_API_CORS_METHODS",
+                DEFAULT_CORS_METHODS,
+            ),
+            allowed_headers: parse_list_var(
+                &var,
+                "TUNSHELL_API_CORS_HEADERS",
+                DEFAULT_CORS_HEADERS,
+            ),
+            relay_host: var("TUNSHELL_RELAY_HOST").unwrap_or_else(|| DEFAULT_RELAY_HOST.to_owned()),
+            key_gen,
+        })
+    }
+
+    pub(crate) fn allows_any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|i| i == "*")
+    }
+
+    /// The features supported by sessions created with this config
+    pub(crate) fn capabilities(&self) -> Capabilities {
+        Capabilities::RELAY | Capabilities::DIRECT_CONNECT | Capabilities::VALIDATE_SESSION
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            allowed_origins: to_owned_list(DEFAULT_CORS_ORIGINS),
+            allowed_methods: to_owned_list(DEFAULT_CORS_METHODS),
+            allowed_headers: to_owned_list(DEFAULT_CORS_HEADERS),
+            relay_host: DEFAULT_RELAY_HOST.to_owned(),
+            key_gen: KeyGenConfig::default(),
+        }
+    }
+}
+
+fn parse_list_var(
+    var: impl Fn(&str) -> Option<String>,
+    name: &str,
+    default: &[&str],
+) -> Vec<String> {
+    match var(name) {
+        Some(value) => value
+            .split(',')
+            .map(|i| i.trim().to_owned())
+            .filter(|i| !i.is_empty())
+            .collect(),
+        None => to_owned_list(default),
+    }
+}
+
+fn parse_key_gen_vars(
+    var: impl Fn(&str) -> Option<String>,
+    length_name: &str,
+    alphabet_name: &str,
+) -> Result<KeyGenConfig> {
+    let default = KeyGenConfig::default();
+
+    let length = match var(length_name) {
+        Some(length) => length
+            .parse::<usize>()
+            .with_context(|| format!("invalid {}", length_name))?,
+        None => default.length(),
+    };
+    let alphabet = var(alphabet_name).unwrap_or_else(|| default.alphabet());
+
+    KeyGenConfig::new(length, &alphabet).context("invalid session key config")
+}
+
+fn to_owned_list(list: &[&str]) -> Vec<String> {
+    list.iter().map(|i| (*i).to_owned()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn vars(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars = vars
+            .iter()
+            .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+            .collect::<HashMap<_, _>>();
+
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_config_from_vars() {
+        assert_eq!(Config::from_vars(vars(&[])).unwrap(), Config::default());
+
+        assert_eq!(
+            Config::from_vars(vars(&[(
+                "TUNSHELL_API_CORS_ORIGINS",
+                "https://a.com, https://b.com"
+            )]))
+            .unwrap()
+            .allowed_origins,
+            vec!["https://a.com".to_owned(), "https://b.com".to_owned()]
+        );
+    }
+
+    #[test]
+    fn test_key_gen_from_vars() {
+        assert_eq!(
+            parse_key_gen_vars(vars(&[]), "TEST_KEY_LENGTH", "TEST_KEY_ALPHABET").unwrap(),
+            KeyGenConfig::default()
+        );
+
+        assert_eq!(
+            parse_key_gen_vars(
+                vars(&[
+                    ("TEST_KEY_LENGTH", "32"),
+                    ("TEST_KEY_ALPHABET", "0123456789abcdef")
+                ]),
+                "TEST_KEY_LENGTH",
+                "TEST_KEY_ALPHABET"
+            )
+            .unwrap(),
+            KeyGenConfig::new(32, "0123456789abcdef").unwrap()
+        );
+
+        parse_key_gen_vars(
+            vars(&[
+                ("TEST_KEY_LENGTH", "8"),
+                ("TEST_KEY_ALPHABET", "0123456789abcdef"),
+            ]),
+            "TEST_KEY_LENGTH",
+            "TEST_KEY_ALPHABET",
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_allows_any_origin() {
+        let mut config = Config::default();
+
+        assert!(!config.allows_any_origin());
+
+        config.allowed_origins = vec!["*".to_owned()];
+
+        assert!(config.allows_any_origin());
+    }
+}
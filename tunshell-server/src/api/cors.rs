@@ -1,20 +1,83 @@
+use super::Config;
 use warp::cors::Cors;
 
-pub fn cors() -> Cors {
-    warp::cors()
-        .allow_origins(vec!["https://tunshell.com", "http://localhost:3003"])
-        .allow_headers(vec![
-            "Host",
-            "User-Agent",
-            "Accept",
-            "Content-Type",
-            "Sec-Fetch-Mode",
-            "Referer",
-            "Origin",
-            "Authority",
-            "Access-Control-Request-Method",
-            "Access-Control-Request-Headers",
-        ])
-        .allow_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
-        .build()
+pub fn cors(config: &Config) -> Cors {
+    let cors = warp::cors()
+        .allow_headers(config.allowed_headers.iter().map(|i| i.as_str()))
+        .allow_methods(config.allowed_methods.iter().map(|i| i.as_str()));
+
+    let cors = if config.allows_any_origin() {
+        cors.allow_any_origin()
+    } else {
+        cors.allow_origins(config.allowed_origins.iter().map(|i| i.as_str()))
+    };
+
+    cors.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::Runtime;
+    use warp::Filter;
+
+    fn request_from_origin(
+        config: &Config,
+        origin: &str,
+    ) -> warp::http::Response<warp::hyper::body::Bytes> {
+        let filter = warp::any().map(warp::reply).with(cors(config));
+
+        Runtime::new().unwrap().block_on(async {
+            warp::test::request()
+                .method("GET")
+                .header("Origin", origin)
+                .reply(&filter)
+                .await
+        })
+    }
+
+    #[test]
+    fn test_allowed_origin() {
+        let config = Config::default();
+
+        let response = request_from_origin(&config, "https://tunshell.com");
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response
+                .headers()
+                .get("access-control-allow-origin")
+                .unwrap(),
+            "https://tunshell.com"
+        );
+    }
+
+    #[test]
+    fn test_disallowed_origin() {
+        let config = Config::default();
+
+        let response = request_from_origin(&config, "https://evil.com");
+
+        assert_eq!(response.status(), 403);
+        assert!(response
+            .headers()
+            .get("access-control-allow-origin")
+            .is_none());
+    }
+
+    #[test]
+    fn test_any_origin() {
+        let config = Config {
+            allowed_origins: vec!["*".to_owned()],
+            ..Config::default()
+        };
+
+        let response = request_from_origin(&config, "https://example.com");
+
+        assert_eq!(response.status(), 200);
+        assert!(response
+            .headers()
+            .get("access-control-allow-origin")
+            .is_some());
+    }
 }
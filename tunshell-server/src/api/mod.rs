@@ -1,5 +1,8 @@
 mod routes;
 mod cors;
 
+mod config;
+pub(crate) use config::*;
+
 mod register;
-pub use register::*;
\ No newline at end of file
+pub use register::*;
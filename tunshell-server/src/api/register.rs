@@ -1,25 +1,67 @@
-use super::{cors::cors, routes};
+use super::{cors::cors, routes, Config};
 use crate::db;
 use anyhow::Result;
 use db::SessionStore;
 use log::*;
+use std::collections::HashMap;
 use warp::{filters::BoxedFilter, Filter, Reply};
 
 pub async fn register() -> Result<BoxedFilter<(impl Reply + 'static,)>> {
     info!("registering api server routes");
 
+    let config = Config::from_env()?;
     let store = SessionStore::new(db::connect().await?);
+    let extend_store = store.clone();
+    let batch_store = store.clone();
+    let create_config = config.clone();
+    let batch_config = config.clone();
+    let capabilities_config = config.clone();
 
     let routes = warp::any()
         .and({
-            warp::path("api").and(
-                // POST /api/sessions
-                warp::path("sessions")
-                    .and(warp::post())
-                    .and_then(move || routes::create_session(store.clone())),
-            )
+            warp::path("api")
+                .and(
+                    // POST /api/sessions/{id}/extend
+                    warp::path!("sessions" / String / "extend")
+                        .and(warp::post())
+                        .and(warp::body::content_length_limit(1024))
+                        .and(warp::body::json())
+                        .and_then(move |id, request| {
+                            routes::extend_session(extend_store.clone(), id, request)
+                        })
+                        // POST /api/sessions/batch
+                        .or(warp::path!("sessions" / "batch")
+                            .and(warp::post())
+                            .and(warp::body::content_length_limit(1024))
+                            .and(warp::body::json())
+                            .and_then(move |request| {
+                                routes::create_session_batch(
+                                    batch_store.clone(),
+                                    batch_config.clone(),
+                                    request,
+                                )
+                            }))
+                        // POST /api/sessions
+                        .or(warp::path("sessions")
+                            .and(warp::path::end())
+                            .and(warp::post())
+                            .and(warp::query::<HashMap<String, String>>())
+                            .and_then(move |query| {
+                                routes::create_session(store.clone(), create_config.clone(), query)
+                            }))
+                        // GET /api/capabilities
+                        .or(warp::path("capabilities")
+                            .and(warp::path::end())
+                            .and(warp::get())
+                            .and_then(move || routes::capabilities(capabilities_config.clone()))),
+                )
+                // GET /metrics
+                .or(warp::path("metrics")
+                    .and(warp::path::end())
+                    .and(warp::get())
+                    .and_then(routes::metrics))
         })
-        .with(cors());
+        .with(cors(&config));
 
     Ok(routes.boxed())
 }
@@ -0,0 +1,62 @@
+use crate::api::Config;
+use serde::{Deserialize, Serialize};
+use tunshell_shared::SHELL_PROTOCOL_VERSION;
+use warp::{Rejection, Reply};
+
+/// The features supported by this server, allowing clients to adapt before connecting
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct CapabilitiesResponse<'a> {
+    protocol_version: u16,
+    /// The capabilities as a bitset, as sent in the shell protocol's hello ack
+    capabilities: u32,
+    features: Vec<&'a str>,
+    relay_host: &'a str,
+}
+
+pub(crate) async fn capabilities(config: Config) -> Result<Box<dyn Reply>, Rejection> {
+    let capabilities = config.capabilities();
+
+    Ok(Box::new(warp::reply::json(&CapabilitiesResponse {
+        protocol_version: SHELL_PROTOCOL_VERSION,
+        capabilities: capabilities.bits(),
+        features: capabilities.names(),
+        relay_host: &config.relay_host,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn test_capabilities() {
+        Runtime::new().unwrap().block_on(async {
+            let config = Config::default();
+
+            let body = capabilities(config.clone())
+                .await
+                .unwrap()
+                .into_response()
+                .into_body()
+                .try_fold(Vec::new(), |mut data, chunk| async move {
+                    data.extend_from_slice(&chunk);
+                    Ok(data)
+                })
+                .await
+                .unwrap();
+
+            let response =
+                serde_json::from_slice::<CapabilitiesResponse<'_>>(body.as_slice()).unwrap();
+
+            assert_eq!(response.protocol_version, SHELL_PROTOCOL_VERSION);
+            assert_eq!(response.capabilities, config.capabilities().bits());
+            assert_eq!(
+                response.features,
+                vec!["relay", "direct_connect", "validate_session"]
+            );
+            assert_eq!(response.relay_host, "relay.tunshell.com");
+        });
+    }
+}
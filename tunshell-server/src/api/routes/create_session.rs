@@ -1,17 +1,115 @@
+use crate::api::Config;
 use crate::db::{Participant, Session, SessionStore};
+use crate::metrics;
 use log::*;
 use serde::{Deserialize, Serialize};
-use warp::{http::Response, hyper::Body, Rejection, Reply};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use tunshell_shared::SessionLink;
+use warp::{http::Response, http::StatusCode, hyper::Body, Rejection, Reply};
 
+/// Everything a client needs to connect to a newly created session
 #[derive(Serialize, Deserialize, Debug)]
-struct ResponsePayload<'a> {
+pub(crate) struct CreateSessionResponse<'a> {
+    session_id: &'a str,
+    /// The key used by the host (target) of the session
+    host_key: &'a str,
+    /// The key used by the client connecting to the host
+    client_key: &'a str,
+    /// Allows the client to reattach to the shell, this is not a participant key
+    reconnect_token: &'a str,
+    /// RFC 3339 timestamp after which the session can no longer be joined
+    expires_at: String,
+    relay_host: &'a str,
+    /// A shareable `tunshell://` link for the client to connect with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    link: Option<String>,
+    // Retained for existing clients, equal to host_key and client_key
     peer1_key: &'a str,
     peer2_key: &'a str,
 }
 
-pub(crate) async fn create_session(mut store: SessionStore) -> Result<Box<dyn Reply>, Rejection> {
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct ValidationPayload {
+    valid: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<String>,
+}
+
+/// Parsed query parameters for session creation
+struct CreateSessionRequest {
+    /// When set the request is only validated and no session is created
+    validate_only: bool,
+    /// When set the session can only be joined from this address
+    allowed_peer: Option<IpAddr>,
+}
+
+fn parse_request(query: &HashMap<String, String>) -> Result<CreateSessionRequest, Vec<String>> {
+    let mut errors = vec![];
+
+    let validate_only = match query.get("validate").map(|i| i.as_str()) {
+        None | Some("false") => false,
+        Some("true") => true,
+        Some(value) => {
+            errors.push(format!("invalid value for validate: {}", value));
+            false
+        }
+    };
+
+    let allowed_peer = match query.get("allowed_peer") {
+        None => None,
+        Some(value) => match value.parse::<IpAddr>() {
+            Ok(ip) => Some(ip),
+            Err(_) => {
+                errors.push(format!("invalid value for allowed_peer: {}", value));
+                None
+            }
+        },
+    };
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(CreateSessionRequest {
+        validate_only,
+        allowed_peer,
+    })
+}
+
+pub(crate) async fn create_session(
+    mut store: SessionStore,
+    config: Config,
+    query: HashMap<String, String>,
+) -> Result<Box<dyn Reply>, Rejection> {
+    let request = match parse_request(&query) {
+        Ok(request) => request,
+        Err(errors) => {
+            debug!("invalid session request: {:?}", errors);
+
+            return Ok(Box::new(warp::reply::with_status(
+                warp::reply::json(&ValidationPayload {
+                    valid: false,
+                    errors,
+                }),
+                StatusCode::BAD_REQUEST,
+            )));
+        }
+    };
+
+    if request.validate_only {
+        return Ok(Box::new(warp::reply::json(&ValidationPayload {
+            valid: true,
+            errors: vec![],
+        })));
+    }
+
     debug!("creating new session");
-    let session = Session::new(Participant::default(), Participant::default());
+    let mut session = Session::new(
+        Participant::new(config.key_gen.generate()),
+        Participant::new(config.key_gen.generate()),
+    );
+    session.allowed_peer = request.allowed_peer;
 
     let result = store.save(&session).await;
 
@@ -26,43 +124,186 @@ pub(crate) async fn create_session(mut store: SessionStore) -> Result<Box<dyn Re
         ));
     }
 
-    Ok(Box::new(warp::reply::json(&ResponsePayload {
+    metrics::SESSIONS_CREATED.inc();
+
+    Ok(Box::new(warp::reply::json(&session_response(
+        &session, &config,
+    ))))
+}
+
+/// Builds the response describing how to connect to the session
+pub(super) fn session_response<'a>(
+    session: &'a Session,
+    config: &'a Config,
+) -> CreateSessionResponse<'a> {
+    let link = match SessionLink::new(session.id(), &config.relay_host, &session.peer2.key) {
+        Ok(link) => Some(link.to_url()),
+        Err(err) => {
+            warn!("could not create session link: {}", err);
+            None
+        }
+    };
+
+    CreateSessionResponse {
+        session_id: session.id(),
+        host_key: &session.peer1.key,
+        client_key: &session.peer2.key,
+        reconnect_token: &session.reconnect_token,
+        expires_at: session.expires_at().to_rfc3339(),
+        relay_host: &config.relay_host,
+        link,
         peer1_key: &session.peer1.key,
         peer2_key: &session.peer2.key,
-    })))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::api::routes::reply::read_body;
     use crate::db;
-    use futures::TryStreamExt;
+    use crate::db::testing::count_sessions;
     use serde_json;
     use tokio::runtime::Runtime;
 
+    fn query(params: &[(&str, &str)]) -> HashMap<String, String> {
+        params
+            .iter()
+            .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+            .collect()
+    }
+
     #[test]
     fn test_create_session() {
         Runtime::new().unwrap().block_on(async {
             let store = SessionStore::new(db::connect().await.unwrap());
 
-            let session = create_session(store).await.unwrap();
-
-            let body = session
-                .into_response()
-                .into_body()
-                .try_fold(Vec::new(), |mut data, chunk| async move {
-                    data.extend_from_slice(&chunk);
-                    Ok(data)
-                })
+            let session = create_session(store, Config::default(), HashMap::new())
                 .await
                 .unwrap();
 
-            let response = serde_json::from_slice::<ResponsePayload<'_>>(body.as_slice()).unwrap();
+            let (_, body) = read_body(session).await;
+
+            let response =
+                serde_json::from_slice::<CreateSessionResponse<'_>>(body.as_slice()).unwrap();
+
+            assert_ne!(response.session_id, "");
+            assert_eq!(response.host_key.len(), 22);
+            assert_eq!(response.client_key.len(), 22);
+            assert_ne!(response.host_key, response.client_key);
+            assert_eq!(response.peer1_key, response.host_key);
+            assert_eq!(response.peer2_key, response.client_key);
+            assert_eq!(response.reconnect_token.len(), 22);
+            assert_ne!(response.reconnect_token, response.host_key);
+            assert_ne!(response.reconnect_token, response.client_key);
+            assert_eq!(response.relay_host, "relay.tunshell.com");
 
-            assert_ne!(response.peer1_key, "");
-            assert_ne!(response.peer2_key, "");
+            let link = SessionLink::from_url(response.link.as_ref().unwrap()).unwrap();
+            assert_eq!(link.session_id, response.session_id);
+            assert_eq!(link.relay_host, response.relay_host);
+            assert_eq!(link.client_key, response.client_key);
+            assert!(
+                chrono::DateTime::parse_from_rfc3339(&response.expires_at).unwrap()
+                    > chrono::Utc::now()
+            );
 
             debug!("response: {:?}", response);
         });
     }
+
+    #[test]
+    fn test_validate_only() {
+        Runtime::new().unwrap().block_on(async {
+            let store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            let reply = create_session(
+                store.clone(),
+                Config::default(),
+                query(&[("validate", "true")]),
+            )
+            .await
+            .unwrap();
+            let (status, body) = read_body(reply).await;
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(
+                serde_json::from_slice::<ValidationPayload>(body.as_slice()).unwrap(),
+                ValidationPayload {
+                    valid: true,
+                    errors: vec![]
+                }
+            );
+            assert_eq!(count_sessions(&store).unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn test_validate_only_invalid() {
+        Runtime::new().unwrap().block_on(async {
+            let store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            let reply = create_session(
+                store.clone(),
+                Config::default(),
+                query(&[("validate", "maybe")]),
+            )
+            .await
+            .unwrap();
+            let (status, body) = read_body(reply).await;
+
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(
+                serde_json::from_slice::<ValidationPayload>(body.as_slice()).unwrap(),
+                ValidationPayload {
+                    valid: false,
+                    errors: vec!["invalid value for validate: maybe".to_owned()]
+                }
+            );
+            assert_eq!(count_sessions(&store).unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn test_create_session_with_allowed_peer() {
+        Runtime::new().unwrap().block_on(async {
+            let mut store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            let reply = create_session(
+                store.clone(),
+                Config::default(),
+                query(&[("allowed_peer", "10.1.2.3")]),
+            )
+            .await
+            .unwrap();
+            let (status, body) = read_body(reply).await;
+
+            assert_eq!(status, StatusCode::OK);
+
+            let response =
+                serde_json::from_slice::<CreateSessionResponse<'_>>(body.as_slice()).unwrap();
+            let session = store.find_by_key(response.host_key).await.unwrap().unwrap();
+
+            assert_eq!(session.allowed_peer, Some("10.1.2.3".parse().unwrap()));
+            assert_eq!(session.reconnect_token, response.reconnect_token);
+        });
+    }
+
+    #[test]
+    fn test_create_session_with_invalid_allowed_peer() {
+        Runtime::new().unwrap().block_on(async {
+            let store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            let reply = create_session(
+                store.clone(),
+                Config::default(),
+                query(&[("allowed_peer", "not-an-ip")]),
+            )
+            .await
+            .unwrap();
+            let (status, _) = read_body(reply).await;
+
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(count_sessions(&store).unwrap(), 0);
+        });
+    }
 }
@@ -0,0 +1,154 @@
+use super::reply::*;
+use super::session_response;
+use crate::api::Config;
+use crate::db::{SessionOptions, SessionStore};
+use crate::metrics;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use warp::{http::Response, http::StatusCode, hyper::Body, Rejection, Reply};
+
+/// The most sessions which can be created in a single request
+const MAX_BATCH_SIZE: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct CreateSessionBatchRequest {
+    /// The number of sessions to create
+    count: usize,
+    /// When set each session can only be joined from this address
+    #[serde(default)]
+    allowed_peer: Option<IpAddr>,
+}
+
+pub(crate) async fn create_session_batch(
+    mut store: SessionStore,
+    config: Config,
+    request: CreateSessionBatchRequest,
+) -> Result<Box<dyn Reply>, Rejection> {
+    if request.count == 0 || request.count > MAX_BATCH_SIZE {
+        return Ok(error_reply(
+            StatusCode::BAD_REQUEST,
+            format!("count must be between 1 and {}", MAX_BATCH_SIZE),
+        ));
+    }
+
+    debug!("creating batch of {} sessions", request.count);
+    let options = SessionOptions {
+        key_gen: config.key_gen.clone(),
+        allowed_peer: request.allowed_peer,
+    };
+
+    let sessions = match store.create_batch(request.count, &options).await {
+        Ok(sessions) => sessions,
+        Err(err) => {
+            error!("error while saving sessions: {}", err);
+
+            return Ok(Box::new(
+                Response::builder()
+                    .status(500)
+                    .body(Body::from("error occurred while saving sessions"))
+                    .unwrap(),
+            ));
+        }
+    };
+
+    metrics::SESSIONS_CREATED.inc_by(sessions.len() as i64);
+
+    let responses = sessions
+        .iter()
+        .map(|session| session_response(session, &config))
+        .collect::<Vec<_>>();
+
+    Ok(Box::new(warp::reply::json(&responses)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use crate::db::testing::count_sessions;
+    use serde_json;
+    use std::collections::HashSet;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn test_create_session_batch() {
+        Runtime::new().unwrap().block_on(async {
+            let mut store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            let reply = create_session_batch(
+                store.clone(),
+                Config::default(),
+                CreateSessionBatchRequest {
+                    count: 10,
+                    allowed_peer: None,
+                },
+            )
+            .await
+            .unwrap();
+            let (status, body) = read_body(reply).await;
+
+            assert_eq!(status, StatusCode::OK);
+
+            let responses = serde_json::from_slice::<Vec<serde_json::Value>>(body.as_slice())
+                .unwrap()
+                .into_iter()
+                .map(|i| {
+                    let field = |name: &str| i[name].as_str().unwrap().to_owned();
+
+                    (field("session_id"), field("host_key"), field("client_key"))
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(responses.len(), 10);
+            assert_eq!(
+                responses
+                    .iter()
+                    .map(|(id, _, _)| id)
+                    .collect::<HashSet<_>>()
+                    .len(),
+                10
+            );
+            assert_eq!(
+                responses
+                    .iter()
+                    .flat_map(|(_, host_key, client_key)| vec![host_key, client_key])
+                    .collect::<HashSet<_>>()
+                    .len(),
+                20
+            );
+            assert_eq!(count_sessions(&store).unwrap(), 10);
+
+            for (id, host_key, client_key) in responses.iter() {
+                let session = store.find_by_key(host_key).await.unwrap().unwrap();
+                assert_eq!(session.id(), id);
+                assert_eq!(&session.peer2.key, client_key);
+            }
+        });
+    }
+
+    #[test]
+    fn test_create_session_batch_invalid_count() {
+        Runtime::new().unwrap().block_on(async {
+            let store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            for count in [0, MAX_BATCH_SIZE + 1].iter() {
+                let reply = create_session_batch(
+                    store.clone(),
+                    Config::default(),
+                    CreateSessionBatchRequest {
+                        count: *count,
+                        allowed_peer: None,
+                    },
+                )
+                .await
+                .unwrap();
+                let (status, _) = read_body(reply).await;
+
+                assert_eq!(status, StatusCode::BAD_REQUEST);
+            }
+
+            assert_eq!(count_sessions(&store).unwrap(), 0);
+        });
+    }
+}
@@ -0,0 +1,280 @@
+use super::reply::*;
+use crate::db::{ExtendTtl, SessionStore, MAX_SESSION_TTL_HOURS};
+use log::*;
+use serde::{Deserialize, Serialize};
+use warp::{http::Response, http::StatusCode, hyper::Body, Rejection, Reply};
+
+/// The longest a session's TTL can be extended by in a single request
+const MAX_EXTENSION_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct ExtendSessionRequest {
+    /// One of the session's participant keys, only participants can extend the session
+    key: String,
+    /// The number of seconds to add to the session's remaining TTL
+    seconds: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct ExtendSessionResponse<'a> {
+    session_id: &'a str,
+    /// RFC 3339 timestamp after which the session can no longer be joined
+    expires_at: String,
+}
+
+pub(crate) async fn extend_session(
+    mut store: SessionStore,
+    id: String,
+    request: ExtendSessionRequest,
+) -> Result<Box<dyn Reply>, Rejection> {
+    if request.seconds == 0 || request.seconds > MAX_EXTENSION_SECS {
+        return Ok(error_reply(
+            StatusCode::BAD_REQUEST,
+            format!("seconds must be between 1 and {}", MAX_EXTENSION_SECS),
+        ));
+    }
+
+    debug!("extending session {} by {}s", id, request.seconds);
+    let extra = chrono::Duration::seconds(request.seconds as i64);
+
+    let expires_at = match store.extend_ttl(&id, &request.key, extra).await {
+        Ok(ExtendTtl::Extended(expires_at)) => expires_at,
+        Ok(ExtendTtl::NotFound) => {
+            return Ok(error_reply(
+                StatusCode::NOT_FOUND,
+                "session not found".to_owned(),
+            ))
+        }
+        Ok(ExtendTtl::Unauthorized) => {
+            return Ok(error_reply(
+                StatusCode::FORBIDDEN,
+                "key is not a participant of the session".to_owned(),
+            ))
+        }
+        Ok(ExtendTtl::Expired) => {
+            return Ok(error_reply(
+                StatusCode::GONE,
+                "session has expired".to_owned(),
+            ))
+        }
+        Ok(ExtendTtl::LimitExceeded(max_expires_at)) => {
+            return Ok(error_reply(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "sessions cannot be extended beyond {} hours, this session must expire by {}",
+                    MAX_SESSION_TTL_HOURS,
+                    max_expires_at.to_rfc3339()
+                ),
+            ))
+        }
+        Err(err) => {
+            error!("error while extending session: {}", err);
+
+            return Ok(Box::new(
+                Response::builder()
+                    .status(500)
+                    .body(Body::from("error occurred while extending session"))
+                    .unwrap(),
+            ));
+        }
+    };
+
+    Ok(Box::new(warp::reply::json(&ExtendSessionResponse {
+        session_id: &id,
+        expires_at: expires_at.to_rfc3339(),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::{find_session_by_id, participant};
+    use crate::db::{self, Session};
+    use chrono::{DateTime, Utc};
+    use serde_json;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn test_extend_session() {
+        Runtime::new().unwrap().block_on(async {
+            let mut store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            let session = Session::new(participant(), participant());
+            store.save(&session).await.unwrap();
+
+            let reply = extend_session(
+                store.clone(),
+                session.id().to_owned(),
+                ExtendSessionRequest {
+                    key: session.peer1.key.clone(),
+                    seconds: 3600,
+                },
+            )
+            .await
+            .unwrap();
+            let (status, body) = read_body(reply).await;
+
+            assert_eq!(status, StatusCode::OK);
+
+            let response =
+                serde_json::from_slice::<ExtendSessionResponse<'_>>(body.as_slice()).unwrap();
+            let expires_at = DateTime::parse_from_rfc3339(&response.expires_at)
+                .unwrap()
+                .with_timezone(&Utc);
+
+            assert_eq!(response.session_id, session.id());
+            assert_eq!(
+                expires_at.timestamp(),
+                (session.expires_at() + chrono::Duration::hours(1)).timestamp()
+            );
+
+            let stored = find_session_by_id(&store, session.id()).unwrap().unwrap();
+            assert_eq!(stored.expires_at().timestamp(), expires_at.timestamp());
+        });
+    }
+
+    #[test]
+    fn test_extend_expired_session() {
+        Runtime::new().unwrap().block_on(async {
+            let mut store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            let mut session = Session::new(participant(), participant());
+            session.created_at = Utc::now() - chrono::Duration::days(2);
+            store.save(&session).await.unwrap();
+
+            let reply = extend_session(
+                store.clone(),
+                session.id().to_owned(),
+                ExtendSessionRequest {
+                    key: session.peer1.key.clone(),
+                    seconds: 3600,
+                },
+            )
+            .await
+            .unwrap();
+            let (status, body) = read_body(reply).await;
+
+            assert_eq!(status, StatusCode::GONE);
+            assert_eq!(
+                serde_json::from_slice::<ErrorPayload>(body.as_slice()).unwrap(),
+                ErrorPayload {
+                    error: "session has expired".to_owned()
+                }
+            );
+
+            let stored = find_session_by_id(&store, session.id()).unwrap().unwrap();
+            assert!(stored.is_expired());
+        });
+    }
+
+    #[test]
+    fn test_extend_session_with_invalid_key() {
+        Runtime::new().unwrap().block_on(async {
+            let mut store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            let session = Session::new(participant(), participant());
+            store.save(&session).await.unwrap();
+
+            let reply = extend_session(
+                store.clone(),
+                session.id().to_owned(),
+                ExtendSessionRequest {
+                    key: session.reconnect_token.clone(),
+                    seconds: 3600,
+                },
+            )
+            .await
+            .unwrap();
+            let (status, _) = read_body(reply).await;
+
+            assert_eq!(status, StatusCode::FORBIDDEN);
+
+            let stored = find_session_by_id(&store, session.id()).unwrap().unwrap();
+            assert_eq!(stored, session);
+        });
+    }
+
+    #[test]
+    fn test_extend_session_beyond_limit() {
+        Runtime::new().unwrap().block_on(async {
+            let mut store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            let session = Session::new(participant(), participant());
+            store.save(&session).await.unwrap();
+
+            let remaining = chrono::Duration::hours(MAX_SESSION_TTL_HOURS)
+                - (session.expires_at() - session.created_at);
+            let extensions = remaining.num_seconds() as u64 / MAX_EXTENSION_SECS;
+
+            for i in 0..=extensions {
+                let reply = extend_session(
+                    store.clone(),
+                    session.id().to_owned(),
+                    ExtendSessionRequest {
+                        key: session.peer1.key.clone(),
+                        seconds: MAX_EXTENSION_SECS,
+                    },
+                )
+                .await
+                .unwrap();
+                let (status, _) = read_body(reply).await;
+
+                if i < extensions {
+                    assert_eq!(status, StatusCode::OK);
+                } else {
+                    assert_eq!(status, StatusCode::BAD_REQUEST);
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_extend_unknown_session() {
+        Runtime::new().unwrap().block_on(async {
+            let store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            let reply = extend_session(
+                store,
+                "unknown".to_owned(),
+                ExtendSessionRequest {
+                    key: "unknown".to_owned(),
+                    seconds: 3600,
+                },
+            )
+            .await
+            .unwrap();
+            let (status, _) = read_body(reply).await;
+
+            assert_eq!(status, StatusCode::NOT_FOUND);
+        });
+    }
+
+    #[test]
+    fn test_extend_session_invalid_duration() {
+        Runtime::new().unwrap().block_on(async {
+            let mut store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            let session = Session::new(participant(), participant());
+            store.save(&session).await.unwrap();
+
+            for seconds in [0, MAX_EXTENSION_SECS + 1].iter() {
+                let reply = extend_session(
+                    store.clone(),
+                    session.id().to_owned(),
+                    ExtendSessionRequest {
+                        key: session.peer1.key.clone(),
+                        seconds: *seconds,
+                    },
+                )
+                .await
+                .unwrap();
+                let (status, _) = read_body(reply).await;
+
+                assert_eq!(status, StatusCode::BAD_REQUEST);
+            }
+
+            let stored = find_session_by_id(&store, session.id()).unwrap().unwrap();
+            assert_eq!(stored, session);
+        });
+    }
+}
@@ -0,0 +1,75 @@
+use crate::metrics;
+use log::*;
+use warp::{http::Response, hyper::Body, Rejection, Reply};
+
+pub(crate) async fn metrics() -> Result<Box<dyn Reply>, Rejection> {
+    let encoded = match metrics::encode() {
+        Ok(encoded) => encoded,
+        Err(err) => {
+            error!("error while encoding metrics: {}", err);
+
+            return Ok(Box::new(
+                Response::builder()
+                    .status(500)
+                    .body(Body::from("error occurred while encoding metrics"))
+                    .unwrap(),
+            ));
+        }
+    };
+
+    Ok(Box::new(
+        Response::builder()
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(Body::from(encoded))
+            .unwrap(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::routes::create_session;
+    use crate::db::{self, SessionStore};
+    use futures::TryStreamExt;
+    use tokio::runtime::Runtime;
+
+    fn parse_counter(body: &str, name: &str) -> i64 {
+        body.lines()
+            .find(|i| i.starts_with(&format!("{} ", name)))
+            .and_then(|i| i.split(' ').nth(1))
+            .and_then(|i| i.parse::<i64>().ok())
+            .unwrap_or(0)
+    }
+
+    async fn scrape() -> String {
+        let body = metrics()
+            .await
+            .unwrap()
+            .into_response()
+            .into_body()
+            .try_fold(Vec::new(), |mut data, chunk| async move {
+                data.extend_from_slice(&chunk);
+                Ok(data)
+            })
+            .await
+            .unwrap();
+
+        String::from_utf8(body).unwrap()
+    }
+
+    #[test]
+    fn test_scrape_metrics_after_creating_session() {
+        Runtime::new().unwrap().block_on(async {
+            let before = parse_counter(&scrape().await, "tunshell_sessions_created_total");
+
+            let store = SessionStore::new(db::connect().await.unwrap());
+            create_session(store, Default::default(), Default::default())
+                .await
+                .unwrap();
+
+            let after = parse_counter(&scrape().await, "tunshell_sessions_created_total");
+
+            assert!(after > before);
+        });
+    }
+}
@@ -1,3 +1,12 @@
+mod capabilities;
 mod create_session;
+mod create_session_batch;
+mod extend_session;
+mod metrics;
+mod reply;
 
+pub(crate) use capabilities::*;
 pub(crate) use create_session::*;
+pub(crate) use create_session_batch::*;
+pub(crate) use extend_session::*;
+pub(crate) use metrics::*;
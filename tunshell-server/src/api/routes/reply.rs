@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+use warp::{http::StatusCode, Reply};
+
+/// The JSON body returned when a request is rejected
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub(super) struct ErrorPayload {
+    pub(super) error: String,
+}
+
+pub(super) fn error_reply(status: StatusCode, error: String) -> Box<dyn Reply> {
+    Box::new(warp::reply::with_status(
+        warp::reply::json(&ErrorPayload { error }),
+        status,
+    ))
+}
+
+/// Collects the status and body of a reply so route handlers can be tested directly
+#[cfg(test)]
+pub(super) async fn read_body(reply: Box<dyn Reply>) -> (StatusCode, Vec<u8>) {
+    use futures::TryStreamExt;
+
+    let response = reply.into_response();
+    let status = response.status();
+
+    let body = response
+        .into_body()
+        .try_fold(Vec::new(), |mut data, chunk| async move {
+            data.extend_from_slice(&chunk);
+            Ok(data)
+        })
+        .await
+        .unwrap();
+
+    (status, body)
+}
@@ -28,6 +28,16 @@ fn connect_sync() -> Result<Connection> {
     Ok(con)
 }
 
+/// Connects to a private in-memory database so tests do not share state
+#[cfg(test)]
+pub(crate) fn connect_in_memory() -> Result<Connection> {
+    let mut con = Connection::open_in_memory()?;
+
+    schema::init(&mut con).context("error while initialising sqlite schema")?;
+
+    Ok(con)
+}
+
 #[cfg(all(test, integration))]
 mod tests {
     use super::*;
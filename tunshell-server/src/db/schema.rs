@@ -11,12 +11,40 @@ pub(crate) fn init(con: &mut Connection) -> Result<()> {
             id TEXT PRIMARY KEY,
             peer1_key TEXT NOT NULL,
             peer2_key TEXT NOT NULL,
-            created_at TEXT NOT NULL
+            created_at TEXT NOT NULL,
+            allowed_peer TEXT NULL,
+            reconnect_token TEXT NULL,
+            expires_at TEXT NULL
         )
         ",
         params![],
     )?;
 
+    // Databases created before allowed_peer was introduced need the column added
+    if con.prepare("SELECT allowed_peer FROM sessions").is_err() {
+        info!("adding allowed_peer column to sessions");
+        con.execute(
+            "ALTER TABLE sessions ADD COLUMN allowed_peer TEXT NULL",
+            params![],
+        )?;
+    }
+
+    if con.prepare("SELECT reconnect_token FROM sessions").is_err() {
+        info!("adding reconnect_token column to sessions");
+        con.execute(
+            "ALTER TABLE sessions ADD COLUMN reconnect_token TEXT NULL",
+            params![],
+        )?;
+    }
+
+    if con.prepare("SELECT expires_at FROM sessions").is_err() {
+        info!("adding expires_at column to sessions");
+        con.execute(
+            "ALTER TABLE sessions ADD COLUMN expires_at TEXT NULL",
+            params![],
+        )?;
+    }
+
     con.execute(
         "
         CREATE UNIQUE INDEX IF NOT EXISTS idx_sessions_peer1_key ON
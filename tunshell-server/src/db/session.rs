@@ -1,11 +1,17 @@
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
-use rand::distributions::Alphanumeric;
-use rand::{thread_rng, Rng};
-use rusqlite::{named_params, params, Connection};
+use ring::constant_time::verify_slices_are_equal;
+use rusqlite::{named_params, params, Connection, Row};
+use std::net::IpAddr;
 use std::sync::{Arc, Mutex};
+use tunshell_shared::KeyGenConfig;
 use uuid::Uuid;
 
+/// The period after creation in which a session can be joined
+const SESSION_TTL_HOURS: i64 = 24;
+/// The longest a session can be kept alive for after creation by extending its TTL
+pub(crate) const MAX_SESSION_TTL_HOURS: i64 = 7 * 24;
+
 #[derive(Clone, PartialEq, Debug)]
 pub(crate) struct Participant {
     pub(crate) key: String,
@@ -17,6 +23,36 @@ pub(crate) struct Session {
     pub(crate) peer1: Participant,
     pub(crate) peer2: Participant,
     pub(crate) created_at: DateTime<Utc>,
+    /// When set the session can only be joined from this address
+    pub(crate) allowed_peer: Option<IpAddr>,
+    /// Allows a client to reattach to its shell, separate from the participant keys
+    /// so it can be revoked independently. Empty when reconnecting is not permitted.
+    pub(crate) reconnect_token: String,
+    /// Set once the session's TTL has been extended, otherwise the session
+    /// expires `SESSION_TTL_HOURS` after it was created
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// The outcome of extending a session's TTL
+#[derive(Debug, PartialEq)]
+pub(crate) enum ExtendTtl {
+    Extended(DateTime<Utc>),
+    NotFound,
+    /// The supplied key is not one of the session's participant keys
+    Unauthorized,
+    /// Expired sessions cannot be revived
+    Expired,
+    /// The session would outlive `MAX_SESSION_TTL_HOURS`, which is when it must expire by
+    LimitExceeded(DateTime<Utc>),
+}
+
+/// The settings shared by each session created in a batch
+#[derive(Clone, Debug)]
+pub(crate) struct SessionOptions {
+    /// Generates the participant keys of each session
+    pub(crate) key_gen: KeyGenConfig,
+    /// When set the sessions can only be joined from this address
+    pub(crate) allowed_peer: Option<IpAddr>,
 }
 
 #[derive(Clone)]
@@ -28,10 +64,6 @@ impl Participant {
     pub(crate) fn new(key: String) -> Self {
         Self { key }
     }
-
-    pub(crate) fn default() -> Self {
-        Self::new(generate_secure_key())
-    }
 }
 
 impl Session {
@@ -41,15 +73,39 @@ impl Session {
             peer1,
             peer2,
             created_at: Utc::now(),
+            allowed_peer: None,
+            reconnect_token: generate_secure_key(),
+            expires_at: None,
         }
     }
 
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub(crate) fn expires_at(&self) -> DateTime<Utc> {
+        self.expires_at
+            .unwrap_or_else(|| self.created_at + chrono::Duration::hours(SESSION_TTL_HOURS))
+    }
+
+    pub(crate) fn is_expired(&self) -> bool {
+        Utc::now() > self.expires_at()
+    }
+
+    /// Checks the supplied key against both participant keys in constant time
+    pub(crate) fn verify_participant_key(&self, key: &str) -> bool {
+        let peer1 = verify_slices_are_equal(self.peer1.key.as_bytes(), key.as_bytes());
+        let peer2 = verify_slices_are_equal(self.peer2.key.as_bytes(), key.as_bytes());
+
+        peer1.is_ok() | peer2.is_ok()
+    }
+
     pub(crate) fn participant(&self, key: &str) -> Option<&Participant> {
-        if self.peer1.key == key {
+        if verify_slices_are_equal(self.peer1.key.as_bytes(), key.as_bytes()).is_ok() {
             return Some(&self.peer1);
         }
 
-        if self.peer2.key == key {
+        if verify_slices_are_equal(self.peer2.key.as_bytes(), key.as_bytes()).is_ok() {
             return Some(&self.peer2);
         }
 
@@ -69,6 +125,12 @@ impl Session {
         None
     }
 
+    /// Prevents reattaching to the session without affecting the participant keys
+    #[allow(dead_code)]
+    pub(crate) fn revoke_reconnect_token(&mut self) {
+        self.reconnect_token.clear();
+    }
+
     pub(crate) fn other_participant(&self, key: &str) -> Option<&Participant> {
         if self.peer1.key == key {
             return Some(&self.peer2);
@@ -105,27 +167,57 @@ impl SessionStore {
     fn find_by_key_sync(con: &Connection, key: &str) -> Result<Option<Session>> {
         let mut statement = con.prepare(
             "
-            SELECT id, peer1_key, peer2_key, created_at FROM sessions
+            SELECT id, peer1_key, peer2_key, created_at, allowed_peer, reconnect_token, expires_at
+            FROM sessions
             WHERE peer1_key = :key OR peer2_key = :key
         ",
         )?;
 
         let mut result = statement.query_named(named_params! {":key": key})?;
 
-        let row = match result.next()? {
-            Some(row) => row,
-            None => return Ok(None),
+        match result.next()? {
+            Some(row) => Ok(Some(Self::parse_row(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn find_by_id_sync(con: &Connection, id: &str) -> Result<Option<Session>> {
+        let mut statement = con.prepare(
+            "
+            SELECT id, peer1_key, peer2_key, created_at, allowed_peer, reconnect_token, expires_at
+            FROM sessions
+            WHERE id = :id
+        ",
+        )?;
+
+        let mut result = statement.query_named(named_params! {":id": id})?;
+
+        match result.next()? {
+            Some(row) => Ok(Some(Self::parse_row(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn parse_row(row: &Row) -> Result<Session> {
+        let parse_date = |value: String| -> Result<DateTime<Utc>> {
+            Ok(DateTime::parse_from_rfc3339(value.as_str())?.with_timezone(&Utc))
         };
 
-        let session = Session {
+        Ok(Session {
             id: row.get(0)?,
             peer1: Participant { key: row.get(1)? },
             peer2: Participant { key: row.get(2)? },
-            created_at: DateTime::parse_from_rfc3339(row.get::<usize, String>(3)?.as_str())?
-                .with_timezone(&Utc),
-        };
-
-        Ok(Some(session))
+            created_at: parse_date(row.get(3)?)?,
+            allowed_peer: row
+                .get::<usize, Option<String>>(4)?
+                .map(|i| i.parse::<IpAddr>())
+                .transpose()?,
+            reconnect_token: row.get::<usize, Option<String>>(5)?.unwrap_or_default(),
+            expires_at: row
+                .get::<usize, Option<String>>(6)?
+                .map(parse_date)
+                .transpose()?,
+        })
     }
 
     pub(crate) async fn save(&mut self, session: &Session) -> Result<()> {
@@ -143,17 +235,101 @@ impl SessionStore {
         Ok(())
     }
 
+    /// Creates the supplied number of sessions, which are inserted in a single
+    /// transaction so either all or none of them are saved
+    pub(crate) async fn create_batch(
+        &mut self,
+        count: usize,
+        options: &SessionOptions,
+    ) -> Result<Vec<Session>> {
+        let con = Arc::clone(&self.con);
+        let sessions = (0..count)
+            .map(|_| {
+                let mut session = Session::new(
+                    Participant::new(options.key_gen.generate()),
+                    Participant::new(options.key_gen.generate()),
+                );
+                session.allowed_peer = options.allowed_peer;
+                session
+            })
+            .collect::<Vec<_>>();
+
+        tokio::task::spawn_blocking(move || {
+            let mut con = con.lock().unwrap();
+            let transaction = con.transaction()?;
+
+            for session in sessions.iter() {
+                Self::save_sync(&transaction, session)?;
+            }
+
+            transaction.commit()?;
+
+            Ok(sessions)
+        })
+        .await
+        .context("error while creating sessions")?
+    }
+
+    /// Pushes back the expiry of the session by the supplied duration, returning the
+    /// time at which it now expires. Only the session's participants can extend it
+    /// and it cannot be extended beyond `MAX_SESSION_TTL_HOURS` after creation.
+    pub(crate) async fn extend_ttl(
+        &mut self,
+        id: &str,
+        key: &str,
+        extra: chrono::Duration,
+    ) -> Result<ExtendTtl> {
+        let con = Arc::clone(&self.con);
+        let id = id.to_owned();
+        let key = key.to_owned();
+
+        tokio::task::spawn_blocking(move || {
+            let con = con.lock().unwrap();
+
+            // The lock is held throughout so concurrent extensions are not lost
+            let mut session = match Self::find_by_id_sync(&con, id.as_str())? {
+                Some(session) => session,
+                None => return Ok(ExtendTtl::NotFound),
+            };
+
+            if !session.verify_participant_key(&key) {
+                return Ok(ExtendTtl::Unauthorized);
+            }
+
+            if session.is_expired() {
+                return Ok(ExtendTtl::Expired);
+            }
+
+            let expires_at = session.expires_at() + extra;
+            let max_expires_at =
+                session.created_at + chrono::Duration::hours(MAX_SESSION_TTL_HOURS);
+            if expires_at > max_expires_at {
+                return Ok(ExtendTtl::LimitExceeded(max_expires_at));
+            }
+
+            session.expires_at = Some(expires_at);
+            Self::save_sync(&con, &session)?;
+
+            Ok(ExtendTtl::Extended(expires_at))
+        })
+        .await
+        .context("error while extending session ttl")?
+    }
+
     fn save_sync(con: &Connection, session: &Session) -> Result<()> {
         con.execute(
             "
-                INSERT OR REPLACE INTO sessions (id, peer1_key, peer2_key, created_at)
-                VALUES (?1, ?2, ?3, ?4)
+                INSERT OR REPLACE INTO sessions (id, peer1_key, peer2_key, created_at, allowed_peer, reconnect_token, expires_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
             ",
             params![
                 session.id,
                 session.peer1.key,
                 session.peer2.key,
-                session.created_at.to_rfc3339()
+                session.created_at.to_rfc3339(),
+                session.allowed_peer.map(|i| i.to_string()),
+                session.reconnect_token,
+                session.expires_at.map(|i| i.to_rfc3339())
             ],
         )?;
 
@@ -163,11 +339,35 @@ impl SessionStore {
 
 // Generates ~131 bits of entropy (22 chars) using alphanumeric charset
 pub(crate) fn generate_secure_key() -> String {
-    thread_rng().sample_iter(&Alphanumeric).take(22).collect()
+    KeyGenConfig::default().generate()
+}
+
+/// Helpers for creating and inspecting stored sessions in tests
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+
+    /// A participant with a randomly generated key
+    pub(crate) fn participant() -> Participant {
+        Participant::new(generate_secure_key())
+    }
+
+    pub(crate) fn find_session_by_id(store: &SessionStore, id: &str) -> Result<Option<Session>> {
+        let con = store.con.lock().unwrap();
+
+        SessionStore::find_by_id_sync(&con, id)
+    }
+
+    pub(crate) fn count_sessions(store: &SessionStore) -> Result<u32> {
+        let con = store.con.lock().unwrap();
+
+        Ok(con.query_row("SELECT COUNT(*) FROM sessions", params![], |row| row.get(0))?)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::testing::*;
     use super::*;
     use crate::db;
     use tokio::runtime::Runtime;
@@ -193,7 +393,10 @@ mod tests {
                 id: "test_id".to_owned(),
                 peer1: Participant { key: "valid_peer1_key".to_owned() },
                 peer2: Participant { key: "valid_peer2_key".to_owned() },
-                created_at: DateTime::parse_from_rfc3339("2000-01-01T01:01:01.000Z").unwrap().with_timezone(&Utc)
+                created_at: DateTime::parse_from_rfc3339("2000-01-01T01:01:01.000Z").unwrap().with_timezone(&Utc),
+                allowed_peer: None,
+                reconnect_token: "".to_owned(),
+                expires_at: None
             };
 
             assert_eq!(store.find_by_key("valid_peer1_key").await.unwrap(), Some(session.clone()));
@@ -218,6 +421,9 @@ mod tests {
                 created_at: DateTime::parse_from_rfc3339("2000-01-01T01:01:01.000Z")
                     .unwrap()
                     .with_timezone(&Utc),
+                allowed_peer: None,
+                reconnect_token: "valid_reconnect_token".to_owned(),
+                expires_at: None,
             };
 
             store.save(&session).await.unwrap();
@@ -233,6 +439,7 @@ mod tests {
                     AND peer1_key = "valid_peer1_key"
                     AND peer2_key = "valid_peer2_key"
                     AND created_at = "2000-01-01T01:01:01+00:00"
+                    AND reconnect_token = "valid_reconnect_token"
                     "#,
                     params![],
                     |r| r.get(0),
@@ -244,6 +451,216 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_save_allowed_peer() {
+        Runtime::new().unwrap().block_on(async {
+            let mut store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            let mut session = Session::new(participant(), participant());
+            session.allowed_peer = Some("10.1.2.3".parse().unwrap());
+
+            store.save(&session).await.unwrap();
+
+            assert_eq!(
+                store.find_by_key(&session.peer1.key).await.unwrap(),
+                Some(session)
+            );
+        });
+    }
+
+    #[test]
+    fn test_create_batch() {
+        Runtime::new().unwrap().block_on(async {
+            let mut store = SessionStore::new(db::connect_in_memory().unwrap());
+            let options = SessionOptions {
+                key_gen: KeyGenConfig::default(),
+                allowed_peer: Some("10.1.2.3".parse().unwrap()),
+            };
+
+            let sessions = store.create_batch(5, &options).await.unwrap();
+
+            assert_eq!(sessions.len(), 5);
+            assert_eq!(count_sessions(&store).unwrap(), 5);
+
+            for session in sessions.iter() {
+                assert_eq!(
+                    find_session_by_id(&store, session.id()).unwrap().as_ref(),
+                    Some(session)
+                );
+                assert_eq!(session.allowed_peer, options.allowed_peer);
+            }
+        });
+    }
+
+    #[test]
+    fn test_find_by_id() {
+        Runtime::new().unwrap().block_on(async {
+            let mut store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            let session = Session::new(participant(), participant());
+            store.save(&session).await.unwrap();
+
+            assert_eq!(
+                find_session_by_id(&store, session.id()).unwrap(),
+                Some(session)
+            );
+            assert_eq!(find_session_by_id(&store, "invalid_id").unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn test_extend_ttl() {
+        Runtime::new().unwrap().block_on(async {
+            let mut store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            let session = Session::new(participant(), participant());
+            store.save(&session).await.unwrap();
+
+            let expected = session.expires_at() + chrono::Duration::hours(2);
+            let result = store
+                .extend_ttl(session.id(), &session.peer1.key, chrono::Duration::hours(2))
+                .await
+                .unwrap();
+
+            assert_eq!(result, ExtendTtl::Extended(expected));
+
+            let stored = find_session_by_id(&store, session.id()).unwrap().unwrap();
+            assert_eq!(stored.expires_at(), expected);
+            assert_eq!(stored.created_at, session.created_at);
+
+            // Extensions accumulate
+            let result = store
+                .extend_ttl(session.id(), &session.peer1.key, chrono::Duration::hours(1))
+                .await
+                .unwrap();
+
+            assert_eq!(
+                result,
+                ExtendTtl::Extended(expected + chrono::Duration::hours(1))
+            );
+        });
+    }
+
+    #[test]
+    fn test_extend_ttl_expired_session() {
+        Runtime::new().unwrap().block_on(async {
+            let mut store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            let mut session = Session::new(participant(), participant());
+            session.created_at = Utc::now() - chrono::Duration::hours(SESSION_TTL_HOURS + 1);
+            store.save(&session).await.unwrap();
+
+            let result = store
+                .extend_ttl(session.id(), &session.peer1.key, chrono::Duration::hours(2))
+                .await
+                .unwrap();
+
+            assert_eq!(result, ExtendTtl::Expired);
+            assert_eq!(
+                find_session_by_id(&store, session.id()).unwrap(),
+                Some(session.clone())
+            );
+
+            let result = store
+                .extend_ttl("invalid_id", &session.peer1.key, chrono::Duration::hours(2))
+                .await
+                .unwrap();
+
+            assert_eq!(result, ExtendTtl::NotFound);
+        });
+    }
+
+    #[test]
+    fn test_extend_ttl_requires_participant_key() {
+        Runtime::new().unwrap().block_on(async {
+            let mut store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            let session = Session::new(participant(), participant());
+            store.save(&session).await.unwrap();
+
+            let result = store
+                .extend_ttl(session.id(), "invalid_key", chrono::Duration::hours(2))
+                .await
+                .unwrap();
+
+            assert_eq!(result, ExtendTtl::Unauthorized);
+
+            let result = store
+                .extend_ttl(session.id(), &session.peer2.key, chrono::Duration::hours(2))
+                .await
+                .unwrap();
+
+            assert_eq!(
+                result,
+                ExtendTtl::Extended(session.expires_at() + chrono::Duration::hours(2))
+            );
+        });
+    }
+
+    #[test]
+    fn test_extend_ttl_limit() {
+        Runtime::new().unwrap().block_on(async {
+            let mut store = SessionStore::new(db::connect_in_memory().unwrap());
+
+            let session = Session::new(participant(), participant());
+            store.save(&session).await.unwrap();
+
+            let max_expires_at =
+                session.created_at + chrono::Duration::hours(MAX_SESSION_TTL_HOURS);
+            let result = store
+                .extend_ttl(
+                    session.id(),
+                    &session.peer1.key,
+                    max_expires_at - session.expires_at() + chrono::Duration::seconds(1),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(result, ExtendTtl::LimitExceeded(max_expires_at));
+            assert_eq!(
+                find_session_by_id(&store, session.id()).unwrap(),
+                Some(session.clone())
+            );
+
+            let result = store
+                .extend_ttl(
+                    session.id(),
+                    &session.peer1.key,
+                    max_expires_at - session.expires_at(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(result, ExtendTtl::Extended(max_expires_at));
+        });
+    }
+
+    #[test]
+    fn test_verify_participant_key() {
+        let session = Session::new(participant(), participant());
+
+        assert!(session.verify_participant_key(&session.peer1.key));
+        assert!(session.verify_participant_key(&session.peer2.key));
+        assert!(!session.verify_participant_key(&session.reconnect_token));
+        assert!(!session.verify_participant_key(""));
+    }
+
+    #[test]
+    fn test_revoke_reconnect_token() {
+        let mut session = Session::new(participant(), participant());
+        let token = session.reconnect_token.clone();
+
+        assert_eq!(token.len(), 22);
+        assert_ne!(token, session.peer1.key);
+        assert_ne!(token, session.peer2.key);
+
+        session.revoke_reconnect_token();
+
+        assert_eq!(session.reconnect_token, "");
+        assert_eq!(session.peer1.key.len(), 22);
+        assert_eq!(session.peer2.key.len(), 22);
+    }
+
     #[test]
     fn test_generate_secure_id() {
         let id1 = generate_secure_key();
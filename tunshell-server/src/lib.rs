@@ -4,6 +4,7 @@ use log::*;
 pub mod api;
 pub mod relay;
 pub mod db;
+pub(crate) mod metrics;
 
 pub async fn start(relay_config: relay::Config) -> Result<()> {
     info!("starting tunshell server");
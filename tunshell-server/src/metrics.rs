@@ -0,0 +1,116 @@
+use anyhow::Result;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram, register_int_counter, register_int_counter_vec, register_int_gauge,
+    Encoder, Histogram, IntCounter, IntCounterVec, IntGauge, TextEncoder,
+};
+use std::time::Instant;
+
+lazy_static! {
+    pub(crate) static ref SESSIONS_CREATED: IntCounter = register_int_counter!(
+        "tunshell_sessions_created_total",
+        "Total number of sessions created via the api"
+    )
+    .unwrap();
+    pub(crate) static ref ACTIVE_SESSIONS: IntGauge = register_int_gauge!(
+        "tunshell_active_sessions",
+        "Number of sessions with both peers currently connected"
+    )
+    .unwrap();
+    pub(crate) static ref RELAYED_BYTES: IntCounterVec = register_int_counter_vec!(
+        "tunshell_relayed_bytes_total",
+        "Total number of bytes relayed between peers",
+        &["direction"]
+    )
+    .unwrap();
+    pub(crate) static ref KEY_REJECTIONS: IntCounter = register_int_counter!(
+        "tunshell_key_rejections_total",
+        "Total number of connections which supplied an invalid key"
+    )
+    .unwrap();
+    pub(crate) static ref SESSION_DURATION: Histogram = register_histogram!(
+        "tunshell_session_duration_seconds",
+        "Duration of paired sessions in seconds",
+        vec![1.0, 10.0, 60.0, 300.0, 900.0, 1800.0, 3600.0]
+    )
+    .unwrap();
+}
+
+/// Direction of relayed bytes, from the perspective of the shell host (peer1)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum RelayDirection {
+    Stdin,
+    Stdout,
+}
+
+impl RelayDirection {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Stdin => "stdin",
+            Self::Stdout => "stdout",
+        }
+    }
+}
+
+pub(crate) fn record_relayed_bytes(direction: RelayDirection, bytes: usize) {
+    RELAYED_BYTES
+        .with_label_values(&[direction.label()])
+        .inc_by(bytes as i64);
+}
+
+/// Tracks a paired session for the lifetime of the guard, updating
+/// the active sessions gauge and the duration histogram
+pub(crate) struct ActiveSessionGuard {
+    started_at: Instant,
+}
+
+impl ActiveSessionGuard {
+    pub(crate) fn new() -> Self {
+        ACTIVE_SESSIONS.inc();
+
+        Self {
+            started_at: Instant::now(),
+        }
+    }
+}
+
+impl Drop for ActiveSessionGuard {
+    fn drop(&mut self) {
+        ACTIVE_SESSIONS.dec();
+        SESSION_DURATION.observe(self.started_at.elapsed().as_secs_f64());
+    }
+}
+
+/// Encodes all registered metrics in the prometheus text format
+pub(crate) fn encode() -> Result<String> {
+    let mut buff = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buff)?;
+
+    Ok(String::from_utf8(buff)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_active_session_guard() {
+        let guard = ActiveSessionGuard::new();
+
+        assert!(ACTIVE_SESSIONS.get() >= 1);
+
+        let observed = SESSION_DURATION.get_sample_count();
+        drop(guard);
+
+        assert!(SESSION_DURATION.get_sample_count() > observed);
+    }
+
+    #[test]
+    fn test_record_relayed_bytes() {
+        let before = RELAYED_BYTES.with_label_values(&["stdin"]).get();
+
+        record_relayed_bytes(RelayDirection::Stdin, 10);
+
+        assert!(RELAYED_BYTES.with_label_values(&["stdin"]).get() >= before + 10);
+    }
+}
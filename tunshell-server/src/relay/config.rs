@@ -1,18 +1,27 @@
-use anyhow::{Error, Result};
+use anyhow::{Context, Error, Result};
 use rustls::{internal::pemfile, Certificate, NoClientAuth, PrivateKey, ServerConfig};
 use std::fs;
 use std::io;
+use std::net::{IpAddr, Ipv4Addr};
 use std::{env, sync::Arc, time::Duration};
+use tunshell_shared::MAX_MESSAGE_SIZE;
 
 const DEFAULT_CLIENT_KEY_TIMEOUT_MS: u64 = 3000;
 const DEFAULT_CLEAN_EXPIRED_CONNECTION_INTERVAL_MS: u64 = 60_000;
-const DEFAULT_WAITING_CONNECTION_EXPIRY_MS: u64 = 3600_000;
-const DEFAULT_CONNECTED_CONNECTION_EXPIRY_MS: u64 = 3600_000;
+const DEFAULT_WAITING_CONNECTION_EXPIRY_MS: u64 = 3_600_000;
+const DEFAULT_CONNECTED_CONNECTION_EXPIRY_MS: u64 = 3_600_000;
 
 #[derive(Clone)]
 pub struct Config {
+    /// The address the relay and api servers listen on, binding to `::` also accepts
+    /// IPv4 connections on platforms where IPv6 sockets are dual-stack by default
+    pub bind_addr: IpAddr,
     pub tls_port: u16,
     pub api_port: u16,
+    /// Serves the api from the relay port as well as the api port. Connections to the
+    /// relay port beginning with an HTTP request are passed to the api server.
+    /// Websocket connections are not supported this way and must use the api port.
+    pub api_mux: bool,
     pub tls_config: Arc<ServerConfig>,
     pub tls_key_path: String,
     pub tls_cert_path: String,
@@ -20,15 +29,44 @@ pub struct Config {
     pub expired_connection_clean_interval: Duration,
     pub waiting_connection_expiry: Duration,
     pub paired_connection_expiry: Duration,
+    /// Messages from clients with a longer payload are rejected and the connection closed
+    pub max_message_size: usize,
 }
 
 impl Config {
     pub fn from_env() -> Result<Config> {
-        let tls_port = env::var("TUNSHELL_RELAY_TLS_PORT")?.parse::<u16>()?;
-        let api_port = env::var("TUNSHELL_API_PORT")?.parse::<u16>()?;
+        Self::from_vars(|name| env::var(name).ok())
+    }
 
-        let tls_cert_path = env::var("TLS_RELAY_CERT")?;
-        let tls_key_path = env::var("TLS_RELAY_PRIVATE_KEY")?;
+    /// Parses the config using `var` to look up each variable by name
+    pub(crate) fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Config> {
+        let required = |name: &str| var(name).with_context(|| format!("{} is not set", name));
+
+        let tls_port = required("TUNSHELL_RELAY_TLS_PORT")?.parse::<u16>()?;
+        let api_port = required("TUNSHELL_API_PORT")?.parse::<u16>()?;
+        let bind_addr = match var("TUNSHELL_BIND_ADDRESS") {
+            Some(addr) => addr
+                .parse::<IpAddr>()
+                .context("invalid TUNSHELL_BIND_ADDRESS")?,
+            None => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        };
+        let api_mux = var("TUNSHELL_API_MUX").is_some_and(|i| i == "1");
+        let max_message_size = match var("TUNSHELL_RELAY_MAX_MESSAGE_SIZE") {
+            Some(size) => size
+                .parse::<usize>()
+                .context("invalid TUNSHELL_RELAY_MAX_MESSAGE_SIZE")?,
+            None => MAX_MESSAGE_SIZE,
+        };
+
+        if max_message_size == 0 || max_message_size > MAX_MESSAGE_SIZE {
+            return Err(Error::msg(format!(
+                "TUNSHELL_RELAY_MAX_MESSAGE_SIZE must be between 1 and {}",
+                MAX_MESSAGE_SIZE
+            )));
+        }
+
+        let tls_cert_path = required("TLS_RELAY_CERT")?;
+        let tls_key_path = required("TLS_RELAY_PRIVATE_KEY")?;
 
         let mut tls_config = ServerConfig::new(NoClientAuth::new());
         tls_config.set_single_cert(
@@ -38,8 +76,10 @@ impl Config {
         let tls_config = Arc::new(tls_config);
 
         Ok(Config {
+            bind_addr,
             tls_port,
             api_port,
+            api_mux,
             tls_config,
             tls_cert_path,
             tls_key_path,
@@ -49,6 +89,7 @@ impl Config {
             ),
             waiting_connection_expiry: Duration::from_millis(DEFAULT_WAITING_CONNECTION_EXPIRY_MS),
             paired_connection_expiry: Duration::from_millis(DEFAULT_CONNECTED_CONNECTION_EXPIRY_MS),
+            max_message_size,
         })
     }
 
@@ -73,26 +114,79 @@ impl Config {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
 
-    #[test]
-    fn test_config_from_env() {
-        env::remove_var("TUNSHELL_RELAY_TLS_PORT");
-        env::remove_var("TUNSHELL_API_PORT");
-        env::remove_var("TLS_RELAY_CERT");
-        env::remove_var("TLS_RELAY_PRIVATE_KEY");
+    fn from_vars(vars: &[(&str, &str)]) -> Result<Config> {
+        let vars = vars
+            .iter()
+            .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+            .collect::<HashMap<_, _>>();
+
+        Config::from_vars(|name| vars.get(name).cloned())
+    }
 
-        assert!(Config::from_env().is_err());
+    const REQUIRED: &[(&str, &str)] = &[
+        ("TUNSHELL_RELAY_TLS_PORT", "1234"),
+        ("TUNSHELL_API_PORT", "1235"),
+        ("TLS_RELAY_CERT", "certs/development.cert"),
+        ("TLS_RELAY_PRIVATE_KEY", "certs/development.key"),
+    ];
 
-        env::set_var("TUNSHELL_RELAY_TLS_PORT", "1234");
-        env::set_var("TUNSHELL_API_PORT", "1235");
-        env::set_var("TLS_RELAY_CERT", "certs/development.cert");
-        env::set_var("TLS_RELAY_PRIVATE_KEY", "certs/development.key");
+    fn with_required(vars: &[(&'static str, &'static str)]) -> Vec<(&'static str, &'static str)> {
+        REQUIRED.iter().chain(vars.iter()).cloned().collect()
+    }
 
-        let config = Config::from_env().unwrap();
+    #[test]
+    fn test_config_from_vars() {
+        assert!(from_vars(&[]).is_err());
 
-        std::io::stdin().lock();
+        let config = from_vars(REQUIRED).unwrap();
 
         assert_eq!(config.tls_port, 1234);
         assert_eq!(config.api_port, 1235);
+        assert_eq!(config.bind_addr, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert!(!config.api_mux);
+        assert_eq!(config.max_message_size, MAX_MESSAGE_SIZE);
+    }
+
+    #[test]
+    fn test_max_message_size() {
+        assert_eq!(
+            from_vars(&with_required(&[(
+                "TUNSHELL_RELAY_MAX_MESSAGE_SIZE",
+                "1024"
+            )]))
+            .unwrap()
+            .max_message_size,
+            1024
+        );
+
+        assert!(from_vars(&with_required(&[("TUNSHELL_RELAY_MAX_MESSAGE_SIZE", "0")])).is_err());
+        assert!(from_vars(&with_required(&[(
+            "TUNSHELL_RELAY_MAX_MESSAGE_SIZE",
+            "100000"
+        )]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_api_mux() {
+        assert!(
+            from_vars(&with_required(&[("TUNSHELL_API_MUX", "1")]))
+                .unwrap()
+                .api_mux
+        );
+    }
+
+    #[test]
+    fn test_bind_address() {
+        assert_eq!(
+            from_vars(&with_required(&[("TUNSHELL_BIND_ADDRESS", "::")]))
+                .unwrap()
+                .bind_addr,
+            "::".parse::<IpAddr>().unwrap()
+        );
+
+        assert!(from_vars(&with_required(&[("TUNSHELL_BIND_ADDRESS", "not-an-ip")])).is_err());
     }
 }
@@ -24,6 +24,9 @@ type ConnectionStream = ClientMessageStream<Box<dyn IoStream>>;
 pub(super) struct Connection {
     pub(super) stream: ConnectionStream,
     pub(super) key: String,
+    /// Whether the connection joined with the peer1 key, which by convention
+    /// is the key used by the host (target) of the shell
+    pub(super) is_host: bool,
     pub(super) connected_at: Instant,
     pub(super) remote_addr: SocketAddr,
 }
@@ -107,7 +110,7 @@ impl Future for WaitingConnections {
             }
         }
 
-        if let None = received {
+        if received.is_none() {
             return Poll::Pending;
         }
 
@@ -1,5 +1,5 @@
 use super::config::Config;
-use crate::db::SessionStore;
+use crate::{db::SessionStore, metrics};
 use anyhow::{Error, Result};
 use log::*;
 use std::time::Instant;
@@ -10,17 +10,19 @@ use warp::{filters::BoxedFilter, Reply};
 
 mod connection;
 mod message_stream;
+mod mux;
 mod relay;
 mod session_validation;
 mod tls;
 mod ws;
 
-pub(self) use connection::*;
-pub(self) use message_stream::*;
-pub(self) use relay::*;
-pub(self) use session_validation::*;
-pub(self) use tls::*;
-pub(self) use ws::*;
+use connection::*;
+use message_stream::*;
+use mux::*;
+use relay::*;
+use session_validation::*;
+use tls::*;
+use ws::*;
 
 #[cfg(test)]
 mod tests;
@@ -43,8 +45,17 @@ impl<R: Reply + 'static> Server<R> {
     }
 
     pub(super) async fn start(&mut self, terminate_rx: Option<mpsc::Receiver<()>>) -> Result<()> {
-        let mut tls_listener = TlsListener::bind(&self.config).await?;
-        let mut ws_listener = WebSocketListener::bind(&self.config, self.routes.clone()).await?;
+        // HTTP requests to the relay port are passed to the api server when multiplexing
+        let (http_tx, http_rx) = if self.config.api_mux {
+            let (tx, rx) = mpsc::channel(128);
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        };
+
+        let mut tls_listener = MuxListener::new(TlsListener::bind(&self.config).await?, http_tx);
+        let mut ws_listener =
+            WebSocketListener::bind(&self.config, self.routes.clone(), http_rx).await?;
 
         // If the terminate channel is not supplied we create a default channel
         // that is never invoked
@@ -61,7 +72,7 @@ impl<R: Reply + 'static> Server<R> {
 
         loop {
             tokio::select! {
-                stream = tls_listener.accept() => { self.handle_new_connection(stream.map(|i| Box::new(i) as Box<dyn IoStream>)); },
+                stream = tls_listener.accept() => { self.handle_new_connection(stream); },
                 stream = ws_listener.accept() => { self.handle_new_connection(stream.map(|i| Box::new(i) as Box<dyn IoStream>)); },
                 accepted = &mut self.connections.new => { self.handle_accepted_connection(accepted); },
                 closed = &mut self.connections.waiting => { self.handle_closed_waiting_connection(closed); },
@@ -93,10 +104,15 @@ impl<R: Reply + 'static> Server<R> {
         debug!("negotiating key");
         let mut sessions = self.sessions.clone();
         let key_timeout = self.config.client_key_timeout;
+        let max_message_size = self.config.max_message_size;
 
         tokio::spawn(async move {
-            let remote_addr = stream.get_peer_addr()?;
+            let mut remote_addr = stream.get_peer_addr()?;
+            remote_addr.set_ip(normalize_peer_ip(remote_addr.ip()));
             let mut connection = ClientMessageStream::new(stream);
+            connection
+                .stream_mut()
+                .set_max_message_size(max_message_size);
 
             let key = connection.wait_for_key(key_timeout).await;
 
@@ -110,6 +126,7 @@ impl<R: Reply + 'static> Server<R> {
 
             if session.is_none() {
                 debug!("key rejected, could not find session");
+                metrics::KEY_REJECTIONS.inc();
                 connection.write(ServerMessage::KeyRejected).await?;
                 return Err(Error::msg("client did not supply valid key"));
             }
@@ -118,16 +135,29 @@ impl<R: Reply + 'static> Server<R> {
 
             if !is_session_valid_to_join(&session, key.as_ref()) {
                 debug!("key rejected, session is not valid to join");
+                metrics::KEY_REJECTIONS.inc();
                 connection.write(ServerMessage::KeyRejected).await?;
                 return Err(Error::msg("session is not valid to join"));
             }
 
+            if !is_peer_allowed(&session, remote_addr.ip()) {
+                debug!(
+                    "peer rejected, {} is not permitted to join session",
+                    remote_addr.ip()
+                );
+                metrics::KEY_REJECTIONS.inc();
+                connection.write(ServerMessage::PeerRejected).await?;
+                return Err(Error::msg("peer address is not permitted to join session"));
+            }
+
             connection.write(ServerMessage::KeyAccepted).await?;
 
             debug!("key accepted");
+            let is_host = session.peer1.key == key;
             let connection = Connection {
                 stream: connection,
                 key,
+                is_host,
                 connected_at: Instant::now(),
                 remote_addr,
             };
@@ -172,6 +202,7 @@ impl<R: Reply + 'static> Server<R> {
             self.connections.paired.0.push(pair_connections(
                 accepted.con,
                 peer,
+                accepted.session.reconnect_token.clone(),
                 self.config.paired_connection_expiry,
             ));
         } else {
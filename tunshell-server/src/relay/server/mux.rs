@@ -0,0 +1,289 @@
+use super::{IoStream, TlsListener};
+use anyhow::{Error, Result};
+use log::*;
+use mpsc::{Receiver, Sender};
+use std::{
+    cmp, io,
+    mem::MaybeUninit,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
+    net::TcpStream,
+    sync::mpsc,
+    task::JoinHandle,
+    time::timeout,
+};
+use tokio_rustls::server::TlsStream;
+
+/// The time allowed for a client to send the first byte of its connection
+const PROTOCOL_DETECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An HTTP connection received on the relay port, to be served by the api
+pub(super) type HttpStream = PrefixedStream<TlsStream<TcpStream>>;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(super) enum Protocol {
+    Http,
+    Tunnel,
+}
+
+impl Protocol {
+    /// HTTP requests begin with an upper case method name whereas the first byte of
+    /// a tunnel handshake is the type id of the client's key message
+    fn detect(first_byte: u8) -> Self {
+        if first_byte.is_ascii_uppercase() {
+            Self::Http
+        } else {
+            Self::Tunnel
+        }
+    }
+}
+
+/// Replays the bytes read while detecting the protocol of the stream
+/// before reading from the stream itself
+pub(super) struct PrefixedStream<S> {
+    prefix: Vec<u8>,
+    inner: S,
+}
+
+impl<S> PrefixedStream<S> {
+    fn new(prefix: Vec<u8>, inner: S) -> Self {
+        Self { prefix, inner }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for PrefixedStream<S> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [MaybeUninit<u8>]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if !self.prefix.is_empty() {
+            let len = cmp::min(buf.len(), self.prefix.len());
+            buf[..len].copy_from_slice(&self.prefix[..len]);
+            self.prefix.drain(..len);
+
+            return Poll::Ready(Ok(len));
+        }
+
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for PrefixedStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl<S: IoStream> IoStream for PrefixedStream<S> {
+    fn get_peer_addr(&self) -> Result<SocketAddr> {
+        self.inner.get_peer_addr()
+    }
+}
+
+/// Reads the first byte of the stream to determine its protocol. HTTP requests are
+/// sent to the api, otherwise the stream is returned to be handled as a tunnel.
+/// The byte read is replayed to whichever handler the stream is given to.
+pub(super) async fn dispatch<S: AsyncRead + Unpin>(
+    mut stream: S,
+    http_tx: &mut Sender<PrefixedStream<S>>,
+) -> Result<Option<PrefixedStream<S>>> {
+    let mut first_byte = [0u8; 1];
+    let read = stream.read(&mut first_byte).await?;
+
+    // A stream which closes immediately is left for the relay to handle
+    let protocol = match read {
+        0 => Protocol::Tunnel,
+        _ => Protocol::detect(first_byte[0]),
+    };
+    let stream = PrefixedStream::new(first_byte[..read].to_vec(), stream);
+
+    debug!("detected {:?} connection", protocol);
+
+    match protocol {
+        Protocol::Http => {
+            http_tx
+                .send(stream)
+                .await
+                .map_err(|_| Error::msg("api server is not accepting connections"))?;
+            Ok(None)
+        }
+        Protocol::Tunnel => Ok(Some(stream)),
+    }
+}
+
+/// Accepts tunnel connections from the relay port. When an HTTP sender is supplied
+/// connections beginning with an HTTP request are sent to it, allowing the api to be
+/// served from the same port as the relay.
+pub(super) struct MuxListener {
+    _listener: JoinHandle<()>,
+    con_rx: Receiver<Result<Box<dyn IoStream>>>,
+    terminate_tx: Sender<()>,
+}
+
+impl MuxListener {
+    pub(super) fn new(tls: TlsListener, http_tx: Option<Sender<HttpStream>>) -> Self {
+        let (terminate_tx, terminate_rx) = mpsc::channel(1);
+
+        let (_listener, con_rx) = Self::listen_for_connections(tls, http_tx, terminate_rx);
+
+        Self {
+            _listener,
+            con_rx,
+            terminate_tx,
+        }
+    }
+
+    fn listen_for_connections(
+        mut tls: TlsListener,
+        http_tx: Option<Sender<HttpStream>>,
+        mut terminate_rx: Receiver<()>,
+    ) -> (JoinHandle<()>, Receiver<Result<Box<dyn IoStream>>>) {
+        let (mut con_tx, con_rx) = mpsc::channel(128);
+
+        let task = tokio::spawn(async move {
+            loop {
+                let incoming = tokio::select! {
+                    con = tls.accept() => con,
+                    _ = terminate_rx.recv() => break
+                };
+
+                let (stream, http_tx) = match (incoming, http_tx.clone()) {
+                    (Ok(stream), Some(http_tx)) => (stream, http_tx),
+                    (incoming, _) => {
+                        let incoming = incoming.map(|i| Box::new(i) as Box<dyn IoStream>);
+
+                        if con_tx.send(incoming).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                };
+
+                // The protocol is detected in its own task so a slow client
+                // does not hold up the connections behind it
+                let mut con_tx = con_tx.clone();
+                tokio::spawn(async move {
+                    if let Some(incoming) = accept_tunnel(stream, http_tx).await {
+                        con_tx.send(incoming).await.unwrap_or_else(|_| {
+                            debug!("listener closed before connection could be accepted")
+                        });
+                    }
+                });
+            }
+        });
+
+        (task, con_rx)
+    }
+
+    pub(super) async fn accept(&mut self) -> Result<Box<dyn IoStream>> {
+        self.con_rx
+            .recv()
+            .await
+            .unwrap_or_else(|| Err(Error::msg("channel closed")))
+    }
+}
+
+impl Drop for MuxListener {
+    fn drop(&mut self) {
+        self.terminate_tx
+            .try_send(())
+            .unwrap_or_else(|err| warn!("failed to send terminate message: {}", err));
+    }
+}
+
+/// Returns the stream if it is a tunnel connection, HTTP connections are sent to the api
+async fn accept_tunnel(
+    stream: TlsStream<TcpStream>,
+    mut http_tx: Sender<HttpStream>,
+) -> Option<Result<Box<dyn IoStream>>> {
+    match timeout(PROTOCOL_DETECT_TIMEOUT, dispatch(stream, &mut http_tx)).await {
+        Ok(Ok(Some(stream))) => Some(Ok(Box::new(stream))),
+        Ok(Ok(None)) => None,
+        Ok(Err(err)) => Some(Err(err)),
+        Err(_) => Some(Err(Error::msg("timed out while detecting protocol"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use tokio::runtime::Runtime;
+    use tunshell_shared::{ClientMessage, KeyPayload, Message};
+
+    #[test]
+    fn test_detect_protocol() {
+        assert_eq!(Protocol::detect(b'G'), Protocol::Http);
+        assert_eq!(Protocol::detect(b'P'), Protocol::Http);
+        assert_eq!(Protocol::detect(1), Protocol::Tunnel);
+    }
+
+    #[test]
+    fn test_dispatch_http_request() {
+        Runtime::new().unwrap().block_on(async {
+            let request = b"GET /api/capabilities HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec();
+            let (mut http_tx, mut http_rx) = mpsc::channel(1);
+
+            let dispatched = dispatch(Cursor::new(request.clone()), &mut http_tx)
+                .await
+                .unwrap();
+
+            assert!(dispatched.is_none());
+
+            let mut stream = http_rx.recv().await.unwrap();
+            let mut received = vec![];
+            stream.read_to_end(&mut received).await.unwrap();
+
+            assert_eq!(received, request);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_tunnel_handshake() {
+        Runtime::new().unwrap().block_on(async {
+            let handshake = ClientMessage::Key(KeyPayload {
+                key: "key".to_owned(),
+            })
+            .serialise()
+            .unwrap()
+            .to_vec();
+            let (mut http_tx, mut http_rx) = mpsc::channel(1);
+
+            let mut stream = dispatch(Cursor::new(handshake.clone()), &mut http_tx)
+                .await
+                .unwrap()
+                .expect("tunnel handshake should not be sent to the api");
+
+            let mut received = vec![];
+            stream.read_to_end(&mut received).await.unwrap();
+
+            assert_eq!(received, handshake);
+            assert!(http_rx.try_recv().is_err());
+        });
+    }
+}
@@ -1,34 +1,51 @@
 use super::{Connection, PairedConnection};
+use crate::metrics::{self, ActiveSessionGuard, RelayDirection};
 use anyhow::{Context as AnyhowContext, Error, Result};
 use futures::FutureExt;
 use log::*;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use std::time::{Duration, Instant};
 use tokio::time::timeout;
-use tunshell_shared::{ClientMessage, PeerJoinedPayload, PortBindings, ServerMessage};
+use tunshell_shared::{ClientMessage, PeerJoinedPayload, ServerMessage};
 
+/// Pairs the connections of a session's participants, the session's reconnect token
+/// is sent only to the host
 pub(super) fn pair_connections(
     mut con1: Connection,
     mut con2: Connection,
+    reconnect_token: String,
     timeout_dur: Duration,
 ) -> PairedConnection {
     debug!("pairing connections");
 
     let session_nonce = generate_secure_nonce();
+    let host_reconnect_token = |con: &Connection| {
+        if con.is_host {
+            reconnect_token.clone()
+        } else {
+            String::new()
+        }
+    };
+    let con1_reconnect_token = host_reconnect_token(&con1);
+    let con2_reconnect_token = host_reconnect_token(&con2);
 
     let task = async move {
+        let _metrics = ActiveSessionGuard::new();
+
         tokio::try_join!(
             con1.stream
                 .write(ServerMessage::PeerJoined(PeerJoinedPayload {
                     peer_ip_address: con2.remote_addr.ip().to_string(),
                     peer_key: con2.key.clone(),
-                    session_nonce: session_nonce.clone()
+                    session_nonce: session_nonce.clone(),
+                    reconnect_token: con1_reconnect_token,
                 })),
             con2.stream
                 .write(ServerMessage::PeerJoined(PeerJoinedPayload {
                     peer_ip_address: con1.remote_addr.ip().to_string(),
                     peer_key: con1.key.clone(),
-                    session_nonce
+                    session_nonce,
+                    reconnect_token: con2_reconnect_token,
                 })),
         )
         .context("sending peer joined message")?;
@@ -117,7 +134,7 @@ async fn attempt_direct_connection(con1: &mut Connection, con2: &mut Connection)
         (ClientMessage::DirectConnectFailed, ClientMessage::DirectConnectBound(_)) => return Ok(false),
         (ClientMessage::DirectConnectBound(_), ClientMessage::DirectConnectFailed) => return Ok(false),
         (ClientMessage::DirectConnectFailed, ClientMessage::DirectConnectFailed) => return Ok(false),
-        msgs @ _ => {
+        msgs => {
             return Err(Error::msg(format!(
                 "unexpected message while attempting to bind for direct connection: {:?}",
                 msgs
@@ -125,25 +142,8 @@ async fn attempt_direct_connection(con1: &mut Connection, con2: &mut Connection)
         }
     };
 
-    let supports_matching_protocols = match (&ports1, &ports2) {
-        (
-            PortBindings {
-                tcp_port: Some(_), ..
-            },
-            PortBindings {
-                tcp_port: Some(_), ..
-            },
-        ) => true,
-        (
-            PortBindings {
-                udp_port: Some(_), ..
-            },
-            PortBindings {
-                udp_port: Some(_), ..
-            },
-        ) => true,
-        _ => false,
-    };
+    let supports_matching_protocols = (ports1.tcp_port.is_some() && ports2.tcp_port.is_some())
+        || (ports1.udp_port.is_some() && ports2.udp_port.is_some());
 
     if !supports_matching_protocols {
         debug!("cannot attempt direct connection due to mismatch of port binding protocols");
@@ -164,7 +164,7 @@ async fn attempt_direct_connection(con1: &mut Connection, con2: &mut Connection)
     let result = match (result1, result2) {
         (ClientMessage::DirectConnectSucceeded, ClientMessage::DirectConnectSucceeded) => true,
         (ClientMessage::DirectConnectFailed, ClientMessage::DirectConnectFailed) => false,
-        msgs @ _ => {
+        msgs => {
             return Err(Error::msg(format!(
                 "unexpected message while attempting direct connection: {:?}",
                 msgs
@@ -197,6 +197,13 @@ async fn relay_loop(con1: &mut Connection, con2: &mut Connection) -> Result<()>
             Err(err) => return Err(err),
         };
 
+        let direction = if dest.is_host {
+            RelayDirection::Stdin
+        } else {
+            RelayDirection::Stdout
+        };
+        metrics::record_relayed_bytes(direction, payload.data.len());
+
         dest.stream.write(ServerMessage::Relay(payload)).await?;
         Ok(ProxyResult::Continue)
     }
@@ -1,9 +1,9 @@
 use crate::db::Session;
-use chrono::Utc;
+use std::net::{IpAddr, Ipv4Addr};
 
 pub(super) fn is_session_valid_to_join(session: &Session, key: &str) -> bool {
-    // Ensure session is not older than a day
-    if Utc::now() - session.created_at > chrono::Duration::days(1) {
+    // Ensure session has not expired
+    if session.is_expired() {
         return false;
     }
 
@@ -13,5 +13,77 @@ pub(super) fn is_session_valid_to_join(session: &Session, key: &str) -> bool {
         return false;
     }
 
-    return true;
+    true
+}
+
+/// Checks the connecting address against the session's allowed peer, if any
+pub(super) fn is_peer_allowed(session: &Session, peer: IpAddr) -> bool {
+    match session.allowed_peer {
+        Some(allowed) => normalize_peer_ip(allowed) == normalize_peer_ip(peer),
+        None => true,
+    }
+}
+
+/// Converts an IPv4-mapped IPv6 address (::ffff:a.b.c.d), as seen for IPv4 peers
+/// connecting to a dual-stack listener, to the IPv4 address it represents
+pub(super) fn normalize_peer_ip(ip: IpAddr) -> IpAddr {
+    let v6 = match ip {
+        IpAddr::V6(v6) => v6,
+        IpAddr::V4(_) => return ip,
+    };
+
+    match v6.segments() {
+        [0, 0, 0, 0, 0, 0xffff, high, low] => IpAddr::V4(Ipv4Addr::new(
+            (high >> 8) as u8,
+            high as u8,
+            (low >> 8) as u8,
+            low as u8,
+        )),
+        _ => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::participant;
+
+    #[test]
+    fn test_normalize_peer_ip() {
+        assert_eq!(
+            normalize_peer_ip("::ffff:10.1.2.3".parse().unwrap()),
+            "10.1.2.3".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            normalize_peer_ip("10.1.2.3".parse().unwrap()),
+            "10.1.2.3".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            normalize_peer_ip("::1".parse().unwrap()),
+            "::1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            normalize_peer_ip("2001:db8::ffff:a01:203".parse().unwrap()),
+            "2001:db8::ffff:a01:203".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_is_peer_allowed_with_mapped_address() {
+        let mut session = Session::new(participant(), participant());
+        session.allowed_peer = Some("10.1.2.3".parse().unwrap());
+
+        assert!(is_peer_allowed(
+            &session,
+            "::ffff:10.1.2.3".parse().unwrap()
+        ));
+        assert!(!is_peer_allowed(
+            &session,
+            "::ffff:10.1.2.4".parse().unwrap()
+        ));
+
+        session.allowed_peer = Some("::ffff:10.1.2.3".parse().unwrap());
+
+        assert!(is_peer_allowed(&session, "10.1.2.3".parse().unwrap()));
+    }
 }
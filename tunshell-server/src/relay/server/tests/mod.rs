@@ -1,6 +1,7 @@
 use super::*;
 use crate::db;
-use crate::db::SessionStore;
+use crate::db::testing::participant;
+use crate::db::{Session, SessionStore};
 use futures::StreamExt;
 use std::time::Duration;
 use tokio::{
@@ -58,6 +59,29 @@ fn test_connect_to_server_without_sending_key_timeout() {
     });
 }
 
+#[test]
+fn test_connect_with_oversized_message() {
+    Runtime::new().unwrap().block_on(async {
+        let mut config = Config::from_env().unwrap();
+        config.max_message_size = 10;
+
+        let server = init_server(config).await;
+        let mut con = create_client_connection_to_server(&server).await;
+
+        let mock_session = create_mock_session().await;
+
+        send_key_to_server(&mut con, &mock_session.peer1.key).await;
+
+        // The connection is closed without the key being accepted
+        assert!(con.next().await.is_none());
+
+        let server = server.stop().await.unwrap();
+
+        assert_eq!(server.connections.waiting.0.len(), 0);
+        assert_eq!(server.connections.paired.0.len(), 0);
+    });
+}
+
 #[test]
 fn test_connect_with_valid_key() {
     Runtime::new().unwrap().block_on(async {
@@ -74,14 +98,11 @@ fn test_connect_with_valid_key() {
 
         assert_eq!(server.connections.new.0.len(), 0);
         assert_eq!(server.connections.waiting.0.len(), 1);
-        assert_eq!(
-            server
-                .connections
-                .waiting
-                .0
-                .contains_key(&mock_session.peer1.key),
-            true
-        );
+        assert!(server
+            .connections
+            .waiting
+            .0
+            .contains_key(&mock_session.peer1.key));
         assert_eq!(server.connections.paired.0.len(), 0);
     });
 }
@@ -131,14 +152,11 @@ fn test_connect_with_valid_client_key() {
 
         assert_eq!(server.connections.new.0.len(), 0);
         assert_eq!(server.connections.waiting.0.len(), 1);
-        assert_eq!(
-            server
-                .connections
-                .waiting
-                .0
-                .contains_key(&mock_session.peer2.key),
-            true
-        );
+        assert!(server
+            .connections
+            .waiting
+            .0
+            .contains_key(&mock_session.peer2.key));
         assert_eq!(server.connections.paired.0.len(), 0);
     });
 }
@@ -165,6 +183,52 @@ fn test_connect_with_invalid_key() {
     });
 }
 
+#[test]
+fn test_connect_from_allowed_peer() {
+    Runtime::new().unwrap().block_on(async {
+        let server = init_server(Config::from_env().unwrap()).await;
+        let mut con = create_client_connection_to_server(&server).await;
+
+        let mut mock_session = Session::new(participant(), participant());
+        mock_session.allowed_peer = Some("127.0.0.1".parse().unwrap());
+        let mock_session = save_mock_session(mock_session).await;
+
+        send_key_to_server(&mut con, &mock_session.peer1.key).await;
+
+        assert_next_message_is_key_accepted(&mut con).await;
+
+        let server = server.stop().await.unwrap();
+
+        assert_eq!(server.connections.waiting.0.len(), 1);
+    });
+}
+
+#[test]
+fn test_connect_from_disallowed_peer() {
+    Runtime::new().unwrap().block_on(async {
+        let server = init_server(Config::from_env().unwrap()).await;
+        let mut con = create_client_connection_to_server(&server).await;
+
+        let mut mock_session = Session::new(participant(), participant());
+        mock_session.allowed_peer = Some("10.1.2.3".parse().unwrap());
+        let mock_session = save_mock_session(mock_session).await;
+
+        send_key_to_server(&mut con, &mock_session.peer1.key).await;
+
+        let response = con.next().await.unwrap().unwrap();
+
+        assert_eq!(response, ServerMessage::PeerRejected);
+
+        delay_for(Duration::from_millis(10)).await;
+
+        let server = server.stop().await.unwrap();
+
+        assert_eq!(server.connections.new.0.len(), 0);
+        assert_eq!(server.connections.waiting.0.len(), 0);
+        assert_eq!(server.connections.paired.0.len(), 0);
+    });
+}
+
 #[test]
 fn test_connect_and_joined_twice_with_same_key() {
     Runtime::new().unwrap().block_on(async {
@@ -306,6 +370,7 @@ fn test_direct_connection() {
             &mut con_host,
             "127.0.0.1",
             mock_session.peer2.key.as_str(),
+            mock_session.reconnect_token.as_str(),
         )
         .await;
 
@@ -313,6 +378,7 @@ fn test_direct_connection() {
             &mut con_client,
             "127.0.0.1",
             mock_session.peer1.key.as_str(),
+            "",
         )
         .await;
 
@@ -323,7 +389,7 @@ fn test_direct_connection() {
 
         match (message_host, message_client) {
             (ServerMessage::BindForDirectConnect, ServerMessage::BindForDirectConnect) => {}
-            msgs @ _ => panic!(
+            msgs => panic!(
                 "expected to receive bind for direct connect messages but received: {:?}",
                 msgs
             ),
@@ -357,7 +423,7 @@ fn test_direct_connection() {
                     tcp_port: Some(2222),
                 }),
             ) => {}
-            msgs @ _ => panic!(
+            msgs => panic!(
                 "expected to receive attempt direct connect messages but received: {:?}",
                 msgs
             ),
@@ -424,6 +490,7 @@ fn test_relayed_connection() {
             &mut con_host,
             "127.0.0.1",
             mock_session.peer2.key.as_str(),
+            mock_session.reconnect_token.as_str(),
         )
         .await;
 
@@ -431,6 +498,7 @@ fn test_relayed_connection() {
             &mut con_client,
             "127.0.0.1",
             mock_session.peer1.key.as_str(),
+            "",
         )
         .await;
 
@@ -439,7 +507,7 @@ fn test_relayed_connection() {
 
         match (message_host, message_client) {
             (ServerMessage::BindForDirectConnect, ServerMessage::BindForDirectConnect) => {}
-            msgs @ _ => panic!(
+            msgs => panic!(
                 "expected to receive bind for direct connect messages but received: {:?}",
                 msgs
             ),
@@ -535,6 +603,7 @@ fn test_clean_up_paired_connection() {
             &mut con_host,
             "127.0.0.1",
             mock_session.peer2.key.as_str(),
+            mock_session.reconnect_token.as_str(),
         )
         .await;
 
@@ -542,6 +611,7 @@ fn test_clean_up_paired_connection() {
             &mut con_client,
             "127.0.0.1",
             mock_session.peer1.key.as_str(),
+            "",
         )
         .await;
 
@@ -550,7 +620,7 @@ fn test_clean_up_paired_connection() {
 
         match (message_host, message_client) {
             (ServerMessage::BindForDirectConnect, ServerMessage::BindForDirectConnect) => {}
-            msgs @ _ => panic!(
+            msgs => panic!(
                 "expected to receive bind for direct connect messages but received: {:?}",
                 msgs
             ),
@@ -1,8 +1,9 @@
 use super::*;
 use crate::db;
+use crate::db::testing::participant;
 use crate::db::SessionStore;
 use anyhow::{Error, Result};
-use db::{Participant, Session};
+use db::Session;
 use futures::StreamExt;
 use lazy_static::lazy_static;
 use rustls::ClientConfig;
@@ -75,7 +76,7 @@ pub(super) async fn init_server(mut server_config: Config) -> TerminableServer {
         )))
         .await;
 
-        if let Ok(_) = socket {
+        if socket.is_ok() {
             break;
         }
 
@@ -144,8 +145,10 @@ pub(crate) fn insecure_tls_config() -> ClientConfig {
 }
 
 pub(super) async fn create_mock_session() -> Session {
-    let mock_session = Session::new(Participant::default(), Participant::default());
+    save_mock_session(Session::new(participant(), participant())).await
+}
 
+pub(super) async fn save_mock_session(mock_session: Session) -> Session {
     let db = db::connect().await.unwrap();
     SessionStore::new(db).save(&mock_session).await.unwrap();
 
@@ -171,11 +174,12 @@ pub(super) async fn assert_next_message_is_peer_joined(
     con: &mut ClientConnection,
     peer_ip_address: &str,
     peer_key: &str,
+    reconnect_token: &str,
 ) -> PeerJoinedPayload {
     let message = con.next().await.unwrap().unwrap();
     let payload = match &message {
         ServerMessage::PeerJoined(i) => i.clone(),
-        msg @ _ => panic!(
+        msg => panic!(
             "expected PeerJoined payload from connection but received: {:?}",
             msg
         ),
@@ -186,7 +190,8 @@ pub(super) async fn assert_next_message_is_peer_joined(
         ServerMessage::PeerJoined(PeerJoinedPayload {
             peer_ip_address: peer_ip_address.to_owned(),
             peer_key: peer_key.to_owned(),
-            session_nonce: payload.session_nonce.clone()
+            session_nonce: payload.session_nonce.clone(),
+            reconnect_token: reconnect_token.to_owned(),
         })
     );
 
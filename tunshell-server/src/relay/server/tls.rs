@@ -2,7 +2,7 @@ use super::{super::config::Config, IoStream};
 use anyhow::{Error, Result};
 use log::*;
 use mpsc::{Receiver, Sender};
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::SocketAddr;
 use std::{sync::Arc, time::Duration};
 use tokio::{
     net::{TcpListener, TcpStream},
@@ -26,7 +26,7 @@ impl IoStream for TlsStream<TcpStream> {
 
 impl TlsListener {
     pub(super) async fn bind(config: &Config) -> Result<Self> {
-        let tcp = TcpListener::bind((Ipv4Addr::new(0, 0, 0, 0), config.tls_port)).await?;
+        let tcp = TcpListener::bind((config.bind_addr, config.tls_port)).await?;
         let tls = TlsAcceptor::from(Arc::clone(&config.tls_config));
 
         let (terminate_tx, terminate_rx) = mpsc::channel(1);
@@ -54,7 +54,7 @@ impl TlsListener {
                     _ = terminate_rx.recv() => break
                 };
 
-                if let Err(_) = con_tx.send(incoming).await {
+                if con_tx.send(incoming).await.is_err() {
                     break;
                 }
             }
@@ -113,7 +113,10 @@ mod tests {
     use crate::relay::server::tests::insecure_tls_config;
     use futures::FutureExt;
     use lazy_static::lazy_static;
-    use std::{net::SocketAddr, sync::Mutex};
+    use std::{
+        net::{Ipv4Addr, SocketAddr},
+        sync::Mutex,
+    };
     use tokio::{
         io::{AsyncReadExt, AsyncWriteExt},
         runtime::Runtime,
@@ -135,7 +138,7 @@ mod tests {
 
     async fn init_server(config: &mut Config) -> TlsListener {
         config.tls_port = init_port_number();
-        let server = TlsListener::bind(&config).await;
+        let server = TlsListener::bind(config).await;
 
         server.unwrap()
     }
@@ -176,7 +179,7 @@ mod tests {
                 server_con.get_ref().0.peer_addr().unwrap()
             );
 
-            client_con.write(&[1, 2, 3]).await.unwrap();
+            client_con.write_all(&[1, 2, 3]).await.unwrap();
 
             let mut buff = [0u8; 1024];
             let read = server_con.read(&mut buff).await.unwrap();
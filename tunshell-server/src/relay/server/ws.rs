@@ -1,10 +1,12 @@
-use super::{super::config::Config, IoStream};
+use super::{super::config::Config, HttpStream, IoStream};
 use anyhow::{Error, Result};
-use futures::{Sink, Stream};
+use futures::{future, stream, Sink, Stream};
 use log::*;
 use mpsc::{Receiver, Sender};
 use std::{
-    cmp, io,
+    cmp,
+    convert::Infallible,
+    io,
     net::SocketAddr,
     pin::Pin,
     sync::{Arc, Mutex},
@@ -43,13 +45,13 @@ impl AsyncRead for WebSocketStream {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
-        while self.recv_buff.len() == 0 {
+        while self.recv_buff.is_empty() {
             let message = {
                 let mut ws = self.ws.lock().unwrap();
 
                 let poll = Pin::new(&mut *ws).poll_next(cx);
 
-                let message = match poll {
+                match poll {
                     Poll::Pending => return Poll::Pending,
                     Poll::Ready(None) => {
                         return Poll::Ready(Err(io::Error::from(io::ErrorKind::NotConnected)))
@@ -58,9 +60,7 @@ impl AsyncRead for WebSocketStream {
                         return Poll::Ready(Err(warp_err_to_io_err(err)))
                     }
                     Poll::Ready(Some(Ok(res))) => res,
-                };
-
-                message
+                }
             };
 
             if message.is_binary() {
@@ -72,7 +72,7 @@ impl AsyncRead for WebSocketStream {
         }
 
         let len = cmp::min(buf.len(), self.recv_buff.len());
-        &buf[..len].copy_from_slice(&self.recv_buff[..len]);
+        buf[..len].copy_from_slice(&self.recv_buff[..len]);
         self.recv_buff.drain(..len);
 
         Poll::Ready(Ok(len))
@@ -98,7 +98,7 @@ impl AsyncWrite for WebSocketStream {
         };
 
         let len = buf.len();
-        return Poll::Ready(
+        Poll::Ready(
             Pin::new(&mut *ws)
                 .start_send(Message::binary(buf.to_vec()))
                 .map(|_| {
@@ -106,7 +106,7 @@ impl AsyncWrite for WebSocketStream {
                     len
                 })
                 .map_err(warp_err_to_io_err),
-        );
+        )
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
@@ -125,7 +125,7 @@ impl AsyncWrite for WebSocketStream {
 }
 
 fn warp_err_to_io_err(err: warp::Error) -> io::Error {
-    io::Error::new(io::ErrorKind::Other, err)
+    io::Error::other(err)
 }
 
 impl IoStream for WebSocketStream {
@@ -141,13 +141,15 @@ pub(super) struct WebSocketListener {
 }
 
 impl WebSocketListener {
+    /// Connections received from `http_rx` are served in addition to those on the api port
     pub(super) async fn bind(
         config: &Config,
         routes: BoxedFilter<(impl Reply + 'static,)>,
+        http_rx: Option<Receiver<HttpStream>>,
     ) -> Result<Self> {
         let (terminate_tx, terminate_rx) = mpsc::channel(1);
         let (_listener, con_rx) =
-            Self::listen_for_connections(config.clone(), routes, terminate_rx);
+            Self::listen_for_connections(config.clone(), routes, http_rx, terminate_rx);
 
         Ok(Self {
             _listener,
@@ -159,6 +161,7 @@ impl WebSocketListener {
     fn listen_for_connections(
         config: Config,
         routes: BoxedFilter<(impl Reply + 'static,)>,
+        http_rx: Option<Receiver<HttpStream>>,
         mut terminate_rx: Receiver<()>,
     ) -> (JoinHandle<()>, Receiver<WebSocketStream>) {
         let (con_tx, con_rx) = mpsc::channel(128);
@@ -183,15 +186,32 @@ impl WebSocketListener {
                 })
             }));
 
-        let server = warp::serve(routes)
+        let server = warp::serve(routes.clone())
             .tls()
             .cert_path(config.tls_cert_path)
             .key_path(config.tls_key_path)
-            .run(([0, 0, 0, 0], config.api_port));
+            .run((config.bind_addr, config.api_port));
+
+        // The TLS of these connections has already been terminated by the relay listener.
+        // Warp cannot determine their remote address so they cannot open a websocket.
+        let muxed_server = async move {
+            match http_rx {
+                Some(http_rx) => {
+                    let incoming = stream::unfold(http_rx, |mut http_rx| async move {
+                        let con = http_rx.recv().await?;
+                        Some((Ok::<_, Infallible>(con), http_rx))
+                    });
+
+                    warp::serve(routes).run_incoming(Box::pin(incoming)).await
+                }
+                None => future::pending().await,
+            }
+        };
 
         let task = tokio::spawn(async move {
             tokio::select! {
                 _ = server => warn!("websocket server ended"),
+                _ = muxed_server => warn!("multiplexed api server ended"),
                 _ = terminate_rx.recv() => debug!("websocket server stopped")
             }
         });
@@ -225,7 +245,7 @@ mod tests {
     use futures::{SinkExt, StreamExt, FutureExt};
     use lazy_static::lazy_static;
     use std::{
-        net::{Ipv4Addr, SocketAddr},
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr},
         sync::Mutex,
         time::Duration,
     };
@@ -253,10 +273,11 @@ mod tests {
     async fn init_server(config: &mut Config) -> WebSocketListener {
         config.api_port = init_port_number();
         let server = WebSocketListener::bind(
-            &config,
+            config,
             warp::path("unused")
                 .map(|| warp::http::StatusCode::OK)
                 .boxed(),
+            None,
         )
         .await;
 
@@ -275,14 +296,27 @@ mod tests {
                 async_tls::client::TlsStream<tokio_util::compat::Compat<TcpStream>>,
             >,
         >,
+    ) {
+        init_connection_to(SocketAddr::from((Ipv4Addr::new(127, 0, 0, 1), port))).await
+    }
+
+    async fn init_connection_to(
+        addr: SocketAddr,
+    ) -> (
+        SocketAddr,
+        ClientWebSocketStream<
+            async_tungstenite::stream::Stream<
+                tokio_util::compat::Compat<TcpStream>,
+                async_tls::client::TlsStream<tokio_util::compat::Compat<TcpStream>>,
+            >,
+        >,
     ) {
         let client_config = insecure_tls_config();
 
-        let addr = SocketAddr::from((Ipv4Addr::new(127, 0, 0, 1), port));
         let tcp = TcpStream::connect(addr).await.unwrap();
         let local_addr = tcp.local_addr().unwrap();
 
-        let url = format!("wss://localhost:{}/ws", port.to_string().as_str());
+        let url = format!("wss://localhost:{}/ws", addr.port().to_string().as_str());
 
         let (ws, _) =
             client_async_tls_with_connector(url.as_str(), tcp.compat(), Some(client_config.into()))
@@ -321,6 +355,31 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_connect_to_listener_over_ipv6() {
+        Runtime::new().unwrap().block_on(async {
+            let mut config = Config::from_env().unwrap();
+            config.bind_addr = Ipv6Addr::LOCALHOST.into();
+            let mut listener = init_server(&mut config).await;
+            let (addr, mut client_con) =
+                init_connection_to(SocketAddr::from((Ipv6Addr::LOCALHOST, config.api_port))).await;
+
+            let mut server_con = listener.accept().await.unwrap();
+
+            assert_eq!(addr, server_con.get_peer_addr().unwrap());
+
+            client_con
+                .send(ClientMessage::binary(vec![1, 2, 3]))
+                .await
+                .unwrap();
+
+            let mut buff = [0u8; 1024];
+            let read = server_con.read(&mut buff).await.unwrap();
+
+            assert_eq!(&buff[..read], &[1, 2, 3]);
+        });
+    }
+
     #[test]
     fn test_simultaneous_connections_to_listener() {
         Runtime::new().unwrap().block_on(async {
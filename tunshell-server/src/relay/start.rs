@@ -8,8 +8,8 @@ pub async fn start(config: Config, routes: BoxedFilter<(impl Reply + 'static,)>)
     let sessions = db::SessionStore::new(db::connect().await?);
 
     info!(
-        "starting relay server on ports (tls: {}, api: {})",
-        config.tls_port, config.api_port
+        "starting relay server on {} (tls: {}, api: {})",
+        config.bind_addr, config.tls_port, config.api_port
     );
 
     Server::new(config, sessions, routes).start(None).await
@@ -1,9 +1,15 @@
+#[cfg(fuzzing)]
 use afl::*;
+#[cfg(fuzzing)]
 use anyhow::Result;
+#[cfg(fuzzing)]
 use futures::executor::block_on_stream;
+#[cfg(fuzzing)]
 use futures::io::Cursor;
+#[cfg(fuzzing)]
 use tunshell_shared::{ClientMessage, MessageStream, ServerMessage};
 
+#[cfg(fuzzing)]
 fn main() {
     fuzz!(|data: &[u8]| {
         let cursor = Cursor::new(data.to_vec());
@@ -16,3 +22,9 @@ fn main() {
         let _ = block_on_stream(stream_server).collect::<Vec<Result<ServerMessage>>>();
     });
 }
+
+#[cfg(not(fuzzing))]
+fn main() {
+    eprintln!("the fuzzing binary must be built with `cargo afl build`");
+    std::process::exit(1);
+}
@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+use std::ops::{BitOr, BitOrAssign};
+
+/// The version of the shell protocol implemented by this build
+pub const SHELL_PROTOCOL_VERSION: u16 = 2;
+
+/// A bitset of optional features supported by a server.
+/// Unknown bits are retained so newer capabilities survive a round trip.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// Shells can be run in a pty
+    pub const PTY: Self = Self(1 << 0);
+    /// Shells can be run with plain pipes
+    pub const PIPE: Self = Self(1 << 1);
+    /// Shells can be run in binary-safe raw mode
+    pub const RAW: Self = Self(1 << 2);
+    /// Signals can be forwarded to the shell
+    pub const SIGNALS: Self = Self(1 << 3);
+    /// Peers can be relayed through the server
+    pub const RELAY: Self = Self(1 << 4);
+    /// Peers can negotiate a direct connection via the server
+    pub const DIRECT_CONNECT: Self = Self(1 << 5);
+    /// Session creation requests can be validated without creating a session
+    pub const VALIDATE_SESSION: Self = Self(1 << 6);
+    /// Shells can be detached and left running on the server
+    pub const DETACH: Self = Self(1 << 7);
+    /// Clients can prove they hold a key by answering a challenge rather than sending it
+    pub const KEY_PROOF: Self = Self(1 << 8);
+    /// Messages can be numbered to diagnose transports which lose or reorder them
+    pub const SEQUENCE_NUMBERS: Self = Self(1 << 9);
+    /// Messages can be sent in version 2 frames, which are checksummed and have room for flags
+    pub const FRAME_V2: Self = Self(1 << 10);
+
+    const NAMES: &'static [(Self, &'static str)] = &[
+        (Self::PTY, "pty"),
+        (Self::PIPE, "pipe"),
+        (Self::RAW, "raw"),
+        (Self::SIGNALS, "signals"),
+        (Self::RELAY, "relay"),
+        (Self::DIRECT_CONNECT, "direct_connect"),
+        (Self::VALIDATE_SESSION, "validate_session"),
+        (Self::DETACH, "detach"),
+        (Self::KEY_PROOF, "key_proof"),
+        (Self::SEQUENCE_NUMBERS, "sequence_numbers"),
+        (Self::FRAME_V2, "frame_v2"),
+    ];
+
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The names of the known capabilities in this set
+    pub fn names(&self) -> Vec<&'static str> {
+        Self::NAMES
+            .iter()
+            .filter(|(capability, _)| self.contains(*capability))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Capabilities {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        let capabilities = Capabilities::PTY | Capabilities::SIGNALS;
+
+        assert!(capabilities.contains(Capabilities::PTY));
+        assert!(capabilities.contains(Capabilities::SIGNALS));
+        assert!(!capabilities.contains(Capabilities::RAW));
+        assert_eq!(capabilities.names(), vec!["pty", "signals"]);
+        assert_eq!(Capabilities::empty().names(), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_capabilities_serde() {
+        let capabilities = Capabilities::PIPE | Capabilities::from_bits(1 << 31);
+
+        assert_eq!(
+            serde_json::to_string(&capabilities).unwrap(),
+            (2u32 | 1 << 31).to_string()
+        );
+        assert_eq!(
+            serde_json::from_str::<Capabilities>(&(2u32 | 1 << 31).to_string()).unwrap(),
+            capabilities
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use anyhow::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+/// The first bytes of every version 2 frame, a frame read from the wrong offset
+/// in the stream is rejected rather than being misinterpreted
+const FRAME_MAGIC: [u8; 2] = [0x74, 0x46];
+
+/// The flags understood by this build, none are defined yet.
+/// Each new flag must be negotiated before it is sent as the payload of a frame
+/// with an unknown flag cannot be read.
+const SUPPORTED_FLAGS: u8 = 0;
+
+/// The layout of the frame around each message payload.
+///
+/// Version 1 is the legacy layout: the type id and a two byte length, followed by the payload.
+/// Version 2 prefixes a magic number, the version and a flags byte, and follows the payload
+/// with a CRC-32 of the whole frame:
+///
+/// `magic (2) | version (1) | flags (1) | type id (1) | length (2) | payload | crc32 (4)`
+///
+/// Streams start with version 1, peers switch to a later version once both have agreed to it.
+/// New framing features, such as per-frame compression, should be added as flags of
+/// version 2 so that peers which have not negotiated them are unaffected.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameVersion {
+    #[default]
+    V1,
+    V2,
+}
+
+impl FrameVersion {
+    pub fn is_v1(&self) -> bool {
+        *self == Self::V1
+    }
+
+    /// The number of bytes preceding the payload
+    pub fn header_len(&self) -> usize {
+        match self {
+            Self::V1 => 3,
+            Self::V2 => 7,
+        }
+    }
+
+    /// The number of bytes following the payload
+    pub fn trailer_len(&self) -> usize {
+        match self {
+            Self::V1 => 0,
+            Self::V2 => 4,
+        }
+    }
+
+    fn number(&self) -> u8 {
+        match self {
+            Self::V1 => 1,
+            Self::V2 => 2,
+        }
+    }
+}
+
+/// Appends the payload framed in the given version to the buffer
+pub(crate) fn write_frame(
+    version: FrameVersion,
+    type_id: u8,
+    payload: &[u8],
+    buff: &mut Vec<u8>,
+) -> Result<()> {
+    if payload.len() > u16::MAX as usize {
+        return Err(Error::msg(format!(
+            "frame length ({}) cannot be greater than {}",
+            payload.len(),
+            u16::MAX
+        )));
+    }
+
+    let start = buff.len();
+    buff.reserve(version.header_len() + payload.len() + version.trailer_len());
+
+    if version == FrameVersion::V2 {
+        buff.extend_from_slice(&FRAME_MAGIC);
+        buff.push(version.number());
+        buff.push(0);
+    }
+
+    buff.push(type_id);
+    buff.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    buff.extend_from_slice(payload);
+
+    if version == FrameVersion::V2 {
+        let checksum = crc32(&buff[start..]);
+        buff.extend_from_slice(&checksum.to_be_bytes());
+    }
+
+    Ok(())
+}
+
+/// Parses the header at the start of the buffer, returning the type id and payload length.
+/// Returns `None` until the whole header has been buffered.
+pub(crate) fn read_frame_header(version: FrameVersion, buff: &[u8]) -> Result<Option<(u8, usize)>> {
+    if buff.len() < version.header_len() {
+        return Ok(None);
+    }
+
+    let header = &buff[..version.header_len()];
+
+    if version == FrameVersion::V2 {
+        if header[..2] != FRAME_MAGIC {
+            return Err(Error::msg("frame does not start with the expected magic"));
+        }
+
+        if header[2] != version.number() {
+            return Err(Error::msg(format!(
+                "frame version {} does not match the negotiated version {}",
+                header[2],
+                version.number()
+            )));
+        }
+
+        if header[3] & !SUPPORTED_FLAGS != 0 {
+            return Err(Error::msg(format!(
+                "frame has unsupported flags {:#04x}",
+                header[3]
+            )));
+        }
+    }
+
+    let header = &header[header.len() - 3..];
+    let length = (header[1] as usize) << 8 | header[2] as usize;
+
+    Ok(Some((header[0], length)))
+}
+
+/// Checks the trailer of the complete frame at the start of the buffer
+pub(crate) fn check_frame_trailer(version: FrameVersion, frame: &[u8]) -> Result<()> {
+    if version == FrameVersion::V1 {
+        return Ok(());
+    }
+
+    let (contents, trailer) = frame.split_at(frame.len() - version.trailer_len());
+    let expected = u32::from_be_bytes(trailer.try_into().unwrap());
+
+    if crc32(contents) != expected {
+        return Err(Error::msg("frame failed its integrity check"));
+    }
+
+    Ok(())
+}
+
+/// The CRC-32 (IEEE) of the data, a table is not worth its size for the length of a frame
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in data {
+        crc ^= *byte as u32;
+
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(version: FrameVersion, type_id: u8, payload: &[u8]) -> Vec<u8> {
+        let mut buff = vec![];
+        write_frame(version, type_id, payload, &mut buff).unwrap();
+        buff
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_write_v1_frame() {
+        assert_eq!(frame(FrameVersion::V1, 3, &[1, 2]), vec![3, 0, 2, 1, 2]);
+    }
+
+    #[test]
+    fn test_write_v2_frame() {
+        let frame = frame(FrameVersion::V2, 3, &[1, 2]);
+
+        assert_eq!(&frame[..9], &[0x74, 0x46, 2, 0, 3, 0, 2, 1, 2]);
+        assert_eq!(&frame[9..], &crc32(&frame[..9]).to_be_bytes());
+    }
+
+    #[test]
+    fn test_read_frame_round_trip() {
+        for version in &[FrameVersion::V1, FrameVersion::V2] {
+            let frame = frame(*version, 7, b"payload");
+
+            assert_eq!(
+                read_frame_header(*version, &frame[..version.header_len() - 1]).unwrap(),
+                None
+            );
+            assert_eq!(read_frame_header(*version, &frame).unwrap(), Some((7, 7)));
+            check_frame_trailer(*version, &frame).unwrap();
+            assert_eq!(
+                frame.len(),
+                version.header_len() + 7 + version.trailer_len()
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_invalid_v2_frames() {
+        let valid = frame(FrameVersion::V2, 7, b"payload");
+
+        let mut bad_magic = valid.clone();
+        bad_magic[0] = 0;
+        read_frame_header(FrameVersion::V2, &bad_magic).unwrap_err();
+
+        let mut bad_version = valid.clone();
+        bad_version[2] = 3;
+        read_frame_header(FrameVersion::V2, &bad_version).unwrap_err();
+
+        let mut unknown_flags = valid.clone();
+        unknown_flags[3] = 0x01;
+        read_frame_header(FrameVersion::V2, &unknown_flags).unwrap_err();
+
+        let mut corrupted = valid.clone();
+        corrupted[8] ^= 0xFF;
+        read_frame_header(FrameVersion::V2, &corrupted).unwrap();
+        check_frame_trailer(FrameVersion::V2, &corrupted).unwrap_err();
+    }
+
+    #[test]
+    fn test_frame_version_serde() {
+        assert_eq!(FrameVersion::default(), FrameVersion::V1);
+        assert_eq!(serde_json::to_string(&FrameVersion::V2).unwrap(), "\"v2\"");
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::{Error, Result};
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+
+/// Upper and lower case letters and digits, all of which are url-safe
+pub const BASE62_ALPHABET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// The minimum entropy of keys generated from a `KeyGenConfig`
+pub const MIN_KEY_ENTROPY_BITS: f64 = 128.0;
+
+/// Controls the length and character set of generated keys.
+/// Defaults to 22 base62 characters (~131 bits of entropy).
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyGenConfig {
+    length: usize,
+    alphabet: Vec<char>,
+}
+
+impl KeyGenConfig {
+    /// Returns an error if the alphabet contains duplicate characters or
+    /// the generated keys would have less than `MIN_KEY_ENTROPY_BITS` of entropy
+    pub fn new(length: usize, alphabet: &str) -> Result<Self> {
+        let alphabet = alphabet.chars().collect::<Vec<char>>();
+
+        let mut unique = alphabet.clone();
+        unique.sort();
+        unique.dedup();
+
+        if unique.len() != alphabet.len() {
+            return Err(Error::msg(
+                "key alphabet cannot contain duplicate characters",
+            ));
+        }
+
+        let config = Self { length, alphabet };
+
+        if config.entropy_bits() < MIN_KEY_ENTROPY_BITS {
+            return Err(Error::msg(format!(
+                "keys of length {} with an alphabet of {} characters have {:.1} bits of entropy, at least {} are required",
+                config.length,
+                config.alphabet.len(),
+                config.entropy_bits(),
+                MIN_KEY_ENTROPY_BITS
+            )));
+        }
+
+        Ok(config)
+    }
+
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    pub fn alphabet(&self) -> String {
+        self.alphabet.iter().collect()
+    }
+
+    pub fn entropy_bits(&self) -> f64 {
+        if self.alphabet.is_empty() {
+            return 0.0;
+        }
+
+        self.length as f64 * (self.alphabet.len() as f64).log2()
+    }
+
+    /// Generates a key using the thread-local CSPRNG
+    pub fn generate(&self) -> String {
+        let mut rng = thread_rng();
+
+        (0..self.length)
+            .map(|_| *self.alphabet.choose(&mut rng).unwrap())
+            .collect()
+    }
+}
+
+impl Default for KeyGenConfig {
+    fn default() -> Self {
+        Self::new(22, BASE62_ALPHABET).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config() {
+        let config = KeyGenConfig::default();
+
+        assert_eq!(config.length(), 22);
+        assert_eq!(config.alphabet(), BASE62_ALPHABET);
+        assert!(config.entropy_bits() > 130.0);
+    }
+
+    #[test]
+    fn test_generate_matches_config() {
+        let config = KeyGenConfig::new(64, "0123456789abcdef").unwrap();
+
+        let key1 = config.generate();
+        let key2 = config.generate();
+
+        assert_eq!(key1.len(), 64);
+        assert_eq!(key2.len(), 64);
+        assert!(key1.chars().all(|i| "0123456789abcdef".contains(i)));
+        assert_ne!(key1, key2);
+    }
+
+    #[test]
+    fn test_insufficient_entropy() {
+        KeyGenConfig::new(16, BASE62_ALPHABET).unwrap_err();
+        KeyGenConfig::new(1000, "").unwrap_err();
+        KeyGenConfig::new(1000, "a").unwrap_err();
+    }
+
+    #[test]
+    fn test_duplicate_characters() {
+        KeyGenConfig::new(100, "aabcdef").unwrap_err();
+    }
+}
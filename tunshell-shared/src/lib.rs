@@ -1,5 +1,13 @@
+mod capabilities;
+mod frame;
+mod key_gen;
 mod message;
 mod message_stream;
+mod session_link;
 
+pub use capabilities::*;
+pub use frame::*;
+pub use key_gen::*;
 pub use message::*;
-pub use message_stream::*;
\ No newline at end of file
+pub use message_stream::*;
+pub use session_link::*;
@@ -1,6 +1,9 @@
 use anyhow::{anyhow, Error, Result};
 use serde::{Deserialize, Serialize};
 
+/// The largest payload which can be framed in a single message
+pub const MAX_MESSAGE_SIZE: usize = i16::MAX as usize;
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct RawMessage {
     type_id: u8,
@@ -15,6 +18,62 @@ where
     fn type_id(&self) -> u8;
     fn serialise(&self) -> Result<RawMessage>;
     fn deserialise(raw_message: &RawMessage) -> Result<Self>;
+
+    /// Appends the framed message to the buffer.
+    /// Implementations can override this to write large payloads
+    /// without first copying them into a `RawMessage`.
+    fn serialise_into(&self, buff: &mut Vec<u8>) -> Result<()> {
+        let raw_message = self.serialise()?;
+        RawMessage::serialise_into(raw_message.type_id(), raw_message.data(), buff)
+    }
+
+    /// Serialises the whole message as JSON, framed with the same type id as the
+    /// binary format. Only supported by messages which opt in to `MessageFormat::Json`.
+    fn serialise_json(&self) -> Result<RawMessage> {
+        Err(anyhow!(
+            "message type {} does not support the JSON format",
+            self.type_id()
+        ))
+    }
+
+    fn deserialise_json(raw_message: &RawMessage) -> Result<Self> {
+        Err(anyhow!(
+            "message type {} does not support the JSON format",
+            raw_message.type_id()
+        ))
+    }
+
+    /// Serialises the message in the given format
+    fn serialise_as(&self, format: MessageFormat) -> Result<RawMessage> {
+        match format {
+            MessageFormat::Binary => self.serialise(),
+            MessageFormat::Json => self.serialise_json(),
+        }
+    }
+
+    /// Deserialises the message from the given format
+    fn deserialise_as(raw_message: &RawMessage, format: MessageFormat) -> Result<Self> {
+        match format {
+            MessageFormat::Binary => Self::deserialise(raw_message),
+            MessageFormat::Json => Self::deserialise_json(raw_message),
+        }
+    }
+}
+
+/// The encoding of message payloads within each frame.
+/// The JSON format is larger but can be read in a packet capture when debugging.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageFormat {
+    #[default]
+    Binary,
+    Json,
+}
+
+impl MessageFormat {
+    pub fn is_binary(&self) -> bool {
+        *self == Self::Binary
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -29,6 +88,8 @@ pub enum ServerMessage {
     AttemptDirectConnect(PortBindings),
     StartRelayMode,
     Relay(RelayPayload),
+    /// The key is valid but the session cannot be joined from the client's address
+    PeerRejected,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -46,6 +107,10 @@ pub struct PeerJoinedPayload {
     pub peer_key: String,
     pub peer_ip_address: String,
     pub session_nonce: String,
+    /// The session's reconnect token, only sent to the host so it can verify clients
+    /// reattaching to a detached shell. Empty when reconnecting is not permitted.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub reconnect_token: String,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
@@ -66,14 +131,14 @@ pub struct RelayPayload {
 
 impl RawMessage {
     pub fn new(type_id: u8, data: Vec<u8>) -> Result<Self> {
-        if data.len() > i16::MAX as usize {
+        if data.len() > MAX_MESSAGE_SIZE {
             return Err(Error::msg(format!(
                 "message length ({}) cannot be greater than {}",
                 data.len(),
-                i16::MAX
+                MAX_MESSAGE_SIZE
             )));
         }
-        assert!(data.len() <= i16::MAX as usize);
+        assert!(data.len() <= MAX_MESSAGE_SIZE);
 
         Ok(Self {
             type_id,
@@ -99,6 +164,25 @@ impl RawMessage {
 
         vec
     }
+
+    /// Appends the framed message to the buffer without allocating a `RawMessage`
+    pub fn serialise_into(type_id: u8, data: &[u8], buff: &mut Vec<u8>) -> Result<()> {
+        if data.len() > MAX_MESSAGE_SIZE {
+            return Err(Error::msg(format!(
+                "message length ({}) cannot be greater than {}",
+                data.len(),
+                MAX_MESSAGE_SIZE
+            )));
+        }
+
+        buff.reserve(3 + data.len());
+        buff.push(type_id);
+        buff.push(((data.len() & 0xFF00) >> 8) as u8);
+        buff.push((data.len() & 0xFF) as u8);
+        buff.extend_from_slice(data);
+
+        Ok(())
+    }
 }
 
 impl Message for ServerMessage {
@@ -114,6 +198,7 @@ impl Message for ServerMessage {
             Self::AttemptDirectConnect(_) => 7,
             Self::StartRelayMode => 8,
             Self::Relay(_) => 9,
+            Self::PeerRejected => 10,
         }
     }
 
@@ -131,6 +216,7 @@ impl Message for ServerMessage {
             Self::AttemptDirectConnect(payload) => serde_json::to_vec(&payload)?,
             Self::StartRelayMode => vec![],
             Self::Relay(payload) => payload.data.clone(),
+            Self::PeerRejected => vec![],
         };
         
         RawMessage::new(type_id, data)
@@ -188,6 +274,11 @@ impl Message for ServerMessage {
                 length: _,
                 data,
             } => Ok(Self::Relay(RelayPayload { data: data.clone() })),
+            RawMessage {
+                type_id: 10,
+                length: 0,
+                data: _,
+            } => Ok(Self::PeerRejected),
             _ => Err(anyhow!("Failed to parse server message: {:?}", raw_message)),
         }
     }
@@ -270,6 +361,43 @@ mod tests {
         assert_eq!(vec, vec![0, 0, 3, 1, 2, 3]);
     }
 
+    #[test]
+    fn test_raw_message_serialise_into() {
+        let mut buff = vec![1];
+
+        RawMessage::serialise_into(5, &[1, 2, 3], &mut buff).unwrap();
+
+        let expected = RawMessage::new(5, vec![1, 2, 3]).unwrap().to_vec();
+
+        assert_eq!(buff, vec![1, 5, 0, 3, 1, 2, 3]);
+        assert_eq!(&buff[1..], expected.as_slice());
+    }
+
+    #[test]
+    fn test_raw_message_serialise_into_too_long() {
+        let mut buff = vec![];
+
+        RawMessage::serialise_into(5, &[0; i16::MAX as usize + 1], &mut buff).unwrap_err();
+    }
+
+    #[test]
+    fn test_message_format_default_is_binary() {
+        assert_eq!(MessageFormat::default(), MessageFormat::Binary);
+        assert_eq!(
+            serde_json::to_string(&MessageFormat::Json).unwrap(),
+            "\"json\""
+        );
+    }
+
+    #[test]
+    fn test_serialise_json_unsupported() {
+        ServerMessage::Close
+            .serialise_as(MessageFormat::Json)
+            .unwrap_err();
+        ServerMessage::deserialise_as(&RawMessage::new(0, vec![]).unwrap(), MessageFormat::Json)
+            .unwrap_err();
+    }
+
     #[test]
     fn test_server_serialise_close() {
         let message = ServerMessage::Close;
@@ -314,12 +442,24 @@ mod tests {
         assert_eq!(raw_message.length, 0);
     }
 
+    #[test]
+    fn test_server_serialise_peer_rejected() {
+        let message = ServerMessage::PeerRejected;
+
+        let raw_message = message.serialise().unwrap();
+
+        assert_eq!(raw_message.type_id, 10);
+        assert_eq!(raw_message.data.len(), 0);
+        assert_eq!(raw_message.length, 0);
+    }
+
     #[test]
     fn test_server_serialise_peer_joined() {
         let message = ServerMessage::PeerJoined(PeerJoinedPayload {
             peer_key: "key".to_string(),
             peer_ip_address: "123.123.123.123".to_string(),
             session_nonce: "nonce".to_string(),
+            reconnect_token: String::new(),
         });
 
         let raw_message = message.serialise().unwrap();
@@ -448,6 +588,15 @@ mod tests {
         assert_eq!(message, ServerMessage::AlreadyJoined);
     }
 
+    #[test]
+    fn test_server_deserialise_peer_rejected() {
+        let raw_message = RawMessage::new(10, vec![]).unwrap();
+
+        let message = ServerMessage::deserialise(&raw_message).unwrap();
+
+        assert_eq!(message, ServerMessage::PeerRejected);
+    }
+
     #[test]
     fn test_server_deserialise_peer_joined() {
         let raw_message = RawMessage::new(
@@ -462,7 +611,8 @@ mod tests {
             ServerMessage::PeerJoined(PeerJoinedPayload {
                 peer_key: "key".to_owned(),
                 peer_ip_address: "123.123.123.123".to_owned(),
-                session_nonce: "nonce".to_owned()
+                session_nonce: "nonce".to_owned(),
+                reconnect_token: String::new(),
             })
         );
     }
@@ -1,18 +1,41 @@
+use crate::frame::*;
 use crate::message::*;
 use anyhow::{Error, Result};
 use futures::prelude::*;
 use futures::stream::Stream;
-use log::debug;
+use log::{debug, warn};
+use std::convert::TryInto;
 use std::marker::PhantomData;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
+/// The length of the sequence number prefixed to each message payload, when enabled
+const SEQUENCE_NUMBER_LEN: usize = 4;
+
 pub struct MessageStream<I: Message, O: Message, S: AsyncRead + AsyncWrite + Unpin> {
     inner: S,
     read_buff: Vec<u8>,
     write_buff: Vec<u8>,
+    // Reused between calls to `write` to avoid allocating per message
+    serialise_buff: Vec<u8>,
 
     closed: bool,
+    // Set once a malformed message has been received, no further messages
+    // are read but the stream can still be written to, eg to report the error
+    read_closed: bool,
+    // Whether message contents are included in debug logs
+    log_payloads: bool,
+    // The encoding of message payloads, agreed with the peer during the handshake
+    format: MessageFormat,
+    // Frames claiming a longer payload are rejected as soon as their header is read
+    max_message_size: usize,
+    // The layout of each frame, agreed with the peer during the handshake
+    frame_version: FrameVersion,
+    // Whether each payload is prefixed with a sequence number, for diagnosing transports
+    sequence_numbers: bool,
+    write_sequence: u32,
+    read_sequence: u32,
+    sequence_anomalies: u64,
 
     // For unused type param I, O
     phantom_i: PhantomData<I>,
@@ -25,7 +48,17 @@ impl<I: Message, O: Message, S: AsyncRead + AsyncWrite + Unpin> MessageStream<I,
             inner,
             read_buff: vec![],
             write_buff: vec![],
+            serialise_buff: vec![],
             closed: false,
+            read_closed: false,
+            log_payloads: true,
+            format: MessageFormat::Binary,
+            max_message_size: MAX_MESSAGE_SIZE,
+            frame_version: FrameVersion::V1,
+            sequence_numbers: false,
+            write_sequence: 0,
+            read_sequence: 0,
+            sequence_anomalies: 0,
             phantom_i: PhantomData,
             phantom_o: PhantomData,
         }
@@ -43,8 +76,138 @@ impl<I: Message, O: Message, S: AsyncRead + AsyncWrite + Unpin> MessageStream<I,
         self.inner
     }
 
+    /// Controls whether the contents of messages are included in debug logs,
+    /// when disabled only the type of each message is logged
+    pub fn set_log_payloads(&mut self, log_payloads: bool) {
+        self.log_payloads = log_payloads;
+    }
+
+    /// Changes the format of messages written to and read from the stream.
+    /// Both peers must switch at the same point in the message sequence.
+    pub fn set_format(&mut self, format: MessageFormat) {
+        self.format = format;
+    }
+
+    pub fn format(&self) -> MessageFormat {
+        self.format
+    }
+
+    /// Limits the payload length accepted from the peer, the length prefix of each frame is
+    /// checked before the payload is buffered so an oversized frame is never read into memory
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.max_message_size = max_message_size;
+    }
+
+    /// Changes the layout of the frames written to and read from the stream.
+    /// Both peers must switch at the same point in the message sequence.
+    pub fn set_frame_version(&mut self, frame_version: FrameVersion) {
+        self.frame_version = frame_version;
+    }
+
+    pub fn frame_version(&self) -> FrameVersion {
+        self.frame_version
+    }
+
+    /// Prefixes each message written with a sequence number and checks the sequence numbers
+    /// of the messages read, logging a warning when a message is missing or out of order.
+    /// This is only diagnostic, messages are still delivered in the order they are read.
+    /// Both peers must enable this at the same point in the message sequence.
+    pub fn set_sequence_numbers(&mut self, enabled: bool) {
+        self.sequence_numbers = enabled;
+    }
+
+    /// The number of messages which have been read out of sequence
+    pub fn sequence_anomalies(&self) -> u64 {
+        self.sequence_anomalies
+    }
+
+    /// The largest frame payload accepted from the peer
+    fn max_frame_size(&self) -> usize {
+        if self.sequence_numbers {
+            self.max_message_size + SEQUENCE_NUMBER_LEN
+        } else {
+            self.max_message_size
+        }
+    }
+
+    /// The length of the whole frame carrying a payload of the length
+    fn frame_len(&self, payload_len: usize) -> usize {
+        self.frame_version.header_len() + payload_len + self.frame_version.trailer_len()
+    }
+
+    /// Converts a message framed in the version 1 layout to the negotiated frame version
+    fn reframe(&self, frame: Vec<u8>) -> Result<Vec<u8>> {
+        if self.frame_version.is_v1() {
+            return Ok(frame);
+        }
+
+        let mut reframed = Vec::with_capacity(self.frame_len(frame.len() - 3));
+        write_frame(self.frame_version, frame[0], &frame[3..], &mut reframed)?;
+
+        Ok(reframed)
+    }
+
+    /// Inserts the next sequence number at the start of the payload of the framed message
+    fn add_sequence_number(&mut self, frame: &mut Vec<u8>) {
+        if !self.sequence_numbers {
+            return;
+        }
+
+        let length = ((frame[1] as usize) << 8 | frame[2] as usize) + SEQUENCE_NUMBER_LEN;
+        frame[1] = ((length & 0xFF00) >> 8) as u8;
+        frame[2] = (length & 0xFF) as u8;
+        frame.splice(3..3, self.write_sequence.to_be_bytes().iter().cloned());
+
+        self.write_sequence = self.write_sequence.wrapping_add(1);
+    }
+
+    /// Removes the sequence number from the start of the payload and checks it follows
+    /// on from the previous message
+    fn check_sequence_number(&mut self, data: &mut Vec<u8>) -> Result<()> {
+        if !self.sequence_numbers {
+            return Ok(());
+        }
+
+        if data.len() < SEQUENCE_NUMBER_LEN {
+            return Err(Error::msg("message is missing its sequence number"));
+        }
+
+        let sequence = u32::from_be_bytes(data[..SEQUENCE_NUMBER_LEN].try_into().unwrap());
+        data.drain(..SEQUENCE_NUMBER_LEN);
+
+        if sequence > self.read_sequence {
+            warn!(
+                "expected message {} but received {}, {} messages are missing or out of order",
+                self.read_sequence,
+                sequence,
+                sequence - self.read_sequence
+            );
+            self.sequence_anomalies += 1;
+        } else if sequence < self.read_sequence {
+            warn!(
+                "received message {} out of order, expected {}",
+                sequence, self.read_sequence
+            );
+            self.sequence_anomalies += 1;
+        }
+
+        if sequence >= self.read_sequence {
+            self.read_sequence = sequence.wrapping_add(1);
+        }
+
+        Ok(())
+    }
+
+    fn log_message<M: Message>(&self, action: &str, message: &M) {
+        if self.log_payloads {
+            debug!("{} message {:?}", action, message);
+        } else {
+            debug!("{} message of type {}", action, message.type_id());
+        }
+    }
+
     pub fn is_closed(&self) -> bool {
-        self.closed
+        self.closed || self.read_closed
     }
 
     fn err_if_closed(&self) -> Result<()> {
@@ -57,12 +220,12 @@ impl<I: Message, O: Message, S: AsyncRead + AsyncWrite + Unpin> MessageStream<I,
 
     fn poll_read_inner_stream(
         self: &mut Pin<&mut Self>,
-        mut cx: &mut Context<'_>,
+        cx: &mut Context<'_>,
     ) -> Poll<Result<usize, std::io::Error>> {
         let mut raw_buff = [0u8; 1024];
         let inner = Pin::new(&mut self.inner);
 
-        match inner.poll_read(&mut cx, &mut raw_buff) {
+        match inner.poll_read(cx, &mut raw_buff) {
             Poll::Ready(Ok(0)) => Poll::Ready(Ok(0)),
             Poll::Ready(Ok(read)) => {
                 self.as_mut().read_buff.extend_from_slice(&raw_buff[..read]);
@@ -74,16 +237,18 @@ impl<I: Message, O: Message, S: AsyncRead + AsyncWrite + Unpin> MessageStream<I,
         }
     }
 
-    fn parse_buffer(&self) -> (u8, usize, usize) {
-        if self.read_buff.len() < 3 {
-            (0, 0, 0)
-        } else {
-            (
-                self.read_buff[0],
-                (self.read_buff[1] as usize) << 8 | (self.read_buff[2] as usize),
-                self.read_buff.len() - 3,
-            )
-        }
+    /// Parses the header of the next frame in the read buffer, once it has been buffered
+    fn parse_buffer(&self) -> Result<Option<(u8, usize)>> {
+        read_frame_header(self.frame_version, &self.read_buff)
+    }
+
+    /// Closes the read side of the stream after receiving an invalid frame
+    fn reject_frame(&mut self, err: Error) -> Poll<Option<Result<O>>> {
+        debug!("Received invalid frame {:?}", err);
+        self.read_closed = true;
+        self.read_buff = vec![];
+
+        Poll::Ready(Some(Err(err)))
     }
 }
 
@@ -92,13 +257,31 @@ impl<I: Message, O: Message, S: AsyncRead + AsyncWrite + Unpin> Stream for Messa
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         debug!("message_stream: poll_next");
-        if self.err_if_closed().is_err() {
+        if self.err_if_closed().is_err() || self.read_closed {
             return Poll::Ready(None);
         }
 
-        let (mut type_id, mut message_length, mut bytes_available) = self.parse_buffer();
+        let (type_id, message_length) = loop {
+            let header = match self.parse_buffer() {
+                Ok(header) => header,
+                Err(err) => return self.reject_frame(err),
+            };
+
+            if let Some((type_id, message_length)) = header {
+                let max = self.max_frame_size();
+
+                if message_length > max {
+                    return self.reject_frame(Error::msg(format!(
+                        "message length ({}) exceeds the maximum message size ({})",
+                        message_length, max
+                    )));
+                }
+
+                if self.read_buff.len() >= self.frame_len(message_length) {
+                    break (type_id, message_length);
+                }
+            }
 
-        while self.read_buff.len() < 3 || bytes_available < message_length {
             match self.poll_read_inner_stream(cx) {
                 Poll::Ready(Ok(0)) => {
                     self.closed = true;
@@ -121,40 +304,42 @@ impl<I: Message, O: Message, S: AsyncRead + AsyncWrite + Unpin> Stream for Messa
                 }
                 Poll::Pending => return Poll::Pending,
             }
+        };
+
+        let frame_len = self.frame_len(message_length);
 
-            let parsed_buff = self.parse_buffer();
-            type_id = parsed_buff.0;
-            message_length = parsed_buff.1;
-            bytes_available = parsed_buff.2;
+        if let Err(err) = check_frame_trailer(self.frame_version, &self.read_buff[..frame_len]) {
+            return self.reject_frame(err);
         }
 
-        let raw_message = RawMessage::new(
-            type_id,
-            self.read_buff
-                .iter()
-                .cloned()
-                .skip(3)
-                .take(message_length)
-                .collect(),
-        );
+        let header_len = self.frame_version.header_len();
+        let mut data = self.read_buff[header_len..header_len + message_length].to_vec();
+        self.read_buff.drain(..frame_len);
 
-        if let Err(err) = raw_message {
+        if let Err(err) = self.check_sequence_number(&mut data) {
             debug!("Could not parse message {:?}", err);
-            self.closed = true;
+            self.read_closed = true;
 
             return Poll::Ready(Some(Err(err)));
         }
 
-        self.read_buff.drain(..3 + message_length);
+        let raw_message = RawMessage::new(type_id, data);
 
-        let result = match O::deserialise(&raw_message.unwrap()) {
+        if let Err(err) = raw_message {
+            debug!("Could not parse message {:?}", err);
+            self.read_closed = true;
+
+            return Poll::Ready(Some(Err(err)));
+        }
+
+        let result = match O::deserialise_as(&raw_message.unwrap(), self.format) {
             Ok(message) => {
-                debug!("Received message {:?}", message);
+                self.log_message("Received", &message);
                 Ok(message)
             }
             Err(err) => {
                 debug!("Error while deserialised received message {:?}", err);
-                self.closed = true;
+                self.read_closed = true;
                 Err(err)
             }
         };
@@ -166,22 +351,24 @@ impl<I: Message, O: Message, S: AsyncRead + AsyncWrite + Unpin> Stream for Messa
 impl<I: Message, O: Message, S: AsyncRead + AsyncWrite + Unpin> MessageStream<I, O, S> {
     pub fn poll_write(
         mut self: Pin<&mut Self>,
-        mut cx: &mut Context<'_>,
+        cx: &mut Context<'_>,
         message: &I,
     ) -> Poll<Result<()>> {
         if let Err(err) = self.err_if_closed() {
             return Poll::Ready(Err(err));
         }
 
-        debug!("Sending message: {:?}", message);
-        let serialised = message.serialise()?.to_vec();
+        self.log_message("Sending", message);
+        let mut serialised = message.serialise_as(self.format)?.to_vec();
+        self.add_sequence_number(&mut serialised);
+        let serialised = self.reframe(serialised)?;
         self.write_buff.extend(serialised);
 
         let buff = self.write_buff.clone();
         let mut written = 0;
 
         while written < buff.len() {
-            let write_result = Pin::new(&mut self.inner).poll_write(&mut cx, &buff[written..]);
+            let write_result = Pin::new(&mut self.inner).poll_write(cx, &buff[written..]);
 
             match write_result {
                 Poll::Ready(Ok(wrote)) => written += wrote,
@@ -206,18 +393,34 @@ impl<I: Message, O: Message, S: AsyncRead + AsyncWrite + Unpin> MessageStream<I,
     pub async fn write(&mut self, message: &I) -> Result<()> {
         self.err_if_closed()?;
 
-        let serialised = message.serialise()?.to_vec();
+        self.serialise_buff.clear();
+        match self.format {
+            MessageFormat::Binary => message.serialise_into(&mut self.serialise_buff)?,
+            MessageFormat::Json => {
+                let raw_message = message.serialise_json()?;
+                RawMessage::serialise_into(
+                    raw_message.type_id(),
+                    raw_message.data(),
+                    &mut self.serialise_buff,
+                )?
+            }
+        }
+
+        let mut frame = std::mem::take(&mut self.serialise_buff);
+        self.add_sequence_number(&mut frame);
+        self.serialise_buff = self.reframe(frame)?;
+
         let mut written = 0;
 
-        while written < serialised.len() {
-            match self.inner.write(&serialised[written..]).await {
+        while written < self.serialise_buff.len() {
+            match self.inner.write(&self.serialise_buff[written..]).await {
                 Ok(wrote) => written += wrote,
                 Err(err) => return Err(Error::new(err)),
             }
         }
 
         self.inner.flush().await?;
-        debug!("sent message: {:?}", message);
+        self.log_message("Sent", message);
 
         Ok(())
     }
@@ -320,7 +523,7 @@ mod tests {
     }
 
     #[test]
-    fn test_read_incomplete_message() {
+    fn test_read_oversized_frame_rejected() {
         let mock_stream = Cursor::new(vec![255, 255, 255]);
         let stream =
             MessageStream::<ServerMessage, ClientMessage, Cursor<Vec<u8>>>::new(mock_stream);
@@ -328,6 +531,22 @@ mod tests {
         let results: Vec<Result<ClientMessage>> =
             executor::block_on(async move { stream.collect().await });
 
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            results[0].as_ref().unwrap_err().to_string(),
+            "message length (65535) exceeds the maximum message size (32767)"
+        );
+    }
+
+    #[test]
+    fn test_read_incomplete_message() {
+        let mock_stream = Cursor::new(vec![255, 0, 5, 1]);
+        let stream =
+            MessageStream::<ServerMessage, ClientMessage, Cursor<Vec<u8>>>::new(mock_stream);
+
+        let results: Vec<Result<ClientMessage>> =
+            executor::block_on(async move { stream.collect().await });
+
         assert_eq!(results.len(), 1);
         assert_eq!(
             results[0].as_ref().unwrap_err().to_string(),
@@ -335,6 +554,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_read_oversized_frame_header() {
+        // The header claims the largest length which can be encoded, with no payload following
+        let mock_stream = Cursor::new(vec![5, 255, 255]);
+        let mut stream =
+            MessageStream::<ServerMessage, ClientMessage, Cursor<Vec<u8>>>::new(mock_stream);
+
+        let result = executor::block_on(stream.next()).unwrap();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "message length (65535) exceeds the maximum message size (32767)"
+        );
+        assert!(stream.is_closed());
+        assert!(stream.read_buff.capacity() < 1024);
+    }
+
+    #[test]
+    fn test_read_frame_exceeding_max_message_size() {
+        let mut data = vec![5, 0, 100];
+        data.extend_from_slice(&[0u8; 100]);
+        let mut stream =
+            MessageStream::<ServerMessage, ClientMessage, Cursor<Vec<u8>>>::new(Cursor::new(data));
+        stream.set_max_message_size(10);
+
+        let result = executor::block_on(stream.next()).unwrap();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "message length (100) exceeds the maximum message size (10)"
+        );
+        assert!(executor::block_on(stream.next()).is_none());
+    }
+
     #[test]
     fn test_read_invalid_message() {
         let mock_stream = Cursor::new(vec![255, 0, 1, 1]);
@@ -351,6 +604,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_write_after_invalid_message() {
+        let mock_stream = Cursor::new(vec![255, 0, 1, 1]);
+        let mut stream =
+            MessageStream::<ServerMessage, ClientMessage, Cursor<Vec<u8>>>::new(mock_stream);
+
+        executor::block_on(async {
+            stream.next().await.unwrap().unwrap_err();
+
+            assert!(stream.is_closed());
+            assert!(stream.next().await.is_none());
+
+            stream.write(&ServerMessage::Close).await.unwrap();
+        });
+
+        assert_eq!(stream.inner.into_inner(), vec![255, 0, 1, 1, 0, 0, 0]);
+    }
+
     #[test]
     fn test_write_client_close_message() {
         let message = ClientMessage::Close;
@@ -405,18 +676,268 @@ mod tests {
         assert_eq!(stream.inner.into_inner(), vec![0, 0, 0]);
     }
 
+    /// Writes the messages with sequence numbers and returns each frame separately,
+    /// so they can be delivered in a different order
+    fn sequenced_frames(messages: &[ClientMessage]) -> Vec<Vec<u8>> {
+        let mut stream = MessageStream::<ClientMessage, ServerMessage, Cursor<Vec<u8>>>::new(
+            Cursor::new(vec![]),
+        );
+        stream.set_sequence_numbers(true);
+
+        let mut frames = vec![];
+        for message in messages {
+            let start = stream.inner().get_ref().len();
+            executor::block_on(stream.write(message)).unwrap();
+            frames.push(stream.inner().get_ref()[start..].to_vec());
+        }
+
+        frames
+    }
+
+    #[test]
+    fn test_write_with_sequence_numbers() {
+        let frames = sequenced_frames(&[ClientMessage::Close, ClientMessage::Close]);
+
+        assert_eq!(
+            frames,
+            vec![vec![0, 0, 4, 0, 0, 0, 0], vec![0, 0, 4, 0, 0, 0, 1]]
+        );
+    }
+
+    #[test]
+    fn test_read_with_sequence_numbers() {
+        let messages = vec![
+            ClientMessage::Key(KeyPayload {
+                key: "key".to_owned(),
+            }),
+            ClientMessage::DirectConnectSucceeded,
+        ];
+        let mock_stream = Cursor::new(sequenced_frames(&messages).concat());
+        let mut stream =
+            MessageStream::<ServerMessage, ClientMessage, Cursor<Vec<u8>>>::new(mock_stream);
+        stream.set_sequence_numbers(true);
+
+        let results = executor::block_on_stream(&mut stream)
+            .map(|i| i.unwrap())
+            .collect::<Vec<ClientMessage>>();
+
+        assert_eq!(results, messages);
+        assert_eq!(stream.sequence_anomalies(), 0);
+    }
+
+    #[test]
+    fn test_read_reordered_messages_with_sequence_numbers() {
+        let messages = vec![
+            ClientMessage::Key(KeyPayload {
+                key: "key".to_owned(),
+            }),
+            ClientMessage::DirectConnectSucceeded,
+            ClientMessage::DirectConnectFailed,
+        ];
+        let frames = sequenced_frames(&messages);
+        // The transport swaps the last two messages
+        let mock_stream = Cursor::new([&frames[0][..], &frames[2][..], &frames[1][..]].concat());
+        let mut stream =
+            MessageStream::<ServerMessage, ClientMessage, Cursor<Vec<u8>>>::new(mock_stream);
+        stream.set_sequence_numbers(true);
+
+        let results = executor::block_on_stream(&mut stream)
+            .map(|i| i.unwrap())
+            .collect::<Vec<ClientMessage>>();
+
+        // The messages are still delivered, the gap and the late message are both reported
+        assert_eq!(
+            results,
+            vec![
+                messages[0].clone(),
+                messages[2].clone(),
+                messages[1].clone()
+            ]
+        );
+        assert_eq!(stream.sequence_anomalies(), 2);
+    }
+
+    #[test]
+    fn test_read_message_missing_sequence_number() {
+        let mock_stream = Cursor::new(ClientMessage::Close.serialise().unwrap().to_vec());
+        let mut stream =
+            MessageStream::<ServerMessage, ClientMessage, Cursor<Vec<u8>>>::new(mock_stream);
+        stream.set_sequence_numbers(true);
+
+        let result = executor::block_on(stream.next()).unwrap();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "message is missing its sequence number"
+        );
+    }
+
+    fn all_server_messages() -> Vec<ServerMessage> {
+        vec![
+            ServerMessage::Close,
+            ServerMessage::KeyAccepted,
+            ServerMessage::KeyRejected,
+            ServerMessage::AlreadyJoined,
+            ServerMessage::PeerJoined(PeerJoinedPayload {
+                peer_key: "key".to_owned(),
+                peer_ip_address: "1.2.3.4".to_owned(),
+                session_nonce: "nonce".to_owned(),
+                reconnect_token: String::new(),
+            }),
+            ServerMessage::PeerLeft,
+            ServerMessage::BindForDirectConnect,
+            ServerMessage::AttemptDirectConnect(PortBindings {
+                udp_port: Some(1234),
+                tcp_port: None,
+            }),
+            ServerMessage::StartRelayMode,
+            ServerMessage::Relay(RelayPayload {
+                data: vec![0, 1, 2, 255],
+            }),
+            ServerMessage::PeerRejected,
+        ]
+    }
+
+    fn all_client_messages() -> Vec<ClientMessage> {
+        vec![
+            ClientMessage::Close,
+            ClientMessage::Key(KeyPayload {
+                key: "key".to_owned(),
+            }),
+            ClientMessage::DirectConnectBound(PortBindings {
+                udp_port: None,
+                tcp_port: Some(1234),
+            }),
+            ClientMessage::DirectConnectSucceeded,
+            ClientMessage::DirectConnectFailed,
+            ClientMessage::Relay(RelayPayload {
+                data: vec![0, 1, 2, 255],
+            }),
+        ]
+    }
+
+    #[test]
+    fn test_messages_round_trip_in_each_frame_version() {
+        for version in &[FrameVersion::V1, FrameVersion::V2] {
+            for sequence_numbers in &[false, true] {
+                let mut server =
+                    MessageStream::<ServerMessage, ClientMessage, Cursor<Vec<u8>>>::new(
+                        Cursor::new(vec![]),
+                    );
+                let mut client =
+                    MessageStream::<ClientMessage, ServerMessage, Cursor<Vec<u8>>>::new(
+                        Cursor::new(vec![]),
+                    );
+
+                server.set_frame_version(*version);
+                client.set_frame_version(*version);
+                server.set_sequence_numbers(*sequence_numbers);
+                client.set_sequence_numbers(*sequence_numbers);
+
+                for message in all_server_messages() {
+                    executor::block_on(server.write(&message)).unwrap();
+                }
+                for message in all_client_messages() {
+                    executor::block_on(client.write(&message)).unwrap();
+                }
+
+                let mut read_server =
+                    MessageStream::<ClientMessage, ServerMessage, Cursor<Vec<u8>>>::new(
+                        Cursor::new(server.into_inner().into_inner()),
+                    );
+                let mut read_client =
+                    MessageStream::<ServerMessage, ClientMessage, Cursor<Vec<u8>>>::new(
+                        Cursor::new(client.into_inner().into_inner()),
+                    );
+                read_server.set_frame_version(*version);
+                read_client.set_frame_version(*version);
+                read_server.set_sequence_numbers(*sequence_numbers);
+                read_client.set_sequence_numbers(*sequence_numbers);
+
+                assert_eq!(
+                    executor::block_on_stream(&mut read_server)
+                        .map(|i| i.unwrap())
+                        .collect::<Vec<ServerMessage>>(),
+                    all_server_messages(),
+                    "{:?} round trip",
+                    version
+                );
+                assert_eq!(
+                    executor::block_on_stream(&mut read_client)
+                        .map(|i| i.unwrap())
+                        .collect::<Vec<ClientMessage>>(),
+                    all_client_messages(),
+                    "{:?} round trip",
+                    version
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_poll_write_v2_frame() {
+        use futures_test::task::noop_context;
+
+        let mut stream = MessageStream::<ClientMessage, ServerMessage, Cursor<Vec<u8>>>::new(
+            Cursor::new(vec![]),
+        );
+        stream.set_frame_version(FrameVersion::V2);
+
+        let result = Pin::new(&mut stream).poll_write(&mut noop_context(), &ClientMessage::Close);
+
+        assert!(result.is_ready());
+        let mut expected = vec![];
+        write_frame(FrameVersion::V2, 0, &[], &mut expected).unwrap();
+        assert_eq!(stream.inner.into_inner(), expected);
+    }
+
+    #[test]
+    fn test_read_corrupted_v2_frame() {
+        let mut frame = vec![];
+        write_frame(FrameVersion::V2, 9, &[1, 2, 3], &mut frame).unwrap();
+        frame[8] ^= 0xFF;
+
+        let mut stream =
+            MessageStream::<ClientMessage, ServerMessage, Cursor<Vec<u8>>>::new(Cursor::new(frame));
+        stream.set_frame_version(FrameVersion::V2);
+
+        let result = executor::block_on(stream.next()).unwrap();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "frame failed its integrity check"
+        );
+        assert!(executor::block_on(stream.next()).is_none());
+    }
+
+    #[test]
+    fn test_read_legacy_frame_as_v2() {
+        let message = ServerMessage::Relay(RelayPayload { data: vec![0; 16] });
+        let mock_stream = Cursor::new(message.serialise().unwrap().to_vec());
+        let mut stream =
+            MessageStream::<ClientMessage, ServerMessage, Cursor<Vec<u8>>>::new(mock_stream);
+        stream.set_frame_version(FrameVersion::V2);
+
+        let result = executor::block_on(stream.next()).unwrap();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "frame does not start with the expected magic"
+        );
+    }
+
     #[test]
     fn test_stream_closed() {
         let mock_stream = Cursor::new(vec![]);
         let mut stream =
             MessageStream::<ClientMessage, ServerMessage, Cursor<Vec<u8>>>::new(mock_stream);
 
-        assert_eq!(stream.closed, false);
+        assert!(!stream.closed);
 
         let result = executor::block_on(async { stream.next().await });
 
-        assert_eq!(result.is_none(), true);
-        assert_eq!(stream.closed, true);
+        assert!(result.is_none());
+        assert!(stream.closed);
     }
 
     #[test]
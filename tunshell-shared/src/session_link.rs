@@ -0,0 +1,196 @@
+use anyhow::{Error, Result};
+
+/// The scheme of session links
+pub const SESSION_LINK_SCHEME: &str = "tunshell://";
+
+/// The maximum length of a session link, longer links are rejected before parsing
+pub const MAX_SESSION_LINK_LENGTH: usize = 512;
+
+/// A one-line connection string for a session, such as for sharing as a QR code.
+/// Formatted as `tunshell://{relay_host}/{session_id}?key={client_key}`.
+/// The components are not percent-encoded, so they are restricted to url-safe characters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionLink {
+    pub session_id: String,
+    pub relay_host: String,
+    pub client_key: String,
+}
+
+impl SessionLink {
+    pub fn new(session_id: &str, relay_host: &str, client_key: &str) -> Result<Self> {
+        let link = Self {
+            session_id: session_id.to_owned(),
+            relay_host: relay_host.to_owned(),
+            client_key: client_key.to_owned(),
+        };
+
+        link.validate()?;
+
+        Ok(link)
+    }
+
+    pub fn to_url(&self) -> String {
+        format!(
+            "{}{}/{}?key={}",
+            SESSION_LINK_SCHEME, self.relay_host, self.session_id, self.client_key
+        )
+    }
+
+    pub fn from_url(url: &str) -> Result<Self> {
+        if url.len() > MAX_SESSION_LINK_LENGTH {
+            return Err(Error::msg(format!(
+                "session link exceeds the maximum length of {} characters",
+                MAX_SESSION_LINK_LENGTH
+            )));
+        }
+
+        let rest = url
+            .strip_prefix(SESSION_LINK_SCHEME)
+            .ok_or_else(|| Error::msg("session link must start with tunshell://"))?;
+
+        let (path, query) = split_once(rest, '?')
+            .ok_or_else(|| Error::msg("session link is missing the client key"))?;
+        let (relay_host, session_id) = split_once(path, '/')
+            .ok_or_else(|| Error::msg("session link is missing the session id"))?;
+        let client_key = query
+            .strip_prefix("key=")
+            .ok_or_else(|| Error::msg("session link is missing the client key"))?;
+
+        Self::new(session_id, relay_host, client_key)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !is_valid_host(&self.relay_host) {
+            return Err(Error::msg(format!(
+                "invalid relay host in session link: {:?}",
+                self.relay_host
+            )));
+        }
+
+        if !is_url_safe(&self.session_id) {
+            return Err(Error::msg(format!(
+                "invalid session id in session link: {:?}",
+                self.session_id
+            )));
+        }
+
+        if !is_url_safe(&self.client_key) {
+            return Err(Error::msg("invalid client key in session link"));
+        }
+
+        if self.to_url().len() > MAX_SESSION_LINK_LENGTH {
+            return Err(Error::msg(format!(
+                "session link exceeds the maximum length of {} characters",
+                MAX_SESSION_LINK_LENGTH
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+fn split_once(value: &str, delimiter: char) -> Option<(&str, &str)> {
+    let index = value.find(delimiter)?;
+
+    Some((&value[..index], &value[index + 1..]))
+}
+
+/// A hostname with an optional port
+fn is_valid_host(value: &str) -> bool {
+    let (host, port) = match split_once(value, ':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (value, None),
+    };
+
+    let host_valid = !host.is_empty()
+        && !host.starts_with('.')
+        && !host.ends_with('.')
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+
+    let port_valid = match port {
+        Some(port) => port.parse::<u16>().is_ok(),
+        None => true,
+    };
+
+    host_valid && port_valid
+}
+
+/// Non-empty and consisting only of unreserved url characters
+fn is_url_safe(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_' || c == '~')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_url() {
+        let link = SessionLink::new(
+            "8c6e2a1e-52f3-4d9c-9a7c-cb8b3e4f0a1d",
+            "relay.tunshell.com",
+            "abcdefghij0123456789AB",
+        )
+        .unwrap();
+
+        assert_eq!(
+            link.to_url(),
+            "tunshell://relay.tunshell.com/8c6e2a1e-52f3-4d9c-9a7c-cb8b3e4f0a1d?key=abcdefghij0123456789AB"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let link = SessionLink::new("session-id", "localhost:5000", "client_key~1").unwrap();
+
+        assert_eq!(SessionLink::from_url(&link.to_url()).unwrap(), link);
+    }
+
+    #[test]
+    fn test_from_url_malformed() {
+        let malformed = [
+            "",
+            "https://relay.tunshell.com/session?key=abc",
+            "tunshell://relay.tunshell.com/session",
+            "tunshell://relay.tunshell.com?key=abc",
+            "tunshell:///session?key=abc",
+            "tunshell://relay.tunshell.com/?key=abc",
+            "tunshell://relay.tunshell.com/session?key=",
+            "tunshell://relay.tunshell.com/session?token=abc",
+            "tunshell://relay.tunshell.com/session/extra?key=abc",
+            "tunshell://relay.tunshell.com/session?key=abc&key=def",
+            "tunshell://relay.tunshell.com:port/session?key=abc",
+            "tunshell://relay.tunshell.com:99999/session?key=abc",
+            "tunshell://user@relay.tunshell.com/session?key=abc",
+            "tunshell://relay.tunshell.com/sess%20ion?key=abc",
+            "tunshell://.relay/session?key=abc",
+        ];
+
+        for url in malformed.iter() {
+            SessionLink::from_url(url)
+                .err()
+                .unwrap_or_else(|| panic!("{:?} should be rejected", url));
+        }
+    }
+
+    #[test]
+    fn test_from_url_oversized() {
+        let url = format!(
+            "tunshell://relay.tunshell.com/session?key={}",
+            "a".repeat(MAX_SESSION_LINK_LENGTH)
+        );
+
+        SessionLink::from_url(&url).expect_err("oversized link should be rejected");
+        SessionLink::new(
+            "session",
+            "relay.tunshell.com",
+            &"a".repeat(MAX_SESSION_LINK_LENGTH),
+        )
+        .expect_err("oversized link should be rejected");
+    }
+}
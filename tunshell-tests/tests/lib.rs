@@ -1,3 +1,6 @@
+// The tests require the client's mock host shell, run them using test.sh
+#![cfg(integration_test)]
+
 use std::sync::Once;
 
 static INIT: Once = Once::new();